@@ -0,0 +1,47 @@
+/*
+ * Arrow IPC file output, also known as Feather, which pandas and polars can memory-map without
+ * parsing. The schema is inferred from the first page of records the same way as for parquet, and
+ * each page is written as a record batch.
+ */
+use arrow_ipc::writer::FileWriter;
+use arrow_json::reader::ReaderBuilder;
+use arrow_schema::{Schema,SchemaRef};
+use std::fs::File;
+use std::sync::Arc;
+
+pub struct ArrowOutput {
+    file: Option<File>,
+    // Created along with the schema once the first non-empty page arrives
+    writer: Option<(FileWriter<File>, SchemaRef)>,
+}
+impl ArrowOutput {
+    pub fn create(path: &std::path::Path) -> Result<Self, Box<dyn std::error::Error+Send+Sync>> {
+        Ok(Self { file: Some(File::create(path)?), writer: None })
+    }
+    pub fn write(&mut self, records: &[serde_json::Value]) -> Result<(), Box<dyn std::error::Error+Send+Sync>> {
+        if records.is_empty() {
+            return Ok(());
+        }
+        if self.writer.is_none() {
+            let schema = Arc::new(crate::schema::infer_schema(records)?);
+            let file = self.file.take().ok_or("Arrow file is already closed.")?;
+            self.writer = Some((FileWriter::try_new(file, &schema)?, schema));
+        }
+        let (writer, schema) = self.writer.as_mut().ok_or("Arrow file is already closed.")?;
+        let mut decoder = ReaderBuilder::new(schema.clone()).build_decoder()?;
+        decoder.serialize(records)?;
+        if let Some(batch) = decoder.flush()? {
+            writer.write(&batch)?;
+        }
+        Ok(())
+    }
+    pub fn finish(mut self) -> Result<(), Box<dyn std::error::Error+Send+Sync>> {
+        let mut writer = match self.writer.take() {
+            Some((writer, _)) => writer,
+            // No records at all, still leave a valid file with no columns behind
+            None => FileWriter::try_new(self.file.take().ok_or("Arrow file is already closed.")?, &Schema::empty())?,
+        };
+        writer.finish()?;
+        Ok(())
+    }
+}
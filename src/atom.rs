@@ -0,0 +1,83 @@
+/*
+ * Atom feed of the fetched records, one entry per record identified by its id, so feed readers and
+ * static site pipelines can pick the results up. Entries are streamed out page by page like the
+ * other outputs, with the feed's updated time being when the run started.
+ */
+use chrono::{DateTime,Utc};
+use std::fs::File;
+use std::io::{BufWriter,Write};
+use std::path::Path;
+use fema::{Config,Dataset};
+
+pub struct AtomFeed {
+    writer: BufWriter<File>,
+    dataset: Dataset,
+    title: String,
+    now: String,
+    entries: usize,
+    // Records without an id, which can't be given a stable entry id
+    skipped: usize,
+}
+impl AtomFeed {
+    pub fn create(cfg: &Config, path: &Path, now: DateTime<Utc>) -> std::io::Result<Self> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        let now = now.to_rfc3339_opts(chrono::SecondsFormat::Secs, true);
+        write!(writer, concat!(
+            "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n",
+            "<feed xmlns=\"http://www.w3.org/2005/Atom\">\n",
+            "  <title>OpenFEMA {dataset}</title>\n",
+            "  <id>urn:fema:{dataset}</id>\n",
+            "  <updated>{now}</updated>\n",
+            "  <link href=\"{link}\"/>\n",
+            "  <author><name>OpenFEMA</name></author>\n",
+            "  <generator>{generator}</generator>\n"),
+            dataset = cfg.dataset, now = now, link = escape(&cfg.base_uri()), generator = fema::APPNAME)?;
+        Ok(Self { writer, dataset: cfg.dataset, title: cfg.atom_title.clone(), now, entries: 0, skipped: 0 })
+    }
+    pub fn write(&mut self, records: &[serde_json::Value]) -> std::io::Result<()> {
+        for record in records {
+            let id = match record.get("id").and_then(serde_json::Value::as_str) {
+                Some(id) => id,
+                None => {
+                    self.skipped += 1;
+                    continue;
+                },
+            };
+            // When the record itself last changed, falling back to when FEMA last refreshed it
+            let updated = ["updateDate", "lastRefresh"].iter().find_map(|field| record.get(*field)?.as_str())
+                .and_then(|date| DateTime::parse_from_rfc3339(date).ok())
+                .map_or_else(|| self.now.clone(), |date| date.with_timezone(&Utc).to_rfc3339_opts(chrono::SecondsFormat::Secs, true));
+            let content: Vec<String> = match record {
+                serde_json::Value::Object(fields) => fields.iter().map(|(name, value)| match value {
+                    serde_json::Value::String(s) => format!("{}: {}", name, s),
+                    value => format!("{}: {}", name, value),
+                }).collect(),
+                record => vec![record.to_string()],
+            };
+            write!(self.writer, concat!(
+                "  <entry>\n",
+                "    <id>urn:fema:{dataset}:{id}</id>\n",
+                "    <title>{title}</title>\n",
+                "    <updated>{updated}</updated>\n",
+                "    <content type=\"text\">{content}</content>\n",
+                "  </entry>\n"),
+                dataset = self.dataset, id = escape(id), title = escape(&fema::render(&self.title, record)), updated = updated, content = escape(&content.join("\n")))?;
+            self.entries += 1;
+        }
+        self.writer.flush()
+    }
+    pub fn entries(&self) -> usize {
+        self.entries
+    }
+    pub fn finish(mut self) -> std::io::Result<()> {
+        if self.skipped > 0 {
+            warn!("Left {} records without an id out of the Atom feed.", self.skipped);
+        }
+        self.writer.write_all(b"</feed>\n")?;
+        self.writer.flush()
+    }
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
@@ -0,0 +1,70 @@
+/*
+ * Failure tracking for watch mode, so an API outage doesn't end the watch or log an error every
+ * poll. Polls that fail talking to the API are counted, and once threshold of them have failed in a
+ * row the breaker trips: an error is logged once, and polling backs off for a window that doubles
+ * with each further failure up to a maximum. A successful poll closes it again. Failures that
+ * retrying can't fix, like bad settings or a full disk, still end the watch.
+ */
+use crate::{Config,Error,METRICS};
+
+// What to do after a failed poll
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+pub enum Breaker {
+    // Below the threshold, poll again as usual
+    Closed,
+    // Just tripped or still failing, don't poll again for at least this long
+    Open(std::time::Duration),
+}
+
+#[derive(Debug,Clone)]
+pub struct CircuitBreaker {
+    threshold: usize,
+    base: std::time::Duration,
+    max: std::time::Duration,
+    // Polls failed in a row
+    failures: usize,
+}
+impl CircuitBreaker {
+    pub fn new(cfg: &Config) -> Self {
+        Self {
+            threshold: cfg.circuit_breaker_threshold.max(1),
+            base: std::time::Duration::from_secs(cfg.circuit_breaker_base_secs),
+            max: std::time::Duration::from_secs(cfg.circuit_breaker_max_secs.max(cfg.circuit_breaker_base_secs)),
+            failures: 0,
+        }
+    }
+    // Whether e is a failure talking to the API that a later poll might not run into
+    pub fn counts(e: &Error) -> bool {
+        matches!(e, Error::Http { .. } | Error::Timeout { .. } | Error::Deserialize { .. })
+    }
+    pub fn is_open(&self) -> bool {
+        self.failures >= self.threshold
+    }
+    // Records a failed poll, logging it unless the breaker was already open
+    pub fn failed(&mut self, e: &Error) -> Breaker {
+        self.failures += 1;
+        if !self.is_open() {
+            error!("Poll failed ({} of {} in a row before backing off): {}", self.failures, self.threshold, e);
+            return Breaker::Closed;
+        }
+        let doublings = (self.failures - self.threshold).min(16) as u32;
+        let window = self.base.checked_mul(1 << doublings).unwrap_or(self.max).min(self.max);
+        METRICS.circuit_open(true);
+        if self.failures == self.threshold {
+            error!("{} polls in a row have failed, the last with: {}. Backing off for {}, further failures won't be logged as errors until polling recovers.",
+                self.failures, e, humantime::format_duration(window));
+        }
+        else {
+            info!("Poll failed again ({} in a row): {}. Backing off for {}.", self.failures, e, humantime::format_duration(window));
+        }
+        Breaker::Open(window)
+    }
+    // Records a successful poll, closing the breaker
+    pub fn succeeded(&mut self) {
+        if self.is_open() {
+            warn!("Polling has recovered after {} failures in a row.", self.failures);
+            METRICS.circuit_open(false);
+        }
+        self.failures = 0;
+    }
+}
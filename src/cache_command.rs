@@ -0,0 +1,32 @@
+/*
+ * The cache subcommand, for keeping an eye on the http_cache between runs: stats to see how much it
+ * holds and how stale it's getting, purge to start it over without deleting files by hand.
+ */
+use chrono::Utc;
+use fema::{CacheStats,Config,Error,ResponseCache};
+use crate::cli::CacheAction;
+
+pub fn cache_command(cfg: &Config, action: &CacheAction) -> Result<(), Error> {
+    let dir = cfg.http_cache.as_deref().ok_or_else(|| Error::Config(String::from("No http_cache is configured.")))?;
+    let cache = ResponseCache::new(dir);
+    match action {
+        CacheAction::Stats => {
+            let stats = cache.stats()?;
+            println!("{} cached responses taking up {} in {}.", stats.entries, indicatif::HumanBytes(stats.bytes), dir.display());
+            if let (Some(oldest), Some(newest)) = (stats.oldest, stats.newest) {
+                println!("Oldest stored {} ago at {}.", age(oldest), oldest.to_rfc3339_opts(chrono::SecondsFormat::Secs, true));
+                println!("Newest stored {} ago at {}.", age(newest), newest.to_rfc3339_opts(chrono::SecondsFormat::Secs, true));
+            }
+        },
+        CacheAction::Purge => {
+            let CacheStats { entries, bytes, .. } = cache.purge()?;
+            println!("Removed {} cached responses, {}, from {}.", entries, indicatif::HumanBytes(bytes), dir.display());
+        },
+    }
+    Ok(())
+}
+
+// How long ago time was, to the second
+fn age(time: chrono::DateTime<Utc>) -> humantime::FormattedDuration {
+    humantime::format_duration(std::time::Duration::from_secs((Utc::now() - time).num_seconds().max(0) as u64))
+}
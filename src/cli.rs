@@ -0,0 +1,427 @@
+/*
+ * Command line flags, which override the corresponding values from the config file
+ */
+use clap::builder::{PossibleValuesParser,TypedValueParser};
+use clap::{Parser,Subcommand};
+use std::path::PathBuf;
+use fema::{ApiVersion,Compression,Config,CsvMode,DateBound,DateFormat,Dataset,LineEnding,OutputColumn,OutputFormat,OutputTimezone,ProgramType,QuoteStyle,StateCode};
+
+// Accepts one of the given names, so that --help and shell completions can list them
+fn one_of<T: std::str::FromStr<Err = String> + Clone + Send + Sync + 'static>(names: impl IntoIterator<Item = &'static str>) -> impl TypedValueParser<Value = T> {
+    PossibleValuesParser::new(names).try_map(|name| name.parse::<T>())
+}
+
+// A csv delimiter given as a single character, or tab since that's awkward to type
+fn delimiter(s: &str) -> Result<char, String> {
+    let mut chars = s.chars();
+    match (chars.next(), chars.next()) {
+        _ if s.eq_ignore_ascii_case("tab") => Ok('\t'),
+        (Some(c), None) => Ok(c),
+        _ => Err(format!("expected a single character or tab, not {:?}", s)),
+    }
+}
+
+// A header given as "Name: value"
+fn header(s: &str) -> Result<(String,String), String> {
+    match s.split_once(':') {
+        Some((name, value)) if !name.trim().is_empty() => Ok((name.trim().to_owned(), value.trim().to_owned())),
+        _ => Err(format!("expected \"Name: value\", not {:?}", s)),
+    }
+}
+
+#[derive(Parser,Debug)]
+#[command(name = fema::APPNAME, version, about = "Fetches FEMA web declaration areas from the OpenFEMA API")]
+pub struct Cli {
+    /// Config file to use instead of the one in the OS-standard location (.toml, .yaml, or .json)
+    #[arg(long, global = true)]
+    pub config: Option<PathBuf>,
+    /// OpenFEMA dataset to fetch
+    #[arg(long, value_parser = one_of::<Dataset>(Dataset::ALL.iter().map(Dataset::name)))]
+    pub dataset: Option<Dataset>,
+    /// Endpoint version to use, the dataset's current version by default
+    #[arg(long, value_parser = one_of::<ApiVersion>(["v1", "v2"]))]
+    pub api_version: Option<ApiVersion>,
+    /// Number of years of declarations to fetch
+    #[arg(long)]
+    pub years: Option<usize>,
+    /// Fetch declarations from this day or RFC 3339 time on instead of a number of years back, e.g. 2017-08-01
+    #[arg(long, value_name = "DATE", conflicts_with = "years")]
+    pub since: Option<DateBound>,
+    /// Only fetch declarations up to the end of this day or up to this RFC 3339 time, e.g. 2017-10-31
+    #[arg(long, value_name = "DATE")]
+    pub until: Option<DateBound>,
+    /// Also fetch declarations that have been closed out, with the date they were
+    #[arg(long)]
+    pub include_closed: bool,
+    /// Only fetch results for this state, given as a two-letter code. Can be repeated or comma-separated
+    #[arg(long = "state", value_delimiter = ',', ignore_case = true, value_parser = PossibleValuesParser::new(fema::STATE_CODES).map(|code| StateCode::from(code.as_str())))]
+    pub states: Vec<StateCode>,
+    /// Only fetch results for this program type. Can be repeated or comma-separated
+    #[arg(long = "program-type", value_delimiter = ',', ignore_case = true, value_parser = PossibleValuesParser::new(fema::PROGRAM_TYPES).map(|code| ProgramType::from(code.as_str())))]
+    pub program_types: Vec<ProgramType>,
+    /// Raw OData condition to AND onto the generated $filter, e.g. "disasterNumber ge 4000"
+    #[arg(long)]
+    pub extra_filter: Option<String>,
+    /// Fields to request and write, in order, instead of the dataset's usual columns. Comma-separated
+    #[arg(long, value_delimiter = ',')]
+    pub select: Vec<String>,
+    /// Columns to write, in order, each a field or "field->name" to rename it, e.g. "placeName->Place,designatedDate". Comma-separated
+    #[arg(long, value_delimiter = ',', value_name = "COLUMNS")]
+    pub output_columns: Vec<OutputColumn>,
+    /// Sort the results by this field, optionally followed by asc or desc, e.g. "designatedDate desc"
+    #[arg(long)]
+    pub order_by: Option<String>,
+    /// Hold back this many records to sort by --order-by before writing, for pages that come out of order
+    #[arg(long, value_name = "RECORDS")]
+    pub sort_window: Option<usize>,
+    /// Write pages in the server's order rather than as each arrives
+    #[arg(long)]
+    pub preserve_server_order: bool,
+    /// Number of results to request per page
+    #[arg(long)]
+    pub page_size: Option<usize>,
+    /// File to write entries to, in the output format (CSV by default). May contain placeholders like {date} and {years}
+    #[arg(long, conflicts_with = "no_csv")]
+    pub csv: Option<PathBuf>,
+    /// Don't write an output file
+    #[arg(long)]
+    pub no_csv: bool,
+    /// Also write an Atom feed of the results to this file, one entry per record
+    #[arg(long, value_name = "FILE")]
+    pub atom_feed: Option<PathBuf>,
+    /// Write one output file per value of this field, e.g. stateCode, in a directory named after the output file
+    #[arg(long, value_name = "FIELD")]
+    pub split_by: Option<String>,
+    /// Compress the output file as it's written, adding .gz or .zst to its name
+    #[arg(long, value_parser = one_of::<Compression>(Compression::ALL.iter().map(Compression::name)))]
+    pub compress: Option<Compression>,
+    /// Write timestamps in this timezone instead of UTC, "local" or an IANA name like America/Chicago
+    #[arg(long, value_name = "TZ")]
+    pub output_timezone: Option<OutputTimezone>,
+    /// How to write dates in csv and xlsx output: rfc3339, epoch, or a strftime pattern like %Y-%m-%d
+    #[arg(long, value_name = "FORMAT")]
+    pub date_format: Option<DateFormat>,
+    /// Field delimiter for csv output, e.g. ";" or tab
+    #[arg(long, value_name = "CHAR", value_parser = delimiter)]
+    pub csv_delimiter: Option<char>,
+    /// Which fields of csv output to quote
+    #[arg(long, value_parser = one_of::<QuoteStyle>(QuoteStyle::ALL.iter().map(QuoteStyle::name)))]
+    pub csv_quote_style: Option<QuoteStyle>,
+    /// Leave out the header row of csv output
+    #[arg(long)]
+    pub no_csv_header: bool,
+    /// Line endings of csv output
+    #[arg(long, value_parser = one_of::<LineEnding>(LineEnding::ALL.iter().map(LineEnding::name)))]
+    pub csv_line_ending: Option<LineEnding>,
+    /// Overwrite the csv file, or append to it leaving out records it already has
+    #[arg(long, value_parser = one_of::<CsvMode>(CsvMode::ALL.iter().map(CsvMode::name)))]
+    pub csv_mode: Option<CsvMode>,
+    /// Add county_fips, county_name, and is_statewide columns decoded from each record's place code
+    #[arg(long)]
+    pub decode_places: bool,
+    /// Add incidentType, declarationTitle, and incidentBeginDate columns from each record's disaster in DisasterDeclarationsSummaries
+    #[arg(long)]
+    pub enrich: bool,
+    /// Fields of DisasterDeclarationsSummaries to add with --enrich instead of the usual ones
+    #[arg(long, value_delimiter = ',', value_name = "FIELDS")]
+    pub enrich_fields: Vec<String>,
+    /// Print counts of the records by state, program type, and month instead of writing them
+    #[arg(long)]
+    pub summarize: bool,
+    /// Write counts of the records by state, program type, and month to this CSV file too
+    #[arg(long, value_name = "FILE")]
+    pub summary: Option<PathBuf>,
+    /// Serve Prometheus metrics at /metrics on this address while running, e.g. 127.0.0.1:9898
+    #[arg(long, value_name = "ADDR")]
+    pub metrics_listen: Option<String>,
+    /// Push Prometheus metrics to this Pushgateway after each run or watch poll
+    #[arg(long, value_name = "URL")]
+    pub metrics_push_url: Option<String>,
+    /// User-Agent to send, e.g. with a contact address so FEMA knows who's asking
+    #[arg(long, value_name = "UA")]
+    pub user_agent: Option<String>,
+    /// Extra header to send with every request, as "Name: value". Can be repeated
+    #[arg(long = "header", value_name = "HEADER", value_parser = header)]
+    pub headers: Vec<(String,String)>,
+    /// Keep responses in this directory and ask the server whether they've changed on later runs, using the cached one if not
+    #[arg(long, value_name = "DIR")]
+    pub http_cache: Option<PathBuf>,
+    /// Repeat the last run using only the responses kept in the http_cache, e.g. to write them in another format
+    #[arg(long)]
+    pub offline: bool,
+    /// Record every response from the API in this directory, to replay the run later with --replay
+    #[arg(long, value_name = "DIR", conflicts_with = "replay")]
+    pub record: Option<PathBuf>,
+    /// Run against the responses recorded with --record in this directory instead of the API
+    #[arg(long, value_name = "DIR")]
+    pub replay: Option<PathBuf>,
+    /// Format of the output file
+    #[arg(long, value_parser = one_of::<OutputFormat>(OutputFormat::ALL.iter().map(OutputFormat::name)))]
+    pub output_format: Option<OutputFormat>,
+    /// Turn on the debug option
+    #[arg(long)]
+    pub debug: bool,
+    /// Log JSON lines instead of text
+    #[arg(long)]
+    pub json_logs: bool,
+    /// Print the number of results for each value of this field instead of fetching them
+    #[arg(long)]
+    pub group_count: Option<String>,
+    /// Continue the interrupted run recorded in the progress file instead of starting over
+    #[arg(long)]
+    pub resume: bool,
+    /// Print the number of matching results without fetching them
+    #[arg(long)]
+    pub count: bool,
+    /// With --count, print the number of matching results per state
+    #[arg(long, requires = "count")]
+    pub by_state: bool,
+    /// Keep running and fetch again after this long, e.g. 15m or 1h, writing only records not seen before
+    #[arg(long, value_name = "INTERVAL", conflicts_with = "schedule")]
+    pub watch: Option<String>,
+    /// Keep running and fetch at the times matching this cron expression, e.g. "0 6 * * *" for 6am local time
+    #[arg(long, value_name = "CRON")]
+    pub schedule: Option<String>,
+    /// Remember the records written in this file and leave them out of later runs until they change
+    #[arg(long, value_name = "FILE")]
+    pub dedup_file: Option<PathBuf>,
+    /// Compare the results to the previous run's snapshot kept in this file, then replace it
+    #[arg(long, value_name = "SNAPSHOT")]
+    pub diff: Option<PathBuf>,
+    /// With --diff, write the added, removed, and changed records here, as JSON for a .json file and CSV otherwise
+    #[arg(long, value_name = "FILE", requires = "diff")]
+    pub diff_output: Option<PathBuf>,
+    /// Write suspicious records, e.g. with dates in the future or no place name, here, as JSON for a .json file and CSV otherwise
+    #[arg(long, value_name = "FILE")]
+    pub validation_report: Option<PathBuf>,
+    /// Fail when FEMA adds or drops fields rather than warning and leaving them out or empty
+    #[arg(long)]
+    pub strict_schema: bool,
+    /// POST newly seen records as JSON to this URL once they're written
+    #[arg(long, value_name = "URL")]
+    pub webhook_url: Option<String>,
+    /// Pop up a desktop notification for new records, e.g. with --watch (requires the desktop feature)
+    #[arg(long)]
+    pub desktop_notifications: bool,
+    /// Prompt for the common settings before fetching
+    #[arg(long)]
+    pub interactive: bool,
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand,Debug)]
+pub enum Command {
+    /// Check DNS, TCP, TLS, and HTTP connectivity to the API
+    Diagnose,
+    /// Print the entry with the given id from the store
+    Get { id: String },
+    /// Print every entry in the store
+    List,
+    /// Browse the output file of an earlier run, or the given file, in the terminal
+    Tui { file: Option<PathBuf> },
+    /// Keep a cache of the entries refreshed from the API and answer REST queries from it
+    Serve {
+        /// Address to listen on instead of the configured one, e.g. 0.0.0.0:8080
+        #[arg(long, value_name = "ADDR")]
+        listen: Option<String>,
+    },
+    /// Report on or empty the http_cache of responses
+    Cache {
+        #[command(subcommand)]
+        action: CacheAction,
+    },
+    /// Print a completion script for the given shell
+    Completions { shell: clap_complete::Shell },
+    /// Show, locate, check, or change the config file
+    Config {
+        #[command(subcommand)]
+        action: ConfigAction,
+    },
+}
+
+#[derive(Subcommand,Debug)]
+pub enum CacheAction {
+    /// Print how many responses are cached, the space they take up, and how old the oldest and newest are
+    Stats,
+    /// Remove every cached response
+    Purge,
+}
+
+#[derive(Subcommand,Debug)]
+pub enum ConfigAction {
+    /// Print the config in effect, including environment variable and command line overrides, as TOML
+    Show,
+    /// Print the path of the config file
+    Path,
+    /// Check that the config parses and makes sense, without fetching anything
+    Validate,
+    /// Set a field in the config file, e.g. "config set num_years_previous 1" or "config set states TX,LA"
+    Set { key: String, value: String },
+}
+
+impl Cli {
+    pub fn apply(&self, cfg: &mut Config) {
+        if let Some(dataset) = self.dataset {
+            cfg.dataset = dataset;
+        }
+        if let Some(version) = self.api_version {
+            cfg.api_version = Some(version);
+        }
+        // A since from the config file would otherwise win over the years asked for here
+        if let Some(years) = self.years {
+            cfg.num_years_previous = years;
+            cfg.since = None;
+        }
+        if let Some(since) = self.since {
+            cfg.since = Some(since);
+        }
+        if let Some(until) = self.until {
+            cfg.until = Some(until);
+        }
+        if self.include_closed {
+            cfg.include_closed = true;
+        }
+        if !self.states.is_empty() {
+            cfg.states = Some(self.states.clone());
+        }
+        if !self.program_types.is_empty() {
+            cfg.program_types = Some(self.program_types.clone());
+        }
+        if let Some(extra) = &self.extra_filter {
+            cfg.extra_filter = Some(extra.clone());
+        }
+        if !self.select.is_empty() {
+            cfg.select = Some(self.select.clone());
+        }
+        if !self.output_columns.is_empty() {
+            cfg.output_columns = Some(self.output_columns.clone());
+        }
+        if let Some(order_by) = &self.order_by {
+            cfg.order_by = Some(order_by.clone());
+        }
+        if let Some(size) = self.sort_window {
+            cfg.sort_window = Some(size);
+        }
+        if self.preserve_server_order {
+            cfg.preserve_server_order = true;
+        }
+        if let Some(page_size) = self.page_size {
+            cfg.page_size = page_size;
+        }
+        if let Some(csv) = &self.csv {
+            cfg.csv = Some(csv.clone());
+        }
+        if self.no_csv {
+            cfg.csv = None;
+        }
+        if let Some(atom_feed) = &self.atom_feed {
+            cfg.atom_feed = Some(atom_feed.clone());
+        }
+        if let Some(field) = &self.split_by {
+            cfg.split_by = Some(field.clone());
+        }
+        if let Some(compress) = self.compress {
+            cfg.compress = Some(compress);
+        }
+        if let Some(timezone) = self.output_timezone {
+            cfg.output_timezone = Some(timezone);
+        }
+        if let Some(format) = &self.date_format {
+            cfg.date_format = format.clone();
+        }
+        if let Some(delimiter) = self.csv_delimiter {
+            cfg.csv_delimiter = delimiter;
+        }
+        if let Some(style) = self.csv_quote_style {
+            cfg.csv_quote_style = style;
+        }
+        if self.no_csv_header {
+            cfg.csv_header = false;
+        }
+        if let Some(ending) = self.csv_line_ending {
+            cfg.csv_line_ending = ending;
+        }
+        if let Some(mode) = self.csv_mode {
+            cfg.csv_mode = mode;
+        }
+        if self.decode_places {
+            cfg.decode_places = true;
+        }
+        if self.enrich {
+            cfg.enrich = true;
+        }
+        if !self.enrich_fields.is_empty() {
+            cfg.enrich_fields = self.enrich_fields.clone();
+        }
+        if self.summarize {
+            cfg.summarize = true;
+        }
+        if let Some(path) = &self.summary {
+            cfg.summary = Some(path.clone());
+        }
+        if let Some(listen) = &self.metrics_listen {
+            cfg.metrics_listen = Some(listen.clone());
+        }
+        if let Some(url) = &self.metrics_push_url {
+            cfg.metrics_push_url = Some(url.clone());
+        }
+        if let Some(user_agent) = &self.user_agent {
+            cfg.user_agent = Some(user_agent.clone());
+        }
+        cfg.headers.extend(self.headers.iter().cloned());
+        if let Some(dir) = &self.http_cache {
+            cfg.http_cache = Some(dir.clone());
+        }
+        if self.offline {
+            cfg.offline = true;
+        }
+        if let Some(dir) = &self.record {
+            cfg.record = Some(dir.clone());
+        }
+        if let Some(dir) = &self.replay {
+            cfg.replay = Some(dir.clone());
+        }
+        if let Some(format) = self.output_format {
+            cfg.output_format = format;
+        }
+        if self.debug {
+            cfg.debug = true;
+        }
+        if self.json_logs {
+            cfg.json_logs = true;
+        }
+        if let Some(field) = &self.group_count {
+            cfg.group_count = Some(field.clone());
+        }
+        if let Some(watch) = &self.watch {
+            cfg.watch = Some(watch.clone());
+            cfg.schedule = None;
+        }
+        if let Some(schedule) = &self.schedule {
+            cfg.schedule = Some(schedule.clone());
+            cfg.watch = None;
+        }
+        if let Some(path) = &self.dedup_file {
+            cfg.dedup_file = Some(path.clone());
+        }
+        if let Some(diff) = &self.diff {
+            cfg.diff = Some(diff.clone());
+        }
+        if let Some(diff_output) = &self.diff_output {
+            cfg.diff_output = Some(diff_output.clone());
+        }
+        if let Some(path) = &self.validation_report {
+            cfg.validation_report = Some(path.clone());
+        }
+        if self.strict_schema {
+            cfg.strict_schema = true;
+        }
+        if let Some(url) = &self.webhook_url {
+            cfg.webhook_url = Some(url.clone());
+        }
+        if self.desktop_notifications {
+            cfg.desktop_notifications = true;
+        }
+    }
+}
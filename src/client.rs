@@ -0,0 +1,189 @@
+/*
+ * Entry point for other programs embedding the OpenFEMA access logic. A client carries the request
+ * settings from a Config (dataset, throttling, connection limits, retries, deprecation policy) and
+ * the base URI of the dataset's endpoint, along with an HTTP client whose connection pool is shared by
+ * every request the client makes.
+ */
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use std::collections::{BTreeMap,HashMap};
+use std::sync::Arc;
+use crate::{encode_query_value,get_json,get_uri,fetch_pages,http_client,Config,Dataset,Entry,Error,OnTotal,Page,Recording,ResponseCache,RetryPolicy,SchemaDrift};
+
+#[derive(Clone)]
+pub struct FemaClient {
+    cfg: Config,
+    base_uri: String,
+    http: reqwest::Client,
+    on_total: Option<Arc<OnTotal>>,
+}
+impl std::fmt::Debug for FemaClient {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("FemaClient").field("cfg", &self.cfg).field("base_uri", &self.base_uri).finish_non_exhaustive()
+    }
+}
+impl FemaClient {
+    pub fn new(cfg: Config) -> reqwest::Result<Self> {
+        let base_uri = cfg.base_uri();
+        let http = http_client(&cfg)?;
+        Ok(Self { cfg, base_uri, http, on_total: None })
+    }
+    // A client with other settings, e.g. for another dataset, sharing this one's connection pool
+    pub fn with_config(&self, cfg: Config) -> Self {
+        Self { base_uri: cfg.base_uri(), cfg, http: self.http.clone(), on_total: None }
+    }
+    pub fn with_base_uri(mut self, base_uri: &str) -> Self {
+        self.base_uri = String::from(base_uri);
+        self
+    }
+    // Writes pages in the server's order whatever preserve_server_order is set to
+    pub fn in_server_order(mut self) -> Self {
+        self.cfg.preserve_server_order = true;
+        self
+    }
+    // Calls on_total with the number of matching records at the start of each fetch, e.g. to size a progress bar
+    pub fn with_on_total<F: Fn(usize) + Send + Sync + 'static>(mut self, on_total: F) -> Self {
+        self.on_total = Some(Arc::new(on_total));
+        self
+    }
+    pub fn base_uri(&self) -> &str {
+        &self.base_uri
+    }
+    pub fn http(&self) -> &reqwest::Client {
+        &self.http
+    }
+    // The query string for a fetch with the given $filter, without paging parameters
+    pub fn query(&self, filter: &str) -> String {
+        let mut query = String::from("$inlinecount=allpages");
+        if let Some(select) = &self.cfg.select {
+            query.push_str(&format!("&$select={}", select.iter().map(|field| encode_query_value(field)).collect::<Vec<_>>().join(",")));
+        }
+        else if let Some(select) = self.cfg.dataset.select(self.cfg.api_version()) {
+            query.push_str(&format!("&$select={}", select));
+        }
+        if !filter.is_empty() {
+            query.push_str(&format!("&$filter={}", encode_query_value(filter)));
+        }
+        if let Some(order_by) = self.cfg.order_by.as_deref().filter(|order_by| !order_by.is_empty()) {
+            query.push_str(&format!("&$orderby={}", encode_query_value(order_by)));
+        }
+        if let Some(expand) = &self.cfg.expand {
+            query.push_str(&format!("&$expand={}", expand.iter().map(|name| encode_query_value(name)).collect::<Vec<_>>().join(",")));
+        }
+        query
+    }
+    pub fn dataset(&self) -> Dataset {
+        self.cfg.dataset
+    }
+    // Fetches every FemaWebDeclarationAreas entry matching filter, e.g. one built with build_filter
+    pub async fn fetch_declaration_areas(&self, filter: &str) -> Result<Vec<Entry>, Error> {
+        if self.cfg.dataset != Dataset::FemaWebDeclarationAreas {
+            return Err(Error::Config(format!("Client is configured for the {} dataset.", self.cfg.dataset)));
+        }
+        Ok(self.fetch_expanded(filter).await?.0)
+    }
+    // Fetches every record of the configured dataset matching filter as T
+    pub async fn fetch<T: DeserializeOwned + Serialize>(&self, filter: &str) -> Result<Vec<T>, Error> {
+        Ok(self.fetch_expanded(filter).await?.0)
+    }
+    // Like fetch, also returning any entity arrays requested with the expand option
+    pub async fn fetch_expanded<T: DeserializeOwned + Serialize>(&self, filter: &str) -> Result<(Vec<T>, HashMap<String,Vec<serde_json::Value>>), Error> {
+        let mut entries = Vec::new();
+        let mut expanded: HashMap<String,Vec<serde_json::Value>> = HashMap::new();
+        self.fetch_each(filter, |records, page_expanded| {
+            entries.extend(records);
+            for (name, values) in page_expanded {
+                expanded.entry(name).or_default().extend(values);
+            }
+            Ok(())
+        }).await?;
+        Ok((entries, expanded))
+    }
+    // Streams the records of the configured dataset matching filter to on_page one page at a time, as each arrives unless
+    // preserve_server_order or in_server_order keeps them in page order
+    pub async fn fetch_each<T, F>(&self, filter: &str, on_page: F) -> Result<usize, Error>
+    where T: DeserializeOwned + Serialize, F: FnMut(Vec<T>, HashMap<String,Vec<serde_json::Value>>) -> Result<(), Error> {
+        self.fetch_each_from(filter, 0, on_page).await
+    }
+    // Like fetch_each, skipping the pages before first_page, e.g. ones already handled by an interrupted run
+    pub async fn fetch_each_from<T, F>(&self, filter: &str, first_page: usize, mut on_page: F) -> Result<usize, Error>
+    where T: DeserializeOwned + Serialize, F: FnMut(Vec<T>, HashMap<String,Vec<serde_json::Value>>) -> Result<(), Error> {
+        let query = self.query(filter);
+        let moved = match fetch_pages(&self.cfg, &self.http, &self.base_uri, &query, first_page, self.on_total.as_deref(), &mut on_page).await {
+            Err(Error::Moved(new_url)) => new_url,
+            result => return result,
+        };
+        warn!("Following the deprecation notice to {}.", moved);
+        // Only follow once, the new endpoint's own deprecation notice is handled by the policy
+        let cfg = Config { follow_new_url: false, ..self.cfg.clone() };
+        fetch_pages(&cfg, &self.http, &moved, &query, first_page, self.on_total.as_deref(), on_page).await
+    }
+    // The number of records matching filter as reported by the server, from a single one-record request
+    pub async fn count(&self, filter: &str) -> Result<usize, Error> {
+        let uri = get_uri(true, &self.base_uri, &self.query(filter), 0, Some(1));
+        let deserialize_error = |source| Error::Deserialize { url: uri.clone(), page: None, source };
+        let page = Page::<serde_json::Value>::from_value(get_json(&self.http, &uri, &RetryPolicy::new(&self.cfg), ResponseCache::from_config(&self.cfg).as_ref(), Recording::from_config(&self.cfg).as_ref()).await?, self.cfg.dataset.name(), &SchemaDrift::default())
+            .map_err(deserialize_error)?;
+        let metadata = page.metadata.ok_or_else(|| deserialize_error(serde::de::Error::missing_field("metadata")))?;
+        if metadata.count == 0 && !page.records.is_empty() {
+            warn!("Server reported 0 matching results but returned some, the count is unreliable for this filter.");
+        }
+        Ok(metadata.count.max(0) as usize)
+    }
+    /*
+     * The number of entries matching filter for each value of field. The count is computed
+     * server-side with $apply if FEMA's API supports it, otherwise every result is fetched and
+     * counted locally.
+     */
+    pub async fn group_counts(&self, filter: &str, field: &str) -> Result<BTreeMap<String,usize>, Error> {
+        if let Some(counts) = self.server_group_counts(filter, field).await? {
+            return Ok(counts);
+        }
+        let mut counts = BTreeMap::new();
+        for record in self.fetch::<serde_json::Value>(filter).await? {
+            *counts.entry(group_key(record.get(field))).or_insert(0) += 1;
+        }
+        Ok(counts)
+    }
+    // Returns None if the server doesn't understand the $apply request
+    async fn server_group_counts(&self, filter: &str, field: &str) -> Result<Option<BTreeMap<String,usize>>, Error> {
+        let apply = if filter.is_empty() {
+            format!("groupby(({}),aggregate($count as count))", field)
+        }
+        else {
+            format!("filter({})/groupby(({}),aggregate($count as count))", filter, field)
+        };
+        let apply = format!("$apply={}", encode_query_value(&apply));
+        let uri = get_uri(false, &self.base_uri, &apply, 0, None);
+        debug!("Requesting grouped counts: {}", uri);
+        let body = match get_json::<serde_json::Value>(&self.http, &uri, &RetryPolicy::new(&self.cfg), ResponseCache::from_config(&self.cfg).as_ref(), Recording::from_config(&self.cfg).as_ref()).await {
+            Ok(body) => body,
+            Err(e) if e.status().is_some() => {
+                warn!("Server rejected grouped count request ({}), counting locally instead.", e);
+                return Ok(None);
+            },
+            Err(e) => return Err(e),
+        };
+        let rows = body.get(self.cfg.dataset.name()).and_then(|v| v.as_array())
+            .ok_or_else(|| Error::Deserialize { url: uri.clone(), page: None, source: serde::de::Error::custom("unexpected response to grouped count request") })?;
+        let mut counts = BTreeMap::new();
+        for row in rows {
+            match row.get("count").and_then(|c| c.as_u64()) {
+                Some(count) => { counts.insert(group_key(row.get(field)), count as usize); },
+                None => {
+                    warn!("Server ignored grouped count request, counting locally instead.");
+                    return Ok(None);
+                }
+            }
+        }
+        Ok(Some(counts))
+    }
+}
+
+fn group_key(value: Option<&serde_json::Value>) -> String {
+    match value {
+        Some(serde_json::Value::String(s)) => s.clone(),
+        Some(serde_json::Value::Null) | None => String::from("null"),
+        Some(v) => v.to_string(),
+    }
+}
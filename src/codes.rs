@@ -0,0 +1,99 @@
+/*
+ * The program type and state codes records and filters are made up of. Codes are read without
+ * regard to case, and ones not listed here are kept as they came in an Other variant, so a new code
+ * from FEMA doesn't fail a page. Filters in the config are checked against the known codes instead,
+ * since an unknown one there would only ever match nothing.
+ */
+use serde::{Serialize,Serializer,Deserialize,Deserializer};
+
+macro_rules! codes {
+    ($(#[$doc:meta])* $name:ident, $list:ident, $what:literal, [$($variant:ident => $code:literal,)+]) => {
+        $(#[$doc])*
+        #[derive(Debug,Clone,PartialEq,Eq,Hash,PartialOrd,Ord)]
+        #[allow(clippy::upper_case_acronyms)]
+        pub enum $name {
+            $($variant,)+
+            Other(String),
+        }
+        pub const $list: &[&str] = &[$($code,)+];
+        impl $name {
+            pub const ALL: &'static [$name] = &[$($name::$variant,)+];
+            pub fn as_str(&self) -> &str {
+                match self {
+                    $($name::$variant => $code,)+
+                    $name::Other(code) => code,
+                }
+            }
+            pub fn is_known(&self) -> bool {
+                !matches!(self, $name::Other(_))
+            }
+        }
+        impl std::default::Default for $name {
+            fn default() -> Self {
+                $name::Other(String::new())
+            }
+        }
+        impl From<&str> for $name {
+            fn from(code: &str) -> Self {
+                Self::ALL.iter().find(|known| known.as_str().eq_ignore_ascii_case(code.trim())).cloned()
+                    .unwrap_or_else(|| $name::Other(code.to_owned()))
+            }
+        }
+        impl AsRef<str> for $name {
+            fn as_ref(&self) -> &str {
+                self.as_str()
+            }
+        }
+        impl std::fmt::Display for $name {
+            fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                f.write_str(self.as_str())
+            }
+        }
+        // Unlike From, only accepts the known codes
+        impl std::str::FromStr for $name {
+            type Err = String;
+            fn from_str(s: &str) -> Result<Self, Self::Err> {
+                Some($name::from(s)).filter($name::is_known)
+                    .ok_or_else(|| format!("Unknown {} {}, expected one of {}", $what, s, $list.join(", ")))
+            }
+        }
+        impl Serialize for $name {
+            fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                serializer.serialize_str(self.as_str())
+            }
+        }
+        impl<'de> Deserialize<'de> for $name {
+            fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+                Ok($name::from(String::deserialize(deserializer)?.as_str()))
+            }
+        }
+    };
+}
+
+codes!(
+    // Individual Assistance, Public Assistance, Hazard Mitigation, and Direct Federal assistance
+    ProgramType, PROGRAM_TYPES, "program type", [
+    IndividualAssistance => "IA",
+    PublicAssistance => "PA",
+    HazardMitigation => "HM",
+    DirectFederal => "DF",
+]);
+
+codes!(
+    // Two-letter codes of the states, DC, and the territories and freely associated states FEMA serves
+    StateCode, STATE_CODES, "state code", [
+    AK => "AK", AL => "AL", AR => "AR", AS => "AS", AZ => "AZ", CA => "CA", CO => "CO", CT => "CT", DC => "DC", DE => "DE",
+    FL => "FL", FM => "FM", GA => "GA", GU => "GU", HI => "HI", IA => "IA", ID => "ID", IL => "IL", IN => "IN", KS => "KS",
+    KY => "KY", LA => "LA", MA => "MA", MD => "MD", ME => "ME", MH => "MH", MI => "MI", MN => "MN", MO => "MO", MP => "MP",
+    MS => "MS", MT => "MT", NC => "NC", ND => "ND", NE => "NE", NH => "NH", NJ => "NJ", NM => "NM", NV => "NV", NY => "NY",
+    OH => "OH", OK => "OK", OR => "OR", PA => "PA", PR => "PR", PW => "PW", RI => "RI", SC => "SC", SD => "SD", TN => "TN",
+    TX => "TX", UT => "UT", VA => "VA", VI => "VI", VT => "VT", WA => "WA", WI => "WI", WV => "WV", WY => "WY",
+]);
+
+// Checks that every code in a filter is a known one, named being the filter's config field
+pub fn validate_codes<T: AsRef<str> + std::str::FromStr<Err=String>>(named: &str, codes: Option<&[T]>) -> Result<(), String> {
+    for code in codes.unwrap_or_default() {
+        code.as_ref().parse::<T>().map_err(|e| format!("{} in {}.", e, named))?;
+    }
+    Ok(())
+}
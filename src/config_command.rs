@@ -0,0 +1,36 @@
+/*
+ * The config subcommand, for finding the config file on whichever OS this is, checking it, and
+ * changing it without opening it by hand
+ */
+use fema::{Config,Error};
+use crate::cli::{Cli,ConfigAction};
+
+pub fn config_command(cli: &Cli, action: &ConfigAction) -> Result<(), Error> {
+    let path = crate::config_path(cli)?;
+    match action {
+        ConfigAction::Path => println!("{}", path.display()),
+        ConfigAction::Show => {
+            let mut cfg = crate::load_config(cli)?;
+            cli.apply(&mut cfg);
+            print!("{}", toml::to_string_pretty(&cfg).map_err(|e| Error::Config(e.to_string()))?);
+        },
+        ConfigAction::Validate => {
+            let mut cfg = crate::load_config(cli)?;
+            cli.apply(&mut cfg);
+            cfg.validate().map_err(Error::Config)?;
+            crate::check_features(&cfg)?;
+            println!("{} is valid.", path.display());
+        },
+        ConfigAction::Set { key, value } => {
+            // Only the file's own values are saved, not the environment or command line overrides
+            let mut cfg = if path.is_file() { Config::load(&path).map_err(Error::Config)? } else { Config::default() };
+            cfg.set(key, value).map_err(Error::Config)?;
+            if let Some(dir) = path.parent() {
+                std::fs::create_dir_all(dir)?;
+            }
+            cfg.save(&path).map_err(Error::Config)?;
+            println!("Set {} in {}.", key, path.display());
+        },
+    }
+    Ok(())
+}
@@ -0,0 +1,116 @@
+/*
+ * OpenFEMA datasets that can be fetched with the same query and paging machinery. Each response
+ * holds the records in an array named after the dataset.
+ */
+use serde::{Serialize,Deserialize};
+
+#[derive(Debug,Clone,Copy,Serialize,Deserialize,PartialEq,Eq,Hash)]
+pub enum Dataset {
+    FemaWebDeclarationAreas,
+    DisasterDeclarationsSummaries,
+    HousingAssistanceOwners,
+    PublicAssistanceFundedProjectsDetails,
+}
+impl Dataset {
+    pub const ALL: &'static [Dataset] = &[
+        Dataset::FemaWebDeclarationAreas,
+        Dataset::DisasterDeclarationsSummaries,
+        Dataset::HousingAssistanceOwners,
+        Dataset::PublicAssistanceFundedProjectsDetails,
+    ];
+    // Entity name, used in the endpoint path and as the key of the records in each response
+    pub fn name(&self) -> &'static str {
+        match self {
+            Dataset::FemaWebDeclarationAreas => "FemaWebDeclarationAreas",
+            Dataset::DisasterDeclarationsSummaries => "DisasterDeclarationsSummaries",
+            Dataset::HousingAssistanceOwners => "HousingAssistanceOwners",
+            Dataset::PublicAssistanceFundedProjectsDetails => "PublicAssistanceFundedProjectsDetails",
+        }
+    }
+    // Version used unless api_version says otherwise
+    pub fn version(&self) -> ApiVersion {
+        match self {
+            Dataset::DisasterDeclarationsSummaries | Dataset::HousingAssistanceOwners => ApiVersion::V2,
+            Dataset::FemaWebDeclarationAreas | Dataset::PublicAssistanceFundedProjectsDetails => ApiVersion::V1,
+        }
+    }
+    pub fn base_uri(&self, version: ApiVersion) -> String {
+        format!("{}/{}/{}", crate::DEFAULT_BASE_URL, version, self.name())
+    }
+    // Field the num_years_previous cutoff applies to, if the dataset has a suitable date
+    pub fn date_field(&self) -> Option<&'static str> {
+        match self {
+            Dataset::FemaWebDeclarationAreas => Some("designatedDate"),
+            Dataset::DisasterDeclarationsSummaries | Dataset::PublicAssistanceFundedProjectsDetails => Some("declarationDate"),
+            Dataset::HousingAssistanceOwners => None,
+        }
+    }
+    // Field that is null until a declaration is closed out, if the dataset has one
+    pub fn closeout_field(&self) -> Option<&'static str> {
+        match self {
+            Dataset::FemaWebDeclarationAreas => Some("closeoutDate"),
+            Dataset::DisasterDeclarationsSummaries => Some("disasterCloseoutDate"),
+            Dataset::HousingAssistanceOwners | Dataset::PublicAssistanceFundedProjectsDetails => None,
+        }
+    }
+    // Field holding the two-letter state code
+    pub fn state_field(&self) -> &'static str {
+        match self {
+            Dataset::FemaWebDeclarationAreas | Dataset::PublicAssistanceFundedProjectsDetails => "stateCode",
+            Dataset::DisasterDeclarationsSummaries | Dataset::HousingAssistanceOwners => "state",
+        }
+    }
+    // Field holding the assistance program code (IA, PA, HM, DF, ...), if the dataset has one
+    pub fn program_type_field(&self) -> Option<&'static str> {
+        match self {
+            Dataset::FemaWebDeclarationAreas => Some("programTypeCode"),
+            _ => None,
+        }
+    }
+    // Fields to $select, if not all of them
+    pub fn select(&self, version: ApiVersion) -> Option<&'static str> {
+        match (self, version) {
+            // Leave out a couple unneeded or redundant fields
+            (Dataset::FemaWebDeclarationAreas, ApiVersion::V1) => Some("disasterNumber,programTypeCode,programTypeDescription,stateCode,placeCode,placeName,designatedDate,entryDate,updateDate,closeoutDate,hash,lastRefresh"),
+            _ => None,
+        }
+    }
+}
+impl std::fmt::Display for Dataset {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(self.name())
+    }
+}
+impl std::str::FromStr for Dataset {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Dataset::ALL.iter().find(|d| d.name().eq_ignore_ascii_case(s)).copied()
+            .ok_or_else(|| format!("Unknown dataset {}, expected one of {}", s, Dataset::ALL.iter().map(Dataset::name).collect::<Vec<_>>().join(", ")))
+    }
+}
+
+// Version of an OpenFEMA endpoint, part of its path. Field sets can differ between versions
+#[derive(Debug,Clone,Copy,Serialize,Deserialize,PartialEq,Eq,Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum ApiVersion {
+    V1,
+    V2,
+}
+impl std::fmt::Display for ApiVersion {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(match self {
+            ApiVersion::V1 => "v1",
+            ApiVersion::V2 => "v2",
+        })
+    }
+}
+impl std::str::FromStr for ApiVersion {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "v1" | "1" => Ok(ApiVersion::V1),
+            "v2" | "2" => Ok(ApiVersion::V2),
+            _ => Err(format!("Unknown API version {}, expected v1 or v2", s)),
+        }
+    }
+}
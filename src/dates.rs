@@ -0,0 +1,97 @@
+/*
+ * How dates are written to CSV and XLSX output: RFC 3339 as FEMA sends them, epoch seconds, or a
+ * strftime pattern like %Y-%m-%d, whichever the system importing the file wants. The records' date
+ * fields are serialized through serialize_date, which uses the format set with DateFormat::scope on
+ * the current thread and RFC 3339 otherwise, so the JSON formats, the store, and everything else
+ * that serializes records are left as they were.
+ */
+use chrono::{DateTime,Utc};
+use chrono::format::{Item,StrftimeItems};
+use serde::{Serialize,Serializer,Deserialize,Deserializer};
+use std::cell::RefCell;
+
+#[derive(Debug,Clone,PartialEq,Eq,Default)]
+pub enum DateFormat {
+    #[default]
+    Rfc3339,
+    // Seconds since 1970-01-01T00:00:00Z, written as a number
+    Epoch,
+    Strftime(String),
+}
+
+thread_local! {
+    static FORMAT: RefCell<DateFormat> = const { RefCell::new(DateFormat::Rfc3339) };
+}
+
+impl DateFormat {
+    // Runs f with dates serialized in this format, e.g. around writing a page of records
+    pub fn scope<R>(&self, f: impl FnOnce() -> R) -> R {
+        let outer = FORMAT.with(|format| format.replace(self.clone()));
+        let result = f();
+        FORMAT.with(|format| format.replace(outer));
+        result
+    }
+    // An RFC 3339 time in this format, None if value isn't one. Strftime patterns are applied in the time's own offset
+    pub fn convert(&self, value: &str) -> Option<serde_json::Value> {
+        let time = DateTime::parse_from_rfc3339(value).ok()?;
+        Some(match self {
+            DateFormat::Rfc3339 => serde_json::Value::from(value),
+            DateFormat::Epoch => serde_json::Value::from(time.timestamp()),
+            DateFormat::Strftime(pattern) => serde_json::Value::from(time.format(pattern).to_string()),
+        })
+    }
+    fn serialize_time<S: Serializer>(&self, time: &DateTime<Utc>, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            DateFormat::Rfc3339 => serializer.serialize_str(&time.to_rfc3339_opts(chrono::SecondsFormat::AutoSi, true)),
+            DateFormat::Epoch => serializer.serialize_i64(time.timestamp()),
+            DateFormat::Strftime(pattern) => serializer.collect_str(&time.format(pattern)),
+        }
+    }
+}
+
+// For #[serde(serialize_with)] on the records' date fields
+pub fn serialize_date<S: Serializer>(time: &DateTime<Utc>, serializer: S) -> Result<S::Ok, S::Error> {
+    FORMAT.with(|format| format.borrow().serialize_time(time, serializer))
+}
+pub fn serialize_optional_date<S: Serializer>(time: &Option<DateTime<Utc>>, serializer: S) -> Result<S::Ok, S::Error> {
+    match time {
+        Some(time) => serialize_date(time, serializer),
+        None => serializer.serialize_none(),
+    }
+}
+
+impl std::fmt::Display for DateFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            DateFormat::Rfc3339 => f.write_str("rfc3339"),
+            DateFormat::Epoch => f.write_str("epoch"),
+            DateFormat::Strftime(pattern) => f.write_str(pattern),
+        }
+    }
+}
+impl std::str::FromStr for DateFormat {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.eq_ignore_ascii_case("rfc3339") {
+            return Ok(DateFormat::Rfc3339);
+        }
+        if s.eq_ignore_ascii_case("epoch") {
+            return Ok(DateFormat::Epoch);
+        }
+        // Otherwise the pattern would only fail once the first date is written
+        if !s.contains('%') || StrftimeItems::new(s).any(|item| item == Item::Error) {
+            return Err(format!("Invalid date format {}, expected rfc3339, epoch, or a strftime pattern like %Y-%m-%d", s));
+        }
+        Ok(DateFormat::Strftime(s.to_owned()))
+    }
+}
+impl Serialize for DateFormat {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+impl<'de> Deserialize<'de> for DateFormat {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?.parse().map_err(serde::de::Error::custom)
+    }
+}
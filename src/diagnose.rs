@@ -0,0 +1,45 @@
+/*
+ * Connectivity checks for the diagnose subcommand, run one step at a time so that a failure points
+ * at DNS, TCP, TLS, or the HTTP request itself rather than the application logic
+ */
+use std::time::Instant;
+use fema::Error;
+
+pub async fn diagnose(http: &reqwest::Client, base_uri: &str) -> Result<(), Error> {
+    let url = reqwest::Url::parse(base_uri).map_err(|e| Error::Config(format!("Base URI {} is invalid: {}", base_uri, e)))?;
+    let host = url.host_str().ok_or_else(|| Error::Config(format!("Base URI {} has no host.", base_uri)))?.to_owned();
+    let port = url.port_or_known_default().unwrap_or(443);
+
+    let started = Instant::now();
+    let addrs: Vec<_> = match tokio::net::lookup_host((host.as_str(), port)).await {
+        Ok(addrs) => addrs.collect(),
+        Err(e) => return Err(std::io::Error::new(e.kind(), format!("DNS resolution of {} failed: {}", host, e)).into()),
+    };
+    let addr = *addrs.first().ok_or_else(|| std::io::Error::new(std::io::ErrorKind::NotFound, format!("DNS resolution of {} returned no addresses.", host)))?;
+    println!("DNS: {} resolved to {:?} in {}ms", host, addrs, started.elapsed().as_millis());
+
+    let started = Instant::now();
+    let stream = match tokio::net::TcpStream::connect(addr).await {
+        Ok(stream) => stream,
+        Err(e) => return Err(std::io::Error::new(e.kind(), format!("TCP connection to {} failed: {}", addr, e)).into()),
+    };
+    println!("TCP: connected to {} in {}ms", addr, started.elapsed().as_millis());
+
+    if url.scheme() == "https" {
+        let started = Instant::now();
+        let connector = tokio_tls::TlsConnector::from(native_tls::TlsConnector::new().map_err(|e| std::io::Error::other(e.to_string()))?);
+        if let Err(e) = connector.connect(&host, stream).await {
+            return Err(std::io::Error::other(format!("TLS handshake with {} failed: {}", host, e)).into());
+        }
+        // native-tls doesn't expose the negotiated protocol version or cipher suite
+        println!("TLS: handshake with {} succeeded in {}ms (version and cipher not available)", host, started.elapsed().as_millis());
+    }
+
+    let uri = format!("{}?$top=1&$metadata=off", base_uri);
+    let started = Instant::now();
+    let http_error = |source: reqwest::Error| Error::Http { url: uri.clone(), status: source.status().map(|s| s.as_u16()), source, retry_after: None };
+    let response = http.get(&uri).send().await.map_err(http_error)?;
+    println!("HTTP: {} in {}ms", response.status(), started.elapsed().as_millis());
+    response.error_for_status().map_err(http_error)?;
+    Ok(())
+}
@@ -0,0 +1,148 @@
+/*
+ * Diff mode: the records of the previous run kept in a snapshot file keyed by id, so each run can
+ * report which records were added, removed, or changed since then. Records are compared by their
+ * hash when both sides have one, otherwise field by field.
+ */
+use serde::{Serialize,Deserialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::path::{Path,PathBuf};
+use crate::{Error,Identified};
+
+#[derive(Debug)]
+pub struct Snapshot {
+    path: PathBuf,
+    records: BTreeMap<String,Value>,
+}
+#[derive(Debug,Serialize,Deserialize,Clone,Copy,PartialEq,Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum ChangeKind {
+    Added,
+    Removed,
+    Changed,
+}
+impl ChangeKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ChangeKind::Added => "added",
+            ChangeKind::Removed => "removed",
+            ChangeKind::Changed => "changed",
+        }
+    }
+}
+// A record as it is now, or as it was for a removed one. previous is only set for changed records
+#[derive(Debug,Serialize,Deserialize,Clone,PartialEq)]
+pub struct Change {
+    pub change: ChangeKind,
+    pub id: String,
+    pub record: Value,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub previous: Option<Value>,
+}
+impl Change {
+    // A record seen for the first time outside diff mode, e.g. one refreshed since the sync checkpoint
+    pub fn added(record: Value) -> Self {
+        let id = record.get("id").and_then(Value::as_str).unwrap_or_default().to_owned();
+        Self { change: ChangeKind::Added, id, record, previous: None }
+    }
+}
+impl Snapshot {
+    // An empty snapshot to be saved to path
+    pub fn new(path: &Path) -> Self {
+        Self { path: path.to_owned(), records: BTreeMap::new() }
+    }
+    // Reads the previous run's snapshot, None if there hasn't been one yet
+    pub fn load(path: &Path) -> Result<Option<Self>, Error> {
+        let records = match std::fs::read_to_string(path) {
+            Ok(contents) => serde_json::from_str(&contents).map_err(|e| Error::Config(format!("Failed to parse diff snapshot {}: {}", path.display(), e)))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(std::io::Error::new(e.kind(), format!("Failed to read diff snapshot {}: {}", path.display(), e)).into()),
+        };
+        Ok(Some(Self { path: path.to_owned(), records }))
+    }
+    pub fn len(&self) -> usize {
+        self.records.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.records.is_empty()
+    }
+    // Adds records to the snapshot, skipping any without an id since there'd be nothing to match them on
+    pub fn extend<T: Serialize + Identified>(&mut self, records: &[T]) -> serde_json::Result<()> {
+        for record in records {
+            if let Some(id) = record.id() {
+                self.records.insert(id.to_owned(), serde_json::to_value(record)?);
+            }
+        }
+        Ok(())
+    }
+    // What changed going from previous to this snapshot, ordered by id
+    pub fn diff(&self, previous: &Snapshot) -> Vec<Change> {
+        let mut changes = Vec::new();
+        for (id, record) in &self.records {
+            match previous.records.get(id) {
+                None => changes.push(Change { change: ChangeKind::Added, id: id.clone(), record: record.clone(), previous: None }),
+                Some(before) if !same(before, record) => {
+                    changes.push(Change { change: ChangeKind::Changed, id: id.clone(), record: record.clone(), previous: Some(before.clone()) });
+                },
+                Some(_) => (),
+            }
+        }
+        for (id, record) in &previous.records {
+            if !self.records.contains_key(id) {
+                changes.push(Change { change: ChangeKind::Removed, id: id.clone(), record: record.clone(), previous: None });
+            }
+        }
+        changes.sort_by(|a, b| a.id.cmp(&b.id));
+        changes
+    }
+    // Written to a temporary file first so an interrupted save can't lose the previous snapshot
+    pub fn save(&self) -> Result<(), Error> {
+        let partial = self.path.with_extension("partial");
+        std::fs::write(&partial, serde_json::to_string(&self.records).map_err(std::io::Error::from)?)?;
+        std::fs::rename(&partial, &self.path)?;
+        Ok(())
+    }
+}
+
+fn same(before: &Value, after: &Value) -> bool {
+    match (before.get("hash").and_then(Value::as_str), after.get("hash").and_then(Value::as_str)) {
+        (Some(before), Some(after)) => before == after,
+        _ => before == after,
+    }
+}
+
+// Writes changes to path as a JSON array if it has a .json extension, otherwise as CSV
+pub fn write_changes(path: &Path, changes: &[Change]) -> Result<(), Box<dyn std::error::Error+Send+Sync>> {
+    if path.extension().and_then(|e| e.to_str()).is_some_and(|e| e.eq_ignore_ascii_case("json")) {
+        serde_json::to_writer_pretty(std::fs::File::create(path)?, changes)?;
+        return Ok(());
+    }
+    let mut writer = csv::Writer::from_path(path)?;
+    write_changes_csv(&mut writer, changes)?;
+    writer.flush()?;
+    Ok(())
+}
+
+// Changes as CSV, with a change column followed by every field of the records
+pub fn write_changes_csv<W: std::io::Write>(writer: &mut csv::Writer<W>, changes: &[Change]) -> csv::Result<()> {
+    let mut columns: Vec<&str> = Vec::new();
+    for change in changes {
+        if let Value::Object(fields) = &change.record {
+            for name in fields.keys() {
+                if !columns.contains(&name.as_str()) {
+                    columns.push(name);
+                }
+            }
+        }
+    }
+    writer.write_record(std::iter::once("change").chain(columns.iter().copied()))?;
+    for change in changes {
+        let cells = columns.iter().map(|name| match change.record.get(name) {
+            Some(Value::String(s)) => s.clone(),
+            Some(Value::Null) | None => String::new(),
+            Some(value) => value.to_string(),
+        });
+        writer.write_record(std::iter::once(change.change.name().to_owned()).chain(cells))?;
+    }
+    Ok(())
+}
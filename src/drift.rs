@@ -0,0 +1,55 @@
+/*
+ * Schema drift: fields FEMA has added to a dataset or stopped sending since the record types here
+ * were written. Each record as it came is compared with the same record deserialized and written
+ * back out, so fields only in the former are ones the record type doesn't know, and fields only in
+ * the latter are ones missing from the response. Drift is logged once per field and the run carries
+ * on, unless strict_schema is set, in which case the page fails instead.
+ */
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::BTreeSet;
+use std::sync::Mutex;
+
+#[derive(Debug,Default)]
+pub struct SchemaDrift {
+    strict: bool,
+    // Fields already logged as added or missing, so each is only logged once
+    added: Mutex<BTreeSet<String>>,
+    missing: Mutex<BTreeSet<String>>,
+}
+impl SchemaDrift {
+    pub fn new(strict: bool) -> Self {
+        Self { strict, ..Self::default() }
+    }
+    // Compares raw records with what they were deserialized into, in the same order
+    pub fn check<T: Serialize>(&self, dataset: &str, raw: &[Value], records: &[T]) -> serde_json::Result<()> {
+        let (mut added, mut missing) = (BTreeSet::new(), BTreeSet::new());
+        for (raw, record) in raw.iter().zip(records) {
+            let (raw, record) = match (raw, serde_json::to_value(record)?) {
+                (Value::Object(raw), Value::Object(record)) => (raw, record),
+                _ => continue,
+            };
+            added.extend(raw.keys().filter(|field| !record.contains_key(*field)).cloned());
+            missing.extend(record.keys().filter(|field| !raw.contains_key(*field)).cloned());
+        }
+        if self.strict && !(added.is_empty() && missing.is_empty()) {
+            return Err(serde::de::Error::custom(format!("{} records don't match the expected fields (added: {:?}, missing: {:?}) and strict_schema is set",
+                dataset, added, missing)));
+        }
+        let added = new_fields(&self.added, added);
+        if !added.is_empty() {
+            warn!("{} records have fields that aren't known here, which won't be written: {}.", dataset, added.join(", "));
+        }
+        let missing = new_fields(&self.missing, missing);
+        if !missing.is_empty() {
+            warn!("{} records are missing fields, which will be written as 0, empty text, or null: {}.", dataset, missing.join(", "));
+        }
+        Ok(())
+    }
+}
+
+// The fields not already in reported, adding them to it
+fn new_fields(reported: &Mutex<BTreeSet<String>>, fields: BTreeSet<String>) -> Vec<String> {
+    let mut reported = reported.lock().unwrap();
+    fields.into_iter().filter(|field| reported.insert(field.clone())).collect()
+}
@@ -0,0 +1,101 @@
+/*
+ * Enrichment of records with fields of the disaster they belong to, joined on disasterNumber from
+ * DisasterDeclarationsSummaries. The disasters in the results are found with a grouped count before
+ * fetching, then their summaries are requested in batches, so only the disasters that turn up in the
+ * output are fetched, a few batches at a time as with pages. Summaries has a row per designated area, all with the same disaster-level
+ * fields, so the first row of each disaster is used.
+ */
+use futures::stream::{self,StreamExt};
+use serde_json::Value;
+use std::cell::Cell;
+use std::collections::{BTreeSet,HashMap};
+use crate::{Config,Dataset,Error,FemaClient,Row};
+
+// Disasters per summaries request, keeping the $filter well short of URL length limits
+const BATCH: usize = 50;
+
+pub const DEFAULT_ENRICH_FIELDS: &[&str] = &["incidentType", "declarationTitle", "incidentBeginDate"];
+
+#[derive(Debug,Clone,Default)]
+pub struct Enrichment {
+    fields: Vec<String>,
+    disasters: HashMap<String,serde_json::Map<String,Value>>,
+    // Rows looked up and those whose disaster had a summary, for the hit rate logged at the end
+    lookups: Cell<usize>,
+    hits: Cell<usize>,
+}
+impl Enrichment {
+    // Fetches the enrich_fields of every disaster with records matching filter
+    pub async fn fetch(cfg: &Config, client: &FemaClient, filter: &str) -> Result<Self, Error> {
+        // Disaster numbers are only ever digits, which keeps anything else out of the generated filter, and
+        // parsing them makes the same disaster counted as a number and as text, or with leading zeros, one
+        let numbers: Vec<String> = client.group_counts(filter, "disasterNumber").await?.into_keys()
+            .filter(|number| !number.is_empty() && number.bytes().all(|b| b.is_ascii_digit()))
+            .filter_map(|number| number.parse::<u64>().ok())
+            .collect::<BTreeSet<_>>().into_iter().map(|number| number.to_string())
+            .collect();
+        let mut select = vec![String::from("disasterNumber")];
+        select.extend(cfg.enrich_fields.iter().filter(|field| *field != "disasterNumber").cloned());
+        let summaries = client.with_config(Config {
+            dataset: Dataset::DisasterDeclarationsSummaries,
+            api_version: None,
+            select: Some(select),
+            order_by: None,
+            expand: None,
+            ..cfg.clone()
+        });
+        let summaries = &summaries;
+        let batches: Vec<String> = numbers.chunks(BATCH)
+            .map(|batch| batch.iter().map(|number| format!("disasterNumber eq {}", number)).collect::<Vec<_>>().join(" or "))
+            .collect();
+        let requests = batches.len();
+        let mut responses = stream::iter(batches)
+            .map(|filter| async move { summaries.fetch::<Value>(&format!("({})", filter)).await })
+            .buffer_unordered(cfg.concurrency.max(1));
+        let mut disasters = HashMap::new();
+        while let Some(records) = responses.next().await {
+            for record in records? {
+                if let Value::Object(mut fields) = record {
+                    let number = match fields.remove("disasterNumber") {
+                        Some(Value::Number(n)) => n.to_string(),
+                        Some(Value::String(s)) => s,
+                        _ => continue,
+                    };
+                    disasters.entry(number).or_insert(fields);
+                }
+            }
+        }
+        info!("Requested the summaries of {} disasters in {} requests.", numbers.len(), requests);
+        let missing = numbers.iter().filter(|number| !disasters.contains_key(*number)).count();
+        if missing > 0 {
+            warn!("{} of {} disasters have no declaration summary, their enrichment columns will be empty.", missing, numbers.len());
+        }
+        Ok(Self { fields: cfg.enrich_fields.clone(), disasters, lookups: Cell::new(0), hits: Cell::new(0) })
+    }
+    pub fn disasters(&self) -> usize {
+        self.disasters.len()
+    }
+    // Rows enriched so far, and how many of them found their disaster's summary
+    pub fn lookups(&self) -> (usize, usize) {
+        (self.lookups.get(), self.hits.get())
+    }
+    // Appends the enrichment fields to row, leaving out any it already has so its own values win
+    pub fn apply(&self, row: &mut Row) {
+        let number = match row.get("disasterNumber") {
+            Some(Value::Number(n)) => Some(n.to_string()),
+            Some(Value::String(s)) => Some(s.clone()),
+            _ => None,
+        };
+        let summary = number.and_then(|number| self.disasters.get(&number));
+        self.lookups.set(self.lookups.get() + 1);
+        if summary.is_some() {
+            self.hits.set(self.hits.get() + 1);
+        }
+        for field in &self.fields {
+            if row.get(field).is_none() {
+                let value = summary.and_then(|summary| summary.get(field)).cloned().unwrap_or(Value::Null);
+                row.0.push((field.clone(), value));
+            }
+        }
+    }
+}
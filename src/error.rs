@@ -0,0 +1,115 @@
+/*
+ * Everything that can go wrong fetching from OpenFEMA, split up by what failed so callers can react
+ * to e.g. a deprecated endpoint or a rejected request differently from a bad page of results.
+ */
+use thiserror::Error;
+
+#[derive(Debug,Error)]
+pub enum Error {
+    // Settings that can't work, caught before or instead of making a request
+    #[error("Invalid configuration: {0}")]
+    Config(String),
+    // The request couldn't be made, or the server answered with an error status, possibly saying when to try again
+    #[error("Request to {url} failed: {source}")]
+    Http {
+        url: String,
+        status: Option<u16>,
+        source: reqwest::Error,
+        retry_after: Option<std::time::Duration>,
+    },
+    // The server stopped sending the response for longer than the read timeout
+    #[error("Request to {url} timed out after waiting {}s for the response", .after.as_secs())]
+    Timeout {
+        url: String,
+        after: std::time::Duration,
+    },
+    // A response that isn't the JSON the dataset's model expects. page is None outside of paging
+    #[error("Could not deserialize {}: {source}", .page.map_or_else(|| format!("response from {}", .url), |page| format!("page {} from {}", page + 1, .url)))]
+    Deserialize {
+        url: String,
+        page: Option<usize>,
+        source: serde_json::Error,
+    },
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    // The endpoint has been deprecated or has disappeared entirely
+    #[error("{0}")]
+    Deprecated(String),
+    // The endpoint is deprecated and follow_new_url is set, so the fetch should be redone against the new URL
+    #[error("Endpoint has moved to {0}")]
+    Moved(String),
+    // The email report couldn't be sent
+    #[error("Failed to email the report: {0}")]
+    Email(#[from] lettre::transport::smtp::Error),
+    // Records couldn't be published to the MQTT broker
+    #[error("Failed to publish to MQTT broker {0}")]
+    Mqtt(String),
+    // Records couldn't be published to Kafka
+    #[cfg(feature = "kafka")]
+    #[error("Failed to publish to Kafka: {0}")]
+    Kafka(#[from] rdkafka::error::KafkaError),
+    // Returned by an on_page callback, e.g. when writing the page out fails
+    #[error(transparent)]
+    Output(#[from] Box<dyn std::error::Error+Send+Sync>),
+}
+impl Error {
+    // The HTTP status the server answered with, if it got that far
+    pub fn status(&self) -> Option<u16> {
+        match self {
+            Error::Http { status, .. } => *status,
+            _ => None,
+        }
+    }
+    /*
+     * Whether the server looks to have cut the response body off partway through, either as an
+     * error while reading the body or as JSON that ends early. Unlike connect errors, these are
+     * worth re-requesting the whole page for.
+     */
+    pub fn is_truncated_body(&self) -> bool {
+        match self {
+            Error::Http { source, .. } => source.is_body(),
+            Error::Deserialize { source, .. } => source.is_eof(),
+            _ => false,
+        }
+    }
+    // Why a failed request is worth retrying, or None if it isn't (e.g. a 4xx response)
+    pub fn transient_failure(&self) -> Option<&'static str> {
+        let e = match self {
+            Error::Http { source, .. } => source,
+            Error::Timeout { .. } => return Some("timed out"),
+            _ => return None,
+        };
+        if e.is_timeout() {
+            Some("timed out")
+        }
+        else if e.is_connect() {
+            Some("connection failed")
+        }
+        else if e.status() == Some(reqwest::StatusCode::TOO_MANY_REQUESTS) {
+            Some("was rate limited")
+        }
+        else if e.status().is_some_and(|s| s.is_server_error()) {
+            Some("server error")
+        }
+        else if e.is_request() && e.status().is_none() {
+            Some("request failed")
+        }
+        else {
+            None
+        }
+    }
+    // How long the server asked for before the request is tried again, from its Retry-After header
+    pub fn retry_after(&self) -> Option<std::time::Duration> {
+        match self {
+            Error::Http { retry_after, .. } => *retry_after,
+            _ => None,
+        }
+    }
+    // Attributes a deserialization error to the page of results it came from
+    pub fn on_page(self, page: usize) -> Self {
+        match self {
+            Error::Deserialize { url, source, .. } => Error::Deserialize { url, page: Some(page), source },
+            e => e,
+        }
+    }
+}
@@ -0,0 +1,526 @@
+use chrono::{DateTime,Utc};
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use std::collections::HashMap;
+use std::time::Instant;
+use std::sync::Mutex;
+use futures::stream::{self,StreamExt};
+use percent_encoding::{utf8_percent_encode,AsciiSet,NON_ALPHANUMERIC};
+use tokio::sync::{Semaphore,SemaphorePermit};
+use crate::{Config,DeprecationPolicy,DEFAULT_USER_AGENT,Error,Recording,ResponseCache,ResponseFormat,SchemaDrift,Throttle,Validators,METRICS};
+use crate::model::Metadata;
+
+#[derive(Debug,Clone)]
+pub struct RetryPolicy {
+    // Retries after transient failures
+    pub retries: usize,
+    // Separate budget for re-requesting truncated bodies
+    pub body_retries: usize,
+    pub base_delay: std::time::Duration,
+    pub max_delay: std::time::Duration,
+    // Longest Retry-After to wait out, beyond which the request fails instead
+    pub max_retry_after: std::time::Duration,
+    // Longest to wait for the response to start or for more of its body before giving up on the attempt
+    pub read_timeout: Option<std::time::Duration>,
+}
+impl RetryPolicy {
+    pub fn new(cfg: &Config) -> Self {
+        Self {
+            retries: cfg.retries,
+            body_retries: cfg.body_retries,
+            base_delay: std::time::Duration::from_millis(cfg.retry_base_delay_ms),
+            max_delay: std::time::Duration::from_millis(cfg.retry_max_delay_ms),
+            max_retry_after: std::time::Duration::from_secs(cfg.max_retry_after_secs),
+            read_timeout: timeout_secs(cfg.read_timeout_secs),
+        }
+    }
+    // Exponential backoff with full jitter, so concurrent requests don't all retry at once
+    pub fn backoff(&self, attempt: usize) -> std::time::Duration {
+        let ceiling = self.base_delay.checked_mul(1 << attempt.min(16) as u32).unwrap_or(self.max_delay).min(self.max_delay);
+        ceiling.mul_f64(rand::random::<f64>())
+    }
+}
+
+/*
+ * The HTTP client shared by every request in a run, so pages reuse pooled keep-alive connections
+ * instead of opening a new TCP/TLS connection each. At most max_connections idle connections are
+ * kept per host, each for up to pool_idle_timeout_secs. Requests go through the configured proxy, or
+ * any given in the environment, and carry the configured User-Agent and extra headers.
+ */
+pub fn http_client(cfg: &Config) -> reqwest::Result<reqwest::Client> {
+    let mut headers = reqwest::header::HeaderMap::new();
+    for (name, value) in &cfg.headers {
+        match (reqwest::header::HeaderName::from_bytes(name.as_bytes()), reqwest::header::HeaderValue::from_str(value)) {
+            (Ok(name), Ok(value)) => { headers.insert(name, value); },
+            _ => warn!("Not sending invalid header {}.", name),
+        }
+    }
+    let mut builder = reqwest::Client::builder()
+        .user_agent(cfg.user_agent.as_deref().unwrap_or(DEFAULT_USER_AGENT))
+        .default_headers(headers)
+        .pool_idle_timeout(std::time::Duration::from_secs(cfg.pool_idle_timeout_secs))
+        .pool_max_idle_per_host(cfg.max_connections.max(1));
+    if let Some(timeout) = timeout_secs(cfg.connect_timeout_secs) {
+        builder = builder.connect_timeout(timeout);
+    }
+    if let Some(timeout) = timeout_secs(cfg.request_timeout_secs) {
+        builder = builder.timeout(timeout);
+    }
+    if let Some(proxy) = &cfg.proxy {
+        builder = proxy.apply(builder)?;
+    }
+    builder.build()
+}
+
+// Timeouts are configured in seconds, with 0 for none
+fn timeout_secs(secs: u64) -> Option<std::time::Duration> {
+    Some(std::time::Duration::from_secs(secs)).filter(|timeout| !timeout.is_zero())
+}
+
+/*
+ * Reads the whole of response into memory within read_timeout of each part arriving, so a server
+ * that stops sending partway through fails the attempt instead of stalling the run
+ */
+async fn read_within(response: reqwest::Response, uri: &str, read_timeout: std::time::Duration) -> Result<reqwest::Response, Error> {
+    let (status, headers) = (response.status(), response.headers().clone());
+    let mut response = response;
+    let mut body = Vec::new();
+    loop {
+        let chunk = tokio::time::timeout(read_timeout, response.chunk()).await
+            .map_err(|_| Error::Timeout { url: uri.to_owned(), after: read_timeout })?
+            .map_err(|source| Error::Http { url: uri.to_owned(), status: source.status().map(|s| s.as_u16()), source, retry_after: None })?;
+        match chunk {
+            Some(chunk) => body.extend_from_slice(&chunk),
+            None => break,
+        }
+    }
+    Ok(crate::recording::response_from(status, headers, body))
+}
+
+/*
+ * Requests and deserializes a page. Truncated bodies are re-requested up to body_retries times and
+ * transient failures retried up to retries times, backing off between attempts. With a cache, the
+ * request is made conditional on the cached response having changed, or not made at all offline.
+ * With a recording, each response is either kept in it or taken from it instead of the server.
+ */
+pub async fn get_json<T: DeserializeOwned>(http: &reqwest::Client, uri: &str, policy: &RetryPolicy, cache: Option<&ResponseCache>, recording: Option<&Recording>) -> Result<T, Error> {
+    let (mut body_attempts, mut attempts) = (0, 0);
+    loop {
+        let e = match request_json(http, uri, policy, cache, recording).await {
+            Ok(response) => return Ok(response),
+            Err(e) => e,
+        };
+        if let Error::Http { status, .. } = &e {
+            METRICS.http_error(*status);
+        }
+        let delay = if e.is_truncated_body() && body_attempts < policy.body_retries {
+            body_attempts += 1;
+            warn!("Response body from {} was truncated ({}), retrying ({}/{}).", uri, e, body_attempts, policy.body_retries);
+            policy.backoff(body_attempts - 1)
+        }
+        else if let Some(reason) = e.transient_failure().filter(|_| attempts < policy.retries) {
+            attempts += 1;
+            match e.retry_after() {
+                // The server knows better than the backoff when it'll be ready again
+                Some(retry_after) if retry_after > policy.max_retry_after => {
+                    warn!("Request to {} {} and asked to wait {}s, longer than max_retry_after_secs.", uri, reason, retry_after.as_secs());
+                    return Err(e);
+                },
+                Some(retry_after) => {
+                    warn!("Request to {} {} ({}), retrying in {}s as asked ({}/{}).", uri, reason, e, retry_after.as_secs(), attempts, policy.retries);
+                    retry_after
+                },
+                None => {
+                    warn!("Request to {} {} ({}), retrying ({}/{}).", uri, reason, e, attempts, policy.retries);
+                    policy.backoff(attempts - 1)
+                },
+            }
+        }
+        else {
+            return Err(e);
+        };
+        tokio::time::delay_for(delay).await;
+    }
+}
+
+// One attempt at get_json, reading the whole body before deserializing so the two kinds of failure stay apart
+async fn request_json<T: DeserializeOwned>(http: &reqwest::Client, uri: &str, policy: &RetryPolicy, cache: Option<&ResponseCache>, recording: Option<&Recording>) -> Result<T, Error> {
+    if let Some(cache) = cache.filter(|cache| cache.offline()) {
+        let body = cache.offline_body(uri)?;
+        return serde_json::from_slice(&body).map_err(|source| Error::Deserialize { url: uri.to_owned(), page: None, source });
+    }
+    let cached = cache.and_then(|cache| cache.validators(uri));
+    let http_error = |source: reqwest::Error, retry_after| Error::Http { url: uri.to_owned(), status: source.status().map(|s| s.as_u16()), source, retry_after };
+    let response = match recording {
+        Some(recording) if recording.replaying() => recording.next(uri)?,
+        _ => {
+            let mut request = http.get(uri);
+            if let Some(cached) = &cached {
+                if let Some(etag) = &cached.etag {
+                    request = request.header(reqwest::header::IF_NONE_MATCH, etag.as_str());
+                }
+                if let Some(last_modified) = &cached.last_modified {
+                    request = request.header(reqwest::header::IF_MODIFIED_SINCE, last_modified.as_str());
+                }
+            }
+            let response = match policy.read_timeout {
+                Some(read_timeout) => {
+                    let response = tokio::time::timeout(read_timeout, request.send()).await
+                        .map_err(|_| Error::Timeout { url: uri.to_owned(), after: read_timeout })?
+                        .map_err(|e| http_error(e, None))?;
+                    read_within(response, uri, read_timeout).await?
+                },
+                None => request.send().await.map_err(|e| http_error(e, None))?,
+            };
+            match recording {
+                Some(recording) => recording.keep(uri, response).await?,
+                None => response,
+            }
+        },
+    };
+    let retry_after = retry_after(&response);
+    // None when the server says the cached response is still current
+    let response = async {
+        if cached.is_some() && response.status() == reqwest::StatusCode::NOT_MODIFIED {
+            return Ok(None);
+        }
+        let response = response.error_for_status()?;
+        let validators = Validators::from_headers(response.headers());
+        Ok(Some((validators, response.bytes().await?)))
+    }.await.map_err(|e| http_error(e, retry_after))?;
+    let body = match (response, cache) {
+        (None, Some(cache)) => {
+            debug!("{} is unchanged, using the cached response.", uri);
+            METRICS.not_modified();
+            cache.body(uri)?
+        },
+        (Some((validators, body)), Some(cache)) => {
+            cache.store(uri, &validators, &body)?;
+            body.to_vec()
+        },
+        (Some((_, body)), _) => body.to_vec(),
+        (None, None) => unreachable!("only requests with cached validators can come back unchanged"),
+    };
+    serde_json::from_slice(&body).map_err(|source| Error::Deserialize { url: uri.to_owned(), page: None, source })
+}
+
+// How long a 429 or 503 response asks to be given before trying again, as seconds or a date in its Retry-After header
+fn retry_after(response: &reqwest::Response) -> Option<std::time::Duration> {
+    if !matches!(response.status(), reqwest::StatusCode::TOO_MANY_REQUESTS | reqwest::StatusCode::SERVICE_UNAVAILABLE) {
+        return None;
+    }
+    let value = response.headers().get(reqwest::header::RETRY_AFTER)?.to_str().ok()?.trim();
+    match value.parse() {
+        Ok(secs) => Some(std::time::Duration::from_secs(secs)),
+        Err(_) => DateTime::parse_from_rfc2822(value).ok().map(|at| (at.with_timezone(&Utc) - Utc::now()).to_std().unwrap_or_default()),
+    }
+}
+
+// Told the total number of matching records once the first page reveals it
+pub type OnTotal = dyn Fn(usize) + Send + Sync;
+
+// Largest $top the API accepts
+pub const MAX_PAGE_SIZE: usize = 10000;
+
+// Characters of query values sent as they are, everything else is percent-encoded so a filter's & or ' can't end or change the parameter
+const QUERY_VALUE: &AsciiSet = &NON_ALPHANUMERIC.remove(b'-').remove(b'.').remove(b'_').remove(b'~');
+
+// A value for the query string, e.g. a $filter, percent-encoded by QUERY_VALUE
+pub fn encode_query_value(value: &str) -> String {
+    utf8_percent_encode(value, QUERY_VALUE).to_string()
+}
+
+// Helper function to make pagination less of a pain
+pub fn get_uri(metadata: bool, base: &str, query: &str, page: usize, size: Option<usize>) -> String {
+    let md_str = {
+        if metadata {
+            "on"
+        }
+        else {
+            "off"
+        }
+    };
+    match size {
+        Some(s) => {
+            format!("{}?{}&$skip={}&$top={}&$metadata={}", &base, &query, page*s, s, &md_str) 
+        },
+        _ => {
+            format!("{}?{}&$metadata={}", &base, &query,&md_str)
+        }
+    }
+}
+
+/*
+ * The metadata always contains a DeprecationInformation object, with null values unless the
+ * endpoint has been deprecated. Applies the configured policy if it has, or asks for the fetch to
+ * be redone against the new URL if follow_new_url is set and FEMA gave one.
+ */
+fn check_deprecation(cfg: &Config, metadata: &Metadata) -> Result<(), Error> {
+    let info = &metadata.DeprecationInformation;
+    let nonempty = |v: &Option<String>| v.clone().filter(|v| !v.is_empty());
+    let dep_date = match nonempty(&info.depDate) {
+        Some(d) => d,
+        None => return Ok(()),
+    };
+    let new_url = nonempty(&info.depNewURL);
+    let message = format!("Endpoint {} is deprecated as of {}: {} New URL: {}", metadata.url, dep_date,
+        nonempty(&info.depApiMessage).unwrap_or_default(), new_url.clone().unwrap_or_default());
+    warn!("==================== DEPRECATED ENDPOINT ====================");
+    warn!("{}", message);
+    if let Some(web_message) = nonempty(&info.depWebMessage) {
+        warn!("{}", web_message);
+    }
+    if let (true, Some(new_url)) = (cfg.follow_new_url, new_url) {
+        return Err(Error::Moved(new_url));
+    }
+    let passed = match DateTime::parse_from_rfc3339(&dep_date) {
+        Ok(d) => d <= Utc::now(),
+        Err(e) => {
+            warn!("Could not parse deprecation date {}: {}", dep_date, e);
+            true
+        }
+    };
+    match cfg.deprecation_policy {
+        DeprecationPolicy::Error => Err(Error::Deprecated(message)),
+        DeprecationPolicy::ErrorAfterDate if passed => Err(Error::Deprecated(message)),
+        _ => Ok(()),
+    }
+}
+
+/*
+ * One page of results. The records are the array named after the dataset; any other entity arrays
+ * in the response (e.g. from $expand) are kept alongside. Records are checked against what they're
+ * deserialized into for schema drift.
+ */
+#[derive(Debug)]
+pub struct Page<T> {
+    pub metadata: Option<Metadata>,
+    pub records: Vec<T>,
+    pub expanded: HashMap<String,Vec<serde_json::Value>>,
+}
+impl<T: DeserializeOwned + Serialize> Page<T> {
+    pub fn from_value(value: serde_json::Value, dataset: &str, drift: &SchemaDrift) -> Result<Self, serde_json::Error> {
+        let mut fields = match value {
+            serde_json::Value::Object(fields) => fields,
+            _ => return Err(serde::de::Error::custom("response is not a JSON object")),
+        };
+        let metadata = match fields.remove("metadata") {
+            Some(metadata) => Some(serde_json::from_value(metadata)?),
+            None => None,
+        };
+        let records = fields.remove(dataset).ok_or_else(|| serde::de::Error::custom(format!("response has no {} array", dataset)))?;
+        let records = Self::records(serde_json::from_value(records)?, dataset, drift)?;
+        let expanded = serde_json::from_value(serde_json::Value::Object(fields))?;
+        Ok(Self { metadata, records, expanded })
+    }
+    // A $format=jsona response, a bare array of records with no metadata or expanded entities
+    pub fn from_array(raw: Vec<serde_json::Value>, dataset: &str, drift: &SchemaDrift) -> Result<Self, serde_json::Error> {
+        Ok(Self { metadata: None, records: Self::records(raw, dataset, drift)?, expanded: HashMap::new() })
+    }
+    fn records(raw: Vec<serde_json::Value>, dataset: &str, drift: &SchemaDrift) -> Result<Vec<T>, serde_json::Error> {
+        let records = raw.iter().map(T::deserialize).collect::<Result<Vec<T>, _>>()?;
+        drift.check(dataset, &raw, &records)?;
+        Ok(records)
+    }
+}
+
+// Every request holds a permit for its duration, capping the number of open connections
+async fn acquire_connection(connections: &Semaphore) -> SemaphorePermit<'_> {
+    match connections.try_acquire() {
+        Ok(permit) => permit,
+        Err(_) => {
+            debug!("Waiting for a free connection.");
+            connections.acquire().await
+        }
+    }
+}
+
+// Request state shared by all the pages of one fetch
+struct Pager<'a> {
+    cfg: &'a Config,
+    http: &'a reqwest::Client,
+    policy: RetryPolicy,
+    cache: Option<ResponseCache>,
+    recording: Option<Recording>,
+    drift: SchemaDrift,
+    connections: Semaphore,
+    throttle: Mutex<Option<Throttle>>,
+    // Fixed gap between the starts of requests for pages, and when the next may start
+    page_delay: std::time::Duration,
+    next_request: Mutex<Instant>,
+    base_uri: &'a str,
+    query: &'a str,
+    size: usize,
+}
+impl<'a> Pager<'a> {
+    // Requests one page after waiting out the throttle delay, if any, and a free connection
+    async fn page<T: DeserializeOwned + Serialize>(&self, page: usize, end: usize) -> Result<Page<T>, Error> {
+        self.wait_turn().await;
+        let delay = self.throttle.lock().unwrap().as_ref().map(Throttle::delay);
+        if let Some(delay) = delay {
+            tokio::time::delay_for(delay).await;
+        }
+        let start = page*self.size;
+        debug!("Requesting results {} through {}.", start, end);
+        let permit = acquire_connection(&self.connections).await;
+        let started = Instant::now();
+        // Response will not contain the metadata
+        let uri = get_uri(false, self.base_uri, self.query, page, Some(self.size));
+        let response = match self.cfg.response_format {
+            ResponseFormat::Json => {
+                let value = get_json(self.http, &uri, &self.policy, self.cache.as_ref(), self.recording.as_ref()).await.map_err(|e| e.on_page(page))?;
+                Page::from_value(value, self.cfg.dataset.name(), &self.drift).map_err(|source| Error::Deserialize { url: uri, page: Some(page), source })?
+            },
+            ResponseFormat::Jsona => {
+                let uri = format!("{}&$format=jsona", uri);
+                let raw = get_json(self.http, &uri, &self.policy, self.cache.as_ref(), self.recording.as_ref()).await.map_err(|e| e.on_page(page))?;
+                Page::from_array(raw, self.cfg.dataset.name(), &self.drift).map_err(|source| Error::Deserialize { url: uri, page: Some(page), source })?
+            },
+        };
+        drop(permit);
+        debug!("Received results {} through {} from server.", start, end);
+        if let Some(throttle) = self.throttle.lock().unwrap().as_mut() {
+            let previous = throttle.delay();
+            let delay = throttle.record(started.elapsed());
+            if delay != previous {
+                info!("Adjusted delay between requests to {}ms.", delay.as_millis());
+            }
+        }
+        Ok(response)
+    }
+    // Waits out page_delay since the last request, spacing requests out even when pages are fetched concurrently
+    async fn wait_turn(&self) {
+        if self.page_delay.is_zero() {
+            return;
+        }
+        let turn = {
+            let mut next_request = self.next_request.lock().unwrap();
+            let turn = (*next_request).max(Instant::now());
+            *next_request = turn + self.page_delay;
+            turn
+        };
+        tokio::time::delay_until(tokio::time::Instant::from_std(turn)).await;
+    }
+}
+
+/*
+ * Pages through every record of the configured dataset matching the query, handing each page's
+ * records and any expanded entity arrays to on_page as it arrives so callers don't need to hold
+ * everything in memory. After the first page, which gives the total count passed to on_total, the
+ * remaining pages are fetched concurrently and passed to on_page as each completes, or in page order
+ * when preserve_server_order is set. Pages before first_page are skipped, apart from the first which
+ * is always requested for the count. Returns the number of records passed to on_page.
+ */
+pub async fn fetch_pages<T, F>(cfg: &Config, http: &reqwest::Client, base_uri: &str, query: &str, first_page: usize, on_total: Option<&OnTotal>, mut on_page: F) -> Result<usize, Error>
+where T: DeserializeOwned + Serialize, F: FnMut(Vec<T>, HashMap<String,Vec<serde_json::Value>>) -> Result<(), Error> {
+    let dataset = cfg.dataset.name();
+    let size = cfg.page_size;
+    if size == 0 || size > MAX_PAGE_SIZE {
+        return Err(Error::Config(format!("Page size must be between 1 and {}, not {}.", MAX_PAGE_SIZE, size)));
+    }
+    let mut pager = Pager {
+        cfg,
+        http,
+        policy: RetryPolicy::new(cfg),
+        cache: ResponseCache::from_config(cfg),
+        recording: Recording::from_config(cfg),
+        drift: SchemaDrift::new(cfg.strict_schema),
+        connections: Semaphore::new(cfg.max_connections.max(1)),
+        throttle: Mutex::new(if cfg.adaptive_throttle { Some(Throttle::new(cfg)) } else { None }),
+        page_delay: std::time::Duration::from_millis(cfg.page_delay_ms),
+        next_request: Mutex::new(Instant::now() + std::time::Duration::from_millis(cfg.page_delay_ms)),
+        base_uri,
+        query,
+        size,
+    };
+    let permit = acquire_connection(&pager.connections).await;
+    let started = Instant::now();
+    // request metadata on the first run so that we can get the total count
+    let uri = get_uri(true, base_uri, query, 0, Some(size));
+    let response: Page<T> = match get_json(http, &uri, &pager.policy, pager.cache.as_ref(), pager.recording.as_ref()).await {
+        Ok(response) => Page::from_value(response, dataset, &pager.drift).map_err(|source| Error::Deserialize { url: uri.clone(), page: Some(0), source })?,
+        // FEMA removing the endpoint shows up as a 404 rather than a deprecation notice
+        Err(e) if e.status() == Some(404) => {
+            return Err(Error::Deprecated(format!("Endpoint {} not found. It may have been removed or renamed; check the base URI.", base_uri)));
+        },
+        Err(e) => return Err(e.on_page(0)),
+    };
+    drop(permit);
+    if let Some(throttle) = pager.throttle.lock().unwrap().as_mut() {
+        throttle.record(started.elapsed());
+    }
+    let metadata = response.metadata.ok_or_else(|| Error::Deserialize { url: uri, page: Some(0), source: serde::de::Error::missing_field("metadata") })?;
+    check_deprecation(cfg, &metadata)?;
+    // Page through with whatever page size the server actually applied in case it lowers its cap
+    if metadata.top > 0 && (metadata.top as usize) < size {
+        warn!("Server returned pages of {} rather than the requested {} results, using the smaller size.", metadata.top, size);
+        pager.size = metadata.top as usize;
+    }
+    let size = pager.size;
+    let count = metadata.count;
+    info!("Server has {} matching results.", count);
+    // Under some filters the server reports a count of 0 but still returns results, so the count can't be trusted
+    let until_empty = count == 0 && !response.records.is_empty();
+    if until_empty {
+        warn!("Server reported 0 matching results but returned {} on the first page, requesting pages until one comes back empty.", response.records.len());
+    }
+    else if let Some(on_total) = on_total {
+        on_total(count.max(0) as usize);
+    }
+    let mut received = 0;
+    if first_page == 0 {
+        received += response.records.len();
+        METRICS.page(response.records.len());
+        on_page(response.records, response.expanded)?;
+    }
+    else {
+        info!("Resuming from page {}.", first_page + 1);
+    }
+    if until_empty {
+        let mut page = first_page.max(1);
+        loop {
+            let response: Page<T> = pager.page(page, (page+1)*size).await?;
+            if response.records.is_empty() {
+                break;
+            }
+            received += response.records.len();
+            METRICS.page(response.records.len());
+            on_page(response.records, response.expanded)?;
+            page += 1;
+        }
+        return Ok(received);
+    }
+    let pager = &pager;
+    let pages = stream::iter(first_page.max(1) .. (count as usize).div_ceil(size))
+        .map(|page| async move {
+            let end = ((page+1)*size).min(count as usize);
+            pager.page::<T>(page, end).await
+        });
+    let mut pages = if cfg.preserve_server_order {
+        pages.buffered(cfg.concurrency.max(1)).left_stream()
+    }
+    else {
+        pages.buffer_unordered(cfg.concurrency.max(1)).right_stream()
+    };
+    while let Some(response) = pages.next().await {
+        let response = response?;
+        received += response.records.len();
+        METRICS.page(response.records.len());
+        on_page(response.records, response.expanded)?;
+    }
+    Ok(received)
+}
+
+// Like fetch_pages, collecting every record and expanded entity array in memory
+pub async fn fetch_entries<T: DeserializeOwned + Serialize>(cfg: &Config, http: &reqwest::Client, base_uri: &str, query: &str) -> Result<(Vec<T>, HashMap<String,Vec<serde_json::Value>>), Error> {
+    let mut entries = Vec::new();
+    let mut expanded: HashMap<String,Vec<serde_json::Value>> = HashMap::new();
+    fetch_pages(cfg, http, base_uri, query, 0, None, |records, page_expanded| {
+        entries.extend(records);
+        for (name, values) in page_expanded {
+            expanded.entry(name).or_default().extend(values);
+        }
+        Ok(())
+    }).await?;
+    Ok((entries, expanded))
+}
@@ -0,0 +1,96 @@
+/*
+ * Geography behind the opaque state and place codes in the records: state FIPS codes, and county
+ * names and approximate centroids, embedded so they work offline. County names are the Census FIPS
+ * list, including some counties that have since been renamed or dissolved since older records use
+ * them. Centroids are the mean location of the ZIP codes in each county, good for dropping a pin on
+ * a map but not for surveying. Territories' municipalities and places other than counties, like
+ * tribal areas, aren't in the table.
+ */
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::OnceLock;
+
+const COUNTIES: &str = include_str!("../data/counties.csv");
+
+// Two-digit FIPS codes of the states, DC, and the territories and freely associated states
+const STATE_FIPS: &[(&str, &str)] = &[
+    ("AL", "01"), ("AK", "02"), ("AZ", "04"), ("AR", "05"), ("CA", "06"), ("CO", "08"), ("CT", "09"), ("DE", "10"), ("DC", "11"),
+    ("FL", "12"), ("GA", "13"), ("HI", "15"), ("ID", "16"), ("IL", "17"), ("IN", "18"), ("IA", "19"), ("KS", "20"), ("KY", "21"),
+    ("LA", "22"), ("ME", "23"), ("MD", "24"), ("MA", "25"), ("MI", "26"), ("MN", "27"), ("MS", "28"), ("MO", "29"), ("MT", "30"),
+    ("NE", "31"), ("NV", "32"), ("NH", "33"), ("NJ", "34"), ("NM", "35"), ("NY", "36"), ("NC", "37"), ("ND", "38"), ("OH", "39"),
+    ("OK", "40"), ("OR", "41"), ("PA", "42"), ("RI", "44"), ("SC", "45"), ("SD", "46"), ("TN", "47"), ("TX", "48"), ("UT", "49"),
+    ("VT", "50"), ("VA", "51"), ("WA", "53"), ("WV", "54"), ("WI", "55"), ("WY", "56"), ("AS", "60"), ("FM", "64"), ("GU", "66"),
+    ("MH", "68"), ("MP", "69"), ("PW", "70"), ("PR", "72"), ("VI", "78"),
+];
+
+#[derive(Debug,Clone,Copy,PartialEq)]
+struct County {
+    name: &'static str,
+    // Latitude and longitude
+    centroid: Option<(f64, f64)>,
+}
+
+// What a record's place code says about where it is, for the decode_places columns
+#[derive(Debug,Clone,PartialEq,Eq)]
+pub struct Place {
+    // Five-digit state and county FIPS code
+    pub county_fips: Option<String>,
+    pub county_name: Option<&'static str>,
+    pub is_statewide: bool,
+}
+
+// Counties by state code followed by the three-digit county FIPS code, e.g. TX001
+fn counties() -> &'static HashMap<String,County> {
+    static TABLE: OnceLock<HashMap<String,County>> = OnceLock::new();
+    TABLE.get_or_init(|| {
+        COUNTIES.lines().skip(1).filter_map(|line| {
+            let mut fields = line.split(',');
+            let (state, county, name) = (fields.next()?, fields.next()?, fields.next()?);
+            let centroid = match (fields.next()?.parse().ok(), fields.next()?.parse().ok()) {
+                (Some(lat), Some(lon)) => Some((lat, lon)),
+                _ => None,
+            };
+            Some((format!("{}{}", state, county), County { name, centroid }))
+        }).collect()
+    })
+}
+
+pub fn state_fips(state: &str) -> Option<&'static str> {
+    STATE_FIPS.iter().find(|(code, _)| code.eq_ignore_ascii_case(state)).map(|(_, fips)| *fips)
+}
+
+// Name of a county given its state code and three-digit county FIPS code, e.g. Anderson County for TX and 001
+pub fn county_name(state: &str, county: &str) -> Option<&'static str> {
+    counties().get(&format!("{}{}", state.to_ascii_uppercase(), county)).map(|county| county.name)
+}
+
+// Centroid of a county given its state code and three-digit county FIPS code, as (latitude, longitude)
+pub fn county_centroid(state: &str, county: &str) -> Option<(f64, f64)> {
+    counties().get(&format!("{}{}", state.to_ascii_uppercase(), county)).and_then(|county| county.centroid)
+}
+
+// The state code and three-digit county FIPS code of the county a record is about, if it's about one
+fn county_of(record: &Value) -> Option<(&str, &str)> {
+    let state = ["stateCode", "state"].iter().find_map(|field| record.get(*field)?.as_str())?;
+    // FEMA writes a county's placeCode as 99 followed by its FIPS code
+    let county = match record.get("fipsCountyCode").and_then(Value::as_str) {
+        Some(county) => county,
+        None => record.get("placeCode").and_then(Value::as_str).filter(|code| code.len() == 5).and_then(|code| code.strip_prefix("99"))?,
+    };
+    Some((state, county)).filter(|(_, county)| county.len() == 3 && county.bytes().all(|b| b.is_ascii_digit()) && *county != "000")
+}
+
+// Centroid of the place a record is about, from its state code and either its fipsCountyCode or a county placeCode
+pub fn place_centroid(record: &Value) -> Option<(f64, f64)> {
+    county_of(record).and_then(|(state, county)| county_centroid(state, county))
+}
+
+// Decodes the place a record is about, a statewide designation being one without a county
+pub fn decode_place(record: &Value) -> Place {
+    let county = county_of(record);
+    let county_fips = county.and_then(|(state, county)| Some(format!("{}{}", state_fips(state)?, county)));
+    let county_name = county.and_then(|(state, county)| county_name(state, county));
+    let statewide_name = record.get("placeName").and_then(Value::as_str).is_some_and(|name| name.eq_ignore_ascii_case("statewide"));
+    let zero_code = ["placeCode", "fipsCountyCode"].iter().filter_map(|field| record.get(*field)?.as_str()).any(|code| !code.is_empty() && code.bytes().all(|b| b == b'0'));
+    Place { county_fips, county_name, is_statewide: county.is_none() && (statewide_name || zero_code) }
+}
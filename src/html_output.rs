@@ -0,0 +1,105 @@
+/*
+ * HTML output: a single self-contained page with the results in a table that can be sorted by
+ * clicking a column heading and filtered by typing in the search box, for people who'd rather not
+ * open a CSV. The page is streamed out like the other formats, with the table's columns taken from
+ * the first record and the script that makes it interactive written at the end.
+ */
+use chrono::{DateTime,Utc};
+use serde::Serialize;
+use std::fs::File;
+use std::io::{BufWriter,Write};
+use std::path::Path;
+use fema::{Config,Row};
+
+const STYLE: &str = "
+body { font-family: system-ui, sans-serif; margin: 2em; }
+table { border-collapse: collapse; font-size: 0.9em; }
+th, td { border: 1px solid #ccc; padding: 0.3em 0.6em; text-align: left; }
+th { background: #eee; cursor: pointer; position: sticky; top: 0; }
+th[data-order=asc]::after { content: \" \\25B2\"; }
+th[data-order=desc]::after { content: \" \\25BC\"; }
+tbody tr:nth-child(even) { background: #f8f8f8; }
+#filter { margin: 1em 0; padding: 0.3em; width: 20em; }
+";
+
+const SCRIPT: &str = "
+const table = document.getElementById(\"results\");
+const rows = () => Array.from(table.tBodies[0].rows);
+document.getElementById(\"filter\").addEventListener(\"input\", event => {
+  const needle = event.target.value.toLowerCase();
+  for (const row of rows()) {
+    row.hidden = !row.textContent.toLowerCase().includes(needle);
+  }
+});
+table.querySelectorAll(\"th\").forEach((heading, column) => heading.addEventListener(\"click\", () => {
+  const ascending = heading.dataset.order !== \"asc\";
+  table.querySelectorAll(\"th\").forEach(other => delete other.dataset.order);
+  heading.dataset.order = ascending ? \"asc\" : \"desc\";
+  const sorted = rows().sort((a, b) => a.cells[column].textContent.localeCompare(b.cells[column].textContent, undefined, { numeric: true }));
+  table.tBodies[0].append(...(ascending ? sorted : sorted.reverse()));
+}));
+";
+
+pub struct HtmlOutput {
+    writer: BufWriter<File>,
+    // Whether the table heading has been written, which happens with the first record
+    started: bool,
+}
+impl HtmlOutput {
+    pub fn create(cfg: &Config, path: &Path, now: DateTime<Utc>, filter: &str) -> std::io::Result<Self> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        write!(writer, concat!(
+            "<!DOCTYPE html>\n<html lang=\"en\">\n<head>\n<meta charset=\"utf-8\">\n<title>OpenFEMA {dataset}</title>\n<style>{style}</style>\n</head>\n<body>\n",
+            "<h1>OpenFEMA {dataset}</h1>\n",
+            "<p>Generated {now} from {uri} with the filter <code>{filter}</code></p>\n",
+            "<input id=\"filter\" type=\"search\" placeholder=\"Filter rows\">\n",
+            "<table id=\"results\">\n"),
+            dataset = cfg.dataset, style = STYLE, now = now.to_rfc3339_opts(chrono::SecondsFormat::Secs, true), uri = escape(&cfg.base_uri()),
+            filter = if filter.is_empty() { String::from("(none)") } else { escape(filter) })?;
+        Ok(Self { writer, started: false })
+    }
+    // Continues a page written by an interrupted run, whose table heading is there if it got any rows
+    pub fn append(file: File, rows: usize) -> Self {
+        Self { writer: BufWriter::new(file), started: rows > 0 }
+    }
+    pub fn write<T: Serialize>(&mut self, record: &T, fetched_at: Option<&str>) -> Result<(), Box<dyn std::error::Error+Send+Sync>> {
+        // Read back from the record's JSON to keep its fields in declaration order
+        let mut row: Row = serde_json::from_slice(&serde_json::to_vec(record)?)?;
+        if let Some(fetched_at) = fetched_at {
+            row.0.push((String::from("fetched_at"), serde_json::Value::from(fetched_at)));
+        }
+        if !self.started {
+            self.writer.write_all(b"<thead><tr>")?;
+            for name in row.names() {
+                write!(self.writer, "<th>{}</th>", escape(name))?;
+            }
+            self.writer.write_all(b"</tr></thead>\n<tbody>\n")?;
+            self.started = true;
+        }
+        self.writer.write_all(b"<tr>")?;
+        for (_, value) in &row.0 {
+            let cell = match value {
+                serde_json::Value::String(s) => escape(s),
+                serde_json::Value::Null => String::new(),
+                value => escape(&value.to_string()),
+            };
+            write!(self.writer, "<td>{}</td>", cell)?;
+        }
+        self.writer.write_all(b"</tr>\n")?;
+        Ok(())
+    }
+    pub fn flush(&mut self) -> std::io::Result<()> {
+        self.writer.flush()
+    }
+    pub fn finish(mut self) -> std::io::Result<()> {
+        if !self.started {
+            self.writer.write_all(b"<tbody>\n")?;
+        }
+        write!(self.writer, "</tbody>\n</table>\n<script>{}</script>\n</body>\n</html>\n", SCRIPT)?;
+        self.writer.flush()
+    }
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
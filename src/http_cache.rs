@@ -0,0 +1,168 @@
+/*
+ * On-disk cache of responses with their validators, for conditional requests. A response that came
+ * with an ETag or Last-Modified header is kept under a hash of its URL, and the next request for the
+ * same URL sends them back as If-None-Match and If-Modified-Since. When nothing has changed the
+ * server answers 304 Not Modified without a body and the cached one is used, which makes frequent
+ * polling much cheaper for both sides. Responses without validators are kept too, and along with a
+ * record of the last run's start and filter they let a run be repeated offline, e.g. to try another
+ * output format without downloading everything again. The cache subcommand reports on it or
+ * empties it.
+ */
+use chrono::{DateTime,Utc};
+use reqwest::header::{HeaderMap,ETAG,LAST_MODIFIED};
+use serde::{Serialize,Deserialize};
+use sha2::{Digest,Sha256};
+use std::path::{Path,PathBuf};
+
+#[derive(Debug,Serialize,Deserialize,Clone,Default,PartialEq)]
+pub struct Validators {
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+}
+impl Validators {
+    pub fn from_headers(headers: &HeaderMap) -> Self {
+        let header = |name| headers.get(name).and_then(|value: &reqwest::header::HeaderValue| value.to_str().ok()).map(String::from);
+        Self { etag: header(ETAG), last_modified: header(LAST_MODIFIED) }
+    }
+    pub fn is_empty(&self) -> bool {
+        self.etag.is_none() && self.last_modified.is_none()
+    }
+}
+
+// What's kept next to each body, the URL along with its validators so the files can be told apart
+#[derive(Debug,Serialize,Deserialize)]
+struct Meta {
+    url: String,
+    #[serde(flatten)]
+    validators: Validators,
+}
+
+// When the last run that went to the server started and what it asked for, so an offline run can ask for the same
+#[derive(Debug,Serialize,Deserialize,Clone,PartialEq)]
+pub struct CachedRun {
+    pub started: DateTime<Utc>,
+    pub filter: String,
+}
+
+// What's in the cache: the responses kept, the space everything in it takes up, and when the oldest and newest responses were stored
+#[derive(Debug,Clone,Default,PartialEq)]
+pub struct CacheStats {
+    pub entries: usize,
+    pub bytes: u64,
+    pub oldest: Option<DateTime<Utc>>,
+    pub newest: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug,Clone)]
+pub struct ResponseCache {
+    dir: PathBuf,
+    // Answer every request from the cache without going to the server
+    offline: bool,
+}
+impl ResponseCache {
+    pub fn new(dir: &Path) -> Self {
+        Self { dir: dir.to_owned(), offline: false }
+    }
+    // The configured http_cache, if any
+    pub fn from_config(cfg: &crate::Config) -> Option<Self> {
+        cfg.http_cache.as_deref().map(|dir| Self { offline: cfg.offline, ..Self::new(dir) })
+    }
+    pub fn offline(&self) -> bool {
+        self.offline
+    }
+    // The body and metadata files for url
+    fn paths(&self, url: &str) -> (PathBuf, PathBuf) {
+        let key = format!("{:x}", Sha256::digest(url.as_bytes()));
+        (self.dir.join(format!("{}.body", key)), self.dir.join(format!("{}.json", key)))
+    }
+    // The validators of the cached response for url, if there is one
+    pub fn validators(&self, url: &str) -> Option<Validators> {
+        let (body, meta) = self.paths(url);
+        let meta: Meta = serde_json::from_slice(&std::fs::read(meta).ok()?).ok()?;
+        if meta.url != url || !body.is_file() {
+            return None;
+        }
+        Some(meta.validators)
+    }
+    pub fn body(&self, url: &str) -> std::io::Result<Vec<u8>> {
+        std::fs::read(self.paths(url).0)
+    }
+    // The cached response for url when offline, as long as it was stored completely
+    pub fn offline_body(&self, url: &str) -> Result<Vec<u8>, crate::Error> {
+        match self.validators(url) {
+            Some(_) => Ok(self.body(url)?),
+            None => Err(crate::Error::Config(format!("{} isn't in the response cache at {}, run without --offline to fetch it.", url, self.dir.display()))),
+        }
+    }
+    fn run_path(&self, dataset: crate::Dataset) -> PathBuf {
+        self.dir.join(format!("last-run-{}.json", dataset.name()))
+    }
+    // The last run of dataset whose responses were cached, if there's been one
+    pub fn last_run(&self, dataset: crate::Dataset) -> std::io::Result<Option<CachedRun>> {
+        match std::fs::read(self.run_path(dataset)) {
+            Ok(run) => Ok(Some(serde_json::from_slice(&run)?)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+    pub fn save_run(&self, dataset: crate::Dataset, run: &CachedRun) -> std::io::Result<()> {
+        std::fs::create_dir_all(&self.dir)?;
+        std::fs::write(self.run_path(dataset), serde_json::to_vec(run)?)
+    }
+    // Counts the responses kept, nothing if the cache hasn't been created yet
+    pub fn stats(&self) -> std::io::Result<CacheStats> {
+        let mut stats = CacheStats::default();
+        let entries = match std::fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(stats),
+            Err(e) => return Err(e),
+        };
+        for entry in entries {
+            let entry = entry?;
+            let metadata = entry.metadata()?;
+            if !metadata.is_file() {
+                continue;
+            }
+            stats.bytes += metadata.len();
+            if entry.path().extension().and_then(|ext| ext.to_str()) == Some("body") {
+                let stored = DateTime::<Utc>::from(metadata.modified()?);
+                stats.entries += 1;
+                stats.oldest = Some(stats.oldest.map_or(stored, |oldest| oldest.min(stored)));
+                stats.newest = Some(stats.newest.map_or(stored, |newest| newest.max(stored)));
+            }
+        }
+        Ok(stats)
+    }
+    // Removes everything in the cache, keeping the directory itself, and returns what was in it
+    pub fn purge(&self) -> std::io::Result<CacheStats> {
+        let stats = self.stats()?;
+        if !self.dir.is_dir() {
+            return Ok(stats);
+        }
+        for entry in std::fs::read_dir(&self.dir)? {
+            let path = entry?.path();
+            if path.is_dir() {
+                std::fs::remove_dir_all(&path)?;
+            }
+            else {
+                std::fs::remove_file(&path)?;
+            }
+        }
+        Ok(stats)
+    }
+    /*
+     * Keeps body as the response for url. The metadata is removed first and written last, so an
+     * interrupted store leaves no validators that would vouch for a half-written body.
+     */
+    pub fn store(&self, url: &str, validators: &Validators, body: &[u8]) -> std::io::Result<()> {
+        let (body_path, meta_path) = self.paths(url);
+        std::fs::create_dir_all(&self.dir)?;
+        match std::fs::remove_file(&meta_path) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e),
+            _ => (),
+        }
+        std::fs::write(&body_path, body)?;
+        let meta = Meta { url: url.to_owned(), validators: validators.clone() };
+        std::fs::write(&meta_path, serde_json::to_vec(&meta)?)
+    }
+}
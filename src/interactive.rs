@@ -0,0 +1,33 @@
+/*
+ * Prompts for the common settings for the --interactive mode, starting from the loaded config,
+ * and optionally saves the answers back to the config file. Settings given on the command line
+ * aren't prompted for.
+ */
+use dialoguer::{Confirm,Input};
+use std::path::PathBuf;
+use fema::{Config,APPNAME};
+use crate::cli::Cli;
+
+pub fn prompt(mut cfg: Config, cli: &Cli) -> Result<Config, Box<dyn std::error::Error+Send+Sync>> {
+    if cli.years.is_none() {
+        cfg.num_years_previous = Input::new()
+            .with_prompt("Number of years of declarations to fetch")
+            .default(cfg.num_years_previous)
+            .interact_text()?;
+    }
+    if cli.csv.is_none() && !cli.no_csv {
+        let csv: String = Input::new()
+            .with_prompt("CSV output path (leave empty for none)")
+            .default(cfg.csv.as_ref().map(|p| p.display().to_string()).unwrap_or_default())
+            .allow_empty(true)
+            .interact_text()?;
+        cfg.csv = if csv.is_empty() { None } else { Some(PathBuf::from(csv)) };
+    }
+    if Confirm::new().with_prompt("Save these settings to the config file?").default(false).interact()? {
+        match &cli.config {
+            Some(path) => cfg.save(path)?,
+            None => confy::store(APPNAME, &cfg)?,
+        }
+    }
+    Ok(cfg)
+}
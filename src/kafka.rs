@@ -0,0 +1,93 @@
+/*
+ * Publishing of new and changed records to a Kafka topic, so a streaming platform can react to them
+ * without polling output files. Each record is a JSON message keyed by its id, which keeps every
+ * version of a record on the same partition, with the dataset and the kind of change as headers.
+ * Like the other notifications it's sent before the sync checkpoint moves on, so records that fail
+ * to publish are fetched and published again by the next run.
+ */
+use serde::{Serialize,Deserialize};
+use std::collections::BTreeMap;
+use crate::{Change,Dataset,Error};
+
+#[derive(Debug,Serialize,Deserialize,Clone,PartialEq,Default)]
+#[serde(default)]
+pub struct KafkaSink {
+    // Comma-separated host:port list of brokers to bootstrap from
+    pub brokers: String,
+    pub topic: String,
+    // Any other librdkafka producer settings, e.g. "security.protocol" = "SASL_SSL"
+    pub properties: BTreeMap<String,String>,
+}
+impl KafkaSink {
+    // Checks the settings that can be checked without connecting
+    pub fn validate(&self) -> Result<(), String> {
+        if !cfg!(feature = "kafka") {
+            return Err(String::from("Publishing to Kafka requires building with the kafka feature."));
+        }
+        if self.brokers.is_empty() {
+            return Err(String::from("The Kafka sink needs brokers to connect to."));
+        }
+        if self.topic.is_empty() {
+            return Err(String::from("The Kafka sink needs a topic."));
+        }
+        Ok(())
+    }
+    // Publishes the added and changed records, waiting until the brokers have acknowledged all of them
+    #[cfg(feature = "kafka")]
+    pub async fn publish(&self, dataset: Dataset, changes: &[Change]) -> Result<usize, Error> {
+        use crate::ChangeKind;
+        use rdkafka::error::{KafkaError,RDKafkaErrorCode};
+        use rdkafka::message::{Header,OwnedHeaders};
+        use rdkafka::producer::{FutureProducer,FutureRecord};
+
+        let mut config = rdkafka::ClientConfig::new();
+        config.set("bootstrap.servers", &self.brokers);
+        for (name, value) in &self.properties {
+            config.set(name, value);
+        }
+        let producer: FutureProducer = config.create()?;
+        let messages = changes.iter()
+            .filter(|change| change.change != ChangeKind::Removed)
+            .map(|change| Ok((change, serde_json::to_vec(&change.record).map_err(std::io::Error::from)?)))
+            .collect::<Result<Vec<_>, Error>>()?;
+        let mut deliveries = Vec::new();
+        for (change, payload) in &messages {
+            let headers = OwnedHeaders::new()
+                .insert(Header { key: "dataset", value: Some(dataset.name()) })
+                .insert(Header { key: "change", value: Some(change.change.name()) });
+            let mut record = FutureRecord::to(&self.topic).payload(payload.as_slice()).headers(headers);
+            if !change.id.is_empty() {
+                record = record.key(change.id.as_str());
+            }
+            let delivery = match producer.send_result(record) {
+                Ok(delivery) => delivery,
+                // Waiting for what's queued to be delivered makes room for the rest
+                Err((KafkaError::MessageProduction(RDKafkaErrorCode::QueueFull), record)) => {
+                    for delivery in deliveries.drain(..) {
+                        delivered(delivery).await?;
+                    }
+                    producer.send_result(record).map_err(|(e, _)| e)?
+                },
+                Err((e, _)) => return Err(e.into()),
+            };
+            deliveries.push(delivery);
+        }
+        for delivery in deliveries {
+            delivered(delivery).await?;
+        }
+        Ok(messages.len())
+    }
+    #[cfg(not(feature = "kafka"))]
+    pub async fn publish(&self, _dataset: Dataset, _changes: &[Change]) -> Result<usize, Error> {
+        Err(Error::Config(String::from("Publishing to Kafka requires building with the kafka feature.")))
+    }
+}
+
+#[cfg(feature = "kafka")]
+async fn delivered(delivery: rdkafka::producer::DeliveryFuture) -> Result<(), Error> {
+    match delivery.await {
+        Ok(Ok(_)) => Ok(()),
+        Ok(Err((e, _))) => Err(e.into()),
+        Err(canceled) => Err(Error::Output(Box::new(canceled))),
+    }
+}
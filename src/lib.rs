@@ -0,0 +1,1183 @@
+extern crate chrono;
+extern crate serde;
+extern crate serde_json;
+extern crate serde_yaml;
+extern crate toml;
+extern crate reqwest;
+extern crate hyper;
+extern crate tokio;
+extern crate futures;
+extern crate rand;
+extern crate thiserror;
+extern crate humantime;
+extern crate cron;
+extern crate csv;
+extern crate hmac;
+extern crate lettre;
+extern crate native_tls;
+extern crate tokio_tls;
+#[cfg(feature = "desktop")]
+extern crate notify_rust;
+#[cfg(feature = "kafka")]
+extern crate rdkafka;
+extern crate sha2;
+#[macro_use] extern crate log;
+use chrono::{DateTime,Local,Months,NaiveDate,Utc};
+use serde::{Serialize,Deserialize};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+mod model;
+mod codes;
+mod fetch;
+mod client;
+mod dataset;
+mod sync;
+mod diff;
+mod notify;
+mod geo;
+mod enrich;
+mod metrics;
+mod s3;
+mod kafka;
+mod mqtt;
+mod http_cache;
+mod recording;
+mod proxy;
+mod breaker;
+mod seen;
+mod quality;
+mod drift;
+mod dates;
+mod sort_window;
+mod error;
+pub use model::*;
+pub use codes::*;
+pub use dataset::*;
+pub use fetch::*;
+pub use client::*;
+pub use sync::*;
+pub use diff::*;
+pub use notify::*;
+pub use geo::*;
+pub use enrich::*;
+pub use metrics::*;
+pub use s3::*;
+pub use kafka::*;
+pub use mqtt::*;
+pub use http_cache::*;
+pub use recording::*;
+pub use proxy::*;
+pub use breaker::*;
+pub use seen::*;
+pub use quality::*;
+pub use drift::*;
+pub use dates::*;
+pub use sort_window::*;
+pub use error::*;
+
+pub const APPNAME: &str = "fema-web-declaration";
+// Prefix of the environment variables overriding config fields, see Config::apply_env
+pub const ENV_PREFIX: &str = "FEMA_WEB_DECLARATION_";
+// Sent as the User-Agent unless user_agent is configured
+pub const DEFAULT_USER_AGENT: &str = concat!("fema-web-declaration/", env!("CARGO_PKG_VERSION"));
+// Root of the OpenFEMA API, followed by the version and dataset name
+pub const DEFAULT_BASE_URL: &str = "https://www.fema.gov/api/open";
+/*
+ * Config file is placed in the expected place for the operating system using the mechanisms
+ * Config file and log file are placed in the expected place for the operating system using the
+ * mechanisms defined by:
+    The XDG base directory and the XDG user directory specifications on Linux
+        Config file defaults to $XDG_CONFIG_HOME/APPNAME/APPNAME.toml or ~/.config/APPNAME/APPNAME.toml (e.g. /home/thor/.config/APPNAME/APPNAME.toml )
+    The Known Folder API on Windows
+        Config file defaults to	{FOLDERID_RoamingAppData}\APPNAME\config\APPNAME.toml (e.g. C:\Users\thor\AppData\Roaming\APPNAME\config )
+    The Standard Directories guidelines on macOS
+        Config file defaults to	$HOME/Library/Application Support/APPNAME/APPNAME.toml (e.g. /Users/thor/Library/Application Support/APPNAME/APPNAME.toml
+ */
+#[derive(Debug,Clone,Serialize,Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub debug: bool,
+    // Log JSON lines instead of the logging config's text encoders, with the run's dataset, filter and page as fields
+    pub json_logs: bool,
+    pub num_years_previous: usize,
+    // Range of dates to fetch instead of num_years_previous back from now, each a YYYY-MM-DD day or an RFC 3339 time, e.g. since = "2017-08-01" and until = "2017-10-31"
+    pub since: Option<DateBound>,
+    pub until: Option<DateBound>,
+    // Also fetch declarations that have been closed out, which are left out by default, with the closeoutDate they were closed on
+    pub include_closed: bool,
+    // File to write results to, in output_format despite the name. May contain output_template's placeholders
+    pub csv: Option<PathBuf>,
+    pub output_format: OutputFormat,
+    // How csv and xlsx output writes dates: "rfc3339", "epoch" seconds, or a strftime pattern like "%Y-%m-%d"
+    pub date_format: DateFormat,
+    // Dialect of csv output: the field delimiter, e.g. ";" or "\t", which fields are quoted, whether there's a header row, and "lf" or "crlf" line endings
+    pub csv_delimiter: char,
+    pub csv_quote_style: QuoteStyle,
+    pub csv_header: bool,
+    pub csv_line_ending: LineEnding,
+    // Whether each run should "overwrite" the csv file or "append" to it, writing the header only once and leaving out records whose id it already has
+    pub csv_mode: CsvMode,
+    // Compress csv, ndjson, json, or geojson output as it's written, adding .gz or .zst to the file name
+    pub compress: Option<Compression>,
+    // Timezone to write the records' timestamps in, "local" or an IANA name like "America/Chicago", UTC if unset
+    pub output_timezone: Option<OutputTimezone>,
+    // Compression codec and maximum rows per row group for parquet output
+    pub parquet_compression: ParquetCompression,
+    pub parquet_row_group_size: usize,
+    // Two-letter codes of the states to fetch, all states if unset
+    pub states: Option<Vec<StateCode>>,
+    // Program type codes to fetch, e.g. ["IA"] for Individual Assistance only, all programs if unset
+    pub program_types: Option<Vec<ProgramType>>,
+    // Raw OData condition ANDed onto the generated $filter, e.g. "disasterNumber ge 4000"
+    pub extra_filter: Option<String>,
+    // Fields to request with $select and write, in this order, instead of the dataset's usual record
+    pub select: Option<Vec<String>>,
+    // Columns of the output file, in this order, each a field optionally renamed, e.g. ["placeName -> Place", "designatedDate"]. Other fields are left out
+    pub output_columns: Option<Vec<OutputColumn>>,
+    // Sort order passed to the API as $orderby, e.g. "designatedDate desc", which the output keeps with preserve_server_order or a sort_window
+    pub order_by: Option<String>,
+    // Records to hold back and sort by order_by before writing, for pages that don't come in order. Only exact if no record arrives more than this many out of place
+    pub sort_window: Option<usize>,
+    // Print the number of results per value of this field instead of fetching and writing them
+    pub group_count: Option<String>,
+    // Refuse to fetch when there is nowhere to write the results, rather than just warning afterwards
+    pub require_output: bool,
+    // Delay between page requests, adjusted within these bounds based on how quickly the server responds
+    pub adaptive_throttle: bool,
+    pub throttle_min_ms: u64,
+    pub throttle_max_ms: u64,
+    pub throttle_latency_threshold_ms: u64,
+    // Minimum time between page requests however fast the server is, to go easy on it when many copies run at once
+    pub page_delay_ms: u64,
+    // Append a fetched_at column with the time the results were retrieved to each output row
+    pub stamp_fetch_time: bool,
+    // Related entities to request with $expand, written as nested JSON to expand_output
+    pub expand: Option<Vec<String>>,
+    pub expand_output: Option<PathBuf>,
+    // How to react when the server reports that the endpoint is deprecated
+    pub deprecation_policy: DeprecationPolicy,
+    // Redo the fetch against the replacement URL given in a deprecation notice instead of applying deprecation_policy
+    pub follow_new_url: bool,
+    // Fail on records with fields added or missing compared to the record types, rather than warning and carrying on
+    pub strict_schema: bool,
+    // Embedded database to archive fetched entries in, keyed by id (requires the store feature)
+    pub store: Option<PathBuf>,
+    // SQLite database to upsert fetched entries into, one row per id (requires the sqlite feature)
+    pub sqlite: Option<PathBuf>,
+    // PostgreSQL connection string, e.g. "postgresql://user@host/db", to upsert fetched entries into (requires the postgresql feature)
+    pub postgres_url: Option<String>,
+    // File holding the latest lastRefresh seen per dataset. When set, only records refreshed since the previous run are fetched
+    pub sync_state: Option<PathBuf>,
+    // File holding the id and hash of every record written or published, so later runs only emit a record again once it changes
+    pub dedup_file: Option<PathBuf>,
+    // Where the progress of a run is saved after each page, so an interrupted run can be continued with --resume. Unset by default
+    pub progress_file: Option<PathBuf>,
+    // Results requested per page, up to MAX_PAGE_SIZE. Smaller pages are quicker to retry on a flaky connection
+    pub page_size: usize,
+    // Response format for every page after the first, which is always json since it carries the count
+    pub response_format: ResponseFormat,
+    // Hard ceiling on the number of requests in flight at once, also the number of idle connections kept pooled
+    pub max_connections: usize,
+    // Seconds an idle pooled connection is kept open for reuse by later requests
+    pub pool_idle_timeout_secs: u64,
+    // Number of pages to fetch concurrently
+    pub concurrency: usize,
+    // Write concurrently fetched pages in the server's order rather than as each arrives, for reproducible output at some cost in speed.
+    // Setting a progress_file turns this on too, since resuming picks up after the number of pages written
+    pub preserve_server_order: bool,
+    /*
+     * Output filename template, used instead of the csv path when set. Available placeholders:
+     *   {date}    date of the run, YYYY-MM-DD
+     *   {time}    time of the run, HHMMSS in UTC
+     *   {count}   number of entries written
+     *   {state}   value of the split_by field, e.g. the state code, only when output is split
+     *   {ext}     extension of the output format, e.g. csv
+     *   {dataset} name of the dataset fetched
+     *   {years}   num_years_previous
+     *   {states}  states fetched joined with "-", e.g. TX-LA, or "all"
+     * For example "fema_{date}.{ext}" or "declarations-{date}-{years}y.csv"
+     */
+    pub output_template: Option<String>,
+    // Drop entries whose designatedDate is in the future (data-entry errors) instead of only warning about them
+    pub drop_future_dates: bool,
+    // Times to re-request a page whose body was cut off while it was being read
+    pub body_retries: usize,
+    // Times to retry a request after a transient failure (connection errors, timeouts, 5xx and 429 responses)
+    pub retries: usize,
+    // Bounds of the exponential backoff between retries, before jitter
+    pub retry_base_delay_ms: u64,
+    pub retry_max_delay_ms: u64,
+    // Seconds to allow for connecting, for the response to start or go on arriving, and for the whole of each request. 0 for no limit
+    pub connect_timeout_secs: u64,
+    pub read_timeout_secs: u64,
+    pub request_timeout_secs: u64,
+    // Longest wait asked for by a 429 or 503 response's Retry-After header that will be honored before giving up
+    pub max_retry_after_secs: u64,
+    // JSON manifest listing every output file written in the run, optionally with SHA-256 checksums
+    pub manifest: Option<PathBuf>,
+    pub manifest_checksums: bool,
+    // OpenFEMA dataset to fetch
+    pub dataset: Dataset,
+    // Endpoint version to use, the dataset's current version if unset
+    pub api_version: Option<ApiVersion>,
+    // Directory to keep responses in with their ETag and Last-Modified, so later requests for the same URL can be answered with 304 Not Modified. The cutoff date then moves a whole day at a time, keeping the URLs the same through the day
+    pub http_cache: Option<PathBuf>,
+    // Repeat the last run from the responses in http_cache without making any requests
+    pub offline: bool,
+    // Directory to record every response from the API in, for the run to be replayed later
+    pub record: Option<PathBuf>,
+    // Directory of a recording to run against instead of the API
+    pub replay: Option<PathBuf>,
+    // Root of the API to use instead of DEFAULT_BASE_URL, e.g. a mirror or a mock server. Also set by FEMA_BASE_URL
+    pub base_url: Option<String>,
+    // Keep running and poll again after this long, e.g. "15m", only writing records not seen before
+    pub watch: Option<String>,
+    // Cron expression to poll on in watch mode instead of a fixed interval, e.g. "0 6 * * *", in local time
+    pub schedule: Option<String>,
+    // Failed polls in a row after which watch mode backs off, for a window starting at base and doubling up to max
+    pub circuit_breaker_threshold: usize,
+    pub circuit_breaker_base_secs: u64,
+    pub circuit_breaker_max_secs: u64,
+    // Snapshot of the previous run's records by id. When set, what was added, removed, or changed since then is reported
+    pub diff: Option<PathBuf>,
+    // File to write the changes found in diff mode to, as JSON if it has a .json extension and CSV otherwise
+    pub diff_output: Option<PathBuf>,
+    // File to write data-quality issues found in the fetched records to, such as dates in the future or empty place names, as JSON if it has a .json extension and CSV otherwise
+    pub validation_report: Option<PathBuf>,
+    // URL to POST newly seen records to as JSON: added records in diff mode, otherwise those fetched since the sync checkpoint or the last watch poll
+    pub webhook_url: Option<String>,
+    // Key for the HMAC-SHA256 signature sent with each webhook request, best set with the FEMA_WEB_DECLARATION_WEBHOOK_SECRET variable
+    pub webhook_secret: Option<String>,
+    // Slack or Discord channels to alert about the same new records, e.g. [[chat]] kind = "slack", url = "...", program_types = ["IA"]
+    pub chat: Vec<ChatChannel>,
+    // SMTP settings and recipients for an email report of what changed after each run, e.g. [email] smtp_host = "..."
+    pub email: Option<EmailReport>,
+    // Kafka topic to publish the same new records to, along with changed ones in diff mode (requires the kafka feature), e.g. [kafka] brokers = "..."
+    pub kafka: Option<KafkaSink>,
+    // MQTT broker to publish the new records to, each on a topic filled in from its fields, e.g. [mqtt] url = "mqtt://..."
+    pub mqtt: Option<MqttSink>,
+    // Pop up a desktop notification naming the new records, e.g. when watching on a workstation (requires the desktop feature)
+    pub desktop_notifications: bool,
+    // Atom feed of the fetched records to write besides the output file, one entry per id titled with atom_title's {fieldName}s
+    pub atom_feed: Option<PathBuf>,
+    pub atom_title: String,
+    // Field to split the output into one file per value of, e.g. "stateCode" for out/TX.csv, out/LA.csv, ... next to out.csv
+    pub split_by: Option<String>,
+    // Add county_fips, county_name, and is_statewide columns decoded from each record's place code to the output
+    pub decode_places: bool,
+    // Add enrich_fields from each record's disaster in DisasterDeclarationsSummaries to the output, joined on disasterNumber
+    pub enrich: bool,
+    pub enrich_fields: Vec<String>,
+    // Count the records by state, program type, and month of the dataset's date, printing a table of the counts instead of writing the records
+    pub summarize: bool,
+    // CSV file to write those counts to, with the records still written unless summarize is set too
+    pub summary: Option<PathBuf>,
+    // Address the serve subcommand listens on
+    pub listen: String,
+    // Address to serve Prometheus metrics on at /metrics while running, e.g. when watching. The serve subcommand has them on its own address
+    pub metrics_listen: Option<String>,
+    // Prometheus Pushgateway to push the metrics to after each run or watch poll, e.g. "http://localhost:9091"
+    pub metrics_push_url: Option<String>,
+    // User-Agent to identify the requests with, e.g. with a contact address, instead of DEFAULT_USER_AGENT
+    pub user_agent: Option<String>,
+    // Extra headers sent with every request, e.g. [headers] X-Api-Key = "..."
+    pub headers: BTreeMap<String,String>,
+    // Bucket and credentials to upload the run's output files to once they're written, e.g. [s3] bucket = "..."
+    pub s3: Option<S3Upload>,
+    // Proxy to make every request through instead of any in HTTP_PROXY and HTTPS_PROXY, e.g. [proxy] url = "..."
+    pub proxy: Option<HttpProxy>,
+}
+impl std::default::Default for Config {
+    fn default() -> Self {
+        Self {
+            debug: false,
+            json_logs: false,
+            num_years_previous: 3,
+            since: None,
+            until: None,
+            include_closed: false,
+            csv: Some(PathBuf::from("out.csv")),
+            output_format: OutputFormat::Csv,
+            date_format: DateFormat::Rfc3339,
+            csv_delimiter: ',',
+            csv_quote_style: QuoteStyle::Necessary,
+            csv_header: true,
+            csv_line_ending: LineEnding::Lf,
+            csv_mode: CsvMode::Overwrite,
+            compress: None,
+            output_timezone: None,
+            parquet_compression: ParquetCompression::Snappy,
+            parquet_row_group_size: 1024 * 1024,
+            states: None,
+            program_types: None,
+            extra_filter: None,
+            select: None,
+            output_columns: None,
+            order_by: None,
+            sort_window: None,
+            group_count: None,
+            require_output: false,
+            adaptive_throttle: false,
+            throttle_min_ms: 0,
+            throttle_max_ms: 10000,
+            throttle_latency_threshold_ms: 2000,
+            page_delay_ms: 0,
+            stamp_fetch_time: false,
+            expand: None,
+            expand_output: None,
+            deprecation_policy: DeprecationPolicy::Warn,
+            follow_new_url: false,
+            strict_schema: false,
+            store: None,
+            sqlite: None,
+            postgres_url: None,
+            sync_state: None,
+            dedup_file: None,
+            progress_file: None,
+            page_size: 1000,
+            response_format: ResponseFormat::Json,
+            max_connections: 4,
+            pool_idle_timeout_secs: 90,
+            concurrency: 4,
+            preserve_server_order: false,
+            output_template: None,
+            drop_future_dates: false,
+            body_retries: 3,
+            retries: 3,
+            retry_base_delay_ms: 500,
+            retry_max_delay_ms: 30000,
+            connect_timeout_secs: 10,
+            read_timeout_secs: 60,
+            request_timeout_secs: 600,
+            max_retry_after_secs: 300,
+            manifest: None,
+            manifest_checksums: false,
+            dataset: Dataset::FemaWebDeclarationAreas,
+            api_version: None,
+            http_cache: None,
+            offline: false,
+            record: None,
+            replay: None,
+            base_url: None,
+            watch: None,
+            schedule: None,
+            circuit_breaker_threshold: 3,
+            circuit_breaker_base_secs: 300,
+            circuit_breaker_max_secs: 3600,
+            diff: None,
+            diff_output: None,
+            validation_report: None,
+            webhook_url: None,
+            webhook_secret: None,
+            chat: Vec::new(),
+            email: None,
+            kafka: None,
+            mqtt: None,
+            desktop_notifications: false,
+            atom_feed: None,
+            atom_title: String::from(DEFAULT_ATOM_TITLE),
+            split_by: None,
+            decode_places: false,
+            enrich: false,
+            enrich_fields: DEFAULT_ENRICH_FIELDS.iter().map(|field| String::from(*field)).collect(),
+            summarize: false,
+            summary: None,
+            listen: String::from("127.0.0.1:8080"),
+            metrics_listen: None,
+            metrics_push_url: None,
+            user_agent: None,
+            headers: BTreeMap::new(),
+            s3: None,
+            proxy: None,
+        }
+    }
+}
+pub const DEFAULT_ATOM_TITLE: &str = "{programTypeDescription} declared for {placeName}, {stateCode}";
+
+pub const TEMPLATE_PLACEHOLDERS: &[&str] = &["date", "time", "count", "state", "ext", "dataset", "years", "states"];
+
+// Values substituted into an output_template
+#[derive(Debug,Clone)]
+pub struct TemplateValues<'a> {
+    pub now: chrono::DateTime<chrono::Utc>,
+    pub count: usize,
+    pub state: Option<&'a str>,
+    pub ext: &'a str,
+    pub dataset: Dataset,
+    pub years: usize,
+    pub states: Option<&'a [StateCode]>,
+}
+
+// Checks that a template only uses known placeholders and has no unclosed braces
+pub fn validate_template(template: &str) -> Result<(), String> {
+    expand_template_with(template, |_| Ok(String::new())).map(|_| ())
+}
+
+pub fn expand_template(template: &str, values: &TemplateValues) -> Result<String, String> {
+    expand_template_with(template, |name| match name {
+        "date" => Ok(values.now.format("%Y-%m-%d").to_string()),
+        "time" => Ok(values.now.format("%H%M%S").to_string()),
+        "count" => Ok(values.count.to_string()),
+        "state" => values.state.map(String::from).ok_or_else(|| String::from("The {state} placeholder is only available when output is split with split_by.")),
+        "dataset" => Ok(values.dataset.to_string()),
+        "years" => Ok(values.years.to_string()),
+        "states" => Ok(match values.states.filter(|states| !states.is_empty()) {
+            Some(states) => states.iter().map(StateCode::as_str).collect::<Vec<_>>().join("-"),
+            None => String::from("all"),
+        }),
+        _ => Ok(values.ext.to_owned()),
+    })
+}
+
+fn expand_template_with<F: Fn(&str) -> Result<String, String>>(template: &str, value: F) -> Result<String, String> {
+    let mut expanded = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(open) = rest.find('{') {
+        expanded.push_str(&rest[..open]);
+        let close = rest[open..].find('}').ok_or_else(|| format!("Unclosed placeholder in template {}", template))? + open;
+        let name = &rest[open+1..close];
+        if !TEMPLATE_PLACEHOLDERS.contains(&name) {
+            return Err(format!("Unknown placeholder {{{}}} in template {}, expected one of {:?}", name, template, TEMPLATE_PLACEHOLDERS));
+        }
+        expanded.push_str(&value(name)?);
+        rest = &rest[close+1..];
+    }
+    expanded.push_str(rest);
+    Ok(expanded)
+}
+
+#[derive(Debug,Serialize,Deserialize,Clone,Copy,PartialEq,Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum DeprecationPolicy {
+    // Log the deprecation notice and continue
+    Warn,
+    // Abort as soon as a deprecation notice is seen
+    Error,
+    // Abort only once the deprecation date has passed
+    ErrorAfterDate,
+}
+#[derive(Debug,Serialize,Deserialize,Clone,Copy,PartialEq,Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum OutputFormat {
+    Csv,
+    // One JSON object per line
+    Ndjson,
+    // A single JSON array
+    Json,
+    // Typed columns, requires the parquet-output feature
+    Parquet,
+    // A single page with a sortable, filterable table, for reading in a browser
+    Html,
+    // An Excel workbook with date cells, requires the xlsx-output feature
+    Xlsx,
+    // Arrow IPC file, also known as Feather, requires the arrow-output feature
+    Arrow,
+    // A FeatureCollection with each record as a point at its county's centroid
+    Geojson,
+}
+impl OutputFormat {
+    pub const ALL: [OutputFormat; 8] = [
+        OutputFormat::Csv, OutputFormat::Ndjson, OutputFormat::Json, OutputFormat::Parquet, OutputFormat::Html, OutputFormat::Xlsx, OutputFormat::Arrow, OutputFormat::Geojson,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            OutputFormat::Csv => "csv",
+            OutputFormat::Ndjson => "ndjson",
+            OutputFormat::Json => "json",
+            OutputFormat::Parquet => "parquet",
+            OutputFormat::Html => "html",
+            OutputFormat::Xlsx => "xlsx",
+            OutputFormat::Arrow => "arrow",
+            OutputFormat::Geojson => "geojson",
+        }
+    }
+    // File extension, the value of the {ext} template placeholder
+    pub fn ext(&self) -> &'static str {
+        self.name()
+    }
+}
+impl std::fmt::Display for OutputFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(self.name())
+    }
+}
+impl std::str::FromStr for OutputFormat {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        OutputFormat::ALL.iter().find(|f| f.name().eq_ignore_ascii_case(s)).copied()
+            .ok_or_else(|| format!("Unknown output format {}, expected one of {}", s, OutputFormat::ALL.iter().map(OutputFormat::name).collect::<Vec<_>>().join(", ")))
+    }
+}
+#[derive(Debug,Serialize,Deserialize,Clone,Copy,PartialEq,Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Compression {
+    Gzip,
+    Zstd,
+}
+impl Compression {
+    pub const ALL: [Compression; 2] = [Compression::Gzip, Compression::Zstd];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Compression::Gzip => "gzip",
+            Compression::Zstd => "zstd",
+        }
+    }
+    // Added to the output file's name
+    pub fn ext(&self) -> &'static str {
+        match self {
+            Compression::Gzip => "gz",
+            Compression::Zstd => "zst",
+        }
+    }
+}
+impl std::fmt::Display for Compression {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(self.name())
+    }
+}
+impl std::str::FromStr for Compression {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Compression::ALL.iter().find(|c| c.name().eq_ignore_ascii_case(s)).copied()
+            .ok_or_else(|| format!("Unknown compression {}, expected one of {}", s, Compression::ALL.iter().map(Compression::name).collect::<Vec<_>>().join(", ")))
+    }
+}
+// A column of the output file: the record field it holds and what it's called, "field -> name" or just the field to keep its name
+#[derive(Debug,Clone,PartialEq,Eq)]
+pub struct OutputColumn {
+    pub field: String,
+    pub name: String,
+}
+impl std::fmt::Display for OutputColumn {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        if self.field == self.name {
+            f.write_str(&self.field)
+        }
+        else {
+            write!(f, "{} -> {}", self.field, self.name)
+        }
+    }
+}
+impl std::str::FromStr for OutputColumn {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (field, name) = s.split_once("->").unwrap_or((s, s));
+        let (field, name) = (field.trim(), name.trim().trim_matches('"'));
+        if field.is_empty() || name.is_empty() {
+            return Err(format!("Invalid output column {:?}, expected a field or \"field -> name\"", s));
+        }
+        Ok(OutputColumn { field: field.to_owned(), name: name.to_owned() })
+    }
+}
+impl Serialize for OutputColumn {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+impl<'de> Deserialize<'de> for OutputColumn {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+// Which fields of csv output are quoted
+#[derive(Debug,Serialize,Deserialize,Clone,Copy,PartialEq,Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum QuoteStyle {
+    // Only those containing the delimiter, a quote, or a line break
+    Necessary,
+    Always,
+    // All but numbers
+    NonNumeric,
+    // None, even if that makes the file ambiguous
+    Never,
+}
+impl QuoteStyle {
+    pub const ALL: [QuoteStyle; 4] = [QuoteStyle::Necessary, QuoteStyle::Always, QuoteStyle::NonNumeric, QuoteStyle::Never];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            QuoteStyle::Necessary => "necessary",
+            QuoteStyle::Always => "always",
+            QuoteStyle::NonNumeric => "non-numeric",
+            QuoteStyle::Never => "never",
+        }
+    }
+}
+impl std::fmt::Display for QuoteStyle {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(self.name())
+    }
+}
+impl std::str::FromStr for QuoteStyle {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        QuoteStyle::ALL.iter().find(|q| q.name().eq_ignore_ascii_case(s)).copied()
+            .ok_or_else(|| format!("Unknown quote style {}, expected one of {}", s, QuoteStyle::ALL.iter().map(QuoteStyle::name).collect::<Vec<_>>().join(", ")))
+    }
+}
+#[derive(Debug,Serialize,Deserialize,Clone,Copy,PartialEq,Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum LineEnding {
+    Lf,
+    // What Windows tools and some older importers expect
+    Crlf,
+}
+impl LineEnding {
+    pub const ALL: [LineEnding; 2] = [LineEnding::Lf, LineEnding::Crlf];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            LineEnding::Lf => "lf",
+            LineEnding::Crlf => "crlf",
+        }
+    }
+}
+impl std::fmt::Display for LineEnding {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(self.name())
+    }
+}
+impl std::str::FromStr for LineEnding {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        LineEnding::ALL.iter().find(|l| l.name().eq_ignore_ascii_case(s)).copied()
+            .ok_or_else(|| format!("Unknown line ending {}, expected one of {}", s, LineEnding::ALL.iter().map(LineEnding::name).collect::<Vec<_>>().join(", ")))
+    }
+}
+#[derive(Debug,Serialize,Deserialize,Clone,Copy,PartialEq,Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum CsvMode {
+    Overwrite,
+    // Add to the end of the file if it already exists
+    Append,
+}
+impl CsvMode {
+    pub const ALL: [CsvMode; 2] = [CsvMode::Overwrite, CsvMode::Append];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            CsvMode::Overwrite => "overwrite",
+            CsvMode::Append => "append",
+        }
+    }
+}
+impl std::fmt::Display for CsvMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(self.name())
+    }
+}
+impl std::str::FromStr for CsvMode {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        CsvMode::ALL.iter().find(|m| m.name().eq_ignore_ascii_case(s)).copied()
+            .ok_or_else(|| format!("Unknown csv mode {}, expected one of {}", s, CsvMode::ALL.iter().map(CsvMode::name).collect::<Vec<_>>().join(", ")))
+    }
+}
+#[derive(Debug,Serialize,Deserialize,Clone,Copy,PartialEq,Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum ResponseFormat {
+    // Records wrapped in an object named after the dataset, alongside any expanded entities
+    Json,
+    // A bare array of records, smaller but without expanded entities
+    Jsona,
+}
+// How watch mode spaces out its polls after the first, which is made straight away
+#[derive(Debug,Clone)]
+pub enum Watch {
+    Every(std::time::Duration),
+    Cron(Box<cron::Schedule>),
+}
+impl Watch {
+    // How long to wait after now for the next poll, None if the schedule has no more times
+    pub fn next_wait(&self, now: DateTime<Local>) -> Option<std::time::Duration> {
+        match self {
+            Watch::Every(interval) => Some(*interval),
+            Watch::Cron(schedule) => schedule.after(&now).next().map(|next| (next - now).to_std().unwrap_or_default()),
+        }
+    }
+}
+
+// An end of the range of dates to fetch, a whole day in UTC or an exact time
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+pub enum DateBound {
+    Day(NaiveDate),
+    Time(DateTime<Utc>),
+}
+impl DateBound {
+    // The first instant the bound covers
+    pub fn start(&self) -> DateTime<Utc> {
+        match self {
+            DateBound::Day(day) => day.and_hms_opt(0, 0, 0).unwrap_or_default().and_utc(),
+            DateBound::Time(time) => *time,
+        }
+    }
+    // The instant after the bound, so a whole day is included up to its end
+    pub fn end(&self) -> DateTime<Utc> {
+        match self {
+            DateBound::Day(day) => day.succ_opt().map_or(DateTime::<Utc>::MAX_UTC, |next| next.and_hms_opt(0, 0, 0).unwrap_or_default().and_utc()),
+            DateBound::Time(time) => *time,
+        }
+    }
+}
+impl std::fmt::Display for DateBound {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            DateBound::Day(day) => write!(f, "{}", day.format("%Y-%m-%d")),
+            DateBound::Time(time) => f.write_str(&time.to_rfc3339_opts(chrono::SecondsFormat::AutoSi, true)),
+        }
+    }
+}
+impl std::str::FromStr for DateBound {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        NaiveDate::parse_from_str(s, "%Y-%m-%d").map(DateBound::Day)
+            .or_else(|_| DateTime::parse_from_rfc3339(s).map(|time| DateBound::Time(time.with_timezone(&Utc))))
+            .map_err(|_| format!("Invalid date {}, expected YYYY-MM-DD or an RFC 3339 time like 2017-08-25T00:00:00Z", s))
+    }
+}
+impl Serialize for DateBound {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+impl<'de> Deserialize<'de> for DateBound {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+// Timezone output timestamps are written in, with the offset it had at each timestamp
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+pub enum OutputTimezone {
+    Utc,
+    // The timezone of the machine doing the writing
+    Local,
+    Named(chrono_tz::Tz),
+}
+impl OutputTimezone {
+    // time as RFC 3339 in this timezone, e.g. 2017-08-25T19:00:00-05:00 for America/Chicago
+    pub fn format(&self, time: DateTime<Utc>) -> String {
+        match self {
+            OutputTimezone::Utc => time.to_rfc3339_opts(chrono::SecondsFormat::AutoSi, true),
+            OutputTimezone::Local => time.with_timezone(&Local).to_rfc3339_opts(chrono::SecondsFormat::AutoSi, false),
+            OutputTimezone::Named(tz) => time.with_timezone(tz).to_rfc3339_opts(chrono::SecondsFormat::AutoSi, false),
+        }
+    }
+    // An RFC 3339 time written out in this timezone, None if value isn't one
+    pub fn convert(&self, value: &str) -> Option<String> {
+        DateTime::parse_from_rfc3339(value).ok().map(|time| self.format(time.with_timezone(&Utc)))
+    }
+}
+impl std::fmt::Display for OutputTimezone {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            OutputTimezone::Utc => f.write_str("UTC"),
+            OutputTimezone::Local => f.write_str("local"),
+            OutputTimezone::Named(tz) => f.write_str(tz.name()),
+        }
+    }
+}
+impl std::str::FromStr for OutputTimezone {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if s.eq_ignore_ascii_case("utc") {
+            return Ok(OutputTimezone::Utc);
+        }
+        if s.eq_ignore_ascii_case("local") {
+            return Ok(OutputTimezone::Local);
+        }
+        s.parse().map(OutputTimezone::Named)
+            .map_err(|_| format!("Unknown timezone {}, expected local, UTC, or an IANA name like America/Chicago", s))
+    }
+}
+impl Serialize for OutputTimezone {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+impl<'de> Deserialize<'de> for OutputTimezone {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+#[derive(Debug,Serialize,Deserialize,Clone,Copy,PartialEq,Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum ParquetCompression {
+    Uncompressed,
+    Snappy,
+    Gzip,
+    Zstd,
+}
+impl Config {
+    // Loads a config file in TOML, YAML, or JSON format depending on its extension
+    pub fn load(path: &std::path::Path) -> Result<Self, String> {
+        let contents = std::fs::read_to_string(path).map_err(|e| format!("Failed to read config file {}: {}", path.display(), e))?;
+        match path.extension().and_then(|e| e.to_str()).map(|e| e.to_ascii_lowercase()).as_deref() {
+            Some("toml") => toml::from_str(&contents).map_err(|e| format!("Failed to parse TOML config file {}: {}", path.display(), e)),
+            Some("yaml") | Some("yml") => serde_yaml::from_str(&contents).map_err(|e| format!("Failed to parse YAML config file {}: {}", path.display(), e)),
+            Some("json") => serde_json::from_str(&contents).map_err(|e| format!("Failed to parse JSON config file {}: {}", path.display(), e)),
+            _ => Err(format!("Config file {} must have a .toml, .yaml, .yml, or .json extension.", path.display())),
+        }
+    }
+    // Saves the config in TOML, YAML, or JSON format depending on the file's extension
+    pub fn save(&self, path: &std::path::Path) -> Result<(), String> {
+        let contents = match path.extension().and_then(|e| e.to_str()).map(|e| e.to_ascii_lowercase()).as_deref() {
+            Some("toml") => toml::to_string_pretty(self).map_err(|e| e.to_string()),
+            Some("yaml") | Some("yml") => serde_yaml::to_string(self).map_err(|e| e.to_string()),
+            Some("json") => serde_json::to_string_pretty(self).map_err(|e| e.to_string()),
+            _ => Err(String::from("must have a .toml, .yaml, .yml, or .json extension")),
+        }.map_err(|e| format!("Failed to save config file {}: {}", path.display(), e))?;
+        std::fs::write(path, contents).map_err(|e| format!("Failed to write config file {}: {}", path.display(), e))
+    }
+    /*
+     * Sets the field with the given name from a string. The value is read as JSON if it parses as a
+     * value the field accepts (numbers, booleans, null, arrays), otherwise as a string, otherwise as
+     * a comma-separated list, so states can be set to TX,LA as well as ["TX","LA"].
+     */
+    pub fn set(&mut self, name: &str, value: &str) -> Result<(), String> {
+        let mut fields = match serde_json::to_value(&*self).map_err(|e| e.to_string())? {
+            serde_json::Value::Object(fields) => fields,
+            _ => unreachable!("Config serializes to a map"),
+        };
+        if !fields.contains_key(name) {
+            return Err(format!("There is no config field named {}.", name));
+        }
+        let candidates = serde_json::from_str(value).into_iter()
+            .chain(std::iter::once(serde_json::Value::String(value.to_owned())))
+            .chain(std::iter::once(value.split(',').map(|v| serde_json::Value::String(v.trim().to_owned())).collect()));
+        for candidate in candidates {
+            fields.insert(name.to_owned(), candidate);
+            if let Ok(cfg) = serde_json::from_value(serde_json::Value::Object(fields.clone())) {
+                *self = cfg;
+                return Ok(());
+            }
+        }
+        Err(format!("{} can't be set to {}.", name, value))
+    }
+    // Overrides fields with ENV_PREFIX variables named after them, e.g. FEMA_WEB_DECLARATION_NUM_YEARS_PREVIOUS=1
+    pub fn apply_env<I: IntoIterator<Item=(String, String)>>(&mut self, vars: I) -> Result<(), String> {
+        for (var, value) in vars {
+            if let Some(name) = var.strip_prefix(ENV_PREFIX) {
+                self.set(&name.to_ascii_lowercase(), &value).map_err(|e| format!("Environment variable {}: {}", var, e))?;
+            }
+        }
+        Ok(())
+    }
+    // Checks the settings that can be checked without making a request
+    pub fn validate(&self) -> Result<(), String> {
+        if self.page_size == 0 || self.page_size > MAX_PAGE_SIZE {
+            return Err(format!("Page size must be between 1 and {}, not {}.", MAX_PAGE_SIZE, self.page_size));
+        }
+        validate_codes("states", self.states.as_deref())?;
+        match (self.sort_window, &self.order_by) {
+            (Some(0), _) => return Err(String::from("sort_window must hold at least 1 record.")),
+            (Some(_), None) => return Err(String::from("sort_window sorts by order_by, which isn't set.")),
+            _ => (),
+        }
+        validate_codes("program_types", self.program_types.as_deref())?;
+        if let (Some(compress), OutputFormat::Parquet | OutputFormat::Html | OutputFormat::Xlsx | OutputFormat::Arrow) = (self.compress, self.output_format) {
+            return Err(format!("Only csv, ndjson, json, and geojson output can be compressed with {}, not {}.", compress, self.output_format));
+        }
+        if self.date_format != DateFormat::Rfc3339 && !matches!(self.output_format, OutputFormat::Csv | OutputFormat::Xlsx) {
+            return Err(format!("date_format only applies to csv and xlsx output, not {}.", self.output_format));
+        }
+        if !self.csv_delimiter.is_ascii() || matches!(self.csv_delimiter, '"' | '\r' | '\n') {
+            return Err(format!("The csv delimiter must be a single ASCII character other than a quote or line break, not {:?}.", self.csv_delimiter));
+        }
+        let dialect = (self.csv_delimiter, self.csv_quote_style, self.csv_header, self.csv_line_ending);
+        if dialect != (',', QuoteStyle::Necessary, true, LineEnding::Lf) && self.output_format != OutputFormat::Csv {
+            return Err(format!("csv_delimiter, csv_quote_style, csv_header, and csv_line_ending only apply to csv output, not {}.", self.output_format));
+        }
+        if self.csv_mode == CsvMode::Append {
+            if self.output_format != OutputFormat::Csv {
+                return Err(format!("Only csv output can be appended to, not {}.", self.output_format));
+            }
+            if self.compress.is_some() {
+                return Err(String::from("Compressed csv output can't be appended to."));
+            }
+            if !self.csv_header {
+                return Err(String::from("Append mode finds the ids already in the csv file by its header, so csv_header can't be turned off."));
+            }
+            if self.path_template().is_some_and(|template| template.contains("{count}")) {
+                return Err(String::from("An output file named after its {count} can't be appended to."));
+            }
+            let id_written = match (&self.output_columns, &self.select) {
+                (Some(columns), _) => columns.iter().any(|column| column.field == "id"),
+                (None, Some(select)) => select.iter().any(|field| field == "id"),
+                (None, None) => true,
+            };
+            if !id_written {
+                return Err(String::from("Append mode leaves out records by id, so the id must be one of the columns written."));
+            }
+        }
+        if let Some(columns) = &self.output_columns {
+            if columns.is_empty() {
+                return Err(String::from("output_columns must list at least one column."));
+            }
+            let mut names = std::collections::HashSet::new();
+            if let Some(column) = columns.iter().find(|column| !names.insert(&column.name)) {
+                return Err(format!("output_columns names more than one column {}.", column.name));
+            }
+        }
+        if let (true, Some(select)) = (self.decode_places, &self.select) {
+            if !select.iter().any(|field| field == "placeCode" || field == "fipsCountyCode") {
+                warn!("Places are decoded from placeCode or fipsCountyCode, which aren't selected, so the decoded columns will be empty.");
+            }
+        }
+        if self.enrich {
+            if self.dataset == Dataset::DisasterDeclarationsSummaries {
+                return Err(String::from("DisasterDeclarationsSummaries records already have their disaster's fields, there's nothing to enrich them with."));
+            }
+            if self.enrich_fields.is_empty() {
+                return Err(String::from("Enrichment needs at least one field in enrich_fields."));
+            }
+            if self.select.as_ref().is_some_and(|select| !select.iter().any(|field| field == "disasterNumber")) {
+                warn!("Enrichment is joined on disasterNumber, which isn't selected, so the enrichment columns will be empty.");
+            }
+        }
+        if self.offline {
+            if self.http_cache.is_none() {
+                return Err(String::from("Running offline needs an http_cache to take the responses from."));
+            }
+            if self.watch.is_some() || self.schedule.is_some() {
+                return Err(String::from("Watch mode can't run offline, the cached responses never change."));
+            }
+        }
+        if self.replay.is_some() {
+            if self.record.is_some() {
+                return Err(String::from("A run can either record or replay, not both."));
+            }
+            if self.offline {
+                return Err(String::from("A replayed run takes its responses from the recording, it can't also run offline from the http_cache."));
+            }
+            if self.watch.is_some() || self.schedule.is_some() {
+                return Err(String::from("Watch mode can't replay a recording, the recorded responses never change."));
+            }
+        }
+        if let (true, Some(field)) = (self.summarize, &self.split_by) {
+            return Err(format!("Summarized output isn't written to files to split by {}, only the counts are.", field));
+        }
+        if let Some(template) = self.path_template() {
+            validate_template(&template)?;
+            if self.split_by.is_some() && !template.contains("{state}") {
+                return Err(format!("Output split by {} needs a {{state}} placeholder in the template for the value.", self.split_by.as_deref().unwrap_or_default()));
+            }
+        }
+        self.watch()?;
+        if let (Some(since), Some(until)) = (self.since, self.until) {
+            if since.start() >= until.end() {
+                return Err(format!("The date range is empty, since ({}) has to come before until ({}).", since, until));
+            }
+        }
+        if self.diff.is_some() {
+            // Anything missing from a partial pull would be reported as removed
+            if self.sync_state.is_some() {
+                return Err(String::from("Diff mode compares complete pulls, so it can't be used with sync_state."));
+            }
+            if self.dedup_file.is_some() {
+                return Err(String::from("Diff mode compares complete pulls, so it can't be used with a dedup_file."));
+            }
+            if self.watch.is_some() || self.schedule.is_some() {
+                return Err(String::from("Diff mode can't be used with watch mode, which already only writes new records."));
+            }
+            if self.select.as_ref().is_some_and(|select| !select.iter().any(|field| field == "id")) {
+                return Err(String::from("Diff mode matches records by id, so select must include it."));
+            }
+        }
+        else if self.diff_output.is_some() {
+            warn!("diff_output is set but diff isn't, no changes will be written.");
+        }
+        if let Some(channel) = self.chat.iter().find(|channel| channel.url.is_empty()) {
+            return Err(format!("The {} chat channel needs a url.", channel.kind.name()));
+        }
+        for channel in &self.chat {
+            validate_codes("the program_types of a chat channel", channel.program_types.as_deref())?;
+            validate_codes("the states of a chat channel", channel.states.as_deref())?;
+        }
+        if let Some(email) = &self.email {
+            email.validate()?;
+        }
+        if let Some(kafka) = &self.kafka {
+            kafka.validate()?;
+        }
+        if let Some(mqtt) = &self.mqtt {
+            mqtt.validate()?;
+        }
+        for (name, value) in &self.headers {
+            reqwest::header::HeaderName::from_bytes(name.as_bytes()).map_err(|_| format!("Invalid header name {:?}.", name))?;
+            reqwest::header::HeaderValue::from_str(value).map_err(|_| format!("Invalid value for header {}.", name))?;
+        }
+        if let Some(proxy) = &self.proxy {
+            proxy.validate()?;
+        }
+        if let Some(s3) = &self.s3 {
+            s3.validate()?;
+            let output_file = !self.summarize && (self.csv.is_some() || self.output_template.is_some());
+            if !output_file && self.summary.is_none() && self.atom_feed.is_none() {
+                warn!("An S3 upload is configured but no output file is, there's nothing to upload.");
+            }
+        }
+        if (self.webhook_url.is_some() || !self.chat.is_empty() || self.email.is_some() || self.kafka.is_some() || self.mqtt.is_some() || self.desktop_notifications) && self.diff.is_none() && self.sync_state.is_none() && self.dedup_file.is_none() && self.watch.is_none() && self.schedule.is_none() {
+            warn!("Notifications are configured without diff, sync_state, dedup_file, or watch mode to tell new records apart, so every fetched record will be posted.");
+        }
+        Ok(())
+    }
+    // When watch mode polls, None if it's off
+    pub fn watch(&self) -> Result<Option<Watch>, String> {
+        match (&self.watch, &self.schedule) {
+            (Some(_), Some(_)) => Err(String::from("Set either a watch interval or a schedule, not both.")),
+            (Some(watch), None) => match humantime::parse_duration(watch) {
+                Ok(interval) if !interval.is_zero() => Ok(Some(Watch::Every(interval))),
+                Ok(_) => Err(String::from("The watch interval can't be zero.")),
+                Err(e) => Err(format!("Invalid watch interval {}: {}", watch, e)),
+            },
+            (None, Some(schedule)) => {
+                // The cron crate wants a seconds field first, which the usual five-field form leaves out
+                let expression = if schedule.split_whitespace().count() == 5 { format!("0 {}", schedule) } else { schedule.clone() };
+                match expression.parse() {
+                    Ok(schedule) => Ok(Some(Watch::Cron(Box::new(schedule)))),
+                    Err(e) => Err(format!("Invalid schedule {}: {}", schedule, e)),
+                }
+            },
+            (None, None) => Ok(None),
+        }
+    }
+    pub fn api_version(&self) -> ApiVersion {
+        self.api_version.unwrap_or_else(|| self.dataset.version())
+    }
+    // Endpoint of the configured dataset and version under the configured base URL
+    pub fn base_uri(&self) -> String {
+        let root = self.base_url.as_deref().unwrap_or(DEFAULT_BASE_URL).trim_end_matches('/');
+        format!("{}/{}/{}", root, self.api_version(), self.dataset.name())
+    }
+    // Template the output path is expanded from: output_template, or the csv path if it has any placeholders
+    pub fn path_template(&self) -> Option<String> {
+        match (&self.output_template, &self.csv) {
+            (Some(template), _) => Some(template.clone()),
+            (None, Some(path)) => path.to_str().filter(|path| path.contains('{')).map(String::from),
+            (None, None) => None,
+        }
+    }
+    // Whether fetched entries will be written anywhere
+    pub fn has_output(&self) -> bool {
+        self.csv.is_some() || self.output_template.is_some() || self.summarize || self.summary.is_some() || self.atom_feed.is_some() || self.store.is_some() || self.sqlite.is_some() || self.postgres_url.is_some()
+    }
+}
+
+/*
+ * Earliest date to request: the start of since if there is one, otherwise num_years_previous
+ * calendar years back from now, or from the start of today with the response cache. A year back
+ * from 29 February is 28 February.
+ */
+pub fn cutoff(cfg: &Config, now: DateTime<Utc>) -> DateTime<Utc> {
+    if let Some(since) = cfg.since {
+        return since.start();
+    }
+    let now = match cfg.http_cache {
+        Some(_) => now.date_naive().and_hms_opt(0, 0, 0).unwrap_or_default().and_utc(),
+        None => now,
+    };
+    let months = cfg.num_years_previous.min(u32::MAX as usize / 12) as u32 * 12;
+    now.checked_sub_months(Months::new(months)).unwrap_or(DateTime::<Utc>::MIN_UTC)
+}
+
+/*
+ * Builds the OData $filter clause for the query from the config, so it can be inspected or reused
+ * without running a fetch. For datasets that track it, only results that haven't been closed out
+ * are requested.
+ */
+pub fn build_filter(cfg: &Config, now: DateTime<Utc>) -> String {
+    let mut clauses = Vec::new();
+    if let Some(field) = cfg.dataset.date_field() {
+        // An explicit since includes its start, so records dated midnight on the first day aren't left out
+        let after = if cfg.since.is_some() { "ge" } else { "gt" };
+        clauses.push(format!("{} {}'{}'", field, after, cutoff(cfg, now).to_rfc3339_opts(chrono::SecondsFormat::Millis, true)));
+        if let Some(until) = cfg.until {
+            clauses.push(format!("{} lt'{}'", field, until.end().to_rfc3339_opts(chrono::SecondsFormat::Millis, true)));
+        }
+    }
+    if let Some(field) = cfg.dataset.closeout_field().filter(|_| !cfg.include_closed) {
+        clauses.push(format!("{} eq null", field));
+    }
+    if let Some(states) = cfg.states.as_ref().filter(|states| !states.is_empty()) {
+        let field = cfg.dataset.state_field();
+        clauses.push(any_of(field, states));
+    }
+    if let (Some(types), Some(field)) = (cfg.program_types.as_ref().filter(|types| !types.is_empty()), cfg.dataset.program_type_field()) {
+        clauses.push(any_of(field, types));
+    }
+    if let Some(extra) = cfg.extra_filter.as_deref().map(str::trim).filter(|extra| !extra.is_empty()) {
+        clauses.push(format!("({})", extra));
+    }
+    clauses.join(" and ")
+}
+
+// "(field eq 'a' or field eq 'b')", quoting the values as OData string literals
+fn any_of<T: AsRef<str>>(field: &str, values: &[T]) -> String {
+    let terms: Vec<String> = values.iter().map(|v| format!("{} eq '{}'", field, v.as_ref().replace('\'', "''"))).collect();
+    format!("({})", terms.join(" or "))
+}
+
+// Narrows a filter to records refreshed after the given time, for incremental sync
+pub fn refreshed_since(filter: &str, since: DateTime<Utc>) -> String {
+    let clause = format!("lastRefresh gt'{}'", since.to_rfc3339_opts(chrono::SecondsFormat::Millis, true));
+    if filter.is_empty() {
+        clause
+    }
+    else {
+        format!("{} and {}", filter, clause)
+    }
+}
+
+/*
+ * Inter-request delay that backs off when recent response latencies rise above a threshold and
+ * recovers when they fall well below it, staying within [min, max].
+ */
+#[derive(Debug)]
+pub struct Throttle {
+    min: std::time::Duration,
+    max: std::time::Duration,
+    threshold: std::time::Duration,
+    delay: std::time::Duration,
+    recent: std::collections::VecDeque<std::time::Duration>,
+}
+impl Throttle {
+    const WINDOW: usize = 5;
+    const STEP: std::time::Duration = std::time::Duration::from_millis(100);
+
+    pub fn new(cfg: &Config) -> Self {
+        let min = std::time::Duration::from_millis(cfg.throttle_min_ms);
+        Self {
+            min,
+            max: std::time::Duration::from_millis(cfg.throttle_max_ms.max(cfg.throttle_min_ms)),
+            threshold: std::time::Duration::from_millis(cfg.throttle_latency_threshold_ms),
+            delay: min,
+            recent: std::collections::VecDeque::with_capacity(Self::WINDOW),
+        }
+    }
+    pub fn delay(&self) -> std::time::Duration {
+        self.delay
+    }
+    // Records the latency of a response and returns the adjusted delay
+    pub fn record(&mut self, latency: std::time::Duration) -> std::time::Duration {
+        if self.recent.len() == Self::WINDOW {
+            self.recent.pop_front();
+        }
+        self.recent.push_back(latency);
+        let average = self.recent.iter().sum::<std::time::Duration>() / self.recent.len() as u32;
+        if average > self.threshold {
+            self.delay = (self.delay * 2).max(self.min + Self::STEP).min(self.max);
+        }
+        else if average < self.threshold / 2 {
+            self.delay = (self.delay / 2).max(self.min);
+        }
+        self.delay
+    }
+}
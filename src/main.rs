@@ -8,123 +8,460 @@ extern crate directories;
 #[macro_use] extern crate log;
 use directories::ProjectDirs;
 extern crate csv;
-use chrono::{DateTime,Utc,Duration};
-use serde::{Serialize,Deserialize};
+extern crate serde_json;
+extern crate fema;
+extern crate clap;
+extern crate clap_complete;
+extern crate indicatif;
+extern crate log_mdc;
+extern crate serde_yaml;
+extern crate toml;
+extern crate humantime;
+extern crate flate2;
+extern crate zstd;
+use chrono::{DateTime,Local,Utc,Duration};
+use serde::Serialize;
+use std::collections::{HashMap,HashSet};
 use std::path::PathBuf;
+use clap::{CommandFactory,Parser};
+use fema::{ApiVersion,Breaker,CircuitBreaker,Config,Dataset,Designated,DisasterDeclarationsSummary,Entry,EntryV2,Error,FemaClient,HousingAssistanceOwner,Identified,OutputFormat,PublicAssistanceFundedProjectsDetail,Recording,Refreshed,ResponseCache,Row,SeenSet,SortWindow,Snapshot,SyncState,Validator,APPNAME};
+
+mod atom;
+mod cache_command;
+mod cli;
+mod config_command;
+mod diagnose;
+mod html_output;
+mod interactive;
+mod manifest;
+mod meter;
+mod output;
+mod progress;
+mod serve;
+mod sink;
+mod summary;
+#[cfg(feature = "arrow-output")]
+mod arrow_output;
+#[cfg(feature = "parquet-output")]
+mod parquet_output;
+#[cfg(any(feature = "parquet-output", feature = "arrow-output"))]
+mod schema;
+#[cfg(feature = "xlsx-output")]
+mod xlsx_output;
+#[cfg(feature = "postgresql")]
+mod pg;
+#[cfg(feature = "sqlite")]
+mod sqlite;
+#[cfg(feature = "store")]
+mod store;
+#[cfg(feature = "tui")]
+mod tui;
+
+// The get and list subcommands read entries back from the store, writing them to stdout as CSV
+#[cfg(feature = "store")]
+fn store_command(cfg: &Config, id: Option<&str>) -> Result<(), Box<dyn std::error::Error+Send+Sync>> {
+    let path = cfg.store.as_ref().ok_or("No store is configured.")?;
+    let store = store::Store::open(path)?;
+    let mut csvwriter = csv::Writer::from_writer(std::io::stdout());
+    if let Some(id) = id {
+        let entry = store.get(id)?.ok_or_else(|| format!("No entry with id {} in the store.", id))?;
+        csvwriter.serialize(entry)?;
+    }
+    else {
+        for entry in store.list() {
+            csvwriter.serialize(entry?)?;
+        }
+    }
+    csvwriter.flush()?;
+    Ok(())
+}
+#[cfg(not(feature = "store"))]
+fn store_command(_cfg: &Config, _id: Option<&str>) -> Result<(), Box<dyn std::error::Error+Send+Sync>> {
+    Err("The get and list subcommands require building with the store feature.".into())
+}
+
+#[cfg(feature = "tui")]
+fn tui_command(cfg: &Config, file: Option<&std::path::Path>) -> Result<(), Box<dyn std::error::Error+Send+Sync>> {
+    tui::browse(cfg, file)
+}
+#[cfg(not(feature = "tui"))]
+fn tui_command(_cfg: &Config, _file: Option<&std::path::Path>) -> Result<(), Box<dyn std::error::Error+Send+Sync>> {
+    Err("The tui subcommand requires building with the tui feature.".into())
+}
+
+// Written to the config directory on the first run, with {log_dir} replaced by the platform's data directory
+const DEFAULT_LOG_CONFIG: &str = include_str!("../log4rs.yml");
 
-const APPNAME: &'static str = "fema-web-declaration";
 /*
- * Config file is placed in the expected place for the operating system using the mechanisms
- * Config file and log file are placed in the expected place for the operating system using the
- * mechanisms defined by:
-    The XDG base directory and the XDG user directory specifications on Linux
-        Config file defaults to $XDG_CONFIG_HOME/APPNAME/APPNAME.toml or ~/.config/APPNAME/APPNAME.toml (e.g. /home/thor/.config/APPNAME/APPNAME.toml )
-    The Known Folder API on Windows
-        Config file defaults to	{FOLDERID_RoamingAppData}\APPNAME\config\APPNAME.toml (e.g. C:\Users\thor\AppData\Roaming\APPNAME\config )
-    The Standard Directories guidelines on macOS
-        Config file defaults to	$HOME/Library/Application Support/APPNAME/APPNAME.toml (e.g. /Users/thor/Library/Application Support/APPNAME/APPNAME.toml
+ * Loads the logging config, swapping every appender's encoder for log4rs's JSON one if json_logs is
+ * set. The JSON lines carry the MDC fields set during the run alongside the time, level, target and
+ * message. The config isn't watched for changes in that case.
  */
-#[derive(Debug,Serialize,Deserialize)]
-struct Config {
-    debug: bool,
-    num_years_previous: usize,
-    csv: Option<PathBuf>,
+fn load_log_config(path: &std::path::Path, json_logs: bool) -> Result<log4rs::config::Config, Box<dyn std::error::Error+Send+Sync>> {
+    if !json_logs {
+        return Ok(log4rs::load_config_file(path, Default::default())?);
+    }
+    let mut raw: serde_yaml::Value = serde_yaml::from_str(&std::fs::read_to_string(path)?)?;
+    if let Some(appenders) = raw.get_mut("appenders").and_then(serde_yaml::Value::as_mapping_mut) {
+        for (_, appender) in appenders.iter_mut() {
+            if let Some(appender) = appender.as_mapping_mut() {
+                appender.insert("encoder".into(), serde_yaml::from_str("kind: json")?);
+            }
+        }
+    }
+    let raw: log4rs::file::RawConfig = serde_yaml::from_value(raw)?;
+    let (appenders, errors) = raw.appenders_lossy(&Default::default());
+    if let Some(e) = errors.into_iter().next() {
+        return Err(e.to_string().into());
+    }
+    Ok(log4rs::config::Config::builder().appenders(appenders).loggers(raw.loggers()).build(raw.root())?)
+}
+
+// Whether this build includes everything the config asks for
+fn check_features(cfg: &Config) -> Result<(), Error> {
+    if cfg.store.is_some() && !cfg!(feature = "store") {
+        return Err(Error::Config(String::from("A store is configured but this build doesn't include the store feature.")));
+    }
+    if cfg.sqlite.is_some() && !cfg!(feature = "sqlite") {
+        return Err(Error::Config(String::from("A SQLite database is configured but this build doesn't include the sqlite feature.")));
+    }
+    if cfg.postgres_url.is_some() && !cfg!(feature = "postgresql") {
+        return Err(Error::Config(String::from("A PostgreSQL database is configured but this build doesn't include the postgresql feature.")));
+    }
+    if cfg.output_format == OutputFormat::Parquet && !cfg!(feature = "parquet-output") {
+        return Err(Error::Config(String::from("Parquet output requires building with the parquet-output feature.")));
+    }
+    if cfg.output_format == OutputFormat::Arrow && !cfg!(feature = "arrow-output") {
+        return Err(Error::Config(String::from("Arrow output requires building with the arrow-output feature.")));
+    }
+    if cfg.output_format == OutputFormat::Xlsx && !cfg!(feature = "xlsx-output") {
+        return Err(Error::Config(String::from("XLSX output requires building with the xlsx-output feature.")));
+    }
+    if cfg.desktop_notifications && !cfg!(feature = "desktop") {
+        return Err(Error::Config(String::from("Desktop notifications require building with the desktop feature.")));
+    }
+    Ok(())
+}
+
+// The config file's location, either given with --config or the OS-standard one confy uses
+fn config_path(cli: &cli::Cli) -> Result<PathBuf, Error> {
+    match &cli.config {
+        Some(path) => Ok(path.clone()),
+        None => {
+            let proj_dirs = ProjectDirs::from("rs", "", APPNAME).ok_or_else(|| Error::Config(String::from("No config directory is known for this OS.")))?;
+            Ok(proj_dirs.config_dir().join(format!("{}.toml", APPNAME)))
+        },
+    }
+}
+
+// Loads the config file, then applies the environment variable overrides on top
+fn load_config(cli: &cli::Cli) -> Result<Config, Error> {
+    let mut cfg: Config = match &cli.config {
+        Some(path) => Config::load(path).map_err(Error::Config)?,
+        None => confy::load(APPNAME).map_err(|e| Error::Config(e.to_string()))?,
+    };
+    if let Ok(url) = std::env::var("FEMA_BASE_URL") {
+        cfg.base_url = Some(url);
+    }
+    cfg.apply_env(std::env::vars()).map_err(Error::Config)?;
+    Ok(cfg)
+}
+
+// Tracks the latest lastRefresh across pages for the incremental sync checkpoint
+fn note_refresh<T: Refreshed>(latest: &mut Option<DateTime<Utc>>, records: &[T]) {
+    *latest = (*latest).max(records.iter().filter_map(Refreshed::last_refresh).max());
+}
+
+// Where fetched pages are written besides the databases, along with what's tracked about the run
+struct Sinks<'a> {
+    // The output file, the notifier and the databases, see sink::FanOut
+    fan_out: sink::FanOut<'a>,
+    progress: Option<progress::Progress>,
+    meter: meter::Meter,
+    // Latest lastRefresh seen so far, for the incremental sync checkpoint
+    refreshed: Option<DateTime<Utc>>,
+    // Ids written so far in watch mode, so each poll only writes new records
+    seen: Option<HashSet<String>>,
+    // Records written by earlier runs, taking the place of seen when there's a dedup file
+    dedup: Option<SeenSet>,
+    // Every record of this run in diff mode, to compare with the previous run's
+    snapshot: Option<Snapshot>,
+    // Issues found in every record fetched, for the validation report
+    validator: Option<Validator>,
+    // Records held back to be written in order_by order, with sort_window
+    window: Option<SortWindow>,
 }
-impl std::default::Default for Config {
-    fn default() -> Self { Self { debug: false, num_years_previous: 3, csv: Some(PathBuf::from("out.csv"))}}
+impl<'a> Sinks<'a> {
+    // Writes a page to the file output, then records the run's progress now that the page is safely on disk
+    fn write_page<T: Serialize + Refreshed + Identified + sink::Batched>(&mut self, mut records: Vec<T>, expanded: HashMap<String,Vec<serde_json::Value>>) -> Result<(), Error> {
+        // The window sorts rows, which it can compare field by field
+        if self.window.is_some() {
+            let rows = records.iter().map(|record| serde_json::from_slice(&serde_json::to_vec(record)?)).collect::<serde_json::Result<Vec<Row>>>().map_err(std::io::Error::from)?;
+            return self.write_row_page(rows, expanded);
+        }
+        log_mdc::insert("page", (self.meter.pages() + 1).to_string());
+        note_refresh(&mut self.refreshed, &records);
+        let fetched = records.len();
+        if let Some(validator) = &mut self.validator {
+            validator.check(self.meter.pages() + 1, &records).map_err(std::io::Error::from)?;
+        }
+        self.drop_seen(&mut records);
+        fema::METRICS.new_records(records.len());
+        self.fan_out.output.keep_expanded(expanded);
+        self.fan_out.write_batch(&T::batch(&records))?;
+        if let Some(snapshot) = &mut self.snapshot {
+            snapshot.extend(&records).map_err(std::io::Error::from)?;
+        }
+        if let Some(progress) = &mut self.progress {
+            progress.record(&self.fan_out.output, self.refreshed)?;
+        }
+        self.meter.page(fetched);
+        Ok(())
+    }
+    // Like write_page, for records made up of the fields chosen with the select option
+    fn write_row_page(&mut self, mut rows: Vec<Row>, expanded: HashMap<String,Vec<serde_json::Value>>) -> Result<(), Error> {
+        log_mdc::insert("page", (self.meter.pages() + 1).to_string());
+        note_refresh(&mut self.refreshed, &rows);
+        let fetched = rows.len();
+        if let Some(validator) = &mut self.validator {
+            validator.check(self.meter.pages() + 1, &rows).map_err(std::io::Error::from)?;
+        }
+        self.drop_seen(&mut rows);
+        fema::METRICS.new_records(rows.len());
+        if let Some(window) = &mut self.window {
+            rows = window.push(rows);
+        }
+        self.emit_rows(rows, expanded)?;
+        if let Some(progress) = &mut self.progress {
+            progress.record(&self.fan_out.output, self.refreshed)?;
+        }
+        self.meter.page(fetched);
+        Ok(())
+    }
+    // Writes whatever the sort window still holds once the pages stop coming
+    fn flush_window(&mut self) -> Result<(), Error> {
+        let (rows, late) = match &mut self.window {
+            Some(window) => (window.drain(), window.take_late()),
+            None => return Ok(()),
+        };
+        if late > 0 {
+            warn!("{} records came more than sort_window records out of place and were written out of order, a larger sort_window would sort them.", late);
+        }
+        self.emit_rows(rows, HashMap::new())
+    }
+    fn emit_rows(&mut self, rows: Vec<Row>, expanded: HashMap<String,Vec<serde_json::Value>>) -> Result<(), Error> {
+        self.fan_out.output.keep_expanded(expanded);
+        self.fan_out.write_batch(&sink::Batch::Rows(&rows))?;
+        if let Some(snapshot) = &mut self.snapshot {
+            snapshot.extend(&rows).map_err(std::io::Error::from)?;
+        }
+        Ok(())
+    }
+    fn drop_seen<T: Identified>(&mut self, records: &mut Vec<T>) {
+        if let Some(dedup) = &mut self.dedup {
+            let before = records.len();
+            records.retain(|record| dedup.insert(record));
+            if records.len() < before {
+                debug!("Left out {} records already written unchanged.", before - records.len());
+            }
+        }
+        else if let Some(seen) = &mut self.seen {
+            records.retain(|record| record.id().is_none_or(|id| seen.insert(id.to_owned())));
+        }
+    }
 }
 
-/* 
- * A couple of structs to define how to deserialize JSON results from the FEMA API
- * and serialize entries to be written to file if the csv option is enabled in the config file
+/*
+ * Declaration areas designated in the future, which are data-entry errors. They're counted, and
+ * dropped before anything is written when drop_future_dates is set
  */
-#[derive(Serialize,Deserialize,Debug)]
-#[allow(non_snake_case)]
-struct Entry {
-    disasterNumber: i32,
-    programTypeCode: String,
-    programTypeDescription: String,
-    stateCode: String,
-    placeCode: String,
-    placeName: String,
-    designatedDate: DateTime<Utc>,
-    entryDate: DateTime<Utc>,
-    updateDate: DateTime<Utc>,
-    hash: String,
-    lastRefresh: DateTime<Utc>,
-    id: String
-}
-#[derive(Deserialize,Debug)]
-#[allow(non_snake_case)]
-struct Response {
-    FemaWebDeclarationAreas: Vec<Entry>,
-}
-#[derive(Deserialize,Debug)]
-#[allow(non_snake_case)]
-struct DeprecationInformation {
-    depDate: DateTime<Utc>,
-    deprecatedComment: String,
-    depApiMessage: String,
-    depNewURL: String,
-    depWebMessage: String,
-}
-#[derive(Deserialize,Debug)]
-#[allow(non_snake_case)]
-struct Metadata {
-    skip: i32,
-    top: i32,
-    count: i32,
-    filter: String,
-    format: String,
-    metadata: bool,
-    orderby: std::collections::HashMap<String,String>,
-    select: String,
-    entityname: String,
-    version: String,
-    url: String,
-    rundate: DateTime<Utc>,
-    DeprecationInformation: std::collections::HashMap<String,Option<String>>,
-}
-#[derive(Deserialize,Debug)]
-#[allow(non_snake_case)]
-struct ResponseWithMetaData {
-    metadata: Metadata,
-    FemaWebDeclarationAreas: Vec<Entry>,
-}
-
-// Helper function to make pagination less of a pain
-fn get_uri(metadata: bool, base: &str, query: &str, page: usize, size: Option<usize>) -> String {
-    let md_str = {
-        if metadata {
-            "on"
+struct FutureDates {
+    drop: bool,
+    // Allow a little clock skew between us and the server before calling a date the future
+    latest: DateTime<Utc>,
+    found: usize,
+}
+impl FutureDates {
+    fn new(drop: bool) -> Self {
+        Self { drop, latest: Utc::now() + Duration::hours(1), found: 0 }
+    }
+    fn check<T: Designated>(&mut self, records: &mut Vec<T>) {
+        let latest = self.latest;
+        let future = |record: &T| record.designated_date().is_some_and(|date| date > latest);
+        let before = records.len();
+        if self.drop {
+            records.retain(|record| !future(record));
+            self.found += before - records.len();
         }
         else {
-            "off"
+            self.found += records.iter().filter(|record| future(record)).count();
         }
-    };
-    match size {
-        Some(s) => {
-            format!("{}?{}&$skip={}&$top={}&$metadata={}", &base, &query, page*s, s, &md_str) 
+    }
+    fn report(&self) {
+        match (self.found, self.drop) {
+            (0, _) => (),
+            (found, true) => warn!("Dropped {} entries with a designatedDate in the future.", found),
+            (found, false) => warn!("{} entries have a designatedDate in the future.", found),
+        }
+    }
+}
+
+// The $filter for a run starting now, only asking for records refreshed since the sync checkpoint if there is one
+fn filter_for(cfg: &Config, now: DateTime<Utc>, sync_state: Option<&SyncState>) -> String {
+    let filter = fema::build_filter(cfg, now);
+    match sync_state.and_then(|state| state.checkpoint(cfg.dataset)) {
+        Some(since) => {
+            info!("Incremental sync, fetching records refreshed after {}.", since);
+            fema::refreshed_since(&filter, since)
         },
-        _ => {
-            format!("{}?{}&$metadata={}", &base, &query,&md_str)
+        None => filter,
+    }
+}
+
+// Resolves on the first SIGINT or SIGTERM, or never if the handlers can't be installed
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        if let Ok(mut terminate) = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            tokio::select! {
+                result = tokio::signal::ctrl_c() => if result.is_err() { futures::future::pending::<()>().await },
+                _ = terminate.recv() => (),
+            }
+            return;
         }
     }
+    if tokio::signal::ctrl_c().await.is_err() {
+        warn!("Failed to install the Ctrl-C handler.");
+        futures::future::pending::<()>().await;
+    }
+}
+
+// Writes the validation report, warning about the issues found so they don't go unnoticed
+fn report_issues(path: &std::path::Path, issues: &[fema::Issue]) -> Result<(), Error> {
+    fema::write_issues(path, issues)?;
+    if issues.is_empty() {
+        info!("No data-quality issues found, wrote an empty report to {}.", path.display());
+    }
+    else {
+        let records: HashSet<(usize, &str)> = issues.iter().map(|issue| (issue.page, issue.id.as_str())).collect();
+        warn!("Found {} data-quality issues in {} records, see {}.", issues.len(), records.len(), path.display());
+    }
+    Ok(())
+}
+
+/*
+ * Compares this run's records with the previous run's snapshot, writing the changes to diff_output
+ * and returning them. The first run has nothing to compare against.
+ */
+fn report_changes(cfg: &Config, snapshot: &Snapshot) -> Result<Vec<fema::Change>, Error> {
+    match cfg.diff.as_deref().map(Snapshot::load).transpose()?.flatten() {
+        Some(previous) => {
+            let changes = snapshot.diff(&previous);
+            let count = |kind| changes.iter().filter(|change| change.change == kind).count();
+            info!("Since the previous run: {} added, {} removed, {} changed.", count(fema::ChangeKind::Added), count(fema::ChangeKind::Removed), count(fema::ChangeKind::Changed));
+            if let Some(path) = &cfg.diff_output {
+                fema::write_changes(path, &changes)?;
+                info!("Changes written to file {}.", path.to_str().unwrap());
+            }
+            Ok(changes)
+        },
+        None => {
+            info!("No previous run to compare against, saving {} records for the next one.", snapshot.len());
+            Ok(Vec::new())
+        },
+    }
+}
+
+// Exit statuses, so scheduled jobs can tell what kind of failure stopped the run
+const EXIT_CONFIG: i32 = 2;
+const EXIT_DEPRECATED: i32 = 3;
+const EXIT_HTTP: i32 = 4;
+const EXIT_DESERIALIZE: i32 = 5;
+const EXIT_IO: i32 = 6;
+// The usual status for a process stopped by SIGINT
+const EXIT_INTERRUPTED: i32 = 130;
+
+fn exit_code(e: &Error) -> i32 {
+    match e {
+        Error::Config(_) => EXIT_CONFIG,
+        Error::Deprecated(_) | Error::Moved(_) => EXIT_DEPRECATED,
+        Error::Http { .. } | Error::Timeout { .. } | Error::Email(_) | Error::Mqtt(_) => EXIT_HTTP,
+        #[cfg(feature = "kafka")]
+        Error::Kafka(_) => EXIT_HTTP,
+        Error::Deserialize { .. } => EXIT_DESERIALIZE,
+        Error::Io(_) => EXIT_IO,
+        // Such as a configuration the output file turns out not to suit
+        Error::Output(e) => e.downcast_ref::<Error>().map_or(1, exit_code),
+    }
 }
 
 #[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error+Send+Sync>> {
-    let (cfg, log_cfg) = {
-        if let Some(proj_dirs) = ProjectDirs::from("", "", &APPNAME) {
+async fn main() {
+    if let Err(e) = run().await {
+        fema::METRICS.failed();
+        if let Some(url) = METRICS_PUSH_URL.get() {
+            push_metrics(&reqwest::Client::new(), url).await;
+        }
+        error!("{}", e);
+        eprintln!("Error: {}", e);
+        std::process::exit(exit_code(&e));
+    }
+}
+
+// Pushgateway of the run in progress, for pushing the failure if it fails
+static METRICS_PUSH_URL: std::sync::OnceLock<String> = std::sync::OnceLock::new();
+
+// A Pushgateway that can't be reached is only warned about, rather than failing a run that worked
+async fn push_metrics(http: &reqwest::Client, url: &str) {
+    if let Err(e) = fema::METRICS.push(http, url).await {
+        warn!("Failed to push metrics to {}: {}", url, e);
+    }
+}
+
+/*
+ * Uploads the run's files under a key prefix of its own, then the manifest if there is one, so its
+ * arrival tells a consumer the rest of the run's files are all there
+ */
+async fn upload_outputs(cfg: &Config, s3: &fema::S3Upload, http: &reqwest::Client, manifest: &manifest::Manifest, run: DateTime<Utc>) -> Result<(), Error> {
+    let mut uploaded = 0;
+    for (path, format) in manifest.files().chain(cfg.manifest.as_deref().map(|path| (path, "json"))) {
+        let key = s3.key(run, path);
+        s3.upload(http, path, &key, fema::content_type(format, path)).await?;
+        info!("Uploaded {} to s3://{}/{}.", path.to_str().unwrap(), s3.bucket, key);
+        uploaded += 1;
+    }
+    if uploaded == 0 {
+        warn!("No output files were written, so nothing was uploaded to S3.");
+    }
+    Ok(())
+}
+
+async fn run() -> Result<(), Error> {
+    let cli = cli::Cli::parse();
+    if let Some(cli::Command::Completions { shell }) = cli.command {
+        // Completions are registered for the name the binary was run as, e.g. after a cargo install
+        let name = std::env::args().next().as_ref().and_then(|arg0| std::path::Path::new(arg0).file_name()?.to_str().map(String::from));
+        clap_complete::generate(shell, &mut cli::Cli::command(), name.as_deref().unwrap_or(APPNAME), &mut std::io::stdout());
+        return Ok(());
+    }
+    if let Some(cli::Command::Config { action }) = &cli.command {
+        return config_command::config_command(&cli, action);
+    }
+    let (mut cfg, log_cfg) = {
+        if let Some(proj_dirs) = ProjectDirs::from("", "", APPNAME) {
             let cfg_dir = proj_dirs.config_dir();
-            let cfg: Config = confy::load(&APPNAME)?;
+            // Environment variables override the config file, and the command line overrides both
+            let cfg = load_config(&cli)?;
+            std::fs::create_dir_all(cfg_dir)?;
             let mut config_file = PathBuf::from(cfg_dir);
             config_file.push("log4rs");
             config_file.set_extension("yml");
             if !config_file.is_file() {
-                std::fs::copy("log4rs.yml", &config_file)?;
+                let log_dir = proj_dirs.data_local_dir().join("log");
+                std::fs::write(&config_file, DEFAULT_LOG_CONFIG.replace("{log_dir}", log_dir.to_str().unwrap()))?;
             }
-            let log_cfg = log4rs::load_config_file(&config_file, Default::default())?;
+            let log_cfg = load_log_config(&config_file, cfg.json_logs || cli.json_logs)
+                .map_err(|e| Error::Config(format!("Failed to load logging config {}: {}", config_file.display(), e)))?;
             (cfg, log_cfg)
         }
         else {
@@ -133,50 +470,329 @@ async fn main() -> Result<(), Box<dyn std::error::Error+Send+Sync>> {
     };
     let _handle = log4rs::init_config(log_cfg);
     info!("Started logger.");
-    let years_before = cfg.num_years_previous;
-    let now: DateTime<Utc> = Utc::now();
-    let cutoff = now - Duration::days(years_before as i64 * 365 as i64); 
-    info!("Filtering for dates after {}.", cutoff);
-    // Only request results with no closeoutDate key, filter a couple unneeded or redundant fields
-    let base_uri = "https://www.fema.gov/api/open/v1/FemaWebDeclarationAreas";
-    debug!("Base URI: {}", base_uri);
-    let query = format!("$inlinecount=allpages&$select=disasterNumber,programTypeCode,programTypeDescription,stateCode,placeCode,placeName,designatedDate,entryDate,updateDate,hash,lastRefresh&$filter=designatedDate gt'{}' and closeoutDate eq null",
-        cutoff.to_rfc3339_opts(chrono::SecondsFormat::Millis, true));
-    let size: usize = 1000; // the maximum allowed and default for fema's API
-    let response = reqwest::get(&get_uri(true, &base_uri, &query, 0, Some(size)))
-                .await?
-                .error_for_status()?
-                .json::<ResponseWithMetaData>() // request metadata on the first run so that we can get the total count
-                .await?;
-    let count = response.metadata.count;
-    info!("Server has {} matching results.", count);
-    let mut entries = Vec::with_capacity(count as usize);
-    for entry in response.FemaWebDeclarationAreas {
-        entries.push(entry);
-    }
-    for page in 1 .. count as usize / size + 1 {
-        let (start, mut end): (usize, i32) = (page*size, (page as i32+1)*size as i32);
-        if end > count {
-            end = count;
-        }
-        debug!("Requesting results {} through {}.", start, end);
-        let response = reqwest::get(&get_uri(false, &base_uri, &query, page, Some(size)))
-                .await?
-                .error_for_status()?
-                .json::<Response>() // Response will not contain the metadata
-                .await?;
-        debug!("Received results {} through {} from server.", start,end);
-        for entry in response.FemaWebDeclarationAreas {
-            entries.push(entry);
-        }
-    }
-    info!("Number of results collected: {}", entries.len());
-    if let Some(path) = &cfg.csv {
-    let mut csvwriter = csv::Writer::from_path(path)?;
-        for entry in entries {
-            csvwriter.serialize(entry)?;
-        }
-        info!("Entries written to file {}.", path.to_str().unwrap());
+    cli.apply(&mut cfg);
+    if cli.interactive {
+        cfg = interactive::prompt(cfg, &cli).map_err(|e| Error::Config(e.to_string()))?;
+    }
+    match &cli.command {
+        Some(cli::Command::Get { id }) => return Ok(store_command(&cfg, Some(id))?),
+        Some(cli::Command::List) => return Ok(store_command(&cfg, None)?),
+        Some(cli::Command::Tui { file }) => return Ok(tui_command(&cfg, file.as_deref())?),
+        Some(cli::Command::Cache { action }) => return cache_command::cache_command(&cfg, action),
+        _ => (),
+    }
+    check_features(&cfg)?;
+    // Before anything builds a filter, so e.g. --count doesn't ask the server about unknown codes
+    cfg.validate().map_err(Error::Config)?;
+    let cache = ResponseCache::from_config(&cfg);
+    let recording = Recording::from_config(&cfg);
+    // An offline or replayed run repeats an earlier one, with nothing sent anywhere and no checkpoint moved past its records
+    let cached_run = match (&cache, &recording) {
+        (Some(cache), _) if cfg.offline => Some(cache.last_run(cfg.dataset)?
+            .ok_or_else(|| Error::Config(format!("There's no cached {} run to repeat offline, run once without --offline first.", cfg.dataset)))?),
+        (_, Some(recording)) if recording.replaying() => Some(recording.run(cfg.dataset)?
+            .ok_or_else(|| Error::Config(format!("There's no recorded {} run to replay.", cfg.dataset)))?),
+        _ => None,
+    };
+    let repeat = cached_run.is_some();
+    if let Some(run) = &cached_run {
+        info!("Repeating the run started at {} without the network.", run.started);
+        cfg.sync_state = None;
+        cfg.dedup_file = None;
+        cfg.s3 = None;
+        cfg.metrics_push_url = None;
+    }
+    if let Some(dir) = cfg.record.as_ref().filter(|_| !repeat) {
+        Recording::start(dir)?;
+    }
+    let resumed = match (cli.resume, &cfg.progress_file) {
+        (true, Some(path)) => Some(progress::Progress::load(path)?),
+        (true, None) => return Err(Error::Config(String::from("Resuming needs a progress_file to be configured."))),
+        (false, _) => None,
+    };
+    if let Some(resumed) = &resumed {
+        if resumed.state.dataset != cfg.dataset {
+            return Err(Error::Config(format!("The interrupted run was fetching {}, not {}.", resumed.state.dataset, cfg.dataset)));
+        }
+        if resumed.state.page_size != cfg.page_size {
+            info!("Continuing with the interrupted run's page size of {}.", resumed.state.page_size);
+            cfg.page_size = resumed.state.page_size;
+        }
+    }
+    // A resumed or offline run carries on with the same cutoff and output file name as when it started
+    let now: DateTime<Utc> = match (&resumed, &cached_run) {
+        (Some(resumed), _) => resumed.state.started,
+        (None, Some(run)) => run.started,
+        (None, None) => Utc::now(),
+    };
+    match cfg.until {
+        Some(until) => info!("Filtering for dates from {} up to {}.", fema::cutoff(&cfg, now), until.end()),
+        None => info!("Filtering for dates after {}.", fema::cutoff(&cfg, now)),
+    }
+    let client = FemaClient::new(cfg.clone()).map_err(|e| Error::Config(format!("Failed to set up the HTTP client: {}", e)))?;
+    debug!("Base URI: {}", client.base_uri());
+    if let Some(cli::Command::Diagnose) = cli.command {
+        return diagnose::diagnose(client.http(), client.base_uri()).await;
+    }
+    if let Some(cli::Command::Serve { listen }) = &cli.command {
+        return serve::serve(&cfg, &client, listen.as_deref().unwrap_or(&cfg.listen)).await;
+    }
+    if cfg.response_format == fema::ResponseFormat::Jsona && cfg.expand.is_some() {
+        warn!("Expanded entities are only returned on the first page with the jsona response format.");
+    }
+    if (cfg.since.is_some() || cfg.until.is_some()) && cfg.dataset.date_field().is_none() {
+        warn!("The {} dataset has no date to filter on, ignoring since and until.", cfg.dataset);
+    }
+    if cfg.program_types.is_some() && cfg.dataset.program_type_field().is_none() {
+        warn!("The {} dataset has no program type, ignoring program_types.", cfg.dataset);
+    }
+    if cfg.drop_future_dates && (cfg.dataset != Dataset::FemaWebDeclarationAreas || cfg.select.as_ref().is_some_and(|select| !select.iter().any(|field| field == "designatedDate"))) {
+        warn!("Only FemaWebDeclarationAreas records with their designatedDate have a date to check, ignoring drop_future_dates.");
+    }
+    let mut sync_state = match &cfg.sync_state {
+        Some(path) => Some(SyncState::load(path)?),
+        None => None,
+    };
+    let mut filter = match &resumed {
+        Some(resumed) => resumed.state.filter.clone(),
+        None => match &cached_run {
+            Some(run) => run.filter.clone(),
+            None => filter_for(&cfg, now, sync_state.as_ref()),
+        },
+    };
+    log_mdc::insert("dataset", cfg.dataset.name());
+    log_mdc::insert("filter", &filter);
+    // Saved up front, so a run that fails can still be replayed to see why
+    if let Some(recording) = recording.as_ref().filter(|_| !repeat) {
+        recording.save_run(cfg.dataset, &fema::CachedRun { started: now, filter: filter.clone() })?;
+    }
+    if cli.count && !cli.by_state {
+        println!("{}", client.count(&filter).await?);
+        return Ok(());
+    }
+    let group_count = if cli.by_state { Some(cfg.dataset.state_field().to_owned()) } else { cfg.group_count.clone() };
+    if let Some(field) = &group_count {
+        for (value, count) in client.group_counts(&filter, field).await? {
+            println!("{}\t{}", value, count);
+        }
+        return Ok(());
+    }
+    if cfg.require_output && !cfg.has_output() {
+        error!("No output is configured and require_output is set, not fetching.");
+        return Err(Error::Config(String::from("No output configured.")));
+    }
+    let watch = cfg.watch().map_err(Error::Config)?;
+    if let Some(url) = &cfg.metrics_push_url {
+        let _ = METRICS_PUSH_URL.set(url.clone());
+    }
+    if let Some(listen) = &cfg.metrics_listen {
+        let server = serve::serve_metrics(listen)?;
+        tokio::spawn(async {
+            if let Err(e) = server.await {
+                error!("Metrics server stopped: {}", e);
+            }
+        });
+    }
+    let (output, progress, mut first_page, refreshed) = match resumed {
+        Some(_) if watch.is_some() => return Err(Error::Config(String::from("Watch mode can't resume an interrupted run."))),
+        Some(_) if cfg.diff.is_some() => return Err(Error::Config(String::from("Diff mode can't resume an interrupted run, the earlier pages aren't kept for comparison."))),
+        Some(_) if cfg.sort_window.is_some() => return Err(Error::Config(String::from("sort_window can't resume an interrupted run, the records it held back weren't written."))),
+        Some(resumed) => {
+            info!("Resuming the interrupted run after its {} pages and {} rows.", resumed.state.pages, resumed.state.rows);
+            let (first_page, refreshed) = (resumed.state.pages, resumed.state.refreshed);
+            (output::Output::resume(&cfg, &resumed.state)?, Some(resumed), first_page, refreshed)
+        },
+        // Every poll would start the progress file over, there's nothing to resume, split, compressed, or summarized output can't be resumed,
+        // and a sort window's pages aren't all on disk once written
+        None if watch.is_some() || cfg.split_by.is_some() || cfg.compress.is_some() || cfg.summarize || cfg.summary.is_some() || cfg.sort_window.is_some() => (output::Output::new(&cfg, now, &filter)?, None, 0, None),
+        None => {
+            let progress = cfg.progress_file.as_ref().map(|path| progress::Progress::start(path, now, cfg.dataset, &filter, cfg.page_size));
+            (output::Output::new(&cfg, now, &filter)?, progress, 0, None)
+        },
+    };
+    let notifier = fema::Notifier::new(&cfg).filter(|_| !repeat);
+    // Diff mode notifies of the changes it finds once the run is done, otherwise of each poll's new records
+    let (notifier, new) = if cfg.diff.is_some() { (notifier, None) } else { (None, notifier) };
+    let meter = meter::Meter::new(first_page, output.rows());
+    let mut sinks = Sinks { fan_out: sink::FanOut::open(&cfg, output, new)?, progress, meter, refreshed, seen: watch.as_ref().map(|_| HashSet::new()),
+        dedup: cfg.dedup_file.as_deref().map(SeenSet::load).transpose()?, snapshot: cfg.diff.as_deref().map(Snapshot::new),
+        validator: cfg.validation_report.as_ref().map(|_| Validator::new()),
+        window: cfg.sort_window.zip(cfg.order_by.as_deref()).map(|(size, order_by)| SortWindow::new(order_by, size)),
+    };
+    let mut breaker = CircuitBreaker::new(&cfg);
+    // Once through unless watching, in which case each pass is one poll
+    loop {
+        fema::METRICS.start_run();
+        // Looked up for each poll since new records can belong to disasters not seen before
+        if cfg.enrich {
+            let enrichment = fema::Enrichment::fetch(&cfg, &client, &filter).await?;
+            info!("Enriching records with the fields of {} disasters.", enrichment.disasters());
+            sinks.fan_out.output.set_enrichment(enrichment);
+        }
+        let mut client = client.clone().with_on_total(sinks.meter.on_total());
+        // Resuming carries on after the number of pages written, so a run that can be resumed writes them in order
+        if sinks.progress.is_some() {
+            client = client.in_server_order();
+        }
+        /*
+         * Pages are handed to the writers whole between requests, so stopping the fetch at the next
+         * await on SIGINT/SIGTERM leaves every page either fully written or not at all
+         */
+        let mut future = FutureDates::new(cfg.drop_future_dates);
+        let fetch = async {
+            if let Some(select) = &cfg.select {
+                client.fetch_each_from::<serde_json::Map<String,serde_json::Value>, _>(&filter, first_page, |records, expanded| {
+                    let mut rows: Vec<Row> = records.into_iter().map(|record| Row::from_map(record, select)).collect();
+                    future.check(&mut rows);
+                    sinks.write_row_page(rows, expanded)
+                }).await?;
+            }
+            else {
+                match cfg.dataset {
+                    Dataset::FemaWebDeclarationAreas if cfg.api_version() == ApiVersion::V2 => {
+                        client.fetch_each_from::<EntryV2, _>(&filter, first_page, |mut records, expanded| {
+                            future.check(&mut records);
+                            sinks.write_page(records, expanded)
+                        }).await?;
+                    },
+                    Dataset::FemaWebDeclarationAreas => {
+                        client.fetch_each_from::<Entry, _>(&filter, first_page, |mut entries, expanded| {
+                            future.check(&mut entries);
+                            sinks.write_page(entries, expanded)
+                        }).await?;
+                    },
+                    Dataset::DisasterDeclarationsSummaries => {
+                        client.fetch_each_from::<DisasterDeclarationsSummary, _>(&filter, first_page, |records, expanded| {
+                            sinks.write_page(records, expanded)
+                        }).await?;
+                    },
+                    Dataset::HousingAssistanceOwners => {
+                        client.fetch_each_from::<HousingAssistanceOwner, _>(&filter, first_page, |records, expanded| {
+                            sinks.write_page(records, expanded)
+                        }).await?;
+                    },
+                    Dataset::PublicAssistanceFundedProjectsDetails => {
+                        client.fetch_each_from::<PublicAssistanceFundedProjectsDetail, _>(&filter, first_page, |records, expanded| {
+                            sinks.write_page(records, expanded)
+                        }).await?;
+                    },
+                }
+            }
+            future.report();
+            Ok::<(), Error>(())
+        };
+        let (interrupted, failure) = tokio::select! {
+            result = fetch => match result {
+                // A watch rides out trouble with the API rather than ending, see CircuitBreaker
+                Err(e) if watch.is_some() && CircuitBreaker::counts(&e) => (false, Some(e)),
+                result => { result?; (false, None) },
+            },
+            _ = shutdown_signal() => (true, None),
+        };
+        // The records held back were all fetched, so they're written however the fetch ended
+        sinks.flush_window()?;
+        if interrupted {
+            sinks.meter.abandon();
+            warn!("Interrupted, not requesting any more pages.");
+            sinks.fan_out.output.close()?;
+            if let (Some(path), Some(_)) = (&cfg.progress_file, &sinks.progress) {
+                info!("Progress is saved in {}, run again with --resume to continue.", path.to_str().unwrap());
+            }
+            std::process::exit(EXIT_INTERRUPTED);
+        }
+        let wait = match failure {
+            // The pages the failed poll did get are kept, and the next poll carries on from the same checkpoint
+            Some(e) => {
+                sinks.meter.abandon();
+                fema::METRICS.failed();
+                let wait = watch.as_ref().and_then(|watch| watch.next_wait(Local::now()));
+                match breaker.failed(&e) {
+                    Breaker::Open(window) => wait.map(|wait| wait.max(window)),
+                    Breaker::Closed => wait,
+                }
+            },
+            None => {
+                sinks.meter.finish();
+                // Sent before the checkpoint moves past these records, so a failed notification is retried by the next run
+                if let Some(new) = &mut sinks.fan_out.notifier {
+                    new.notify_pending(client.http(), cfg.dataset).await?;
+                }
+                // Rewritten after each watch poll with every issue found since the watch started
+                if let (Some(path), Some(validator)) = (&cfg.validation_report, &sinks.validator) {
+                    report_issues(path, validator.issues())?;
+                }
+                let wait = match &watch {
+                    Some(watch) => watch.next_wait(Local::now()),
+                    None => break,
+                };
+                // Each poll's records are already on disk, so checkpoint now rather than when the watch ends
+                if let (Some(state), Some(refreshed)) = (&mut sync_state, sinks.refreshed) {
+                    state.advance(cfg.dataset, refreshed);
+                    state.save()?;
+                }
+                if let Some(dedup) = &sinks.dedup {
+                    dedup.save()?;
+                }
+                breaker.succeeded();
+                fema::METRICS.succeeded();
+                wait
+            },
+        };
+        if let Some(url) = &cfg.metrics_push_url {
+            push_metrics(client.http(), url).await;
+        }
+        let wait = match wait {
+            Some(wait) => wait,
+            None => {
+                info!("The schedule has no more times, stopped watching.");
+                break;
+            },
+        };
+        // Whole seconds are plenty for telling when the next poll is
+        info!("Polling again in {}.", humantime::format_duration(std::time::Duration::from_secs(wait.as_secs())));
+        let stopped = tokio::select! {
+            _ = tokio::time::delay_for(wait) => false,
+            _ = shutdown_signal() => true,
+        };
+        if stopped {
+            info!("Stopped watching.");
+            break;
+        }
+        filter = filter_for(&cfg, Utc::now(), sync_state.as_ref());
+        log_mdc::insert("filter", &filter);
+        first_page = 0;
+        sinks.meter = meter::Meter::new(0, 0);
+    }
+    let manifest = sinks.fan_out.finish()?;
+    if let Some(s3) = &cfg.s3 {
+        upload_outputs(&cfg, s3, client.http(), &manifest, now).await?;
+    }
+    if let Some(snapshot) = &sinks.snapshot {
+        let changes = report_changes(&cfg, snapshot)?;
+        if let Some(notifier) = &notifier {
+            notifier.notify(client.http(), cfg.dataset, &changes).await?;
+        }
+        snapshot.save()?;
+    }
+    // Only checkpoint once everything has been written, so a failed run is fetched again next time
+    if let (Some(state), Some(refreshed)) = (&mut sync_state, sinks.refreshed) {
+        state.advance(cfg.dataset, refreshed);
+        state.save()?;
+        info!("Sync checkpoint for {} is now {}.", cfg.dataset, state.checkpoint(cfg.dataset).unwrap_or(refreshed));
+    }
+    if let Some(dedup) = &sinks.dedup {
+        dedup.save()?;
+        debug!("{} records are in the dedup file.", dedup.len());
+    }
+    if let Some(progress) = sinks.progress {
+        progress.finish()?;
+    }
+    if let Some(cache) = cache.filter(|_| !repeat) {
+        cache.save_run(cfg.dataset, &fema::CachedRun { started: now, filter })?;
+    }
+    fema::METRICS.succeeded();
+    if let Some(url) = &cfg.metrics_push_url {
+        push_metrics(client.http(), url).await;
     }
     Ok(())
 }
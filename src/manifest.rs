@@ -0,0 +1,50 @@
+/*
+ * Record of the output files produced in a run, written as JSON for loaders that need to know
+ * exactly which files to pick up
+ */
+use serde::Serialize;
+use sha2::{Digest,Sha256};
+use std::path::{Path,PathBuf};
+
+#[derive(Serialize,Debug)]
+pub struct ManifestEntry {
+    path: PathBuf,
+    format: &'static str,
+    rows: usize,
+    bytes: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    sha256: Option<String>,
+}
+
+#[derive(Default,Debug)]
+pub struct Manifest {
+    files: Vec<ManifestEntry>,
+    checksums: bool,
+}
+impl Manifest {
+    pub fn new(checksums: bool) -> Self {
+        Self { files: Vec::new(), checksums }
+    }
+    // Records a file once it has been completely written
+    pub fn add(&mut self, path: &Path, format: &'static str, rows: usize) -> std::io::Result<()> {
+        let bytes = std::fs::metadata(path)?.len();
+        let sha256 = if self.checksums {
+            let mut hasher = Sha256::new();
+            std::io::copy(&mut std::fs::File::open(path)?, &mut hasher)?;
+            Some(format!("{:x}", hasher.finalize()))
+        }
+        else {
+            None
+        };
+        self.files.push(ManifestEntry { path: path.to_owned(), format, rows, bytes, sha256 });
+        Ok(())
+    }
+    // Paths and formats of the files, in the order they were written
+    pub fn files(&self) -> impl Iterator<Item=(&Path, &'static str)> {
+        self.files.iter().map(|file| (file.path.as_path(), file.format))
+    }
+    pub fn write(&self, path: &Path) -> Result<(), Box<dyn std::error::Error+Send+Sync>> {
+        serde_json::to_writer_pretty(std::fs::File::create(path)?, &self.files)?;
+        Ok(())
+    }
+}
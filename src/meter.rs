@@ -0,0 +1,74 @@
+/*
+ * Progress of a long download for whoever is watching it: a progress bar when stderr is a terminal,
+ * otherwise a log line every LOG_INTERVAL, either way with the results and pages done so far, the
+ * throughput, and an ETA once the first page has given the total
+ */
+use indicatif::{ProgressBar,ProgressDrawTarget,ProgressStyle};
+use std::io::IsTerminal;
+use std::time::{Duration,Instant};
+
+const LOG_INTERVAL: Duration = Duration::from_secs(30);
+
+pub struct Meter {
+    bar: ProgressBar,
+    tty: bool,
+    pages: usize,
+    logged: Instant,
+}
+impl Meter {
+    // Starts from the pages and rows an interrupted run already wrote when resuming
+    pub fn new(pages: usize, rows: usize) -> Self {
+        let tty = std::io::stderr().is_terminal();
+        let bar = if tty { ProgressBar::with_draw_target(None, ProgressDrawTarget::stderr()) } else { ProgressBar::hidden() };
+        bar.set_style(style("{spinner} {pos} results, {msg} ({per_sec})"));
+        bar.set_position(rows as u64);
+        bar.set_message(format!("{} pages", pages));
+        // Only what this run fetches counts towards the throughput
+        bar.reset_eta();
+        Self { bar, tty, pages, logged: Instant::now() }
+    }
+    // For FemaClient::with_on_total, switching to a bar with an ETA once the total is known
+    pub fn on_total(&self) -> impl Fn(usize) + Send + Sync + 'static {
+        let bar = self.bar.clone();
+        move |total| {
+            bar.set_length(total as u64);
+            bar.set_style(style("{bar:40} {pos}/{len} results, {msg} ({per_sec}, ETA {eta})"));
+        }
+    }
+    pub fn pages(&self) -> usize {
+        self.pages
+    }
+    pub fn page(&mut self, rows: usize) {
+        self.pages += 1;
+        self.bar.inc(rows as u64);
+        self.bar.set_message(format!("{} pages", self.pages));
+        if !self.tty && self.logged.elapsed() >= LOG_INTERVAL {
+            self.logged = Instant::now();
+            self.log();
+        }
+    }
+    pub fn finish(&self) {
+        if self.tty {
+            self.bar.finish();
+        }
+        else {
+            self.log();
+        }
+    }
+    // Leaves the bar as it stands when the run stops early
+    pub fn abandon(&self) {
+        self.bar.abandon();
+    }
+    fn log(&self) {
+        let total = self.bar.length().map_or_else(String::new, |total| format!(" of {}", total));
+        let eta = match self.bar.length() {
+            Some(_) => format!(", about {}s left", self.bar.eta().as_secs()),
+            None => String::new(),
+        };
+        info!("{}{} results in {} pages, {:.0} results/s{}.", self.bar.position(), total, self.pages, self.bar.per_sec(), eta);
+    }
+}
+
+fn style(template: &str) -> ProgressStyle {
+    ProgressStyle::with_template(template).expect("Progress bar template is valid.")
+}
@@ -0,0 +1,104 @@
+/*
+ * Prometheus metrics for keeping an eye on long-running or scheduled syncs: pages and records
+ * fetched, new records, failed requests by status, and when the last run or poll succeeded. They're
+ * counted process-wide as the fetching happens and rendered in Prometheus' text format, to be served
+ * at /metrics or pushed to a Pushgateway. Alerting on the age of the last success catches a sync
+ * that has stopped working without anyone noticing.
+ */
+use chrono::Utc;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64,Ordering};
+use crate::{Error,APPNAME};
+
+pub static METRICS: Metrics = Metrics::new();
+
+#[derive(Debug)]
+pub struct Metrics {
+    pages: AtomicU64,
+    records: AtomicU64,
+    new_records: AtomicU64,
+    // New records so far in the current run or poll, and in the last one to succeed
+    run_new_records: AtomicU64,
+    last_run_new_records: AtomicU64,
+    failures: AtomicU64,
+    last_success: AtomicU64,
+    // Requests answered from the response cache after a 304
+    not_modified: AtomicU64,
+    // 1 while watch mode is backing off after repeated failures
+    circuit_open: AtomicU64,
+    // Failed requests by HTTP status, 0 for those that got no response
+    http_errors: Mutex<BTreeMap<u16,u64>>,
+}
+impl Metrics {
+    const fn new() -> Self {
+        Self {
+            pages: AtomicU64::new(0),
+            records: AtomicU64::new(0),
+            new_records: AtomicU64::new(0),
+            run_new_records: AtomicU64::new(0),
+            last_run_new_records: AtomicU64::new(0),
+            failures: AtomicU64::new(0),
+            last_success: AtomicU64::new(0),
+            not_modified: AtomicU64::new(0),
+            circuit_open: AtomicU64::new(0),
+            http_errors: Mutex::new(BTreeMap::new()),
+        }
+    }
+    pub fn page(&self, records: usize) {
+        self.pages.fetch_add(1, Ordering::Relaxed);
+        self.records.fetch_add(records as u64, Ordering::Relaxed);
+    }
+    pub fn not_modified(&self) {
+        self.not_modified.fetch_add(1, Ordering::Relaxed);
+    }
+    pub fn circuit_open(&self, open: bool) {
+        self.circuit_open.store(open as u64, Ordering::Relaxed);
+    }
+    pub fn http_error(&self, status: Option<u16>) {
+        *self.http_errors.lock().unwrap().entry(status.unwrap_or(0)).or_insert(0) += 1;
+    }
+    // Records written out, or added to the serve cache
+    pub fn new_records(&self, records: usize) {
+        self.new_records.fetch_add(records as u64, Ordering::Relaxed);
+        self.run_new_records.fetch_add(records as u64, Ordering::Relaxed);
+    }
+    pub fn start_run(&self) {
+        self.run_new_records.store(0, Ordering::Relaxed);
+    }
+    pub fn succeeded(&self) {
+        self.last_success.store(Utc::now().timestamp().max(0) as u64, Ordering::Relaxed);
+        self.last_run_new_records.store(self.run_new_records.load(Ordering::Relaxed), Ordering::Relaxed);
+    }
+    pub fn failed(&self) {
+        self.failures.fetch_add(1, Ordering::Relaxed);
+    }
+    // The metrics in Prometheus' text exposition format
+    pub fn render(&self) -> String {
+        let mut text = String::new();
+        let mut metric = |name: &str, kind: &str, help: &str, value: u64| {
+            let _ = write!(text, "# HELP {name} {help}\n# TYPE {name} {kind}\n{name} {value}\n", name = name, kind = kind, help = help, value = value);
+        };
+        metric("fema_pages_fetched_total", "counter", "Pages of results fetched from OpenFEMA.", self.pages.load(Ordering::Relaxed));
+        metric("fema_records_fetched_total", "counter", "Records fetched from OpenFEMA.", self.records.load(Ordering::Relaxed));
+        metric("fema_new_records_total", "counter", "Records written, less those already written earlier in a watch or already in the serve cache.", self.new_records.load(Ordering::Relaxed));
+        metric("fema_last_run_new_records", "gauge", "New records in the last successful run, poll, or refresh.", self.last_run_new_records.load(Ordering::Relaxed));
+        metric("fema_run_failures_total", "counter", "Runs, polls, or refreshes that failed.", self.failures.load(Ordering::Relaxed));
+        metric("fema_last_success_timestamp_seconds", "gauge", "When the last run, poll, or refresh succeeded, 0 if none has.", self.last_success.load(Ordering::Relaxed));
+        metric("fema_not_modified_total", "counter", "Requests OpenFEMA answered with 304 Not Modified, served from the response cache.", self.not_modified.load(Ordering::Relaxed));
+        metric("fema_circuit_breaker_open", "gauge", "1 while watch mode is backing off after repeated failed polls, otherwise 0.", self.circuit_open.load(Ordering::Relaxed));
+        text.push_str("# HELP fema_http_errors_total Failed requests to OpenFEMA by HTTP status, 0 for those without a response.\n# TYPE fema_http_errors_total counter\n");
+        for (status, count) in self.http_errors.lock().unwrap().iter() {
+            let _ = writeln!(text, "fema_http_errors_total{{status=\"{}\"}} {}", status, count);
+        }
+        text
+    }
+    // Replaces this program's metrics on a Prometheus Pushgateway, e.g. http://localhost:9091
+    pub async fn push(&self, http: &reqwest::Client, url: &str) -> Result<(), Error> {
+        let url = format!("{}/metrics/job/{}", url.trim_end_matches('/'), APPNAME);
+        http.put(&url).body(self.render()).send().await.and_then(reqwest::Response::error_for_status)
+            .map_err(|source| Error::Http { url, status: source.status().map(|s| s.as_u16()), source, retry_after: None })?;
+        Ok(())
+    }
+}
@@ -0,0 +1,360 @@
+use chrono::{DateTime,Utc};
+use serde::{Serialize,Serializer,Deserialize};
+use serde::ser::SerializeMap;
+use std::collections::{BTreeMap,HashMap};
+use crate::{ProgramType,StateCode};
+
+/* 
+ * A couple of structs to define how to deserialize JSON results from the FEMA API
+ * and serialize entries to be written to file if the csv option is enabled in the config file.
+ * Dates are serialized in the configured date_format when writing CSV, see DateFormat.
+ * Fields FEMA stops sending are left empty rather than failing the page, see SchemaDrift, other than
+ * the id and designatedDate every record needs. Dates that can be missing are None rather than 1970.
+ */
+#[derive(Serialize,Deserialize,Debug,Clone,Default)]
+#[allow(non_snake_case)]
+pub struct Entry {
+    #[serde(default)]
+    pub disasterNumber: i32,
+    #[serde(default)]
+    pub programTypeCode: ProgramType,
+    #[serde(default)]
+    pub programTypeDescription: String,
+    #[serde(default)]
+    pub stateCode: StateCode,
+    #[serde(default)]
+    pub placeCode: String,
+    #[serde(default)]
+    pub placeName: String,
+    #[serde(serialize_with = "crate::serialize_date")]
+    pub designatedDate: DateTime<Utc>,
+    #[serde(serialize_with = "crate::serialize_optional_date")]
+    pub entryDate: Option<DateTime<Utc>>,
+    #[serde(serialize_with = "crate::serialize_optional_date")]
+    pub updateDate: Option<DateTime<Utc>>,
+    // Null unless the declaration has been closed out, which only shows up with include_closed
+    #[serde(serialize_with = "crate::serialize_optional_date")]
+    pub closeoutDate: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub hash: String,
+    #[serde(serialize_with = "crate::serialize_optional_date")]
+    pub lastRefresh: Option<DateTime<Utc>>,
+    pub id: String,
+    // Fields FEMA has added since, kept as they came but not written out
+    #[serde(flatten, skip_serializing)]
+    pub extra: BTreeMap<String,serde_json::Value>,
+}
+/*
+ * FemaWebDeclarationAreas record from the v2 endpoint. Without a default $select the full record
+ * comes back, and since the v2 field set isn't settled everything but the id is optional.
+ */
+#[derive(Serialize,Deserialize,Debug)]
+#[allow(non_snake_case)]
+pub struct EntryV2 {
+    pub disasterNumber: Option<i32>,
+    pub programTypeCode: Option<ProgramType>,
+    pub programTypeDescription: Option<String>,
+    pub stateCode: Option<StateCode>,
+    pub placeCode: Option<String>,
+    pub placeName: Option<String>,
+    #[serde(serialize_with = "crate::serialize_optional_date")]
+    pub designatedDate: Option<DateTime<Utc>>,
+    #[serde(serialize_with = "crate::serialize_optional_date")]
+    pub entryDate: Option<DateTime<Utc>>,
+    #[serde(serialize_with = "crate::serialize_optional_date")]
+    pub updateDate: Option<DateTime<Utc>>,
+    #[serde(serialize_with = "crate::serialize_optional_date")]
+    pub closeoutDate: Option<DateTime<Utc>>,
+    pub hash: Option<String>,
+    #[serde(serialize_with = "crate::serialize_optional_date")]
+    pub lastRefresh: Option<DateTime<Utc>>,
+    pub id: String,
+}
+// Every field is null unless the endpoint has been deprecated
+#[derive(Deserialize,Debug,Default)]
+#[serde(default)]
+#[allow(non_snake_case)]
+pub struct DeprecationInformation {
+    pub depDate: Option<String>,
+    pub deprecatedComment: Option<String>,
+    pub depApiMessage: Option<String>,
+    pub depNewURL: Option<String>,
+    pub depWebMessage: Option<String>,
+}
+#[derive(Deserialize,Debug)]
+#[allow(non_snake_case)]
+pub struct Metadata {
+    pub skip: i32,
+    pub top: i32,
+    pub count: i32,
+    pub filter: String,
+    pub format: String,
+    pub metadata: bool,
+    pub orderby: HashMap<String,String>,
+    pub select: String,
+    pub entityname: String,
+    pub version: String,
+    pub url: String,
+    pub rundate: DateTime<Utc>,
+    #[serde(default)]
+    pub DeprecationInformation: DeprecationInformation,
+}
+
+/*
+ * Records of the other supported datasets. Most fields are optional since they are sparsely
+ * populated, particularly in older records.
+ */
+#[derive(Serialize,Deserialize,Debug)]
+#[allow(non_snake_case)]
+pub struct DisasterDeclarationsSummary {
+    pub femaDeclarationString: String,
+    pub disasterNumber: i32,
+    pub state: StateCode,
+    pub declarationType: String,
+    #[serde(serialize_with = "crate::serialize_date")]
+    pub declarationDate: DateTime<Utc>,
+    pub fyDeclared: i32,
+    pub incidentType: String,
+    pub declarationTitle: String,
+    pub ihProgramDeclared: bool,
+    pub iaProgramDeclared: bool,
+    pub paProgramDeclared: bool,
+    pub hmProgramDeclared: bool,
+    #[serde(serialize_with = "crate::serialize_optional_date")]
+    pub incidentBeginDate: Option<DateTime<Utc>>,
+    #[serde(serialize_with = "crate::serialize_optional_date")]
+    pub incidentEndDate: Option<DateTime<Utc>>,
+    #[serde(serialize_with = "crate::serialize_optional_date")]
+    pub disasterCloseoutDate: Option<DateTime<Utc>>,
+    pub fipsStateCode: Option<String>,
+    pub fipsCountyCode: Option<String>,
+    pub placeCode: Option<String>,
+    pub designatedArea: Option<String>,
+    pub declarationRequestNumber: Option<String>,
+    pub hash: Option<String>,
+    #[serde(serialize_with = "crate::serialize_optional_date")]
+    pub lastRefresh: Option<DateTime<Utc>>,
+    pub id: String,
+}
+#[derive(Serialize,Deserialize,Debug)]
+#[allow(non_snake_case)]
+pub struct HousingAssistanceOwner {
+    pub disasterNumber: i32,
+    pub state: StateCode,
+    pub county: Option<String>,
+    pub city: Option<String>,
+    pub zipCode: Option<String>,
+    pub validRegistrations: Option<i64>,
+    pub averageFemaInspectedDamage: Option<f64>,
+    pub totalInspected: Option<i64>,
+    pub totalDamage: Option<f64>,
+    pub noFemaInspectedDamage: Option<i64>,
+    pub femaInspectedDamageBetween1And10000: Option<i64>,
+    pub femaInspectedDamageBetween10001And20000: Option<i64>,
+    pub femaInspectedDamageBetween20001And30000: Option<i64>,
+    pub femaInspectedDamageGreaterThan30000: Option<i64>,
+    pub approvedForFemaAssistance: Option<i64>,
+    pub totalApprovedIhpAmount: Option<f64>,
+    pub repairReplaceAmount: Option<f64>,
+    pub rentalAmount: Option<f64>,
+    pub otherNeedsAmount: Option<f64>,
+    pub approvedBetween1And10000: Option<i64>,
+    pub approvedBetween10001And25000: Option<i64>,
+    pub approvedBetween25001AndMax: Option<i64>,
+    pub totalMaxGrants: Option<i64>,
+    #[serde(serialize_with = "crate::serialize_optional_date")]
+    pub lastRefresh: Option<DateTime<Utc>>,
+    pub id: String,
+}
+#[derive(Serialize,Deserialize,Debug)]
+#[allow(non_snake_case)]
+pub struct PublicAssistanceFundedProjectsDetail {
+    pub disasterNumber: i32,
+    #[serde(serialize_with = "crate::serialize_optional_date")]
+    pub declarationDate: Option<DateTime<Utc>>,
+    pub incidentType: Option<String>,
+    pub pwNumber: Option<i64>,
+    pub applicationTitle: Option<String>,
+    pub applicantId: Option<String>,
+    pub damageCategoryCode: Option<String>,
+    pub dcc: Option<String>,
+    pub damageCategory: Option<String>,
+    pub projectSize: Option<String>,
+    pub county: Option<String>,
+    pub countyCode: Option<String>,
+    pub state: Option<String>,
+    pub stateCode: Option<StateCode>,
+    pub stateNumberCode: Option<String>,
+    pub projectAmount: Option<f64>,
+    pub federalShareObligated: Option<f64>,
+    pub totalObligated: Option<f64>,
+    #[serde(serialize_with = "crate::serialize_optional_date")]
+    pub obligatedDate: Option<DateTime<Utc>>,
+    pub hash: Option<String>,
+    #[serde(serialize_with = "crate::serialize_optional_date")]
+    pub lastRefresh: Option<DateTime<Utc>>,
+    pub id: String,
+}
+
+/*
+ * A record of any dataset holding only the fields requested with the select option, in the order
+ * they were listed. Fields missing from a response are null.
+ */
+#[derive(Debug,Clone,PartialEq)]
+pub struct Row(pub Vec<(String,serde_json::Value)>);
+impl Row {
+    pub fn from_map(mut fields: serde_json::Map<String,serde_json::Value>, select: &[String]) -> Self {
+        Row(select.iter().map(|name| (name.clone(), fields.remove(name).unwrap_or(serde_json::Value::Null))).collect())
+    }
+    pub fn get(&self, name: &str) -> Option<&serde_json::Value> {
+        self.0.iter().find(|(field, _)| field == name).map(|(_, value)| value)
+    }
+    pub fn names(&self) -> impl Iterator<Item=&str> {
+        self.0.iter().map(|(name, _)| name.as_str())
+    }
+}
+// Keeps the fields in the order they come in, e.g. a record's declaration order when read back from its JSON
+impl<'de> Deserialize<'de> for Row {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct RowVisitor;
+        impl<'de> serde::de::Visitor<'de> for RowVisitor {
+            type Value = Row;
+            fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                f.write_str("a map of fields")
+            }
+            fn visit_map<A: serde::de::MapAccess<'de>>(self, mut map: A) -> Result<Row, A::Error> {
+                let mut fields = Vec::with_capacity(map.size_hint().unwrap_or(0));
+                while let Some(field) = map.next_entry()? {
+                    fields.push(field);
+                }
+                Ok(Row(fields))
+            }
+        }
+        deserializer.deserialize_map(RowVisitor)
+    }
+}
+impl Serialize for Row {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(self.0.len()))?;
+        for (name, value) in &self.0 {
+            map.serialize_entry(name, value)?;
+        }
+        map.end()
+    }
+}
+
+// Records that carry the time FEMA last refreshed them, which incremental sync checkpoints on
+pub trait Refreshed {
+    fn last_refresh(&self) -> Option<DateTime<Utc>>;
+}
+impl Refreshed for Entry {
+    fn last_refresh(&self) -> Option<DateTime<Utc>> {
+        self.lastRefresh
+    }
+}
+impl Refreshed for EntryV2 {
+    fn last_refresh(&self) -> Option<DateTime<Utc>> {
+        self.lastRefresh
+    }
+}
+impl Refreshed for DisasterDeclarationsSummary {
+    fn last_refresh(&self) -> Option<DateTime<Utc>> {
+        self.lastRefresh
+    }
+}
+impl Refreshed for HousingAssistanceOwner {
+    fn last_refresh(&self) -> Option<DateTime<Utc>> {
+        self.lastRefresh
+    }
+}
+impl Refreshed for PublicAssistanceFundedProjectsDetail {
+    fn last_refresh(&self) -> Option<DateTime<Utc>> {
+        self.lastRefresh
+    }
+}
+impl Refreshed for Row {
+    fn last_refresh(&self) -> Option<DateTime<Utc>> {
+        let value = self.get("lastRefresh")?.as_str()?;
+        DateTime::parse_from_rfc3339(value).ok().map(|d| d.with_timezone(&Utc))
+    }
+}
+
+// Declaration areas with the date they were designated, which drop_future_dates checks
+pub trait Designated {
+    fn designated_date(&self) -> Option<DateTime<Utc>>;
+}
+impl Designated for Entry {
+    fn designated_date(&self) -> Option<DateTime<Utc>> {
+        Some(self.designatedDate)
+    }
+}
+impl Designated for EntryV2 {
+    fn designated_date(&self) -> Option<DateTime<Utc>> {
+        self.designatedDate
+    }
+}
+// Only if designatedDate is one of the selected fields
+impl Designated for Row {
+    fn designated_date(&self) -> Option<DateTime<Utc>> {
+        let value = self.get("designatedDate")?.as_str()?;
+        DateTime::parse_from_rfc3339(value).ok().map(|d| d.with_timezone(&Utc))
+    }
+}
+
+// Records with the unique id FEMA gives them, which watch mode tells new records apart by
+pub trait Identified {
+    fn id(&self) -> Option<&str>;
+    // What changes when FEMA updates the record, its hash or otherwise when it was last updated
+    fn version(&self) -> Option<String> {
+        None
+    }
+}
+impl Identified for Entry {
+    fn id(&self) -> Option<&str> {
+        Some(&self.id)
+    }
+    fn version(&self) -> Option<String> {
+        Some(self.hash.clone())
+    }
+}
+impl Identified for EntryV2 {
+    fn id(&self) -> Option<&str> {
+        Some(&self.id)
+    }
+    fn version(&self) -> Option<String> {
+        self.hash.clone().or_else(|| self.updateDate.map(|date| date.to_rfc3339()))
+    }
+}
+impl Identified for DisasterDeclarationsSummary {
+    fn id(&self) -> Option<&str> {
+        Some(&self.id)
+    }
+    fn version(&self) -> Option<String> {
+        self.hash.clone().or_else(|| self.lastRefresh.map(|date| date.to_rfc3339()))
+    }
+}
+impl Identified for HousingAssistanceOwner {
+    fn id(&self) -> Option<&str> {
+        Some(&self.id)
+    }
+    fn version(&self) -> Option<String> {
+        self.lastRefresh.map(|date| date.to_rfc3339())
+    }
+}
+impl Identified for PublicAssistanceFundedProjectsDetail {
+    fn id(&self) -> Option<&str> {
+        Some(&self.id)
+    }
+    fn version(&self) -> Option<String> {
+        self.hash.clone().or_else(|| self.lastRefresh.map(|date| date.to_rfc3339()))
+    }
+}
+// Only if id is one of the selected fields, likewise hash or updateDate
+impl Identified for Row {
+    fn id(&self) -> Option<&str> {
+        self.get("id")?.as_str()
+    }
+    fn version(&self) -> Option<String> {
+        ["hash", "updateDate"].iter().find_map(|field| self.get(field)?.as_str().map(String::from))
+    }
+}
@@ -0,0 +1,217 @@
+/*
+ * Publishing of new records to an MQTT broker, for lightweight integrations like Node-RED flows or
+ * dashboards that already subscribe to one. Each record is published as JSON to a topic filled in
+ * from its fields, e.g. fema/TX/IA, so subscribers can pick out the states and programs they care
+ * about with topic filters. Only what publishing needs of MQTT 3.1.1 is spoken: a clean session,
+ * PUBLISH at QoS 0 or 1, and DISCONNECT, over TCP or TLS.
+ */
+use serde::{Serialize,Deserialize};
+use tokio::io::{AsyncRead,AsyncReadExt,AsyncWrite,AsyncWriteExt};
+use crate::{Dataset,Error};
+
+pub const DEFAULT_MQTT_TOPIC: &str = "fema/{stateCode}/{programTypeCode}";
+
+// Longest a publish may take from connecting to disconnecting, so a broker that stops answering can't hang the run
+const TIMEOUT: std::time::Duration = std::time::Duration::from_secs(30);
+const KEEP_ALIVE_SECS: u16 = 60;
+
+#[derive(Debug,Serialize,Deserialize,Clone,PartialEq)]
+#[serde(default)]
+pub struct MqttSink {
+    // mqtt://host:1883, or mqtts://host:8883 for TLS
+    pub url: String,
+    // Topic of each record, with each {fieldName} filled in from it
+    pub topic: String,
+    pub client_id: String,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    // 0 to send and forget, 1 to wait for the broker to acknowledge each record
+    pub qos: u8,
+    // Have the broker keep the last record on each topic for subscribers that connect later
+    pub retain: bool,
+}
+impl std::default::Default for MqttSink {
+    fn default() -> Self {
+        Self {
+            url: String::new(),
+            topic: String::from(DEFAULT_MQTT_TOPIC),
+            client_id: String::from(crate::APPNAME),
+            username: None,
+            password: None,
+            qos: 1,
+            retain: false,
+        }
+    }
+}
+impl MqttSink {
+    // Checks the settings that can be checked without connecting
+    pub fn validate(&self) -> Result<(), String> {
+        self.address()?;
+        if self.topic.is_empty() || self.topic.contains(['+', '#']) {
+            return Err(format!("Invalid MQTT topic {:?}, it can't be empty or contain the + and # wildcards.", self.topic));
+        }
+        if self.qos > 1 {
+            return Err(format!("MQTT QoS must be 0 or 1, not {}.", self.qos));
+        }
+        if self.password.is_some() && self.username.is_none() {
+            return Err(String::from("An MQTT password needs a username to go with it."));
+        }
+        Ok(())
+    }
+    // Host, port, and whether to use TLS
+    fn address(&self) -> Result<(String, u16, bool), String> {
+        let url = reqwest::Url::parse(&self.url).map_err(|e| format!("Invalid MQTT URL {}: {}", self.url, e))?;
+        let tls = match url.scheme() {
+            "mqtt" | "tcp" => false,
+            "mqtts" | "ssl" => true,
+            scheme => return Err(format!("MQTT URL {} should start with mqtt:// or mqtts://, not {}://.", self.url, scheme)),
+        };
+        let host = url.host_str().filter(|host| !host.is_empty()).ok_or_else(|| format!("MQTT URL {} has no host.", self.url))?;
+        Ok((host.to_owned(), url.port().unwrap_or(if tls { 8883 } else { 1883 }), tls))
+    }
+    // The topic for record, with any / or wildcards in its values replaced so they can't add levels or fail the publish
+    pub fn topic_for(&self, record: &serde_json::Value) -> String {
+        let mut record = record.clone();
+        if let serde_json::Value::Object(fields) = &mut record {
+            for value in fields.values_mut() {
+                if let serde_json::Value::String(s) = value {
+                    *s = s.replace(['/', '+', '#'], "_");
+                }
+            }
+        }
+        crate::render(&self.topic, &record)
+    }
+    pub async fn publish(&self, dataset: Dataset, records: &[serde_json::Value]) -> Result<(), Error> {
+        let (host, port, tls) = self.address().map_err(Error::Config)?;
+        let error = |e: std::io::Error| Error::Mqtt(format!("{}: {}", self.url, e));
+        let publish = async {
+            let stream = tokio::net::TcpStream::connect((host.as_str(), port)).await?;
+            if tls {
+                let connector = tokio_tls::TlsConnector::from(native_tls::TlsConnector::new().map_err(std::io::Error::other)?);
+                let stream = connector.connect(&host, stream).await.map_err(std::io::Error::other)?;
+                self.session(stream, records).await
+            }
+            else {
+                self.session(stream, records).await
+            }
+        };
+        match tokio::time::timeout(TIMEOUT, publish).await {
+            Ok(result) => result.map_err(error)?,
+            Err(_) => return Err(error(std::io::Error::new(std::io::ErrorKind::TimedOut, "the broker stopped responding"))),
+        }
+        info!("Published {} new {} records to MQTT broker {}.", records.len(), dataset.name(), self.url);
+        Ok(())
+    }
+    async fn session<S: AsyncRead + AsyncWrite + Unpin>(&self, mut stream: S, records: &[serde_json::Value]) -> std::io::Result<()> {
+        stream.write_all(&self.connect_packet()).await?;
+        let (kind, body) = read_packet(&mut stream).await?;
+        if kind != CONNACK || body.len() != 2 {
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "expected a CONNACK"));
+        }
+        if body[1] != 0 {
+            return Err(std::io::Error::new(std::io::ErrorKind::ConnectionRefused, format!("the broker refused the connection: {}", refusal(body[1]))));
+        }
+        for (i, record) in records.iter().enumerate() {
+            // Packet ids are nonzero and only need to be unique among those awaiting acknowledgement
+            let packet_id = (i % u16::MAX as usize) as u16 + 1;
+            let payload = serde_json::to_vec(record)?;
+            stream.write_all(&self.publish_packet(&self.topic_for(record), &payload, packet_id)).await?;
+            if self.qos == 1 {
+                let (kind, body) = read_packet(&mut stream).await?;
+                if kind != PUBACK || body != packet_id.to_be_bytes() {
+                    return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, format!("expected a PUBACK for packet {}", packet_id)));
+                }
+            }
+        }
+        stream.write_all(&[DISCONNECT << 4, 0]).await?;
+        stream.flush().await
+    }
+    fn connect_packet(&self) -> Vec<u8> {
+        let mut flags = 0x02;
+        let mut body = Vec::new();
+        put_string(&mut body, "MQTT");
+        body.push(4);
+        let flags_at = body.len();
+        body.push(0);
+        body.extend_from_slice(&KEEP_ALIVE_SECS.to_be_bytes());
+        put_string(&mut body, &self.client_id);
+        if let Some(username) = &self.username {
+            flags |= 0x80;
+            put_string(&mut body, username);
+        }
+        if let Some(password) = &self.password {
+            flags |= 0x40;
+            put_string(&mut body, password);
+        }
+        body[flags_at] = flags;
+        packet(CONNECT << 4, &body)
+    }
+    fn publish_packet(&self, topic: &str, payload: &[u8], packet_id: u16) -> Vec<u8> {
+        let mut body = Vec::with_capacity(topic.len() + payload.len() + 4);
+        put_string(&mut body, topic);
+        if self.qos > 0 {
+            body.extend_from_slice(&packet_id.to_be_bytes());
+        }
+        body.extend_from_slice(payload);
+        packet(PUBLISH << 4 | self.qos << 1 | self.retain as u8, &body)
+    }
+}
+
+// Control packet types
+const CONNECT: u8 = 1;
+const CONNACK: u8 = 2;
+const PUBLISH: u8 = 3;
+const PUBACK: u8 = 4;
+const DISCONNECT: u8 = 14;
+
+fn put_string(buf: &mut Vec<u8>, s: &str) {
+    buf.extend_from_slice(&(s.len() as u16).to_be_bytes());
+    buf.extend_from_slice(s.as_bytes());
+}
+
+// A packet with its fixed header, the body's length in 7-bit groups
+fn packet(header: u8, body: &[u8]) -> Vec<u8> {
+    let mut packet = vec![header];
+    let mut length = body.len();
+    loop {
+        let byte = (length % 128) as u8;
+        length /= 128;
+        packet.push(if length > 0 { byte | 0x80 } else { byte });
+        if length == 0 {
+            break;
+        }
+    }
+    packet.extend_from_slice(body);
+    packet
+}
+
+// The type and body of the next packet from the broker
+async fn read_packet<S: AsyncRead + Unpin>(stream: &mut S) -> std::io::Result<(u8, Vec<u8>)> {
+    let header = stream.read_u8().await?;
+    let (mut length, mut shift) = (0usize, 0);
+    loop {
+        let byte = stream.read_u8().await?;
+        length |= ((byte & 0x7f) as usize) << shift;
+        if byte & 0x80 == 0 {
+            break;
+        }
+        shift += 7;
+        if shift > 21 {
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "malformed packet length"));
+        }
+    }
+    let mut body = vec![0; length];
+    stream.read_exact(&mut body).await?;
+    Ok((header >> 4, body))
+}
+
+fn refusal(code: u8) -> &'static str {
+    match code {
+        1 => "unacceptable protocol version",
+        2 => "client id rejected",
+        3 => "server unavailable",
+        4 => "bad username or password",
+        5 => "not authorized",
+        _ => "unknown reason",
+    }
+}
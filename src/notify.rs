@@ -0,0 +1,409 @@
+/*
+ * Notifications of newly seen records, sent once a run (or a watch poll) has written them. Records
+ * are POSTed as JSON to a webhook, signed with an HMAC-SHA256 of the body when a secret is set so
+ * the receiver can check where they came from, and summed up as chat messages for Slack or Discord.
+ * An email report can also list everything that changed, not just the new records, and a desktop
+ * notification can pop up on a workstation running in watch mode.
+ */
+use hmac::{Hmac,Mac};
+use lettre::message::{Attachment,Mailbox,MultiPart,SinglePart};
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{Message,SmtpTransport,Transport};
+use serde::{Serialize,Deserialize};
+use sha2::Sha256;
+use crate::{Change,ChangeKind,Config,Dataset,Error,KafkaSink,MqttSink,ProgramType,RetryPolicy,StateCode};
+
+// Header carrying "sha256=" followed by the hex HMAC of the body
+pub const SIGNATURE_HEADER: &str = "X-Fema-Signature-256";
+
+#[derive(Serialize,Debug)]
+struct Payload<'a> {
+    dataset: &'static str,
+    count: usize,
+    records: &'a [serde_json::Value],
+}
+
+#[derive(Debug,Clone)]
+pub struct Webhook {
+    url: String,
+    secret: Option<String>,
+}
+impl Webhook {
+    // None unless a webhook_url is configured
+    pub fn new(cfg: &Config) -> Option<Self> {
+        let url = cfg.webhook_url.clone()?;
+        Some(Self { url, secret: cfg.webhook_secret.clone() })
+    }
+    pub async fn send(&self, http: &reqwest::Client, policy: &RetryPolicy, dataset: Dataset, records: &[serde_json::Value]) -> Result<(), Error> {
+        let body = serde_json::to_vec(&Payload { dataset: dataset.name(), count: records.len(), records }).map_err(std::io::Error::from)?;
+        let signature = self.secret.as_ref().map(|secret| signature(secret, &body));
+        post(http, policy, &self.url, body, signature).await?;
+        info!("Notified {} of {} new records.", self.url, records.len());
+        Ok(())
+    }
+}
+
+// The value of SIGNATURE_HEADER for a body signed with secret
+pub fn signature(secret: &str, body: &[u8]) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC takes keys of any length");
+    mac.update(body);
+    format!("sha256={:x}", mac.finalize().into_bytes())
+}
+
+#[derive(Debug,Serialize,Deserialize,Clone,Copy,PartialEq,Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum ChatKind {
+    Slack,
+    Discord,
+}
+impl ChatKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ChatKind::Slack => "Slack",
+            ChatKind::Discord => "Discord",
+        }
+    }
+}
+
+pub const DEFAULT_CHAT_TEMPLATE: &str = "New {programTypeCode} designation: {placeName}, {stateCode}, designated {designatedDate}";
+
+/*
+ * An incoming webhook of a Slack or Discord channel, sent one message listing the new records it
+ * cares about. template is filled in once per record with the record's {fieldName}s.
+ */
+#[derive(Debug,Serialize,Deserialize,Clone,PartialEq)]
+#[serde(default)]
+pub struct ChatChannel {
+    pub kind: ChatKind,
+    pub url: String,
+    pub template: String,
+    // Only tell the channel about records for these program types or states, every record if unset
+    pub program_types: Option<Vec<ProgramType>>,
+    pub states: Option<Vec<StateCode>>,
+}
+impl std::default::Default for ChatChannel {
+    fn default() -> Self {
+        Self {
+            kind: ChatKind::Slack,
+            url: String::new(),
+            template: String::from(DEFAULT_CHAT_TEMPLATE),
+            program_types: None,
+            states: None,
+        }
+    }
+}
+impl ChatChannel {
+    // Most records listed in one message, the rest are only counted so it stays under the chat's length limit
+    pub const MAX_LINES: usize = 20;
+
+    pub fn wants(&self, dataset: Dataset, record: &serde_json::Value) -> bool {
+        let value = |field: Option<&str>| field.and_then(|field| record.get(field)?.as_str());
+        matches(self.program_types.as_deref(), value(dataset.program_type_field())) && matches(self.states.as_deref(), value(Some(dataset.state_field())))
+    }
+    // The message for these records, None if there are none the channel wants
+    pub fn message(&self, dataset: Dataset, records: &[serde_json::Value]) -> Option<String> {
+        let wanted: Vec<&serde_json::Value> = records.iter().filter(|record| self.wants(dataset, record)).collect();
+        if wanted.is_empty() {
+            return None;
+        }
+        let mut lines: Vec<String> = wanted.iter().take(Self::MAX_LINES).map(|record| render(&self.template, record)).collect();
+        if wanted.len() > Self::MAX_LINES {
+            lines.push(format!("…and {} more", wanted.len() - Self::MAX_LINES));
+        }
+        Some(lines.join("\n"))
+    }
+    pub async fn send(&self, http: &reqwest::Client, policy: &RetryPolicy, dataset: Dataset, records: &[serde_json::Value]) -> Result<(), Error> {
+        let message = match self.message(dataset, records) {
+            Some(message) => message,
+            None => return Ok(()),
+        };
+        let body = match self.kind {
+            ChatKind::Slack => serde_json::json!({ "text": message }),
+            ChatKind::Discord => serde_json::json!({ "content": message }),
+        };
+        post(http, policy, &self.url, serde_json::to_vec(&body).map_err(std::io::Error::from)?, None).await?;
+        info!("Sent {} alert of new records.", self.kind.name());
+        Ok(())
+    }
+}
+
+// Whether a record's code is one of codes, any code being wanted if unset. A record without the field can't be one of them
+fn matches<T: AsRef<str>>(codes: Option<&[T]>, value: Option<&str>) -> bool {
+    match (codes, value) {
+        (Some(codes), Some(value)) => codes.iter().any(|code| code.as_ref().eq_ignore_ascii_case(value)),
+        (Some(_), None) => false,
+        (None, _) => true,
+    }
+}
+
+// Fills each {fieldName} in template with the record's value for it, leaving it empty if there's none
+pub fn render(template: &str, record: &serde_json::Value) -> String {
+    let mut rendered = String::with_capacity(template.len());
+    let mut rest = template;
+    while let (Some(open), Some(close)) = (rest.find('{'), rest.find('}')) {
+        if close < open {
+            rendered.push_str(&rest[..=close]);
+            rest = &rest[close+1..];
+            continue;
+        }
+        rendered.push_str(&rest[..open]);
+        match record.get(&rest[open+1..close]) {
+            Some(serde_json::Value::String(s)) => rendered.push_str(s),
+            Some(serde_json::Value::Null) | None => (),
+            Some(value) => rendered.push_str(&value.to_string()),
+        }
+        rest = &rest[close+1..];
+    }
+    rendered.push_str(rest);
+    rendered
+}
+
+#[derive(Debug,Serialize,Deserialize,Clone,Copy,PartialEq,Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum SmtpTls {
+    // Upgrade a plain connection, port 587 by default
+    Starttls,
+    // TLS from the start, port 465 by default
+    Tls,
+    // Plain text, port 25 by default. Only for a relay on the local network
+    None,
+}
+
+pub const DEFAULT_EMAIL_SUBJECT: &str = "{dataset}: {added} new, {changed} changed, {removed} removed";
+pub const DEFAULT_EMAIL_TEMPLATE: &str = "{stateCode} {placeName}: {programTypeCode} designated {designatedDate}";
+
+/*
+ * Summary emailed after each run (or watch poll) with new records. subject is filled in with the
+ * {dataset} and the {added}, {changed}, and {removed} counts, template once per record like a chat
+ * channel's.
+ */
+#[derive(Debug,Serialize,Deserialize,Clone,PartialEq)]
+#[serde(default)]
+pub struct EmailReport {
+    pub smtp_host: String,
+    // The usual port for the tls setting if unset
+    pub smtp_port: Option<u16>,
+    pub tls: SmtpTls,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    pub from: String,
+    pub to: Vec<String>,
+    pub subject: String,
+    pub template: String,
+    // Attach the records as CSV instead of listing them in the body
+    pub attach_csv: bool,
+    // Send the report even when nothing is new, e.g. as a daily all-clear
+    pub always: bool,
+}
+impl std::default::Default for EmailReport {
+    fn default() -> Self {
+        Self {
+            smtp_host: String::new(),
+            smtp_port: None,
+            tls: SmtpTls::Starttls,
+            username: None,
+            password: None,
+            from: String::new(),
+            to: Vec::new(),
+            subject: String::from(DEFAULT_EMAIL_SUBJECT),
+            template: String::from(DEFAULT_EMAIL_TEMPLATE),
+            attach_csv: false,
+            always: false,
+        }
+    }
+}
+impl EmailReport {
+    // Checks the settings that can be checked without connecting
+    pub fn validate(&self) -> Result<(), String> {
+        if self.smtp_host.is_empty() {
+            return Err(String::from("The email report needs an smtp_host."));
+        }
+        if self.to.is_empty() {
+            return Err(String::from("The email report needs at least one address to send to."));
+        }
+        for address in std::iter::once(&self.from).chain(&self.to) {
+            address.parse::<Mailbox>().map_err(|e| format!("Invalid email address {}: {}", address, e))?;
+        }
+        Ok(())
+    }
+    pub fn message(&self, dataset: Dataset, changes: &[Change]) -> Result<Message, Box<dyn std::error::Error+Send+Sync>> {
+        let count = |kind| changes.iter().filter(|change| change.change == kind).count();
+        let counts = serde_json::json!({
+            "dataset": dataset.name(),
+            "added": count(ChangeKind::Added),
+            "changed": count(ChangeKind::Changed),
+            "removed": count(ChangeKind::Removed),
+        });
+        let mut builder = Message::builder().from(self.from.parse()?).subject(render(&self.subject, &counts));
+        for to in &self.to {
+            builder = builder.to(to.parse()?);
+        }
+        let mut body = render("{added} new, {changed} changed, and {removed} removed {dataset} records.\n", &counts);
+        if self.attach_csv {
+            let mut writer = csv::Writer::from_writer(Vec::new());
+            crate::write_changes_csv(&mut writer, changes)?;
+            let csv = writer.into_inner().map_err(|e| e.to_string())?;
+            let attachment = Attachment::new(format!("{}-changes.csv", dataset.name())).body(csv, "text/csv".parse()?);
+            return Ok(builder.multipart(MultiPart::mixed().singlepart(SinglePart::plain(body)).singlepart(attachment))?);
+        }
+        if !changes.is_empty() {
+            body.push('\n');
+        }
+        for change in changes {
+            body.push_str(&format!("{}: {}\n", change.change.name(), render(&self.template, &change.record)));
+        }
+        Ok(builder.body(body)?)
+    }
+    /*
+     * Sends the report through the SMTP server, retrying transient failures. The transport blocks, so
+     * it's run on the blocking thread pool.
+     */
+    pub async fn send(&self, policy: &RetryPolicy, dataset: Dataset, changes: &[Change]) -> Result<(), Error> {
+        let message = self.message(dataset, changes)?;
+        let mut transport = match self.tls {
+            SmtpTls::Starttls => SmtpTransport::starttls_relay(&self.smtp_host).map_err(|e| Error::Config(e.to_string()))?,
+            SmtpTls::Tls => SmtpTransport::relay(&self.smtp_host).map_err(|e| Error::Config(e.to_string()))?,
+            SmtpTls::None => SmtpTransport::builder_dangerous(&self.smtp_host).port(25),
+        };
+        if let Some(port) = self.smtp_port {
+            transport = transport.port(port);
+        }
+        if let Some(username) = &self.username {
+            transport = transport.credentials(Credentials::new(username.clone(), self.password.clone().unwrap_or_default()));
+        }
+        let transport = std::sync::Arc::new(transport.build());
+        let message = std::sync::Arc::new(message);
+        let mut attempts = 0;
+        loop {
+            let (transport, message) = (transport.clone(), message.clone());
+            let e = match tokio::task::spawn_blocking(move || transport.send(&message)).await.map_err(|e| Error::Output(e.into()))? {
+                Ok(_) => {
+                    info!("Emailed the report to {}.", self.to.join(", "));
+                    return Ok(());
+                },
+                Err(e) => e,
+            };
+            if !e.is_transient() && !e.is_timeout() || attempts >= policy.retries {
+                return Err(Error::Email(e));
+            }
+            attempts += 1;
+            warn!("Emailing the report through {} failed ({}), retrying ({}/{}).", self.smtp_host, e, attempts, policy.retries);
+            tokio::time::delay_for(policy.backoff(attempts - 1)).await;
+        }
+    }
+}
+
+pub const DESKTOP_TEMPLATE: &str = "{placeName}, {stateCode} ({programTypeCode})";
+// Most records named in a desktop notification, which only has room for a few lines
+pub const DESKTOP_LINES: usize = 5;
+
+// Summary and body of the desktop notification for new records
+pub fn desktop_message(dataset: Dataset, records: &[serde_json::Value]) -> (String, String) {
+    let summary = format!("{} new {} record{}", records.len(), dataset.name(), if records.len() == 1 { "" } else { "s" });
+    let mut lines: Vec<String> = records.iter().take(DESKTOP_LINES).map(|record| render(DESKTOP_TEMPLATE, record)).collect();
+    if records.len() > DESKTOP_LINES {
+        lines.push(format!("…and {} more", records.len() - DESKTOP_LINES));
+    }
+    (summary, lines.join("\n"))
+}
+
+// Best effort, a workstation without a notification daemon only gets a warning. The D-Bus call blocks
+#[cfg(feature = "desktop")]
+async fn show_desktop(summary: String, body: String) {
+    let shown = tokio::task::spawn_blocking(move || {
+        notify_rust::Notification::new().appname(crate::APPNAME).summary(&summary).body(&body).show().map(|_| ()).map_err(|e| e.to_string())
+    }).await.map_err(|e| e.to_string()).and_then(|shown| shown);
+    if let Err(e) = shown {
+        warn!("Failed to show a desktop notification: {}", e);
+    }
+}
+#[cfg(not(feature = "desktop"))]
+async fn show_desktop(_summary: String, _body: String) {
+    warn!("Desktop notifications require building with the desktop feature.");
+}
+
+// Everywhere new records are announced, with the retry settings for reaching them
+#[derive(Debug,Clone)]
+pub struct Notifier {
+    webhook: Option<Webhook>,
+    chat: Vec<ChatChannel>,
+    email: Option<EmailReport>,
+    kafka: Option<KafkaSink>,
+    mqtt: Option<MqttSink>,
+    desktop: bool,
+    policy: RetryPolicy,
+    // Records written since the last notification, outside diff mode
+    pending: Vec<Change>,
+}
+impl Notifier {
+    // None if nothing is configured to be notified
+    pub fn new(cfg: &Config) -> Option<Self> {
+        let webhook = Webhook::new(cfg);
+        if webhook.is_none() && cfg.chat.is_empty() && cfg.email.is_none() && cfg.kafka.is_none() && cfg.mqtt.is_none() && !cfg.desktop_notifications {
+            return None;
+        }
+        Some(Self { webhook, chat: cfg.chat.clone(), email: cfg.email.clone(), kafka: cfg.kafka.clone(), mqtt: cfg.mqtt.clone(), desktop: cfg.desktop_notifications, policy: RetryPolicy::new(cfg), pending: Vec::new() })
+    }
+    // The webhook, chat channels, and MQTT broker hear about added records, Kafka about added and changed ones, the email report about every change
+    pub async fn notify(&self, http: &reqwest::Client, dataset: Dataset, changes: &[Change]) -> Result<(), Error> {
+        let added: Vec<serde_json::Value> = changes.iter().filter(|change| change.change == ChangeKind::Added).map(|change| change.record.clone()).collect();
+        if added.is_empty() {
+            debug!("No new records to notify of.");
+        }
+        else {
+            if let Some(webhook) = &self.webhook {
+                webhook.send(http, &self.policy, dataset, &added).await?;
+            }
+            for channel in &self.chat {
+                channel.send(http, &self.policy, dataset, &added).await?;
+            }
+            if let Some(mqtt) = &self.mqtt {
+                mqtt.publish(dataset, &added).await?;
+            }
+            if self.desktop {
+                let (summary, body) = desktop_message(dataset, &added);
+                show_desktop(summary, body).await;
+            }
+        }
+        if let Some(email) = self.email.as_ref().filter(|email| email.always || !changes.is_empty()) {
+            email.send(&self.policy, dataset, changes).await?;
+        }
+        if let Some(kafka) = &self.kafka {
+            let published = kafka.publish(dataset, changes).await?;
+            debug!("Published {} records to Kafka topic {}.", published, kafka.topic);
+        }
+        Ok(())
+    }
+    // Holds a change back to be notified of along with the rest of the poll's, see notify_pending
+    pub fn queue(&mut self, change: Change) {
+        self.pending.push(change);
+    }
+    // Notifies of the queued changes and clears them
+    pub async fn notify_pending(&mut self, http: &reqwest::Client, dataset: Dataset) -> Result<(), Error> {
+        let pending = std::mem::take(&mut self.pending);
+        self.notify(http, dataset, &pending).await
+    }
+}
+
+// POSTs a JSON body, retrying transient failures like any other request
+async fn post(http: &reqwest::Client, policy: &RetryPolicy, url: &str, body: Vec<u8>, signature: Option<String>) -> Result<(), Error> {
+    let mut attempts = 0;
+    loop {
+        let mut request = http.post(url).header(reqwest::header::CONTENT_TYPE, "application/json").body(body.clone());
+        if let Some(signature) = &signature {
+            request = request.header(SIGNATURE_HEADER, signature.as_str());
+        }
+        let e = match request.send().await.and_then(reqwest::Response::error_for_status) {
+            Ok(_) => return Ok(()),
+            Err(source) => Error::Http { url: url.to_owned(), status: source.status().map(|s| s.as_u16()), source, retry_after: None },
+        };
+        match e.transient_failure().filter(|_| attempts < policy.retries) {
+            Some(reason) => {
+                attempts += 1;
+                warn!("Notifying {} {} ({}), retrying ({}/{}).", url, reason, e, attempts, policy.retries);
+                tokio::time::delay_for(policy.backoff(attempts - 1)).await;
+            },
+            None => return Err(e),
+        }
+    }
+}
@@ -0,0 +1,728 @@
+/*
+ * Writes fetched records to the configured outputs page by page as they arrive, so memory use
+ * doesn't grow with the number of results
+ */
+use chrono::{DateTime,SubsecRound,Utc};
+use serde::Serialize;
+use std::borrow::Borrow;
+use std::collections::{BTreeMap,HashMap,HashSet};
+use std::fs::File;
+use std::io::{BufWriter,Write};
+use std::path::{Path,PathBuf};
+use flate2::write::GzEncoder;
+use fema::{Compression,Config,CsvMode,DateFormat,Enrichment,Identified,LineEnding,OutputColumn,OutputFormat,OutputTimezone,QuoteStyle,Row};
+use crate::atom::AtomFeed;
+use crate::html_output::HtmlOutput;
+use crate::manifest::Manifest;
+use crate::progress::RunState;
+use crate::summary::Summary;
+
+// Output record for the stamp_fetch_time option, serialized as the record's columns followed by fetched_at
+#[derive(Serialize,Debug)]
+struct StampedEntry<'a, T>(&'a T, FetchTime);
+#[derive(Serialize,Debug)]
+struct FetchTime {
+    fetched_at: String,
+}
+
+// The output file, compressed on the way in when the compress option is set
+enum Sink {
+    Plain(File),
+    Gzip(GzEncoder<File>),
+    Zstd(zstd::Encoder<'static, File>),
+}
+impl Sink {
+    fn create(cfg: &Config, path: &Path) -> std::io::Result<Self> {
+        let file = File::create(path)?;
+        Ok(match cfg.compress {
+            None => Sink::Plain(file),
+            Some(Compression::Gzip) => Sink::Gzip(GzEncoder::new(file, flate2::Compression::default())),
+            Some(Compression::Zstd) => Sink::Zstd(zstd::Encoder::new(file, zstd::DEFAULT_COMPRESSION_LEVEL)?),
+        })
+    }
+    // Writes out whatever the encoder still holds along with the end of the compressed stream
+    fn finish(self) -> std::io::Result<()> {
+        match self {
+            Sink::Plain(mut file) => file.flush(),
+            Sink::Gzip(encoder) => encoder.finish().map(drop),
+            Sink::Zstd(encoder) => encoder.finish().map(drop),
+        }
+    }
+}
+impl Write for Sink {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self {
+            Sink::Plain(file) => file.write(buf),
+            Sink::Gzip(encoder) => encoder.write(buf),
+            Sink::Zstd(encoder) => encoder.write(buf),
+        }
+    }
+    // Compressed streams are flushed at a block boundary, so everything written so far can be decompressed
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            Sink::Plain(file) => file.flush(),
+            Sink::Gzip(encoder) => encoder.flush(),
+            Sink::Zstd(encoder) => encoder.flush(),
+        }
+    }
+}
+
+enum Writer {
+    // With the date_format dates are written in
+    Csv(Box<csv::Writer<Sink>>, DateFormat),
+    Ndjson(BufWriter<Sink>),
+    // Whether a record has been written yet, which decides whether the next one needs a separator
+    Json(BufWriter<Sink>, bool),
+    // Like Json, with the records as the features of a FeatureCollection
+    Geojson(BufWriter<Sink>, bool),
+    #[cfg(feature = "parquet-output")]
+    Parquet(Box<crate::parquet_output::ParquetOutput>),
+    Html(HtmlOutput),
+    #[cfg(feature = "xlsx-output")]
+    Xlsx(Box<crate::xlsx_output::XlsxOutput>),
+    #[cfg(feature = "arrow-output")]
+    Arrow(crate::arrow_output::ArrowOutput),
+}
+impl Writer {
+    // now and filter are only shown in the HTML format's heading
+    fn create(cfg: &Config, path: &std::path::Path, now: DateTime<Utc>, filter: &str) -> Result<Self, Box<dyn std::error::Error+Send+Sync>> {
+        Ok(match cfg.output_format {
+            OutputFormat::Csv => Writer::Csv(Box::new(csv_writer(cfg, cfg.csv_header).from_writer(Sink::create(cfg, path)?)), cfg.date_format.clone()),
+            OutputFormat::Ndjson => Writer::Ndjson(BufWriter::new(Sink::create(cfg, path)?)),
+            OutputFormat::Json => {
+                let mut writer = BufWriter::new(Sink::create(cfg, path)?);
+                writer.write_all(b"[")?;
+                Writer::Json(writer, false)
+            },
+            OutputFormat::Geojson => {
+                let mut writer = BufWriter::new(Sink::create(cfg, path)?);
+                writer.write_all(GEOJSON_HEADER)?;
+                Writer::Geojson(writer, false)
+            },
+            #[cfg(feature = "parquet-output")]
+            OutputFormat::Parquet => Writer::Parquet(Box::new(crate::parquet_output::ParquetOutput::create(cfg, path)?)),
+            #[cfg(not(feature = "parquet-output"))]
+            OutputFormat::Parquet => return Err("Parquet output requires building with the parquet-output feature.".into()),
+            OutputFormat::Html => Writer::Html(HtmlOutput::create(cfg, path, now, filter)?),
+            #[cfg(feature = "xlsx-output")]
+            OutputFormat::Xlsx => Writer::Xlsx(Box::new(crate::xlsx_output::XlsxOutput::create(cfg, path)?)),
+            #[cfg(not(feature = "xlsx-output"))]
+            OutputFormat::Xlsx => return Err("XLSX output requires building with the xlsx-output feature.".into()),
+            #[cfg(feature = "arrow-output")]
+            OutputFormat::Arrow => Writer::Arrow(crate::arrow_output::ArrowOutput::create(path)?),
+            #[cfg(not(feature = "arrow-output"))]
+            OutputFormat::Arrow => return Err("Arrow output requires building with the arrow-output feature.".into()),
+        })
+    }
+    // Reopens a file written by an interrupted run, dropping anything after its last complete page
+    fn append(cfg: &Config, path: &Path, bytes: u64, rows: usize) -> Result<Self, Box<dyn std::error::Error+Send+Sync>> {
+        let file = std::fs::OpenOptions::new().write(true).open(path).map_err(|e| format!("Failed to reopen output file {}: {}", path.display(), e))?;
+        file.set_len(bytes)?;
+        let file = std::fs::OpenOptions::new().append(true).open(path)?;
+        Ok(match cfg.output_format {
+            OutputFormat::Csv => Writer::Csv(Box::new(csv_writer(cfg, cfg.csv_header && bytes == 0).from_writer(Sink::Plain(file))), cfg.date_format.clone()),
+            OutputFormat::Ndjson => Writer::Ndjson(BufWriter::new(Sink::Plain(file))),
+            OutputFormat::Json if bytes == 0 => {
+                let mut writer = BufWriter::new(Sink::Plain(file));
+                writer.write_all(b"[")?;
+                Writer::Json(writer, false)
+            },
+            // Anything past the opening bracket is a complete record
+            OutputFormat::Json => Writer::Json(BufWriter::new(Sink::Plain(file)), bytes > 1),
+            OutputFormat::Geojson if bytes == 0 => {
+                let mut writer = BufWriter::new(Sink::Plain(file));
+                writer.write_all(GEOJSON_HEADER)?;
+                Writer::Geojson(writer, false)
+            },
+            OutputFormat::Geojson => Writer::Geojson(BufWriter::new(Sink::Plain(file)), bytes > GEOJSON_HEADER.len() as u64),
+            OutputFormat::Parquet => return Err("Parquet output can't be resumed, its footer is only written at the end.".into()),
+            OutputFormat::Html => Writer::Html(HtmlOutput::append(file, rows)),
+            OutputFormat::Xlsx => return Err("XLSX output can't be resumed, the workbook is only saved at the end.".into()),
+            OutputFormat::Arrow => return Err("Arrow output can't be resumed, its footer is only written at the end.".into()),
+        })
+    }
+    fn serialize<T: Serialize>(&mut self, records: &[T], fetched_at: Option<&str>) -> Result<(), Box<dyn std::error::Error+Send+Sync>> {
+        #[cfg(feature = "parquet-output")]
+        {
+            if let Writer::Parquet(writer) = self {
+                let records = records.iter().map(|r| json_record(r, fetched_at)).collect::<Result<Vec<_>, _>>()?;
+                return writer.write(&records);
+            }
+        }
+        #[cfg(feature = "arrow-output")]
+        {
+            if let Writer::Arrow(writer) = self {
+                let records = records.iter().map(|r| json_record(r, fetched_at)).collect::<Result<Vec<_>, _>>()?;
+                return writer.write(&records);
+            }
+        }
+        for record in records {
+            self.serialize_one(record, fetched_at)?;
+        }
+        Ok(())
+    }
+    fn serialize_one<T: Serialize>(&mut self, record: &T, fetched_at: Option<&str>) -> Result<(), Box<dyn std::error::Error+Send+Sync>> {
+        match self {
+            Writer::Csv(writer, dates) => dates.scope(|| match fetched_at {
+                Some(fetched_at) => writer.serialize(StampedEntry(record, FetchTime { fetched_at: cell(dates, &serde_json::Value::from(fetched_at)) })),
+                None => writer.serialize(record),
+            })?,
+            Writer::Ndjson(writer) => {
+                serde_json::to_writer(&mut *writer, &json_record(record, fetched_at)?)?;
+                writer.write_all(b"\n")?;
+            },
+            Writer::Json(writer, started) => {
+                writer.write_all(if *started { b",\n" } else { b"\n" })?;
+                serde_json::to_writer_pretty(&mut *writer, &json_record(record, fetched_at)?)?;
+                *started = true;
+            },
+            Writer::Geojson(writer, started) => {
+                writer.write_all(if *started { b",\n" } else { b"\n" })?;
+                serde_json::to_writer(&mut *writer, &feature(json_record(record, fetched_at)?))?;
+                *started = true;
+            },
+            #[cfg(feature = "parquet-output")]
+            Writer::Parquet(_) => unreachable!("parquet records are written a page at a time"),
+            #[cfg(feature = "arrow-output")]
+            Writer::Arrow(_) => unreachable!("arrow records are written a page at a time"),
+            Writer::Html(writer) => writer.write(record, fetched_at)?,
+            #[cfg(feature = "xlsx-output")]
+            Writer::Xlsx(writer) => writer.write(record, fetched_at)?,
+        }
+        Ok(())
+    }
+    // The csv crate can't serialize a map, so the columns of selected fields are written by hand. Rows are cut down to columns first if given
+    fn serialize_rows<R: Borrow<Row> + Serialize>(&mut self, rows: &[R], fetched_at: Option<&str>, header: bool, columns: Option<&[OutputColumn]>) -> Result<(), Box<dyn std::error::Error+Send+Sync>> {
+        if let Some(columns) = columns {
+            let rows = rows.iter().map(|row| pick_columns(row.borrow(), columns)).collect::<Vec<_>>();
+            return self.serialize_rows(&rows, fetched_at, header, None);
+        }
+        let (writer, dates) = match self {
+            Writer::Csv(writer, dates) => (writer, dates),
+            _ => return self.serialize(rows, fetched_at),
+        };
+        if let (true, Some(row)) = (header, rows.first()) {
+            writer.write_record(row.borrow().names().chain(fetched_at.map(|_| "fetched_at")))?;
+        }
+        for row in rows {
+            let cells = row.borrow().0.iter().map(|(_, value)| cell(dates, value));
+            writer.write_record(cells.chain(fetched_at.map(|fetched_at| cell(dates, &serde_json::Value::from(fetched_at)))))?;
+        }
+        Ok(())
+    }
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            Writer::Csv(writer, _) => writer.flush(),
+            Writer::Ndjson(writer) | Writer::Json(writer, _) | Writer::Geojson(writer, _) => writer.flush(),
+            // Row groups are written out once they fill up, flushing every page would make them tiny
+            #[cfg(feature = "parquet-output")]
+            Writer::Parquet(_) => Ok(()),
+            // Each page is already written out as a record batch
+            #[cfg(feature = "arrow-output")]
+            Writer::Arrow(_) => Ok(()),
+            Writer::Html(writer) => writer.flush(),
+            // Nothing reaches the disk before the workbook is saved
+            #[cfg(feature = "xlsx-output")]
+            Writer::Xlsx(_) => Ok(()),
+        }
+    }
+    // Closes the JSON array or feature collection, HTML page, parquet or arrow footer, or compressed stream, or saves the workbook, and flushes everything to disk
+    fn finish(self) -> Result<(), Box<dyn std::error::Error+Send+Sync>> {
+        match self {
+            Writer::Csv(writer, _) => writer.into_inner().map_err(|e| e.into_error())?.finish()?,
+            Writer::Ndjson(writer) => writer.into_inner().map_err(|e| e.into_error())?.finish()?,
+            Writer::Json(mut writer, _) => {
+                writer.write_all(b"\n]\n")?;
+                writer.into_inner().map_err(|e| e.into_error())?.finish()?;
+            },
+            Writer::Geojson(mut writer, _) => {
+                writer.write_all(b"\n]}\n")?;
+                writer.into_inner().map_err(|e| e.into_error())?.finish()?;
+            },
+            #[cfg(feature = "parquet-output")]
+            Writer::Parquet(writer) => writer.finish()?,
+            Writer::Html(writer) => writer.finish()?,
+            #[cfg(feature = "xlsx-output")]
+            Writer::Xlsx(writer) => writer.finish()?,
+            #[cfg(feature = "arrow-output")]
+            Writer::Arrow(writer) => writer.finish()?,
+        }
+        Ok(())
+    }
+}
+
+/*
+ * The ids of the records in a csv file being appended to, when it already has bytes of them, found
+ * by the header of the column the ids are written in. None unless appending
+ */
+fn appended_ids(cfg: &Config, path: &Path, bytes: u64) -> Result<Option<HashSet<String>>, Box<dyn std::error::Error+Send+Sync>> {
+    match (cfg.csv_mode, bytes) {
+        (CsvMode::Overwrite, _) => return Ok(None),
+        (CsvMode::Append, 0) => return Ok(Some(HashSet::new())),
+        _ => (),
+    }
+    let mut reader = csv::ReaderBuilder::new().delimiter(cfg.csv_delimiter as u8).from_path(path)
+        .map_err(|e| format!("Failed to read output file {} to append to: {}", path.display(), e))?;
+    let column = cfg.output_columns.iter().flatten().find(|column| column.field == "id").map_or("id", |column| column.name.as_str());
+    let index = reader.headers()?.iter().position(|name| name == column)
+        .ok_or_else(|| fema::Error::Config(format!("Output file {} has no {} column to tell which records it already has, it can't be appended to.", path.display(), column)))?;
+    reader.records().map(|record| Ok(record?.get(index).unwrap_or_default().to_owned())).collect::<Result<_, csv::Error>>().map(Some).map_err(Into::into)
+}
+
+// The first line of a file, with its line ending
+fn first_line(path: &Path) -> std::io::Result<Vec<u8>> {
+    let mut line = Vec::new();
+    std::io::BufRead::read_until(&mut std::io::BufReader::new(File::open(path)?), b'\n', &mut line)?;
+    Ok(line)
+}
+
+// A writer of the configured csv dialect
+fn csv_writer(cfg: &Config, header: bool) -> csv::WriterBuilder {
+    let mut builder = csv::WriterBuilder::new();
+    builder.delimiter(cfg.csv_delimiter as u8).has_headers(header)
+        .quote_style(match cfg.csv_quote_style {
+            QuoteStyle::Necessary => csv::QuoteStyle::Necessary,
+            QuoteStyle::Always => csv::QuoteStyle::Always,
+            QuoteStyle::NonNumeric => csv::QuoteStyle::NonNumeric,
+            QuoteStyle::Never => csv::QuoteStyle::Never,
+        })
+        .terminator(match cfg.csv_line_ending {
+            LineEnding::Lf => csv::Terminator::Any(b'\n'),
+            LineEnding::Crlf => csv::Terminator::CRLF,
+        });
+    builder
+}
+
+// A field's value as a CSV cell, with dates in the date format
+fn cell(dates: &DateFormat, value: &serde_json::Value) -> String {
+    match value {
+        serde_json::Value::String(s) => match dates.convert(s) {
+            Some(serde_json::Value::String(date)) => date,
+            Some(date) => date.to_string(),
+            None => s.clone(),
+        },
+        serde_json::Value::Null => String::new(),
+        value => value.to_string(),
+    }
+}
+
+const GEOJSON_HEADER: &[u8] = b"{\"type\":\"FeatureCollection\",\"features\":[";
+
+// A record as a GeoJSON feature with its fields as properties, placed at its county's centroid if it's known
+fn feature(record: serde_json::Value) -> serde_json::Value {
+    let geometry = match fema::place_centroid(&record) {
+        Some((lat, lon)) => serde_json::json!({ "type": "Point", "coordinates": [lon, lat] }),
+        None => serde_json::Value::Null,
+    };
+    let mut feature = serde_json::json!({ "type": "Feature", "geometry": geometry, "properties": record });
+    if let Some(id) = feature["properties"].get("id").cloned() {
+        feature["id"] = id;
+    }
+    feature
+}
+
+// The per-state, program type and month counts, when they're wanted
+fn summary(cfg: &Config) -> Option<Summary> {
+    Some(Summary::new(cfg.dataset)).filter(|_| cfg.summarize || cfg.summary.is_some())
+}
+
+// JSON formats get fetched_at as an extra field of the record rather than a trailing column
+fn json_record<T: Serialize>(record: &T, fetched_at: Option<&str>) -> serde_json::Result<serde_json::Value> {
+    let mut value = serde_json::to_value(record)?;
+    if let (Some(fetched_at), serde_json::Value::Object(fields)) = (fetched_at, &mut value) {
+        fields.insert(String::from("fetched_at"), serde_json::Value::from(fetched_at));
+    }
+    Ok(value)
+}
+
+struct FileOutput {
+    writer: Writer,
+    path: PathBuf,
+    // Set when the final path depends on the number of rows, which is only known at the end
+    template: Option<String>,
+    // Value of the split_by field the file holds the records for
+    split: Option<String>,
+    rows: usize,
+    // Whether the header row is still to be written along with the first rows
+    header: bool,
+    // Ids of the records the file already has when appending to it, and how many records were left out for that
+    existing: Option<HashSet<String>>,
+    skipped: usize,
+    // The header line of the file being appended to, checked against the columns and dialect of the first rows written to it
+    appended_header: Option<Vec<u8>>,
+}
+impl FileOutput {
+    // Opens the output file at the start of a run, or the first time a split_by value turns up. None if there's no file output
+    fn open(cfg: &Config, now: DateTime<Utc>, filter: &str, split: Option<&str>) -> Result<Option<Self>, Box<dyn std::error::Error+Send+Sync>> {
+        let (path, template) = match (cfg.path_template(), &cfg.csv) {
+            (Some(template), _) if template.contains("{count}") => {
+                let path = compressed(cfg, PathBuf::from(fema::expand_template(&template, &values(cfg, now, 0, split))?));
+                (with_suffix(path, "partial"), Some(template))
+            },
+            (Some(template), _) => (compressed(cfg, PathBuf::from(fema::expand_template(&template, &values(cfg, now, 0, split))?)), None),
+            // out.csv split on state becomes out/TX.csv, out/LA.csv, ...
+            (None, Some(path)) => match split {
+                Some(value) => (compressed(cfg, path.with_extension("").join(format!("{}.{}", value, cfg.output_format.ext()))), None),
+                None => (compressed(cfg, path.clone()), None),
+            },
+            (None, None) => return Ok(None),
+        };
+        // Templated and split paths may point into directories that don't exist yet, e.g. archive/{date}/out.csv
+        if let (true, Some(parent)) = (split.is_some() || cfg.path_template().is_some(), path.parent()) {
+            std::fs::create_dir_all(parent)?;
+        }
+        let bytes = match cfg.csv_mode {
+            CsvMode::Append => std::fs::metadata(&path).map_or(0, |metadata| metadata.len()),
+            CsvMode::Overwrite => 0,
+        };
+        let writer = if bytes > 0 { Writer::append(cfg, &path, bytes, 0)? } else { Writer::create(cfg, &path, now, filter)? };
+        let existing = appended_ids(cfg, &path, bytes)?;
+        let appended_header = existing.as_ref().filter(|_| bytes > 0).map(|_| first_line(&path)).transpose()?;
+        Ok(Some(Self { writer, path, template, split: split.map(String::from), rows: 0, header: cfg.csv_header && bytes == 0, existing, skipped: 0, appended_header }))
+    }
+    // Writes rows to the file, leaving out those it already has when appending to it
+    fn write_rows<R: Borrow<Row>>(&mut self, cfg: &Config, rows: &[R], fetched_at: Option<&str>, columns: Option<&[OutputColumn]>) -> Result<(), Box<dyn std::error::Error+Send+Sync>> {
+        if let (Some(found), Some(row)) = (&self.appended_header, rows.first()) {
+            let names: Vec<&str> = match columns {
+                Some(columns) => columns.iter().map(|column| column.name.as_str()).collect(),
+                None => row.borrow().names().collect(),
+            };
+            let mut expected = csv_writer(cfg, false).from_writer(Vec::new());
+            expected.write_record(names.into_iter().chain(fetched_at.map(|_| "fetched_at")))?;
+            let expected = expected.into_inner().map_err(|e| e.into_error())?;
+            if expected != *found {
+                return Err(Box::new(fema::Error::Config(format!("Output file {} starts with the header {:?} rather than the {:?} of the columns and csv dialect configured, it can't be appended to.",
+                    self.path.display(), String::from_utf8_lossy(found), String::from_utf8_lossy(&expected)))));
+            }
+            self.appended_header = None;
+        }
+        let (existing, given) = (&mut self.existing, rows.len());
+        let rows: Vec<&Row> = rows.iter().map(Borrow::borrow).filter(|row| match (existing.as_mut(), row.id()) {
+            (Some(ids), Some(id)) => ids.insert(id.to_owned()),
+            _ => true,
+        }).collect();
+        self.skipped += given - rows.len();
+        self.writer.serialize_rows(&rows, fetched_at, self.header, columns)?;
+        self.writer.flush()?;
+        self.header &= rows.is_empty();
+        self.rows += rows.len();
+        Ok(())
+    }
+    // Finishes writing the file and gives it its final name, returning that
+    fn finish(mut self, cfg: &Config, now: DateTime<Utc>) -> Result<(PathBuf, usize), Box<dyn std::error::Error+Send+Sync>> {
+        self.writer.finish()?;
+        if let Some(template) = &self.template {
+            let path = compressed(cfg, PathBuf::from(fema::expand_template(template, &values(cfg, now, self.rows, self.split.as_deref()))?));
+            std::fs::rename(&self.path, &path)?;
+            self.path = path;
+        }
+        Ok((self.path, self.rows))
+    }
+}
+
+pub struct Output<'a> {
+    cfg: &'a Config,
+    now: DateTime<Utc>,
+    filter: String,
+    file: Option<FileOutput>,
+    // Files by split_by value when the output is split, opened as the values turn up
+    split: BTreeMap<String,FileOutput>,
+    rows: usize,
+    expanded: HashMap<String,Vec<serde_json::Value>>,
+    atom: Option<AtomFeed>,
+    enrichment: Option<Enrichment>,
+    summary: Option<Summary>,
+}
+impl<'a> Output<'a> {
+    pub fn new(cfg: &'a Config, now: DateTime<Utc>, filter: &str) -> Result<Self, Box<dyn std::error::Error+Send+Sync>> {
+        // Only the counts are written when summarizing instead
+        let file = if cfg.split_by.is_some() || cfg.summarize { None } else { FileOutput::open(cfg, now, filter, None)? };
+        let atom = cfg.atom_feed.as_ref().map(|path| AtomFeed::create(cfg, path, now)).transpose()?;
+        Ok(Self { cfg, now, filter: filter.to_owned(), file, split: BTreeMap::new(), rows: 0, expanded: HashMap::new(), atom, enrichment: None, summary: summary(cfg) })
+    }
+    // Continues the output of an interrupted run where its last complete page left off
+    pub fn resume(cfg: &'a Config, state: &RunState) -> Result<Self, Box<dyn std::error::Error+Send+Sync>> {
+        if cfg.split_by.is_some() {
+            return Err("Output split with split_by can't be resumed.".into());
+        }
+        if cfg.compress.is_some() {
+            return Err("Compressed output can't be resumed.".into());
+        }
+        if cfg.summarize || cfg.summary.is_some() {
+            return Err("A summary can't be resumed, the earlier pages' counts aren't kept.".into());
+        }
+        let file = match &state.output {
+            Some(path) => Some(FileOutput {
+                writer: Writer::append(cfg, path, state.bytes, state.rows)?,
+                path: path.clone(),
+                template: cfg.path_template().filter(|t| t.contains("{count}")),
+                split: None,
+                rows: state.rows,
+                header: cfg.csv_header && state.bytes == 0,
+                existing: appended_ids(cfg, path, state.bytes)?,
+                skipped: 0,
+                appended_header: Some(path).filter(|_| cfg.csv_mode == CsvMode::Append && state.bytes > 0).map(|path| first_line(path)).transpose()?,
+            }),
+            None if state.pages == 0 => return Self::new(cfg, state.started, &state.filter),
+            None => None,
+        };
+        if cfg.expand_output.is_some() {
+            warn!("Expanded entities from before the run was interrupted aren't kept, only those from the remaining pages will be written.");
+        }
+        if cfg.atom_feed.is_some() {
+            warn!("The Atom feed is started over, only records from the remaining pages will be in it.");
+        }
+        let atom = cfg.atom_feed.as_ref().map(|path| AtomFeed::create(cfg, path, state.started)).transpose()?;
+        Ok(Self { cfg, now: state.started, filter: state.filter.clone(), file, split: BTreeMap::new(), rows: state.rows, expanded: HashMap::new(), atom, enrichment: None, summary: summary(cfg) })
+    }
+    pub fn rows(&self) -> usize {
+        self.rows
+    }
+    // Disaster fields to add to each record from here on
+    pub fn set_enrichment(&mut self, enrichment: Enrichment) {
+        self.enrichment = Some(enrichment);
+    }
+    // The output file and how much has been written to it, once everything written so far is flushed. None when split
+    pub fn position(&self) -> std::io::Result<Option<(&Path, u64)>> {
+        match &self.file {
+            Some(file) => Ok(Some((&file.path, std::fs::metadata(&file.path)?.len()))),
+            None => Ok(None),
+        }
+    }
+    pub fn write<T: Serialize>(&mut self, records: &[T], expanded: HashMap<String,Vec<serde_json::Value>>) -> Result<(), Box<dyn std::error::Error+Send+Sync>> {
+        // The decoded place and enrichment columns are added to the records' fields, which rows keep in order, as are converted timestamps and output columns,
+        // and rows are what's matched against the ids already in a file being appended to
+        if self.cfg.decode_places || self.enrichment.is_some() || self.cfg.output_timezone.is_some() || self.cfg.output_columns.is_some() || self.cfg.csv_mode == CsvMode::Append {
+            let rows = records.iter().map(|record| serde_json::from_slice(&serde_json::to_vec(record)?)).collect::<serde_json::Result<Vec<Row>>>()?;
+            return self.write_rows(&rows, expanded);
+        }
+        let fetched_at = fetched_at(self.cfg);
+        let fetched_at = fetched_at.as_deref();
+        if let Some(field) = &self.cfg.split_by {
+            for (value, group) in group_by(records, field)? {
+                if let Some(file) = self.split_file(&value)? {
+                    file.writer.serialize(&group, fetched_at)?;
+                    file.writer.flush()?;
+                    file.rows += group.len();
+                }
+            }
+        }
+        else if let Some(file) = &mut self.file {
+            file.writer.serialize(records, fetched_at)?;
+            file.writer.flush()?;
+            file.rows += records.len();
+        }
+        self.write_atom(records)?;
+        if let Some(summary) = &mut self.summary {
+            summary.add(records)?;
+        }
+        self.rows += records.len();
+        self.keep_expanded(expanded);
+        Ok(())
+    }
+    // Like write, for records made up of the fields chosen with the select option
+    pub fn write_rows(&mut self, rows: &[Row], expanded: HashMap<String,Vec<serde_json::Value>>) -> Result<(), Box<dyn std::error::Error+Send+Sync>> {
+        let decoded: Vec<Row>;
+        let rows = if self.cfg.decode_places || self.enrichment.is_some() || self.cfg.output_timezone.is_some() {
+            decoded = rows.iter().map(|row| {
+                let mut row = if self.cfg.decode_places { decode_place(row)? } else { row.clone() };
+                if let Some(enrichment) = &self.enrichment {
+                    enrichment.apply(&mut row);
+                }
+                if let Some(timezone) = self.cfg.output_timezone {
+                    row = localize(row, timezone);
+                }
+                Ok(row)
+            }).collect::<serde_json::Result<_>>()?;
+            &decoded
+        }
+        else {
+            rows
+        };
+        let fetched_at = fetched_at(self.cfg);
+        let fetched_at = fetched_at.as_deref();
+        let cfg = self.cfg;
+        let columns = cfg.output_columns.as_deref();
+        if let Some(field) = &cfg.split_by {
+            for (value, group) in group_by(rows, field)? {
+                if let Some(file) = self.split_file(&value)? {
+                    file.write_rows(cfg, &group, fetched_at, columns)?;
+                }
+            }
+        }
+        else if let Some(file) = &mut self.file {
+            file.write_rows(cfg, rows, fetched_at, columns)?;
+        }
+        self.write_atom(rows)?;
+        if let Some(summary) = &mut self.summary {
+            summary.add(rows)?;
+        }
+        self.rows += rows.len();
+        self.keep_expanded(expanded);
+        Ok(())
+    }
+    fn split_file(&mut self, value: &str) -> Result<Option<&mut FileOutput>, Box<dyn std::error::Error+Send+Sync>> {
+        if !self.split.contains_key(value) {
+            match FileOutput::open(self.cfg, self.now, &self.filter, Some(value))? {
+                Some(file) => { self.split.insert(value.to_owned(), file); },
+                None => return Ok(None),
+            }
+        }
+        Ok(self.split.get_mut(value))
+    }
+    fn write_atom<T: Serialize>(&mut self, records: &[T]) -> Result<(), Box<dyn std::error::Error+Send+Sync>> {
+        if let Some(atom) = &mut self.atom {
+            atom.write(&records.iter().map(|record| json_record(record, None)).collect::<Result<Vec<_>, _>>()?)?;
+        }
+        Ok(())
+    }
+    // Expanded entities are written all together at the end
+    pub fn keep_expanded(&mut self, expanded: HashMap<String,Vec<serde_json::Value>>) {
+        for (name, values) in expanded {
+            self.expanded.entry(name).or_default().extend(values);
+        }
+    }
+    // Returns the manifest of the files written, whether or not it's written out itself
+    pub fn finish(self) -> Result<Manifest, Box<dyn std::error::Error+Send+Sync>> {
+        info!("Number of results collected: {}", self.rows);
+        let mut manifest = Manifest::new(self.cfg.manifest_checksums);
+        let split = self.split.len();
+        let skipped: usize = self.file.iter().chain(self.split.values()).map(|file| file.skipped).sum();
+        if skipped > 0 {
+            info!("{} results were left out since the output file already has them.", skipped);
+        }
+        if let Some((lookups, hits)) = self.enrichment.as_ref().map(Enrichment::lookups).filter(|(lookups, _)| *lookups > 0) {
+            info!("Enriched {} of {} results ({:.1}%), the rest have no declaration summary.", hits, lookups, hits as f64 * 100.0 / lookups as f64);
+        }
+        for file in self.file.into_iter().chain(self.split.into_values()) {
+            let (path, rows) = file.finish(self.cfg, self.now)?;
+            manifest.add(&path, self.cfg.output_format.name(), rows)?;
+            if split == 0 {
+                info!("Entries written to file {}.", path.to_str().unwrap());
+            }
+        }
+        if split > 0 {
+            info!("Entries written to {} files split by {}.", split, self.cfg.split_by.as_deref().unwrap_or_default());
+        }
+        if let (Some(atom), Some(path)) = (self.atom, &self.cfg.atom_feed) {
+            let entries = atom.entries();
+            atom.finish()?;
+            manifest.add(path, "atom", entries)?;
+            info!("Atom feed written to file {}.", path.to_str().unwrap());
+        }
+        if let Some(summary) = &self.summary {
+            if let Some(path) = &self.cfg.summary {
+                summary.write_csv(path)?;
+                manifest.add(path, "csv", summary.len())?;
+                info!("Summary written to file {}.", path.to_str().unwrap());
+            }
+            summary.print(&mut std::io::stdout().lock())?;
+        }
+        if let Some(path) = &self.cfg.expand_output {
+            serde_json::to_writer_pretty(File::create(path)?, &self.expanded)?;
+            manifest.add(path, "json", self.expanded.values().map(Vec::len).sum())?;
+            info!("Expanded entities written to file {}.", path.to_str().unwrap());
+        }
+        else if !self.expanded.is_empty() {
+            warn!("Response contained expanded entities but no expand_output is configured.");
+        }
+        if let Some(path) = &self.cfg.manifest {
+            manifest.write(path)?;
+            info!("Manifest written to file {}.", path.to_str().unwrap());
+        }
+        if !self.cfg.has_output() {
+            warn!("Fetched {} records but no output is configured.", self.rows);
+        }
+        Ok(manifest)
+    }
+    /*
+     * Flushes and closes the output file of an interrupted run as it stands, without renaming it or
+     * writing the manifest, so --resume finds it where the progress file says it is
+     */
+    pub fn close(self) -> Result<(), Box<dyn std::error::Error+Send+Sync>> {
+        if let Some(file) = self.file {
+            file.writer.finish()?;
+            info!("{} results written to file {} before the run was interrupted.", self.rows, file.path.to_str().unwrap());
+        }
+        for file in self.split.into_values() {
+            file.writer.finish()?;
+        }
+        if let Some(atom) = self.atom {
+            atom.finish()?;
+        }
+        Ok(())
+    }
+}
+
+// The row followed by the county_fips, county_name, and is_statewide columns decoded from its place code
+fn decode_place(row: &Row) -> serde_json::Result<Row> {
+    let place = fema::decode_place(&serde_json::to_value(row)?);
+    let mut row = row.clone();
+    row.0.push((String::from("county_fips"), serde_json::Value::from(place.county_fips)));
+    row.0.push((String::from("county_name"), serde_json::Value::from(place.county_name)));
+    row.0.push((String::from("is_statewide"), serde_json::Value::from(place.is_statewide)));
+    Ok(row)
+}
+
+// The row's fields named in columns, in their order and under their new names. Fields the row doesn't have are null
+fn pick_columns(row: &Row, columns: &[OutputColumn]) -> Row {
+    Row(columns.iter().map(|column| (column.name.clone(), row.get(&column.field).cloned().unwrap_or(serde_json::Value::Null))).collect())
+}
+
+// The row with its timestamps, any field holding an RFC 3339 time, written in the output timezone
+fn localize(row: Row, timezone: OutputTimezone) -> Row {
+    Row(row.0.into_iter().map(|(name, value)| match value.as_str().and_then(|value| timezone.convert(value)) {
+        Some(time) => (name, serde_json::Value::from(time)),
+        None => (name, value),
+    }).collect())
+}
+
+// The time to stamp the records of a page with if stamp_fetch_time is set, in the output timezone
+fn fetched_at(cfg: &Config) -> Option<String> {
+    let now = Utc::now().trunc_subsecs(0);
+    Some(cfg.output_timezone.unwrap_or(OutputTimezone::Utc).format(now)).filter(|_| cfg.stamp_fetch_time)
+}
+
+// Groups records by their value of field, in the order the values first turn up
+fn group_by<'r, T: Serialize>(records: &'r [T], field: &str) -> serde_json::Result<Vec<(String, Vec<&'r T>)>> {
+    let mut groups: Vec<(String, Vec<&T>)> = Vec::new();
+    for record in records {
+        let value = split_value(serde_json::to_value(record)?.get(field));
+        match groups.iter_mut().find(|(group, _)| *group == value) {
+            Some((_, group)) => group.push(record),
+            None => groups.push((value, vec![record])),
+        }
+    }
+    Ok(groups)
+}
+
+// The value as it appears in a file name, with anything that would reach outside the directory replaced
+fn split_value(value: Option<&serde_json::Value>) -> String {
+    let value = match value {
+        Some(serde_json::Value::String(s)) if !s.is_empty() => s.clone(),
+        Some(serde_json::Value::Null) | Some(serde_json::Value::String(_)) | None => String::from("none"),
+        Some(value) => value.to_string(),
+    };
+    value.replace(['/', '\\'], "_").replace("..", "_")
+}
+
+// The output path with the compression's extension added, unless it's already there
+fn compressed(cfg: &Config, path: PathBuf) -> PathBuf {
+    match cfg.compress {
+        Some(compress) if path.extension().and_then(|e| e.to_str()) != Some(compress.ext()) => with_suffix(path, compress.ext()),
+        _ => path,
+    }
+}
+
+fn with_suffix(path: PathBuf, suffix: &str) -> PathBuf {
+    let mut path = path.into_os_string();
+    path.push(".");
+    path.push(suffix);
+    PathBuf::from(path)
+}
+
+fn values<'a>(cfg: &'a Config, now: DateTime<Utc>, count: usize, state: Option<&'a str>) -> fema::TemplateValues<'a> {
+    fema::TemplateValues {
+        now, count, state, ext: cfg.output_format.ext(), dataset: cfg.dataset, years: cfg.num_years_previous, states: cfg.states.as_deref(),
+    }
+}
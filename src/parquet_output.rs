@@ -0,0 +1,61 @@
+/*
+ * Parquet output. The schema is inferred from the JSON form of the first page of records, with
+ * string columns holding RFC 3339 dates stored as UTC timestamps, so the column types follow
+ * whichever dataset is being fetched.
+ */
+use arrow_json::reader::ReaderBuilder;
+use arrow_schema::{Schema,SchemaRef};
+use parquet::arrow::ArrowWriter;
+use parquet::basic::{Compression,GzipLevel,ZstdLevel};
+use parquet::file::properties::WriterProperties;
+use std::fs::File;
+use std::sync::Arc;
+use fema::{Config,ParquetCompression};
+
+pub struct ParquetOutput {
+    file: Option<File>,
+    props: WriterProperties,
+    // Created along with the schema once the first non-empty page arrives
+    writer: Option<(ArrowWriter<File>, SchemaRef)>,
+}
+impl ParquetOutput {
+    pub fn create(cfg: &Config, path: &std::path::Path) -> Result<Self, Box<dyn std::error::Error+Send+Sync>> {
+        let compression = match cfg.parquet_compression {
+            ParquetCompression::Uncompressed => Compression::UNCOMPRESSED,
+            ParquetCompression::Snappy => Compression::SNAPPY,
+            ParquetCompression::Gzip => Compression::GZIP(GzipLevel::default()),
+            ParquetCompression::Zstd => Compression::ZSTD(ZstdLevel::default()),
+        };
+        let props = WriterProperties::builder()
+            .set_compression(compression)
+            .set_max_row_group_size(cfg.parquet_row_group_size.max(1))
+            .build();
+        Ok(Self { file: Some(File::create(path)?), props, writer: None })
+    }
+    pub fn write(&mut self, records: &[serde_json::Value]) -> Result<(), Box<dyn std::error::Error+Send+Sync>> {
+        if records.is_empty() {
+            return Ok(());
+        }
+        if self.writer.is_none() {
+            let schema = Arc::new(crate::schema::infer_schema(records)?);
+            let file = self.file.take().ok_or("Parquet file is already closed.")?;
+            self.writer = Some((ArrowWriter::try_new(file, schema.clone(), Some(self.props.clone()))?, schema));
+        }
+        let (writer, schema) = self.writer.as_mut().ok_or("Parquet file is already closed.")?;
+        let mut decoder = ReaderBuilder::new(schema.clone()).build_decoder()?;
+        decoder.serialize(records)?;
+        if let Some(batch) = decoder.flush()? {
+            writer.write(&batch)?;
+        }
+        Ok(())
+    }
+    pub fn finish(mut self) -> Result<(), Box<dyn std::error::Error+Send+Sync>> {
+        let writer = match self.writer.take() {
+            Some((writer, _)) => writer,
+            // No records at all, still leave a valid file with no columns behind
+            None => ArrowWriter::try_new(self.file.take().ok_or("Parquet file is already closed.")?, Arc::new(Schema::empty()), Some(self.props.clone()))?,
+        };
+        writer.close()?;
+        Ok(())
+    }
+}
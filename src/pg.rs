@@ -0,0 +1,98 @@
+/*
+ * PostgreSQL table of fetched entries with one row per id, written a page at a time as multi-row
+ * upserts in one transaction. The blocking client runs its own small runtime for the connection.
+ */
+use std::collections::HashMap;
+use postgres::{Client,NoTls};
+use postgres::types::ToSql;
+use fema::Entry;
+use crate::sink::{Batch,OutputSink};
+
+// Columns written for each entry
+const COLUMNS: usize = 13;
+// Entries upserted by each statement, keeping well under PostgreSQL's limit of 65535 parameters
+const ROWS_PER_STATEMENT: usize = 1000;
+
+pub struct Warehouse {
+    client: Client,
+}
+impl Warehouse {
+    pub fn connect(url: &str) -> Result<Self, Box<dyn std::error::Error+Send+Sync>> {
+        let mut client = Client::connect(url, NoTls)?;
+        client.batch_execute("CREATE TABLE IF NOT EXISTS fema_web_declaration_areas (
+            id TEXT PRIMARY KEY,
+            disaster_number INTEGER NOT NULL,
+            program_type_code TEXT NOT NULL,
+            program_type_description TEXT NOT NULL,
+            state_code TEXT NOT NULL,
+            place_code TEXT NOT NULL,
+            place_name TEXT NOT NULL,
+            designated_date TIMESTAMPTZ NOT NULL,
+            entry_date TIMESTAMPTZ,
+            update_date TIMESTAMPTZ,
+            hash TEXT NOT NULL,
+            last_refresh TIMESTAMPTZ,
+            closeout_date TIMESTAMPTZ
+        );
+        -- Tables from before closeout_date was kept get the column, and ones from before dates other than
+        -- designated_date could be missing from a record allow them to be null
+        ALTER TABLE fema_web_declaration_areas
+            ADD COLUMN IF NOT EXISTS closeout_date TIMESTAMPTZ,
+            ALTER COLUMN entry_date DROP NOT NULL,
+            ALTER COLUMN update_date DROP NOT NULL,
+            ALTER COLUMN last_refresh DROP NOT NULL")?;
+        Ok(Self { client })
+    }
+    // Inserts or replaces the entries in one transaction, returning the number that weren't already in the table
+    pub fn upsert(&mut self, entries: &[Entry]) -> Result<usize, Box<dyn std::error::Error+Send+Sync>> {
+        // A statement can't update the same row twice, so only the last of any repeated id is written
+        let mut last: HashMap<&str, usize> = HashMap::new();
+        for (i, e) in entries.iter().enumerate() {
+            last.insert(&e.id, i);
+        }
+        let entries: Vec<&Entry> = entries.iter().enumerate().filter(|(i, e)| last[e.id.as_str()] == *i).map(|(_, e)| e).collect();
+        let mut tx = self.client.transaction()?;
+        let mut added = 0;
+        for chunk in entries.chunks(ROWS_PER_STATEMENT) {
+            let codes: Vec<(&str, &str)> = chunk.iter().map(|e| (e.programTypeCode.as_str(), e.stateCode.as_str())).collect();
+            let mut params: Vec<&(dyn ToSql + Sync)> = Vec::with_capacity(chunk.len() * COLUMNS);
+            for (e, (program_type, state)) in chunk.iter().zip(&codes) {
+                params.extend_from_slice(&[&e.id, &e.disasterNumber, program_type, &e.programTypeDescription, state, &e.placeCode, &e.placeName,
+                    &e.designatedDate, &e.entryDate, &e.updateDate, &e.hash, &e.lastRefresh, &e.closeoutDate]);
+            }
+            let values: Vec<String> = (0..chunk.len()).map(|row| {
+                let placeholders: Vec<String> = (1..=COLUMNS).map(|column| format!("${}", row * COLUMNS + column)).collect();
+                format!("({})", placeholders.join(", "))
+            }).collect();
+            // xmax is only zero for rows this statement inserted rather than updated
+            let rows = tx.query(format!("INSERT INTO fema_web_declaration_areas
+                (id, disaster_number, program_type_code, program_type_description, state_code, place_code, place_name, designated_date, entry_date, update_date, hash, last_refresh, closeout_date)
+                VALUES {}
+                ON CONFLICT (id) DO UPDATE SET
+                    disaster_number = EXCLUDED.disaster_number,
+                    program_type_code = EXCLUDED.program_type_code,
+                    program_type_description = EXCLUDED.program_type_description,
+                    state_code = EXCLUDED.state_code,
+                    place_code = EXCLUDED.place_code,
+                    place_name = EXCLUDED.place_name,
+                    designated_date = EXCLUDED.designated_date,
+                    entry_date = EXCLUDED.entry_date,
+                    update_date = EXCLUDED.update_date,
+                    hash = EXCLUDED.hash,
+                    last_refresh = EXCLUDED.last_refresh,
+                    closeout_date = EXCLUDED.closeout_date
+                RETURNING (xmax = 0) AS inserted", values.join(", ")).as_str(), &params)?;
+            added += rows.iter().filter(|row| row.get::<_, bool>("inserted")).count();
+        }
+        tx.commit()?;
+        Ok(added)
+    }
+}
+impl OutputSink for Warehouse {
+    fn describe(&self) -> String {
+        String::from("PostgreSQL")
+    }
+    fn write_batch(&mut self, batch: &Batch) -> Result<usize, Box<dyn std::error::Error+Send+Sync>> {
+        self.upsert(&batch.entries()?)
+    }
+}
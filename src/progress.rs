@@ -0,0 +1,67 @@
+/*
+ * Progress of the current run, saved after every page is written out so that an interrupted run
+ * can be continued with --resume instead of being fetched again from the start
+ */
+use chrono::{DateTime,Utc};
+use serde::{Serialize,Deserialize};
+use std::path::{Path,PathBuf};
+use fema::Dataset;
+use crate::output::Output;
+
+#[derive(Serialize,Deserialize,Debug)]
+pub struct RunState {
+    // Time the run started, which the output file name and date cutoff were based on
+    pub started: DateTime<Utc>,
+    pub dataset: Dataset,
+    pub filter: String,
+    // Page numbers only line up if the run continues with the same page size
+    pub page_size: usize,
+    // Pages and rows written so far
+    pub pages: usize,
+    pub rows: usize,
+    // Output file and its length after the last complete page, anything past that is discarded
+    pub output: Option<PathBuf>,
+    pub bytes: u64,
+    // Latest lastRefresh seen so far, for the incremental sync checkpoint
+    pub refreshed: Option<DateTime<Utc>>,
+}
+
+pub struct Progress {
+    path: PathBuf,
+    pub state: RunState,
+}
+impl Progress {
+    pub fn start(path: &Path, started: DateTime<Utc>, dataset: Dataset, filter: &str, page_size: usize) -> Self {
+        if path.is_file() {
+            warn!("Progress file {} from an interrupted run exists, starting over. Pass --resume to continue that run instead.", path.display());
+        }
+        let state = RunState { started, dataset, filter: filter.to_owned(), page_size, pages: 0, rows: 0, output: None, bytes: 0, refreshed: None };
+        Self { path: path.to_owned(), state }
+    }
+    pub fn load(path: &Path) -> Result<Self, Box<dyn std::error::Error+Send+Sync>> {
+        let contents = std::fs::read_to_string(path).map_err(|e| format!("No interrupted run to resume, failed to read progress file {}: {}", path.display(), e))?;
+        let state = serde_json::from_str(&contents).map_err(|e| format!("Failed to parse progress file {}: {}", path.display(), e))?;
+        Ok(Self { path: path.to_owned(), state })
+    }
+    // Called once a page has been completely written to every output
+    pub fn record(&mut self, output: &Output, refreshed: Option<DateTime<Utc>>) -> Result<(), Box<dyn std::error::Error+Send+Sync>> {
+        self.state.pages += 1;
+        self.state.rows = output.rows();
+        if let Some((path, bytes)) = output.position()? {
+            self.state.output = Some(path.to_owned());
+            self.state.bytes = bytes;
+        }
+        self.state.refreshed = refreshed;
+        let partial = self.path.with_extension("partial");
+        std::fs::write(&partial, serde_json::to_string_pretty(&self.state)?)?;
+        std::fs::rename(&partial, &self.path)?;
+        Ok(())
+    }
+    // The run completed, so there's nothing left to resume
+    pub fn finish(self) -> std::io::Result<()> {
+        match std::fs::remove_file(&self.path) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    }
+}
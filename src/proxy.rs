@@ -0,0 +1,60 @@
+/*
+ * Proxy settings for networks where the API can only be reached through one. Without them the
+ * standard HTTP_PROXY, HTTPS_PROXY, and NO_PROXY environment variables are honored, credentials
+ * included as user:password@ in the URL. Configured proxies take the place of those, with one for
+ * every request or separate ones for http and https URLs, speaking HTTP or SOCKS5.
+ */
+use serde::{Serialize,Deserialize};
+
+const SCHEMES: [&str; 4] = ["http", "https", "socks5", "socks5h"];
+
+#[derive(Debug,Serialize,Deserialize,Clone,PartialEq,Default)]
+#[serde(default)]
+pub struct HttpProxy {
+    // Proxy for every request, e.g. http://proxy.example.gov:8080 or socks5h://localhost:1080
+    pub url: Option<String>,
+    // Proxies for http and https URLs only, taking precedence over url
+    pub http: Option<String>,
+    pub https: Option<String>,
+    // Credentials for the proxy, sent with Basic authentication
+    pub username: Option<String>,
+    pub password: Option<String>,
+}
+impl HttpProxy {
+    // Checks the settings that can be checked without connecting
+    pub fn validate(&self) -> Result<(), String> {
+        if self.proxies().next().is_none() {
+            return Err(String::from("The proxy section needs a url, or an http or https proxy."));
+        }
+        for (_, proxy) in self.proxies() {
+            let url = reqwest::Url::parse(proxy).map_err(|e| format!("Invalid proxy URL {}: {}", proxy, e))?;
+            if !SCHEMES.contains(&url.scheme()) {
+                return Err(format!("Proxy URL {} should start with one of {:?}, not {}://.", proxy, SCHEMES, url.scheme()));
+            }
+        }
+        if self.password.is_some() && self.username.is_none() {
+            return Err(String::from("A proxy password needs a username to go with it."));
+        }
+        Ok(())
+    }
+    // The configured proxies with the schemes of the URLs they're for, None for all, most specific first
+    fn proxies(&self) -> impl Iterator<Item=(Option<&'static str>, &str)> {
+        vec![(Some("http"), &self.http), (Some("https"), &self.https), (None, &self.url)].into_iter()
+            .filter_map(|(scheme, proxy)| Some((scheme, proxy.as_deref()?)))
+    }
+    // Routes the client's requests through the proxies instead of any from the environment
+    pub fn apply(&self, mut builder: reqwest::ClientBuilder) -> reqwest::Result<reqwest::ClientBuilder> {
+        for (scheme, url) in self.proxies() {
+            let mut proxy = match scheme {
+                Some("http") => reqwest::Proxy::http(url)?,
+                Some(_) => reqwest::Proxy::https(url)?,
+                None => reqwest::Proxy::all(url)?,
+            };
+            if let Some(username) = &self.username {
+                proxy = proxy.basic_auth(username, self.password.as_deref().unwrap_or_default());
+            }
+            builder = builder.proxy(proxy);
+        }
+        Ok(builder)
+    }
+}
@@ -0,0 +1,113 @@
+/*
+ * Data-quality checks on the records as they're fetched, flagging ones that look like upstream
+ * glitches: dates in the future, an entry made after its last update, a missing place name or state,
+ * or an id repeated within a page. Records are checked as the JSON they're written out as, so any
+ * dataset or selection of fields can be checked for whichever of those fields it has. Flagged
+ * records are still written, the issues go to a separate report.
+ */
+use chrono::{DateTime,Duration,Utc};
+use serde::{Serialize,Deserialize};
+use serde_json::Value;
+use std::collections::HashSet;
+use std::path::Path;
+
+// Dates that can't be later than when the record was fetched
+const PAST_DATES: [&str; 4] = ["designatedDate", "declarationDate", "entryDate", "updateDate"];
+// Fields that should always have a value
+const REQUIRED: [&str; 2] = ["placeName", "stateCode"];
+
+#[derive(Debug,Serialize,Deserialize,Clone,Copy,PartialEq,Eq,Hash)]
+#[serde(rename_all = "kebab-case")]
+pub enum IssueKind {
+    FutureDate,
+    EntryAfterUpdate,
+    MissingValue,
+    DuplicateId,
+}
+impl IssueKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            IssueKind::FutureDate => "future-date",
+            IssueKind::EntryAfterUpdate => "entry-after-update",
+            IssueKind::MissingValue => "missing-value",
+            IssueKind::DuplicateId => "duplicate-id",
+        }
+    }
+}
+
+// Something suspicious about one field of a record. id is empty for records without one
+#[derive(Debug,Serialize,Deserialize,Clone,PartialEq)]
+pub struct Issue {
+    pub page: usize,
+    pub id: String,
+    pub issue: IssueKind,
+    pub field: String,
+    pub detail: String,
+}
+
+#[derive(Debug,Default)]
+pub struct Validator {
+    issues: Vec<Issue>,
+}
+impl Validator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn issues(&self) -> &[Issue] {
+        &self.issues
+    }
+    // Checks the records of a page, page being its number counting from 1
+    pub fn check<T: Serialize>(&mut self, page: usize, records: &[T]) -> serde_json::Result<()> {
+        // Allow a little clock skew between us and the server before calling a date in the future
+        let latest = Utc::now() + Duration::hours(1);
+        let mut ids = HashSet::new();
+        for record in records {
+            let record = serde_json::to_value(record)?;
+            let id = record.get("id").and_then(Value::as_str).unwrap_or_default().to_owned();
+            let mut flag = |issue, field: &str, detail: String| {
+                self.issues.push(Issue { page, id: id.clone(), issue, field: field.to_owned(), detail });
+            };
+            for field in &PAST_DATES {
+                if let Some(date) = date(&record, field).filter(|date| *date > latest) {
+                    flag(IssueKind::FutureDate, field, format!("{} is in the future", date.to_rfc3339()));
+                }
+            }
+            if let (Some(entered), Some(updated)) = (date(&record, "entryDate"), date(&record, "updateDate")) {
+                if entered > updated {
+                    flag(IssueKind::EntryAfterUpdate, "entryDate", format!("entered {} but last updated {}", entered.to_rfc3339(), updated.to_rfc3339()));
+                }
+            }
+            for field in &REQUIRED {
+                match record.get(field) {
+                    Some(Value::Null) => flag(IssueKind::MissingValue, field, String::from("null")),
+                    Some(Value::String(value)) if value.trim().is_empty() => flag(IssueKind::MissingValue, field, String::from("empty")),
+                    _ => (),
+                }
+            }
+            if !id.is_empty() && !ids.insert(id.clone()) {
+                flag(IssueKind::DuplicateId, "id", format!("{} appears more than once in page {}", id, page));
+            }
+        }
+        Ok(())
+    }
+}
+
+// A field holding an RFC 3339 date, None if it's missing or isn't one
+fn date(record: &Value, field: &str) -> Option<DateTime<Utc>> {
+    DateTime::parse_from_rfc3339(record.get(field)?.as_str()?).ok().map(|date| date.with_timezone(&Utc))
+}
+
+// Writes issues to path as a JSON array if it has a .json extension, otherwise as CSV
+pub fn write_issues(path: &Path, issues: &[Issue]) -> Result<(), Box<dyn std::error::Error+Send+Sync>> {
+    if path.extension().and_then(|e| e.to_str()).is_some_and(|e| e.eq_ignore_ascii_case("json")) {
+        serde_json::to_writer_pretty(std::fs::File::create(path)?, issues)?;
+        return Ok(());
+    }
+    let mut writer = csv::Writer::from_path(path)?;
+    writer.write_record(["page", "id", "issue", "field", "detail"])?;
+    for issue in issues {
+        writer.write_record([issue.page.to_string().as_str(), &issue.id, issue.issue.name(), &issue.field, &issue.detail])?;
+    }
+    writer.flush()?;
+    Ok(())
+}
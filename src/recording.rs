@@ -0,0 +1,134 @@
+/*
+ * Recording of the responses a run gets from the API, to replay later without a network so the run
+ * can be repeated exactly, e.g. against the responses a bug report came with or for a demo. Every
+ * response is kept as it arrived, status, headers, and body, including errors that were retried.
+ * They're kept under a hash of the URL and the number of times it had already been requested, so
+ * a replayed run sees a URL's responses in the same order, retries and all.
+ */
+use reqwest::header::{HeaderMap,HeaderName,HeaderValue,CONTENT_ENCODING,CONTENT_LENGTH};
+use serde::{Serialize,Deserialize};
+use sha2::{Digest,Sha256};
+use std::collections::HashMap;
+use std::path::{Path,PathBuf};
+use std::sync::Mutex;
+use crate::{CachedRun,Dataset,Error};
+
+// What's kept next to each body
+#[derive(Debug,Serialize,Deserialize)]
+struct Exchange {
+    url: String,
+    status: u16,
+    headers: Vec<(String,String)>,
+}
+
+#[derive(Debug)]
+pub struct Recording {
+    dir: PathBuf,
+    replay: bool,
+    // How many times each URL has been requested through this recording
+    requests: Mutex<HashMap<String,usize>>,
+}
+impl Recording {
+    pub fn record(dir: &Path) -> Self {
+        Self { dir: dir.to_owned(), replay: false, requests: Mutex::new(HashMap::new()) }
+    }
+    pub fn replay(dir: &Path) -> Self {
+        Self { replay: true, ..Self::record(dir) }
+    }
+    // The configured record or replay directory, if any
+    pub fn from_config(cfg: &crate::Config) -> Option<Self> {
+        match (&cfg.record, &cfg.replay) {
+            (_, Some(dir)) => Some(Self::replay(dir)),
+            (Some(dir), None) => Some(Self::record(dir)),
+            (None, None) => None,
+        }
+    }
+    pub fn replaying(&self) -> bool {
+        self.replay
+    }
+    // Clears out an earlier recording in dir, so it isn't mixed up with the one about to be made
+    pub fn start(dir: &Path) -> std::io::Result<()> {
+        std::fs::create_dir_all(dir)?;
+        for entry in std::fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.extension().is_some_and(|ext| ext == "json" || ext == "body") {
+                std::fs::remove_file(path)?;
+            }
+        }
+        Ok(())
+    }
+    // The body and metadata files of the nth request for url
+    fn paths(&self, url: &str, n: usize) -> (PathBuf, PathBuf) {
+        let key = format!("{:x}-{}", Sha256::digest(url.as_bytes()), n);
+        (self.dir.join(format!("{}.body", key)), self.dir.join(format!("{}.json", key)))
+    }
+    fn next_request(&self, url: &str) -> usize {
+        let mut requests = self.requests.lock().unwrap();
+        let n = requests.entry(url.to_owned()).or_insert(0);
+        *n += 1;
+        *n - 1
+    }
+    /*
+     * Keeps response as the next one for url, returning it to be read as usual. Bodies are kept as
+     * decoded by the client, so the headers saying how they were encoded are left out.
+     */
+    pub async fn keep(&self, url: &str, response: reqwest::Response) -> Result<reqwest::Response, Error> {
+        let (status, mut headers) = (response.status(), response.headers().clone());
+        let body = response.bytes().await
+            .map_err(|source| Error::Http { url: url.to_owned(), status: source.status().map(|s| s.as_u16()), source, retry_after: None })?;
+        headers.remove(CONTENT_ENCODING);
+        headers.remove(CONTENT_LENGTH);
+        let exchange = Exchange {
+            url: url.to_owned(),
+            status: status.as_u16(),
+            headers: headers.iter().filter_map(|(name, value)| Some((name.to_string(), value.to_str().ok()?.to_owned()))).collect(),
+        };
+        let (body_path, meta_path) = self.paths(url, self.next_request(url));
+        std::fs::create_dir_all(&self.dir)?;
+        std::fs::write(&body_path, &body)?;
+        std::fs::write(&meta_path, serde_json::to_vec(&exchange).map_err(std::io::Error::from)?)?;
+        Ok(response_from(status, headers, body.to_vec()))
+    }
+    // The recorded response to the next request for url. Past the last one recorded, that one is repeated
+    pub fn next(&self, url: &str) -> Result<reqwest::Response, Error> {
+        let missing = || Error::Config(format!("{} wasn't requested in the recording at {}.", url, self.dir.display()));
+        let n = self.next_request(url);
+        let (body_path, meta_path) = (0..=n).rev().map(|n| self.paths(url, n)).find(|(_, meta)| meta.is_file()).ok_or_else(missing)?;
+        let exchange: Exchange = serde_json::from_slice(&std::fs::read(meta_path)?).map_err(std::io::Error::from)?;
+        if exchange.url != url {
+            return Err(missing());
+        }
+        let status = reqwest::StatusCode::from_u16(exchange.status)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, format!("Recorded response to {} has an invalid status: {}", url, e)))?;
+        let mut headers = HeaderMap::new();
+        for (name, value) in exchange.headers {
+            if let (Ok(name), Ok(value)) = (HeaderName::from_bytes(name.as_bytes()), HeaderValue::from_str(&value)) {
+                headers.append(name, value);
+            }
+        }
+        debug!("Replaying the recorded response to {}.", url);
+        Ok(response_from(status, headers, std::fs::read(body_path)?))
+    }
+    fn run_path(&self, dataset: Dataset) -> PathBuf {
+        self.dir.join(format!("run-{}.json", dataset.name()))
+    }
+    // The recorded run of dataset, for a replay to start from the same time with the same filter
+    pub fn run(&self, dataset: Dataset) -> std::io::Result<Option<CachedRun>> {
+        match std::fs::read(self.run_path(dataset)) {
+            Ok(run) => Ok(Some(serde_json::from_slice(&run)?)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+    pub fn save_run(&self, dataset: Dataset, run: &CachedRun) -> std::io::Result<()> {
+        std::fs::create_dir_all(&self.dir)?;
+        std::fs::write(self.run_path(dataset), serde_json::to_vec(run)?)
+    }
+}
+
+pub(crate) fn response_from(status: reqwest::StatusCode, headers: HeaderMap, body: Vec<u8>) -> reqwest::Response {
+    let mut response = hyper::Response::new(body);
+    *response.status_mut() = status;
+    *response.headers_mut() = headers;
+    reqwest::Response::from(response)
+}
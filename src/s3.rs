@@ -0,0 +1,184 @@
+/*
+ * Upload of the output files to S3 or S3-compatible storage like MinIO once a run has written them,
+ * so a pipeline doesn't need an aws-cli step after the fetch. Requests are signed with AWS Signature
+ * Version 4. Every run uploads under its own key, the prefix followed by the run's start time, so a
+ * run never overwrites objects a consumer might be reading, and the manifest goes up last to mark
+ * the run's files as complete.
+ */
+use chrono::{DateTime,Utc};
+use hmac::{Hmac,Mac};
+use serde::{Serialize,Deserialize};
+use sha2::{Digest,Sha256};
+use std::path::Path;
+use crate::Error;
+
+// Used when neither region nor AWS_REGION is set
+pub const DEFAULT_S3_REGION: &str = "us-east-1";
+
+#[derive(Debug,Serialize,Deserialize,Clone,PartialEq,Default)]
+#[serde(default)]
+pub struct S3Upload {
+    pub bucket: String,
+    // Put before each run's keys, e.g. "fema/" for fema/20240101T000000Z/output.csv
+    pub prefix: String,
+    // AWS_REGION or AWS_DEFAULT_REGION if unset, then us-east-1
+    pub region: Option<String>,
+    // Base URL of S3-compatible storage, e.g. http://localhost:9000, addressed path-style
+    pub endpoint: Option<String>,
+    // AWS_ACCESS_KEY_ID, AWS_SECRET_ACCESS_KEY, and AWS_SESSION_TOKEN if unset
+    pub access_key_id: Option<String>,
+    pub secret_access_key: Option<String>,
+    pub session_token: Option<String>,
+}
+
+#[derive(Debug,Clone)]
+pub struct Credentials {
+    pub access_key_id: String,
+    pub secret_access_key: String,
+    pub session_token: Option<String>,
+}
+
+impl S3Upload {
+    // Checks the settings that can be checked without a request
+    pub fn validate(&self) -> Result<(), String> {
+        if self.bucket.is_empty() {
+            return Err(String::from("The S3 upload needs a bucket."));
+        }
+        if let Some(endpoint) = &self.endpoint {
+            reqwest::Url::parse(endpoint).map_err(|e| format!("Invalid S3 endpoint {}: {}", endpoint, e))?;
+        }
+        self.credentials()?;
+        Ok(())
+    }
+    pub fn region(&self) -> String {
+        self.region.clone()
+            .or_else(|| std::env::var("AWS_REGION").ok())
+            .or_else(|| std::env::var("AWS_DEFAULT_REGION").ok())
+            .unwrap_or_else(|| String::from(DEFAULT_S3_REGION))
+    }
+    // The configured keys, or the usual AWS environment variables
+    pub fn credentials(&self) -> Result<Credentials, String> {
+        let setting = |value: &Option<String>, var: &str| value.clone().or_else(|| std::env::var(var).ok()).filter(|value| !value.is_empty());
+        match (setting(&self.access_key_id, "AWS_ACCESS_KEY_ID"), setting(&self.secret_access_key, "AWS_SECRET_ACCESS_KEY")) {
+            (Some(access_key_id), Some(secret_access_key)) => Ok(Credentials {
+                access_key_id,
+                secret_access_key,
+                session_token: setting(&self.session_token, "AWS_SESSION_TOKEN"),
+            }),
+            _ => Err(String::from("The S3 upload needs access_key_id and secret_access_key, or AWS_ACCESS_KEY_ID and AWS_SECRET_ACCESS_KEY.")),
+        }
+    }
+    // Key of a file uploaded by the run started at run, under a prefix of its own
+    pub fn key(&self, run: DateTime<Utc>, path: &Path) -> String {
+        let name = path.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+        let prefix = match self.prefix.trim_start_matches('/') {
+            "" => String::new(),
+            prefix if prefix.ends_with('/') => prefix.to_owned(),
+            prefix => format!("{}/", prefix),
+        };
+        format!("{}{}/{}", prefix, run.format("%Y%m%dT%H%M%SZ"), name)
+    }
+    fn url(&self, key: &str) -> Result<reqwest::Url, Error> {
+        let key = key.split('/').map(uri_encode).collect::<Vec<_>>().join("/");
+        let url = match &self.endpoint {
+            Some(endpoint) => format!("{}/{}/{}", endpoint.trim_end_matches('/'), uri_encode(&self.bucket), key),
+            None => format!("https://{}.s3.{}.amazonaws.com/{}", self.bucket, self.region(), key),
+        };
+        reqwest::Url::parse(&url).map_err(|e| Error::Config(format!("Invalid S3 URL {}: {}", url, e)))
+    }
+    // PUTs the file at path to key in the bucket
+    pub async fn upload(&self, http: &reqwest::Client, path: &Path, key: &str, content_type: &str) -> Result<(), Error> {
+        let body = tokio::fs::read(path).await?;
+        let url = self.url(key)?;
+        let credentials = self.credentials().map_err(Error::Config)?;
+        let now = Utc::now();
+        let payload_sha256 = format!("{:x}", Sha256::digest(&body));
+        let host = match url.port() {
+            Some(port) => format!("{}:{}", url.host_str().unwrap_or_default(), port),
+            None => url.host_str().unwrap_or_default().to_owned(),
+        };
+        let mut headers = vec![
+            ("content-type", content_type.to_owned()),
+            ("host", host),
+            ("x-amz-content-sha256", payload_sha256.clone()),
+            ("x-amz-date", now.format("%Y%m%dT%H%M%SZ").to_string()),
+        ];
+        if let Some(token) = &credentials.session_token {
+            headers.push(("x-amz-security-token", token.clone()));
+        }
+        let authorization = sign("PUT", &url, &headers, &payload_sha256, &credentials, &self.region(), now);
+        let mut request = http.put(url.clone()).header("authorization", authorization).body(body);
+        // reqwest sets the host header itself
+        for (name, value) in headers.into_iter().filter(|(name, _)| *name != "host") {
+            request = request.header(name, value);
+        }
+        request.send().await.and_then(reqwest::Response::error_for_status)
+            .map_err(|source| Error::Http { url: url.to_string(), status: source.status().map(|s| s.as_u16()), source, retry_after: None })?;
+        Ok(())
+    }
+}
+
+/*
+ * The Authorization header of a request signed with AWS Signature Version 4 for S3. headers are the
+ * ones to sign, which need to include host, x-amz-date, and x-amz-content-sha256 with the hex SHA-256
+ * of the payload.
+ */
+pub fn sign(method: &str, url: &reqwest::Url, headers: &[(&str, String)], payload_sha256: &str, credentials: &Credentials, region: &str, time: DateTime<Utc>) -> String {
+    let mut headers: Vec<(String, String)> = headers.iter()
+        .map(|(name, value)| (name.to_ascii_lowercase(), value.split_whitespace().collect::<Vec<_>>().join(" ")))
+        .collect();
+    headers.sort();
+    let signed_headers = headers.iter().map(|(name, _)| name.as_str()).collect::<Vec<_>>().join(";");
+    let mut query: Vec<(String, String)> = url.query_pairs().map(|(name, value)| (uri_encode(&name), uri_encode(&value))).collect();
+    query.sort();
+    let canonical_request = format!("{}\n{}\n{}\n{}\n{}\n{}",
+        method,
+        url.path(),
+        query.iter().map(|(name, value)| format!("{}={}", name, value)).collect::<Vec<_>>().join("&"),
+        headers.iter().map(|(name, value)| format!("{}:{}\n", name, value)).collect::<String>(),
+        signed_headers,
+        payload_sha256,
+    );
+    let date = time.format("%Y%m%d").to_string();
+    let scope = format!("{}/{}/s3/aws4_request", date, region);
+    let string_to_sign = format!("AWS4-HMAC-SHA256\n{}\n{}\n{:x}", time.format("%Y%m%dT%H%M%SZ"), scope, Sha256::digest(canonical_request.as_bytes()));
+    let key = [date.as_str(), region, "s3", "aws4_request"].iter()
+        .fold(format!("AWS4{}", credentials.secret_access_key).into_bytes(), |key, part| hmac(&key, part.as_bytes()));
+    let signature: String = hmac(&key, string_to_sign.as_bytes()).iter().map(|b| format!("{:02x}", b)).collect();
+    format!("AWS4-HMAC-SHA256 Credential={}/{},SignedHeaders={},Signature={}", credentials.access_key_id, scope, signed_headers, signature)
+}
+
+fn hmac(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC takes keys of any length");
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
+
+// Percent-encodes everything but the unreserved characters, as SigV4 expects
+fn uri_encode(value: &str) -> String {
+    value.bytes().map(|b| match b {
+        b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => (b as char).to_string(),
+        b => format!("%{:02X}", b),
+    }).collect()
+}
+
+// Content type of an output file of the given format, or of its compressed stream
+pub fn content_type(format: &str, path: &Path) -> &'static str {
+    match path.extension().and_then(|ext| ext.to_str()) {
+        Some("gz") => return "application/gzip",
+        Some("zst") => return "application/zstd",
+        _ => (),
+    }
+    match format {
+        "csv" => "text/csv",
+        "ndjson" => "application/x-ndjson",
+        "json" => "application/json",
+        "geojson" => "application/geo+json",
+        "html" => "text/html; charset=utf-8",
+        "atom" => "application/atom+xml",
+        "parquet" => "application/vnd.apache.parquet",
+        "arrow" => "application/vnd.apache.arrow.file",
+        "xlsx" => "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet",
+        _ => "application/octet-stream",
+    }
+}
@@ -0,0 +1,26 @@
+/*
+ * Arrow schema shared by the parquet and Arrow IPC outputs, inferred from the JSON form of the
+ * first page of records. String columns holding RFC 3339 dates become UTC timestamps.
+ */
+use arrow_json::reader::infer_json_schema_from_iterator;
+use arrow_schema::{DataType,Field,Schema,TimeUnit};
+
+pub fn infer_schema(records: &[serde_json::Value]) -> Result<Schema, Box<dyn std::error::Error+Send+Sync>> {
+    let inferred = infer_json_schema_from_iterator(records.iter().map(Ok))?;
+    let fields: Vec<Field> = inferred.fields().iter().map(|field| {
+        let data_type = match field.data_type() {
+            DataType::Utf8 if is_timestamp_column(records, field.name()) => DataType::Timestamp(TimeUnit::Millisecond, Some("+00:00".into())),
+            // Null on every record of the first page, later pages may have strings
+            DataType::Null => DataType::Utf8,
+            data_type => data_type.clone(),
+        };
+        Field::new(field.name(), data_type, true)
+    }).collect();
+    Ok(Schema::new(fields))
+}
+
+// Whether every value of the column on the first page is an RFC 3339 date
+fn is_timestamp_column(records: &[serde_json::Value], name: &str) -> bool {
+    let mut values = records.iter().filter_map(|r| r.get(name)).filter(|v| !v.is_null()).peekable();
+    values.peek().is_some() && values.all(|v| v.as_str().is_some_and(|s| chrono::DateTime::parse_from_rfc3339(s).is_ok()))
+}
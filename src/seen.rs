@@ -0,0 +1,59 @@
+/*
+ * Deduplication across runs: the id of every record written or published, with the version it was
+ * at, kept in a JSON file between runs. A record the file already has at the same version is left
+ * out, so overlapping fetch windows don't emit the same declaration area twice, while one FEMA has
+ * since updated goes out again.
+ */
+use std::collections::BTreeMap;
+use std::path::{Path,PathBuf};
+use crate::{Error,Identified};
+
+#[derive(Debug)]
+pub struct SeenSet {
+    path: PathBuf,
+    // Version of each record by id, empty for records without one
+    records: BTreeMap<String,String>,
+}
+impl SeenSet {
+    // Reads the file, starting from nothing seen if it doesn't exist yet
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        let records = match std::fs::read_to_string(path) {
+            Ok(contents) => serde_json::from_str(&contents).map_err(|e| Error::Config(format!("Failed to parse dedup file {}: {}", path.display(), e)))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(e) => return Err(std::io::Error::new(e.kind(), format!("Failed to read dedup file {}: {}", path.display(), e)).into()),
+        };
+        Ok(Self { path: path.to_owned(), records })
+    }
+    pub fn len(&self) -> usize {
+        self.records.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.records.is_empty()
+    }
+    // Whether record hasn't been seen before at its current version, noting that it has now. Records without an id always are
+    pub fn insert<T: Identified>(&mut self, record: &T) -> bool {
+        let id = match record.id() {
+            Some(id) => id,
+            None => return true,
+        };
+        let version = record.version().unwrap_or_default();
+        match self.records.get_mut(id) {
+            Some(seen) if *seen == version => false,
+            Some(seen) => {
+                *seen = version;
+                true
+            },
+            None => {
+                self.records.insert(id.to_owned(), version);
+                true
+            },
+        }
+    }
+    // Written to a temporary file first so an interrupted save can't lose what was seen before
+    pub fn save(&self) -> Result<(), Error> {
+        let partial = self.path.with_extension("partial");
+        std::fs::write(&partial, serde_json::to_string(&self.records).map_err(std::io::Error::from)?)?;
+        std::fs::rename(&partial, &self.path)?;
+        Ok(())
+    }
+}
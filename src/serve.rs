@@ -0,0 +1,224 @@
+/*
+ * The serve subcommand: a cache of FemaWebDeclarationAreas entries kept refreshed from the API,
+ * answering simple REST queries so dashboards can ask the local copy instead of FEMA. The cache is
+ * the SQLite database if one is configured, which keeps it between restarts, and otherwise lives in
+ * memory and is filled again on start. Each refresh only asks for entries refreshed after the latest
+ * one in the cache, on the watch interval or schedule, or hourly if neither is set.
+ *
+ *   GET /areas?state=TX&program_type=IA&since=2021-01-01   entries designated since then, oldest first
+ *   GET /areas/{id}                                        the entry with that id
+ *   GET /metrics                                           Prometheus metrics
+ */
+use chrono::{DateTime,Local,NaiveDate,Utc};
+use hyper::header::CONTENT_TYPE;
+use hyper::service::{make_service_fn,service_fn};
+use hyper::{Body,Method,Request,Response,Server,StatusCode};
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::{Arc,Mutex};
+use fema::{ApiVersion,Config,Dataset,Entry,Error,FemaClient,METRICS};
+
+// How often to refresh without a watch interval or schedule
+const DEFAULT_REFRESH: std::time::Duration = std::time::Duration::from_secs(60 * 60);
+
+enum Cache {
+    Memory(HashMap<String,Entry>),
+    #[cfg(feature = "sqlite")]
+    Sqlite(crate::sqlite::Database),
+}
+impl Cache {
+    fn open(cfg: &Config) -> Result<Self, Box<dyn std::error::Error+Send+Sync>> {
+        match &cfg.sqlite {
+            #[cfg(feature = "sqlite")]
+            Some(path) => Ok(Cache::Sqlite(crate::sqlite::Database::open(path)?)),
+            _ => Ok(Cache::Memory(HashMap::new())),
+        }
+    }
+    // Returns the number of entries that weren't in the cache yet
+    fn upsert(&mut self, entries: &[Entry]) -> Result<usize, Box<dyn std::error::Error+Send+Sync>> {
+        match self {
+            Cache::Memory(cache) => Ok(entries.iter().filter(|&entry| cache.insert(entry.id.clone(), entry.clone()).is_none()).count()),
+            #[cfg(feature = "sqlite")]
+            Cache::Sqlite(database) => database.upsert(entries),
+        }
+    }
+    fn get(&self, id: &str) -> Result<Option<Entry>, Box<dyn std::error::Error+Send+Sync>> {
+        match self {
+            Cache::Memory(cache) => Ok(cache.get(id).cloned()),
+            #[cfg(feature = "sqlite")]
+            Cache::Sqlite(database) => database.get(id),
+        }
+    }
+    fn query(&self, query: &Query) -> Result<Vec<Entry>, Box<dyn std::error::Error+Send+Sync>> {
+        match self {
+            Cache::Memory(cache) => {
+                let mut entries: Vec<Entry> = cache.values().filter(|entry| {
+                    query.state.as_ref().is_none_or(|state| entry.stateCode.as_str() == state)
+                        && query.program_type.as_ref().is_none_or(|program_type| entry.programTypeCode.as_str() == program_type)
+                        && query.since.is_none_or(|since| entry.designatedDate >= since)
+                }).cloned().collect();
+                entries.sort_by(|a, b| a.designatedDate.cmp(&b.designatedDate).then_with(|| a.id.cmp(&b.id)));
+                Ok(entries)
+            },
+            #[cfg(feature = "sqlite")]
+            Cache::Sqlite(database) => database.query(query.state.as_deref(), query.program_type.as_deref(), query.since),
+        }
+    }
+    fn latest_refresh(&self) -> Result<Option<DateTime<Utc>>, Box<dyn std::error::Error+Send+Sync>> {
+        match self {
+            Cache::Memory(cache) => Ok(cache.values().filter_map(|entry| entry.lastRefresh).max()),
+            #[cfg(feature = "sqlite")]
+            Cache::Sqlite(database) => database.latest_refresh(),
+        }
+    }
+}
+
+#[derive(Debug,Default)]
+struct Query {
+    state: Option<String>,
+    program_type: Option<String>,
+    since: Option<DateTime<Utc>>,
+}
+impl Query {
+    fn parse(query: &str) -> Result<Self, String> {
+        let url = reqwest::Url::parse(&format!("http://localhost/?{}", query)).map_err(|e| format!("Invalid query: {}", e))?;
+        let mut parsed = Query::default();
+        for (name, value) in url.query_pairs() {
+            match name.as_ref() {
+                "state" => parsed.state = Some(value.to_uppercase()),
+                "program_type" => parsed.program_type = Some(value.to_uppercase()),
+                // Either a whole date, taken as midnight UTC, or an RFC 3339 time
+                "since" => parsed.since = Some(match NaiveDate::parse_from_str(&value, "%Y-%m-%d") {
+                    Ok(date) => date.and_hms_opt(0, 0, 0).unwrap_or_default().and_utc(),
+                    Err(_) => DateTime::parse_from_rfc3339(&value).map_err(|e| format!("Invalid since {}: {}", value, e))?.with_timezone(&Utc),
+                }),
+                name => return Err(format!("Unknown parameter {}, expected state, program_type, or since.", name)),
+            }
+        }
+        Ok(parsed)
+    }
+}
+
+fn respond(cache: &Mutex<Cache>, request: &Request<Body>) -> Response<Body> {
+    if request.method() != Method::GET {
+        return error(StatusCode::METHOD_NOT_ALLOWED, String::from("Only GET requests are supported."));
+    }
+    if request.uri().path() == "/metrics" {
+        return metrics();
+    }
+    let internal = |e: Box<dyn std::error::Error+Send+Sync>| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string());
+    let result = match request.uri().path().trim_end_matches('/').strip_prefix("/areas") {
+        Some("") => Query::parse(request.uri().query().unwrap_or_default())
+            .map_err(|e| (StatusCode::BAD_REQUEST, e))
+            .and_then(|query| cache.lock().unwrap().query(&query).map_err(internal))
+            .and_then(|entries| serde_json::to_vec(&entries).map_err(|e| internal(e.into()))),
+        Some(id) if id.starts_with('/') && !id[1..].contains('/') => {
+            let id = &id[1..];
+            match cache.lock().unwrap().get(id).map_err(internal) {
+                Ok(Some(entry)) => serde_json::to_vec(&entry).map_err(|e| internal(e.into())),
+                Ok(None) => Err((StatusCode::NOT_FOUND, format!("No entry with id {}.", id))),
+                Err(e) => Err(e),
+            }
+        },
+        _ => Err((StatusCode::NOT_FOUND, String::from("Not found, try /areas, /areas/{id}, or /metrics."))),
+    };
+    match result {
+        Ok(body) => Response::builder().header(CONTENT_TYPE, "application/json").body(Body::from(body)).unwrap(),
+        Err((status, message)) => error(status, message),
+    }
+}
+
+fn metrics() -> Response<Body> {
+    Response::builder().header(CONTENT_TYPE, "text/plain; version=0.0.4").body(Body::from(METRICS.render())).unwrap()
+}
+
+fn error(status: StatusCode, message: String) -> Response<Body> {
+    let body = serde_json::json!({ "error": message }).to_string();
+    Response::builder().status(status).header(CONTENT_TYPE, "application/json").body(Body::from(body)).unwrap()
+}
+
+// Fetches the entries refreshed since the latest in the cache, or all of them into an empty cache
+async fn refresh(cfg: &Config, client: &FemaClient, cache: &Mutex<Cache>) -> Result<(), Error> {
+    let mut filter = fema::build_filter(cfg, Utc::now());
+    if let Some(latest) = cache.lock().unwrap().latest_refresh()? {
+        filter = fema::refreshed_since(&filter, latest);
+    }
+    let (mut fetched, mut added) = (0, 0);
+    client.fetch_each::<Entry, _>(&filter, |entries, _| {
+        fetched += entries.len();
+        let new = cache.lock().unwrap().upsert(&entries)?;
+        METRICS.new_records(new);
+        added += new;
+        Ok(())
+    }).await?;
+    info!("Refreshed the cache with {} entries, {} of them new.", fetched, added);
+    Ok(())
+}
+
+// Serves the cache on listen until SIGINT or SIGTERM, refreshing it in between requests
+pub async fn serve(cfg: &Config, client: &FemaClient, listen: &str) -> Result<(), Error> {
+    if cfg.dataset != Dataset::FemaWebDeclarationAreas || cfg.api_version() != ApiVersion::V1 || cfg.select.is_some() {
+        return Err(Error::Config(String::from("Only complete v1 FemaWebDeclarationAreas entries can be served.")));
+    }
+    let addr: SocketAddr = listen.parse().map_err(|e| Error::Config(format!("Invalid listen address {}: {}", listen, e)))?;
+    let watch = cfg.watch().map_err(Error::Config)?;
+    let cache = Arc::new(Mutex::new(Cache::open(cfg)?));
+    let service = {
+        let cache = cache.clone();
+        make_service_fn(move |_| {
+            let cache = cache.clone();
+            async move {
+                Ok::<_, Infallible>(service_fn(move |request| {
+                    let response = respond(&cache, &request);
+                    async move { Ok::<_, Infallible>(response) }
+                }))
+            }
+        })
+    };
+    let server = Server::try_bind(&addr).map_err(|e| Error::Config(format!("Failed to listen on {}: {}", addr, e)))?.serve(service);
+    info!("Serving the cache on http://{}.", server.local_addr());
+    let refresher = async {
+        loop {
+            // A failed refresh leaves the cache as it was, to be brought up to date by the next one
+            METRICS.start_run();
+            match refresh(cfg, client, &cache).await {
+                Ok(()) => METRICS.succeeded(),
+                Err(e) => {
+                    METRICS.failed();
+                    warn!("Failed to refresh the cache: {}", e);
+                },
+            }
+            let wait = match &watch {
+                Some(watch) => watch.next_wait(Local::now()),
+                None => Some(DEFAULT_REFRESH),
+            };
+            match wait {
+                Some(wait) => tokio::time::delay_for(wait).await,
+                None => {
+                    info!("The schedule has no more times, no longer refreshing the cache.");
+                    futures::future::pending::<()>().await;
+                },
+            }
+        }
+    };
+    tokio::select! {
+        result = server => result.map_err(|e| Error::Output(Box::new(e)))?,
+        _ = refresher => (),
+        _ = crate::shutdown_signal() => info!("Stopped serving."),
+    }
+    Ok(())
+}
+
+// Binds listen for serving just /metrics alongside a fetch or watch, returning the server to run
+pub fn serve_metrics(listen: &str) -> Result<impl std::future::Future<Output = Result<(), Error>>, Error> {
+    let addr: SocketAddr = listen.parse().map_err(|e| Error::Config(format!("Invalid metrics address {}: {}", listen, e)))?;
+    let service = make_service_fn(|_| async {
+        Ok::<_, Infallible>(service_fn(|request: Request<Body>| async move {
+            Ok::<_, Infallible>(if request.uri().path() == "/metrics" { metrics() } else { error(StatusCode::NOT_FOUND, String::from("Not found, try /metrics.")) })
+        }))
+    });
+    let server = Server::try_bind(&addr).map_err(|e| Error::Config(format!("Failed to listen on {}: {}", addr, e)))?.serve(service);
+    info!("Serving metrics on http://{}/metrics.", server.local_addr());
+    Ok(async { server.await.map_err(|e| Error::Output(Box::new(e))) })
+}
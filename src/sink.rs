@@ -0,0 +1,193 @@
+/*
+ * Destinations for fetched records, behind one trait so that any number of them can be configured
+ * together and each page fans out to all of them. A new destination only needs an OutputSink
+ * implementation and a line in FanOut::open. Pages come as a Batch of whichever record type the
+ * dataset has; the output file and the notifier take every dataset, while the databases only hold
+ * complete v1 FemaWebDeclarationAreas entries and are only opened for those. The output file is kept
+ * apart from the rest since a run's progress is tracked against it.
+ */
+use fema::{Config,ApiVersion,Dataset,DisasterDeclarationsSummary,Entry,EntryV2,HousingAssistanceOwner,Notifier,PublicAssistanceFundedProjectsDetail,Row};
+use crate::manifest::Manifest;
+use crate::output::Output;
+
+pub trait OutputSink {
+    // Where the records go, for the log
+    fn describe(&self) -> String;
+    // Writes a page of records, returning how many of them the sink didn't already have
+    fn write_batch(&mut self, batch: &Batch) -> Result<usize, Box<dyn std::error::Error+Send+Sync>>;
+    // Called once every page has been written, for sinks that buffer or hold a connection open
+    fn finish(self: Box<Self>) -> Result<(), Box<dyn std::error::Error+Send+Sync>> {
+        Ok(())
+    }
+}
+
+// A page of records of one of the datasets, or of the fields picked with select
+pub enum Batch<'r> {
+    Entries(&'r [Entry]),
+    EntriesV2(&'r [EntryV2]),
+    Summaries(&'r [DisasterDeclarationsSummary]),
+    HousingAssistanceOwners(&'r [HousingAssistanceOwner]),
+    PublicAssistanceProjects(&'r [PublicAssistanceFundedProjectsDetail]),
+    Rows(&'r [Row]),
+}
+impl Batch<'_> {
+    pub fn len(&self) -> usize {
+        match self {
+            Batch::Entries(records) => records.len(),
+            Batch::EntriesV2(records) => records.len(),
+            Batch::Summaries(records) => records.len(),
+            Batch::HousingAssistanceOwners(records) => records.len(),
+            Batch::PublicAssistanceProjects(records) => records.len(),
+            Batch::Rows(records) => records.len(),
+        }
+    }
+    // The records as JSON objects
+    pub fn values(&self) -> serde_json::Result<Vec<serde_json::Value>> {
+        match self {
+            Batch::Entries(records) => records.iter().map(serde_json::to_value).collect(),
+            Batch::EntriesV2(records) => records.iter().map(serde_json::to_value).collect(),
+            Batch::Summaries(records) => records.iter().map(serde_json::to_value).collect(),
+            Batch::HousingAssistanceOwners(records) => records.iter().map(serde_json::to_value).collect(),
+            Batch::PublicAssistanceProjects(records) => records.iter().map(serde_json::to_value).collect(),
+            Batch::Rows(records) => records.iter().map(serde_json::to_value).collect(),
+        }
+    }
+    // The records as v1 entries, read back from rows of whole entries, e.g. ones held in a sort window. Empty for other datasets
+    #[cfg(any(feature = "store", feature = "sqlite", feature = "postgresql"))]
+    pub fn entries(&self) -> serde_json::Result<std::borrow::Cow<'_, [Entry]>> {
+        match self {
+            Batch::Entries(entries) => Ok(std::borrow::Cow::Borrowed(entries)),
+            Batch::Rows(rows) => rows.iter().map(|row| serde_json::from_value(serde_json::to_value(row)?)).collect::<serde_json::Result<Vec<Entry>>>().map(std::borrow::Cow::Owned),
+            _ => Ok(std::borrow::Cow::Borrowed(&[])),
+        }
+    }
+}
+
+// Records that can be handed to the sinks a page at a time
+pub trait Batched: Sized {
+    fn batch(records: &[Self]) -> Batch<'_>;
+}
+impl Batched for Entry {
+    fn batch(records: &[Self]) -> Batch<'_> {
+        Batch::Entries(records)
+    }
+}
+impl Batched for EntryV2 {
+    fn batch(records: &[Self]) -> Batch<'_> {
+        Batch::EntriesV2(records)
+    }
+}
+impl Batched for DisasterDeclarationsSummary {
+    fn batch(records: &[Self]) -> Batch<'_> {
+        Batch::Summaries(records)
+    }
+}
+impl Batched for HousingAssistanceOwner {
+    fn batch(records: &[Self]) -> Batch<'_> {
+        Batch::HousingAssistanceOwners(records)
+    }
+}
+impl Batched for PublicAssistanceFundedProjectsDetail {
+    fn batch(records: &[Self]) -> Batch<'_> {
+        Batch::PublicAssistanceProjects(records)
+    }
+}
+impl Batched for Row {
+    fn batch(records: &[Self]) -> Batch<'_> {
+        Batch::Rows(records)
+    }
+}
+
+impl OutputSink for Output<'_> {
+    fn describe(&self) -> String {
+        String::from("the output file")
+    }
+    fn write_batch(&mut self, batch: &Batch) -> Result<usize, Box<dyn std::error::Error+Send+Sync>> {
+        // Expanded entities are kept separately, see Output::keep_expanded
+        let expanded = std::collections::HashMap::new();
+        match *batch {
+            Batch::Entries(records) => self.write(records, expanded)?,
+            Batch::EntriesV2(records) => self.write(records, expanded)?,
+            Batch::Summaries(records) => self.write(records, expanded)?,
+            Batch::HousingAssistanceOwners(records) => self.write(records, expanded)?,
+            Batch::PublicAssistanceProjects(records) => self.write(records, expanded)?,
+            Batch::Rows(rows) => self.write_rows(rows, expanded)?,
+        }
+        Ok(batch.len())
+    }
+}
+
+// Records are queued as they're written and sent at the end of each poll, see Notifier::notify_pending
+impl OutputSink for Notifier {
+    fn describe(&self) -> String {
+        String::from("the notifier")
+    }
+    fn write_batch(&mut self, batch: &Batch) -> Result<usize, Box<dyn std::error::Error+Send+Sync>> {
+        for record in batch.values()? {
+            self.queue(fema::Change::added(record));
+        }
+        Ok(batch.len())
+    }
+}
+
+// Every configured sink, with the number of new records each of the databases has taken so far
+pub struct FanOut<'a> {
+    pub output: Output<'a>,
+    // Outside diff mode, which notifies of the changes it finds once the run is done instead
+    pub notifier: Option<Notifier>,
+    databases: Vec<(Box<dyn OutputSink>, usize)>,
+}
+impl<'a> FanOut<'a> {
+    // Built without any of the database features, there's nothing more to open
+    #[allow(unused_mut)]
+    pub fn open(cfg: &Config, output: Output<'a>, notifier: Option<Notifier>) -> Result<Self, Box<dyn std::error::Error+Send+Sync>> {
+        let mut databases: Vec<Box<dyn OutputSink>> = Vec::new();
+        let entries = cfg.dataset == Dataset::FemaWebDeclarationAreas && cfg.api_version() == ApiVersion::V1 && cfg.select.is_none();
+        if cfg.store.is_some() && !entries {
+            warn!("The store only holds complete v1 FemaWebDeclarationAreas entries, not storing {} records.", cfg.dataset);
+        }
+        if cfg.sqlite.is_some() && !entries {
+            warn!("The SQLite database only holds complete v1 FemaWebDeclarationAreas entries, not writing {} records.", cfg.dataset);
+        }
+        if cfg.postgres_url.is_some() && !entries {
+            warn!("The PostgreSQL table only holds complete v1 FemaWebDeclarationAreas entries, not writing {} records.", cfg.dataset);
+        }
+        #[cfg(feature = "store")]
+        {
+            if let Some(path) = cfg.store.as_ref().filter(|_| entries) {
+                databases.push(Box::new(crate::store::Store::open(path)?));
+            }
+        }
+        #[cfg(feature = "sqlite")]
+        {
+            if let Some(path) = cfg.sqlite.as_ref().filter(|_| entries) {
+                databases.push(Box::new(crate::sqlite::Database::open(path)?));
+            }
+        }
+        #[cfg(feature = "postgresql")]
+        {
+            if let Some(url) = cfg.postgres_url.as_ref().filter(|_| entries) {
+                databases.push(Box::new(crate::pg::Warehouse::connect(url)?));
+            }
+        }
+        Ok(Self { output, notifier, databases: databases.into_iter().map(|sink| (sink, 0)).collect() })
+    }
+    pub fn write_batch(&mut self, batch: &Batch) -> Result<(), Box<dyn std::error::Error+Send+Sync>> {
+        self.output.write_batch(batch)?;
+        if let Some(notifier) = &mut self.notifier {
+            notifier.write_batch(batch)?;
+        }
+        for (sink, added) in &mut self.databases {
+            *added += sink.write_batch(batch)?;
+        }
+        Ok(())
+    }
+    // Finishes every sink, returning the output file's manifest
+    pub fn finish(self) -> Result<Manifest, Box<dyn std::error::Error+Send+Sync>> {
+        for (sink, added) in self.databases {
+            info!("Entries written to {}, {} of them new.", sink.describe(), added);
+            sink.finish()?;
+        }
+        self.output.finish()
+    }
+}
@@ -0,0 +1,110 @@
+/*
+ * Sorting the output by order_by as it streams, for when pages can arrive out of order. Records are
+ * held in a window of at most sort_window of them, and once it's full the ones that sort first are
+ * written as more come in, so memory stays bounded however many records there are. The output is
+ * only exactly sorted if no record arrives more than the window's size behind where it belongs;
+ * records that do are written late, and counted so the run can say so.
+ */
+use serde_json::Value;
+use std::cmp::Ordering;
+use crate::Row;
+
+#[derive(Debug)]
+pub struct SortWindow {
+    size: usize,
+    // Fields to sort on, each descending or not, as in $orderby
+    keys: Vec<(String, bool)>,
+    rows: Vec<Row>,
+    // Keys of the last record released, which every later one should sort after
+    last: Option<Vec<Value>>,
+    late: usize,
+}
+impl SortWindow {
+    // order_by as given to the API, e.g. "stateCode, designatedDate desc"
+    pub fn new(order_by: &str, size: usize) -> Self {
+        let keys = order_by.split(',').filter_map(|key| {
+            let mut words = key.split_whitespace();
+            let field = words.next()?.to_owned();
+            Some((field, words.next().is_some_and(|dir| dir.eq_ignore_ascii_case("desc"))))
+        }).collect();
+        Self { size, keys, rows: Vec::new(), last: None, late: 0 }
+    }
+    // Adds rows to the window, returning those that no longer fit in it, in order
+    pub fn push(&mut self, rows: Vec<Row>) -> Vec<Row> {
+        self.rows.extend(rows);
+        let excess = self.rows.len().saturating_sub(self.size);
+        self.release(excess)
+    }
+    // Everything still in the window, in order, for the end of the run
+    pub fn drain(&mut self) -> Vec<Row> {
+        self.release(self.rows.len())
+    }
+    // Records released after one they should have come before, since this was last called
+    pub fn take_late(&mut self) -> usize {
+        std::mem::take(&mut self.late)
+    }
+    fn release(&mut self, count: usize) -> Vec<Row> {
+        if count == 0 {
+            return Vec::new();
+        }
+        let keys = &self.keys;
+        // Stable, so records that tie stay in the order they came in
+        self.rows.sort_by(|a, b| compare(keys, a, b));
+        let released: Vec<Row> = self.rows.drain(..count).collect();
+        for row in &released {
+            let values = self.values(row);
+            if self.last.as_ref().is_some_and(|last| compare_values(keys, last, &values) == Ordering::Greater) {
+                self.late += 1;
+            }
+            else {
+                self.last = Some(values);
+            }
+        }
+        released
+    }
+    fn values(&self, row: &Row) -> Vec<Value> {
+        self.keys.iter().map(|(field, _)| row.get(field).cloned().unwrap_or(Value::Null)).collect()
+    }
+}
+
+fn compare(keys: &[(String, bool)], a: &Row, b: &Row) -> Ordering {
+    for (field, desc) in keys {
+        let ordering = compare_value(a.get(field).unwrap_or(&Value::Null), b.get(field).unwrap_or(&Value::Null));
+        if ordering != Ordering::Equal {
+            return if *desc { ordering.reverse() } else { ordering };
+        }
+    }
+    Ordering::Equal
+}
+
+fn compare_values(keys: &[(String, bool)], a: &[Value], b: &[Value]) -> Ordering {
+    for ((_, desc), (a, b)) in keys.iter().zip(a.iter().zip(b)) {
+        let ordering = compare_value(a, b);
+        if ordering != Ordering::Equal {
+            return if *desc { ordering.reverse() } else { ordering };
+        }
+    }
+    Ordering::Equal
+}
+
+// Nulls first, then numbers by value, then strings, dates by when they are since their offsets can differ
+fn compare_value(a: &Value, b: &Value) -> Ordering {
+    match (a, b) {
+        (Value::Number(a), Value::Number(b)) => a.as_f64().unwrap_or_default().partial_cmp(&b.as_f64().unwrap_or_default()).unwrap_or(Ordering::Equal),
+        (Value::String(a), Value::String(b)) => match (chrono::DateTime::parse_from_rfc3339(a), chrono::DateTime::parse_from_rfc3339(b)) {
+            (Ok(a), Ok(b)) => a.cmp(&b),
+            _ => a.cmp(b),
+        },
+        (a, b) => rank(a).cmp(&rank(b)),
+    }
+}
+
+fn rank(value: &Value) -> u8 {
+    match value {
+        Value::Null => 0,
+        Value::Bool(_) => 1,
+        Value::Number(_) => 2,
+        Value::String(_) => 3,
+        Value::Array(_) | Value::Object(_) => 4,
+    }
+}
@@ -0,0 +1,145 @@
+/*
+ * SQLite database of fetched entries with one row per id. Entries seen again on later runs replace
+ * the existing row, so the table always holds the latest version of every declaration area.
+ */
+use chrono::{DateTime,Utc};
+use rusqlite::{params,Connection,OptionalExtension};
+use std::path::{Path,PathBuf};
+use fema::{Entry,ProgramType,StateCode};
+use crate::sink::{Batch,OutputSink};
+
+const COLUMNS: &str = "id, disasterNumber, programTypeCode, programTypeDescription, stateCode, placeCode, placeName, designatedDate, entryDate, updateDate, hash, lastRefresh, closeoutDate";
+
+// Dates other than designatedDate can be missing from a record
+const TABLE: &str = "
+    id TEXT PRIMARY KEY NOT NULL,
+    disasterNumber INTEGER NOT NULL,
+    programTypeCode TEXT NOT NULL,
+    programTypeDescription TEXT NOT NULL,
+    stateCode TEXT NOT NULL,
+    placeCode TEXT NOT NULL,
+    placeName TEXT NOT NULL,
+    designatedDate TEXT NOT NULL,
+    entryDate TEXT,
+    updateDate TEXT,
+    hash TEXT NOT NULL,
+    lastRefresh TEXT,
+    closeoutDate TEXT
+";
+
+pub struct Database {
+    conn: Connection,
+    path: PathBuf,
+}
+impl Database {
+    pub fn open(path: &Path) -> Result<Self, Box<dyn std::error::Error+Send+Sync>> {
+        let mut conn = Connection::open(path)?;
+        conn.execute_batch(&format!("CREATE TABLE IF NOT EXISTS fema_web_declaration_areas ({})", TABLE))?;
+        migrate(&mut conn)?;
+        Ok(Self { conn, path: path.to_owned() })
+    }
+    // Inserts or replaces the entries in one transaction, returning the number that weren't already in the table
+    pub fn upsert(&mut self, entries: &[Entry]) -> Result<usize, Box<dyn std::error::Error+Send+Sync>> {
+        let tx = self.conn.transaction()?;
+        let before: i64 = tx.query_row("SELECT COUNT(*) FROM fema_web_declaration_areas", [], |row| row.get(0))?;
+        {
+            let mut statement = tx.prepare_cached(&format!("INSERT INTO fema_web_declaration_areas ({})
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13)
+                ON CONFLICT(id) DO UPDATE SET
+                    disasterNumber = excluded.disasterNumber,
+                    programTypeCode = excluded.programTypeCode,
+                    programTypeDescription = excluded.programTypeDescription,
+                    stateCode = excluded.stateCode,
+                    placeCode = excluded.placeCode,
+                    placeName = excluded.placeName,
+                    designatedDate = excluded.designatedDate,
+                    entryDate = excluded.entryDate,
+                    updateDate = excluded.updateDate,
+                    hash = excluded.hash,
+                    lastRefresh = excluded.lastRefresh,
+                    closeoutDate = excluded.closeoutDate", COLUMNS))?;
+            for e in entries {
+                statement.execute(params![e.id, e.disasterNumber, e.programTypeCode.as_str(), e.programTypeDescription, e.stateCode.as_str(), e.placeCode, e.placeName,
+                    e.designatedDate.to_rfc3339(), e.entryDate.map(|date| date.to_rfc3339()), e.updateDate.map(|date| date.to_rfc3339()), e.hash,
+                    e.lastRefresh.map(|date| date.to_rfc3339()), e.closeoutDate.map(|date| date.to_rfc3339())])?;
+            }
+        }
+        let after: i64 = tx.query_row("SELECT COUNT(*) FROM fema_web_declaration_areas", [], |row| row.get(0))?;
+        tx.commit()?;
+        Ok((after - before) as usize)
+    }
+    pub fn get(&self, id: &str) -> Result<Option<Entry>, Box<dyn std::error::Error+Send+Sync>> {
+        let entry = self.conn.query_row(&format!("SELECT {} FROM fema_web_declaration_areas WHERE id = ?1", COLUMNS), [id], entry).optional()?;
+        Ok(entry)
+    }
+    // Entries for a state and program type designated since a date, each only if given, in order of designation
+    pub fn query(&self, state: Option<&str>, program_type: Option<&str>, since: Option<DateTime<Utc>>) -> Result<Vec<Entry>, Box<dyn std::error::Error+Send+Sync>> {
+        let mut statement = self.conn.prepare_cached(&format!("SELECT {} FROM fema_web_declaration_areas
+            WHERE (?1 IS NULL OR stateCode = ?1) AND (?2 IS NULL OR programTypeCode = ?2) AND (?3 IS NULL OR designatedDate >= ?3)
+            ORDER BY designatedDate, id", COLUMNS))?;
+        let entries = statement.query_map(params![state, program_type, since.map(|since| since.to_rfc3339())], entry)?.collect::<rusqlite::Result<_>>()?;
+        Ok(entries)
+    }
+    // The latest lastRefresh in the table, for picking up where the last refresh left off
+    pub fn latest_refresh(&self) -> Result<Option<DateTime<Utc>>, Box<dyn std::error::Error+Send+Sync>> {
+        let latest: Option<String> = self.conn.query_row("SELECT MAX(lastRefresh) FROM fema_web_declaration_areas", [], |row| row.get(0))?;
+        Ok(latest.map(|latest| DateTime::parse_from_rfc3339(&latest).map(|latest| latest.with_timezone(&Utc))).transpose()?)
+    }
+}
+impl OutputSink for Database {
+    fn describe(&self) -> String {
+        format!("SQLite database {}", self.path.display())
+    }
+    fn write_batch(&mut self, batch: &Batch) -> Result<usize, Box<dyn std::error::Error+Send+Sync>> {
+        self.upsert(&batch.entries()?)
+    }
+}
+
+/*
+ * Brings tables made by earlier versions up to date: ones from before closeoutDate was kept get the
+ * column, empty for the rows already there, and ones from before dates could be missing have them
+ * NOT NULL, which SQLite can only drop by copying the rows into a new table.
+ */
+fn migrate(conn: &mut Connection) -> rusqlite::Result<()> {
+    let column = |conn: &Connection, name: &str| conn.query_row("SELECT \"notnull\" FROM pragma_table_info('fema_web_declaration_areas') WHERE name = ?1", [name], |row| row.get::<_, bool>(0)).optional();
+    if column(conn, "closeoutDate")?.is_none() {
+        info!("Adding the closeoutDate column to the SQLite table fema_web_declaration_areas.");
+        conn.execute_batch("ALTER TABLE fema_web_declaration_areas ADD COLUMN closeoutDate TEXT")?;
+    }
+    if column(conn, "entryDate")? != Some(true) {
+        return Ok(());
+    }
+    info!("Allowing missing dates in the SQLite table fema_web_declaration_areas.");
+    let tx = conn.transaction()?;
+    tx.execute_batch(&format!("ALTER TABLE fema_web_declaration_areas RENAME TO fema_web_declaration_areas_old;
+        CREATE TABLE fema_web_declaration_areas ({});
+        INSERT INTO fema_web_declaration_areas ({columns}) SELECT {columns} FROM fema_web_declaration_areas_old;
+        DROP TABLE fema_web_declaration_areas_old;", TABLE, columns = COLUMNS))?;
+    tx.commit()
+}
+
+// Dates are stored as RFC 3339 text, which also sorts them
+fn entry(row: &rusqlite::Row) -> rusqlite::Result<Entry> {
+    let date = |i: usize| -> rusqlite::Result<Option<DateTime<Utc>>> {
+        let text: Option<String> = row.get(i)?;
+        text.map(|text| DateTime::parse_from_rfc3339(&text).map(|date| date.with_timezone(&Utc))
+            .map_err(|e| rusqlite::Error::FromSqlConversionFailure(i, rusqlite::types::Type::Text, Box::new(e)))).transpose()
+    };
+    Ok(Entry {
+        id: row.get(0)?,
+        disasterNumber: row.get(1)?,
+        programTypeCode: ProgramType::from(row.get::<_, String>(2)?.as_str()),
+        programTypeDescription: row.get(3)?,
+        stateCode: StateCode::from(row.get::<_, String>(4)?.as_str()),
+        placeCode: row.get(5)?,
+        placeName: row.get(6)?,
+        // Required, so never null
+        designatedDate: date(7)?.unwrap_or_default(),
+        entryDate: date(8)?,
+        updateDate: date(9)?,
+        closeoutDate: date(12)?,
+        hash: row.get(10)?,
+        lastRefresh: date(11)?,
+        extra: Default::default(),
+    })
+}
@@ -0,0 +1,45 @@
+/*
+ * Embedded sled database of fetched entries keyed by id, with the serialized Entry as the value.
+ * Re-inserting an id overwrites the previous value, so entries are deduplicated across runs.
+ */
+use std::path::{Path,PathBuf};
+use fema::Entry;
+use crate::sink::{Batch,OutputSink};
+
+pub struct Store {
+    db: sled::Db,
+    path: PathBuf,
+}
+impl Store {
+    pub fn open(path: &Path) -> Result<Self, Box<dyn std::error::Error+Send+Sync>> {
+        Ok(Self { db: sled::open(path)?, path: path.to_owned() })
+    }
+    // Returns the number of entries that weren't already in the store
+    pub fn insert(&self, entries: &[Entry]) -> Result<usize, Box<dyn std::error::Error+Send+Sync>> {
+        let mut added = 0;
+        for entry in entries {
+            if self.db.insert(entry.id.as_bytes(), serde_json::to_vec(entry)?)?.is_none() {
+                added += 1;
+            }
+        }
+        self.db.flush()?;
+        Ok(added)
+    }
+    pub fn get(&self, id: &str) -> Result<Option<Entry>, Box<dyn std::error::Error+Send+Sync>> {
+        match self.db.get(id.as_bytes())? {
+            Some(value) => Ok(Some(serde_json::from_slice(&value)?)),
+            None => Ok(None),
+        }
+    }
+    pub fn list(&self) -> impl Iterator<Item=Result<Entry, Box<dyn std::error::Error+Send+Sync>>> {
+        self.db.iter().values().map(|value| Ok(serde_json::from_slice(&value?)?))
+    }
+}
+impl OutputSink for Store {
+    fn describe(&self) -> String {
+        format!("the store {}", self.path.display())
+    }
+    fn write_batch(&mut self, batch: &Batch) -> Result<usize, Box<dyn std::error::Error+Send+Sync>> {
+        self.insert(&batch.entries()?)
+    }
+}
@@ -0,0 +1,67 @@
+/*
+ * Counts of the fetched records by state, program type, and month of the dataset's date, the
+ * aggregation most reporting starts with. The counts are kept as the pages come in and written out
+ * as a small CSV and a table on the terminal when the run finishes. Datasets without a program type
+ * or date leave those columns empty.
+ */
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::path::Path;
+use fema::Dataset;
+
+const HEADER: [&str; 4] = ["state", "program_type", "month", "count"];
+
+#[derive(Debug)]
+pub struct Summary {
+    dataset: Dataset,
+    // Counts by state, program type, and month as YYYY-MM
+    counts: BTreeMap<(String, String, String), usize>,
+}
+impl Summary {
+    pub fn new(dataset: Dataset) -> Self {
+        Self { dataset, counts: BTreeMap::new() }
+    }
+    pub fn add<T: Serialize>(&mut self, records: &[T]) -> serde_json::Result<()> {
+        for record in records {
+            let record = serde_json::to_value(record)?;
+            let field = |name: Option<&str>| name.and_then(|name| record.get(name)?.as_str()).unwrap_or_default();
+            let state = field(Some(self.dataset.state_field())).to_owned();
+            let program_type = field(self.dataset.program_type_field()).to_owned();
+            // Dates are RFC 3339, so the month is their first seven characters
+            let month = field(self.dataset.date_field()).chars().take(7).collect();
+            *self.counts.entry((state, program_type, month)).or_insert(0) += 1;
+        }
+        Ok(())
+    }
+    // Number of rows in the summary, one per combination that turned up
+    pub fn len(&self) -> usize {
+        self.counts.len()
+    }
+    pub fn write_csv(&self, path: &Path) -> Result<(), Box<dyn std::error::Error+Send+Sync>> {
+        let mut writer = csv::Writer::from_path(path)?;
+        writer.write_record(HEADER)?;
+        for ((state, program_type, month), count) in &self.counts {
+            writer.write_record([state, program_type, month, &count.to_string()])?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+    // Prints the counts as a table with aligned columns, followed by the total
+    pub fn print(&self, out: &mut impl Write) -> std::io::Result<()> {
+        let rows: Vec<[String; 4]> = self.counts.iter()
+            .map(|((state, program_type, month), count)| [state.clone(), program_type.clone(), month.clone(), count.to_string()])
+            .collect();
+        let mut widths = HEADER.map(str::len);
+        for row in &rows {
+            for (width, cell) in widths.iter_mut().zip(row) {
+                *width = (*width).max(cell.len());
+            }
+        }
+        writeln!(out, "{:<w0$}  {:<w1$}  {:<w2$}  {:>w3$}", HEADER[0], HEADER[1], HEADER[2], HEADER[3], w0 = widths[0], w1 = widths[1], w2 = widths[2], w3 = widths[3])?;
+        for [state, program_type, month, count] in &rows {
+            writeln!(out, "{:<w0$}  {:<w1$}  {:<w2$}  {:>w3$}", state, program_type, month, count, w0 = widths[0], w1 = widths[1], w2 = widths[2], w3 = widths[3])?;
+        }
+        writeln!(out, "Total: {}", self.counts.values().sum::<usize>())
+    }
+}
@@ -0,0 +1,40 @@
+/*
+ * Checkpoints for incremental sync: the latest lastRefresh seen for each dataset, kept in a small
+ * JSON file between runs so the next run only asks for records refreshed since then.
+ */
+use chrono::{DateTime,Utc};
+use std::collections::BTreeMap;
+use std::path::{Path,PathBuf};
+use crate::{Dataset,Error};
+
+#[derive(Debug)]
+pub struct SyncState {
+    path: PathBuf,
+    checkpoints: BTreeMap<String,DateTime<Utc>>,
+}
+impl SyncState {
+    // Reads the state file, starting from no checkpoints if it doesn't exist yet
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        let checkpoints = match std::fs::read_to_string(path) {
+            Ok(contents) => serde_json::from_str(&contents).map_err(|e| Error::Config(format!("Failed to parse sync state file {}: {}", path.display(), e)))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(e) => return Err(std::io::Error::new(e.kind(), format!("Failed to read sync state file {}: {}", path.display(), e)).into()),
+        };
+        Ok(Self { path: path.to_owned(), checkpoints })
+    }
+    pub fn checkpoint(&self, dataset: Dataset) -> Option<DateTime<Utc>> {
+        self.checkpoints.get(dataset.name()).copied()
+    }
+    // Moves the checkpoint forward, never back, so a run that saw nothing new keeps the old one
+    pub fn advance(&mut self, dataset: Dataset, latest: DateTime<Utc>) {
+        let checkpoint = self.checkpoints.entry(dataset.name().to_owned()).or_insert(latest);
+        *checkpoint = (*checkpoint).max(latest);
+    }
+    // Written to a temporary file first so an interrupted save can't lose the previous checkpoints
+    pub fn save(&self) -> Result<(), Error> {
+        let partial = self.path.with_extension("partial");
+        std::fs::write(&partial, serde_json::to_string_pretty(&self.checkpoints).map_err(std::io::Error::from)?)?;
+        std::fs::rename(&partial, &self.path)?;
+        Ok(())
+    }
+}
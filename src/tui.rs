@@ -0,0 +1,346 @@
+/*
+ * Terminal browser for the results of an earlier run, the tui subcommand. The output file (csv,
+ * ndjson, or json, compressed or not) is read into memory and shown as a table that can be paged
+ * through, sorted by any column, and filtered by state, place name, program type, and date. The rows
+ * currently shown can be exported to a CSV file.
+ */
+use flate2::read::GzDecoder;
+use ratatui::crossterm::event::{self,Event,KeyCode,KeyEventKind};
+use ratatui::layout::{Constraint,Layout};
+use ratatui::style::{Modifier,Style};
+use ratatui::text::Line;
+use ratatui::widgets::{Cell,Row as TableRow,Table as TableWidget,TableState};
+use ratatui::{DefaultTerminal,Frame};
+use std::cmp::Ordering;
+use std::io::{BufRead,BufReader,Read};
+use std::path::{Path,PathBuf};
+use fema::{Config,OutputFormat,Row};
+
+// Widest a column is drawn, longer values are cut off
+const MAX_WIDTH: usize = 30;
+// Rows looked at when sizing the columns
+const SIZING_ROWS: usize = 1000;
+
+// The results as text, which is all that's needed to show, sort, filter, and export them
+struct Table {
+    columns: Vec<String>,
+    rows: Vec<Vec<String>>,
+}
+impl Table {
+    fn load(path: &Path, cfg: &Config) -> Result<Self, Box<dyn std::error::Error+Send+Sync>> {
+        let file = std::fs::File::open(path).map_err(|e| format!("Failed to open {}: {}", path.display(), e))?;
+        // The format is told by the extension under any compression one, falling back to output_format
+        let (reader, name): (Box<dyn Read>, &Path) = match path.extension().and_then(|ext| ext.to_str()) {
+            Some("gz") => (Box::new(GzDecoder::new(file)), path.file_stem().map(Path::new).unwrap_or(path)),
+            Some("zst") => (Box::new(zstd::Decoder::new(file)?), path.file_stem().map(Path::new).unwrap_or(path)),
+            _ => (Box::new(file), path),
+        };
+        let format = match name.extension().and_then(|ext| ext.to_str()) {
+            Some("csv") => OutputFormat::Csv,
+            Some("ndjson") | Some("jsonl") => OutputFormat::Ndjson,
+            Some("json") => OutputFormat::Json,
+            _ => cfg.output_format,
+        };
+        match format {
+            OutputFormat::Csv => {
+                let mut reader = csv::ReaderBuilder::new().delimiter(cfg.csv_delimiter as u8).from_reader(reader);
+                let columns = reader.headers()?.iter().map(String::from).collect();
+                let rows = reader.records().map(|record| Ok(record?.iter().map(String::from).collect())).collect::<Result<_, csv::Error>>()?;
+                Ok(Self { columns, rows })
+            },
+            OutputFormat::Ndjson => {
+                let mut records = Vec::new();
+                for line in BufReader::new(reader).lines() {
+                    let line = line?;
+                    if !line.trim().is_empty() {
+                        records.push(serde_json::from_str(&line)?);
+                    }
+                }
+                Ok(Self::from_records(records))
+            },
+            OutputFormat::Json => Ok(Self::from_records(serde_json::from_reader(reader)?)),
+            format => Err(format!("Only csv, ndjson, and json output can be browsed, not {}.", format).into()),
+        }
+    }
+    // Columns are the first record's fields followed by any that only turn up later
+    fn from_records(records: Vec<Row>) -> Self {
+        let mut columns: Vec<String> = Vec::new();
+        for record in &records {
+            for name in record.names() {
+                if !columns.iter().any(|column| column == name) {
+                    columns.push(name.to_owned());
+                }
+            }
+        }
+        let rows = records.iter().map(|record| columns.iter().map(|column| match record.get(column) {
+            Some(serde_json::Value::String(s)) => s.clone(),
+            Some(serde_json::Value::Null) | None => String::new(),
+            Some(value) => value.to_string(),
+        }).collect()).collect();
+        Self { columns, rows }
+    }
+    fn column(&self, names: &[&str]) -> Option<usize> {
+        names.iter().find_map(|name| self.columns.iter().position(|column| column == name))
+    }
+}
+
+#[derive(Debug,Clone,Copy,PartialEq)]
+enum Filter {
+    State,
+    Place,
+    ProgramType,
+    Date,
+}
+impl Filter {
+    const ALL: [Filter; 4] = [Filter::State, Filter::Place, Filter::ProgramType, Filter::Date];
+    fn label(&self) -> &'static str {
+        match self {
+            Filter::State => "state",
+            Filter::Place => "place",
+            Filter::ProgramType => "program type",
+            Filter::Date => "date",
+        }
+    }
+    // States and program types match whole codes, place names any part, and dates from the start, e.g. 2021-08
+    fn matches(&self, value: &str, wanted: &str) -> bool {
+        match self {
+            Filter::State | Filter::ProgramType => value.eq_ignore_ascii_case(wanted),
+            Filter::Place => value.to_lowercase().contains(&wanted.to_lowercase()),
+            Filter::Date => value.starts_with(wanted),
+        }
+    }
+}
+
+enum Input {
+    Filter(Filter),
+    Export(String),
+}
+
+struct Browser {
+    table: Table,
+    path: PathBuf,
+    // The column each filter applies to, if the results have one
+    filter_columns: [Option<usize>; 4],
+    filters: [String; 4],
+    // Column sorted by and whether it's descending
+    sort: Option<(usize, bool)>,
+    // Indices into the table's rows of those shown, in the order shown
+    visible: Vec<usize>,
+    selected: usize,
+    offset: usize,
+    column: usize,
+    first_column: usize,
+    widths: Vec<usize>,
+    // Rows that fit on screen, as of the last draw
+    page: usize,
+    input: Option<Input>,
+    message: String,
+}
+impl Browser {
+    fn new(table: Table, path: &Path, cfg: &Config) -> Self {
+        let date = cfg.dataset.date_field().unwrap_or("designatedDate");
+        let filter_columns = [
+            table.column(&["stateCode", "state"]),
+            table.column(&["placeName", "designatedArea"]),
+            table.column(&["programTypeCode"]),
+            table.column(&[date, "designatedDate", "declarationDate"]),
+        ];
+        let widths = table.columns.iter().enumerate().map(|(i, name)| {
+            table.rows.iter().take(SIZING_ROWS).map(|row| row[i].chars().count()).fold(name.chars().count(), usize::max).min(MAX_WIDTH)
+        }).collect();
+        let visible = (0..table.rows.len()).collect();
+        Self {
+            table, path: path.to_owned(), filter_columns, filters: Default::default(), sort: None, visible,
+            selected: 0, offset: 0, column: 0, first_column: 0, widths, page: 1, input: None, message: String::new(),
+        }
+    }
+    // Recomputes the rows shown after a filter or the sort changes
+    fn refresh(&mut self) {
+        let (table, filters, filter_columns) = (&self.table, &self.filters, &self.filter_columns);
+        self.visible = (0..table.rows.len()).filter(|&i| Filter::ALL.iter().zip(filters).zip(filter_columns).all(|((filter, wanted), column)| {
+            wanted.is_empty() || column.is_some_and(|column| filter.matches(&table.rows[i][column], wanted))
+        })).collect();
+        if let Some((column, descending)) = self.sort {
+            self.visible.sort_by(|&a, &b| {
+                let ordering = compare(&table.rows[a][column], &table.rows[b][column]);
+                if descending { ordering.reverse() } else { ordering }
+            });
+        }
+        self.selected = self.selected.min(self.visible.len().saturating_sub(1));
+    }
+    fn move_to(&mut self, row: usize) {
+        self.selected = row.min(self.visible.len().saturating_sub(1));
+    }
+    fn export(&self, path: &Path) -> Result<usize, Box<dyn std::error::Error+Send+Sync>> {
+        let mut writer = csv::Writer::from_path(path)?;
+        writer.write_record(&self.table.columns)?;
+        for &i in &self.visible {
+            writer.write_record(&self.table.rows[i])?;
+        }
+        writer.flush()?;
+        Ok(self.visible.len())
+    }
+    fn default_export_path(&self) -> String {
+        let stem = self.path.file_name().and_then(|name| name.to_str()).and_then(|name| name.split('.').next()).unwrap_or("results");
+        self.path.with_file_name(format!("{}-selection.csv", stem)).display().to_string()
+    }
+    // Handles a key press, returning false once it's time to quit
+    fn key(&mut self, code: KeyCode) -> bool {
+        match self.input.take() {
+            Some(Input::Filter(filter)) => {
+                let wanted = &mut self.filters[filter as usize];
+                match code {
+                    KeyCode::Enter | KeyCode::Esc => return true,
+                    KeyCode::Backspace => { wanted.pop(); },
+                    KeyCode::Char(c) => wanted.push(c),
+                    _ => (),
+                }
+                self.input = Some(Input::Filter(filter));
+                self.refresh();
+            },
+            Some(Input::Export(mut path)) => match code {
+                KeyCode::Esc => self.message = String::from("Export cancelled."),
+                KeyCode::Enter => {
+                    self.message = match self.export(Path::new(&path)) {
+                        Ok(rows) => format!("Exported {} rows to {}.", rows, path),
+                        Err(e) => format!("Export failed: {}", e),
+                    };
+                },
+                KeyCode::Backspace => { path.pop(); self.input = Some(Input::Export(path)); },
+                KeyCode::Char(c) => { path.push(c); self.input = Some(Input::Export(path)); },
+                _ => self.input = Some(Input::Export(path)),
+            },
+            None => {
+                self.message.clear();
+                match code {
+                    KeyCode::Char('q') | KeyCode::Esc => return false,
+                    KeyCode::Down | KeyCode::Char('j') => self.move_to(self.selected + 1),
+                    KeyCode::Up | KeyCode::Char('k') => self.move_to(self.selected.saturating_sub(1)),
+                    KeyCode::PageDown | KeyCode::Char(' ') => self.move_to(self.selected + self.page),
+                    KeyCode::PageUp => self.move_to(self.selected.saturating_sub(self.page)),
+                    KeyCode::Home | KeyCode::Char('g') => self.move_to(0),
+                    KeyCode::End | KeyCode::Char('G') => self.move_to(usize::MAX),
+                    KeyCode::Right | KeyCode::Char('l') => self.column = (self.column + 1).min(self.table.columns.len().saturating_sub(1)),
+                    KeyCode::Left | KeyCode::Char('h') => self.column = self.column.saturating_sub(1),
+                    // Sorting by the same column again reverses it
+                    KeyCode::Char('s') => {
+                        self.sort = match self.sort {
+                            Some((column, descending)) if column == self.column => Some((column, !descending)),
+                            _ => Some((self.column, false)),
+                        };
+                        self.refresh();
+                    },
+                    KeyCode::Char(c @ ('t' | '/' | 'p' | 'd')) => {
+                        let filter = match c {
+                            't' => Filter::State,
+                            '/' => Filter::Place,
+                            'p' => Filter::ProgramType,
+                            _ => Filter::Date,
+                        };
+                        if self.filter_columns[filter as usize].is_some() {
+                            self.input = Some(Input::Filter(filter));
+                        }
+                        else {
+                            self.message = format!("These results have no {} column to filter on.", filter.label());
+                        }
+                    },
+                    KeyCode::Char('c') => {
+                        self.filters = Default::default();
+                        self.refresh();
+                    },
+                    KeyCode::Char('e') => self.input = Some(Input::Export(self.default_export_path())),
+                    _ => (),
+                }
+            },
+        }
+        true
+    }
+    fn draw(&mut self, frame: &mut Frame) {
+        let [body, status, footer] = Layout::vertical([Constraint::Min(1), Constraint::Length(1), Constraint::Length(1)]).areas(frame.area());
+        // The header takes a line
+        self.page = (body.height as usize).saturating_sub(1).max(1);
+        if self.selected < self.offset {
+            self.offset = self.selected;
+        }
+        else if self.selected >= self.offset + self.page {
+            self.offset = self.selected + 1 - self.page;
+        }
+        // Scroll sideways until the selected column fits
+        self.first_column = self.first_column.min(self.column);
+        while self.first_column < self.column && self.widths[self.first_column..=self.column].iter().map(|w| w + 1).sum::<usize>() > body.width as usize {
+            self.first_column += 1;
+        }
+        let columns = self.first_column..self.table.columns.len();
+        let header = TableRow::new(columns.clone().map(|i| {
+            let mut name = self.table.columns[i].clone();
+            if let Some((_, descending)) = self.sort.filter(|(column, _)| *column == i) {
+                name.push_str(if descending { " v" } else { " ^" });
+            }
+            let style = if i == self.column { Style::default().add_modifier(Modifier::BOLD | Modifier::REVERSED) } else { Style::default().add_modifier(Modifier::BOLD) };
+            Cell::from(name).style(style)
+        }));
+        let rows = self.visible.iter().skip(self.offset).take(self.page).map(|&i| {
+            TableRow::new(columns.clone().map(|column| Cell::from(self.table.rows[i][column].as_str())))
+        });
+        let widths = columns.clone().map(|i| Constraint::Length(self.widths[i] as u16));
+        let table = TableWidget::new(rows, widths).header(header).row_highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+        let mut state = TableState::default().with_selected(Some(self.selected - self.offset).filter(|_| !self.visible.is_empty()));
+        frame.render_stateful_widget(table, body, &mut state);
+        let filters: Vec<String> = Filter::ALL.iter().zip(&self.filters).filter(|(_, wanted)| !wanted.is_empty())
+            .map(|(filter, wanted)| format!("{}: {}", filter.label(), wanted)).collect();
+        let mut line = format!("{} of {} rows", self.visible.len(), self.table.rows.len());
+        if !filters.is_empty() {
+            line.push_str(&format!(" | {}", filters.join(", ")));
+        }
+        if !self.message.is_empty() {
+            line.push_str(&format!(" | {}", self.message));
+        }
+        frame.render_widget(Line::from(line).style(Style::default().add_modifier(Modifier::REVERSED)), status);
+        let footer_line = match &self.input {
+            Some(Input::Filter(filter)) => format!("Filter by {}: {}_  (enter to finish)", filter.label(), self.filters[*filter as usize]),
+            Some(Input::Export(path)) => format!("Export shown rows to: {}_  (enter to write, esc to cancel)", path),
+            None => String::from("arrows move  s sort  t state  / place  p program  d date  c clear  e export  q quit"),
+        };
+        frame.render_widget(Line::from(footer_line), footer);
+    }
+}
+
+// Numbers, like disaster numbers, sort as numbers and everything else, dates included, as text
+fn compare(a: &str, b: &str) -> Ordering {
+    match (a.parse::<f64>(), b.parse::<f64>()) {
+        (Ok(a), Ok(b)) => a.partial_cmp(&b).unwrap_or(Ordering::Equal),
+        _ => a.cmp(b),
+    }
+}
+
+fn run(terminal: &mut DefaultTerminal, browser: &mut Browser) -> std::io::Result<()> {
+    loop {
+        terminal.draw(|frame| browser.draw(frame))?;
+        if let Event::Key(key) = event::read()? {
+            if key.kind == KeyEventKind::Press && !browser.key(key.code) {
+                return Ok(());
+            }
+        }
+    }
+}
+
+// Browses the given file, or the configured output file
+pub fn browse(cfg: &Config, path: Option<&Path>) -> Result<(), Box<dyn std::error::Error+Send+Sync>> {
+    let path = match path {
+        Some(path) => path.to_owned(),
+        None if cfg.path_template().is_some() => return Err("The output path is a template, give the file to browse.".into()),
+        None => {
+            let path = cfg.csv.clone().ok_or("No output file is configured, give the file to browse.")?;
+            match cfg.compress {
+                Some(compression) => PathBuf::from(format!("{}.{}", path.display(), compression.ext())),
+                None => path,
+            }
+        },
+    };
+    let table = Table::load(&path, cfg)?;
+    let mut browser = Browser::new(table, &path, cfg);
+    let mut terminal = ratatui::try_init()?;
+    let result = run(&mut terminal, &mut browser);
+    ratatui::try_restore()?;
+    Ok(result?)
+}
@@ -0,0 +1,90 @@
+/*
+ * Excel output, for people who'd otherwise import the CSV into Excel and have it mangle the dates.
+ * RFC 3339 dates are written as date cells in the timezone they're given in, or in the date_format
+ * if one is set, numbers and booleans as such, and everything else as text, under a frozen header
+ * row with filters. The workbook is kept in memory and saved
+ * when the run finishes, since the file is a zip archive that can't be appended to.
+ */
+use rust_xlsxwriter::{ColNum,Format,RowNum,Workbook,Worksheet};
+use serde::Serialize;
+use std::path::{Path,PathBuf};
+use fema::{Config,DateFormat,Row};
+
+// Longest worksheet name Excel allows
+const MAX_SHEET_NAME: usize = 31;
+
+pub struct XlsxOutput {
+    path: PathBuf,
+    worksheet: Worksheet,
+    header: Format,
+    date: Format,
+    dates: DateFormat,
+    // Column names, written as the header row along with the first record
+    columns: Vec<String>,
+    rows: RowNum,
+}
+impl XlsxOutput {
+    pub fn create(cfg: &Config, path: &Path) -> Result<Self, Box<dyn std::error::Error+Send+Sync>> {
+        // Created empty up front so an unwritable path fails before fetching, and so there's a file to track progress on
+        std::fs::File::create(path)?;
+        let mut worksheet = Worksheet::new();
+        worksheet.set_name(cfg.dataset.name().chars().take(MAX_SHEET_NAME).collect::<String>())?;
+        Ok(Self {
+            path: path.to_owned(),
+            worksheet,
+            header: Format::new().set_bold(),
+            date: Format::new().set_num_format("yyyy-mm-dd hh:mm:ss"),
+            dates: cfg.date_format.clone(),
+            columns: Vec::new(),
+            rows: 0,
+        })
+    }
+    pub fn write<T: Serialize>(&mut self, record: &T, fetched_at: Option<&str>) -> Result<(), Box<dyn std::error::Error+Send+Sync>> {
+        // Read back from the record's JSON to keep its fields in declaration order
+        let mut row: Row = serde_json::from_slice(&serde_json::to_vec(record)?)?;
+        if let Some(fetched_at) = fetched_at {
+            row.0.push((String::from("fetched_at"), serde_json::Value::from(fetched_at)));
+        }
+        // Epoch seconds become numbers and strftime dates text
+        if self.dates != DateFormat::Rfc3339 {
+            for (_, value) in &mut row.0 {
+                if let Some(date) = value.as_str().and_then(|s| self.dates.convert(s)) {
+                    *value = date;
+                }
+            }
+        }
+        if self.columns.is_empty() {
+            self.columns = row.names().map(String::from).collect();
+            for (col, name) in self.columns.iter().enumerate() {
+                self.worksheet.write_string_with_format(0, col as ColNum, name, &self.header)?;
+            }
+        }
+        self.rows += 1;
+        for (col, (_, value)) in row.0.iter().enumerate() {
+            let col = col as ColNum;
+            match value {
+                serde_json::Value::Null => (),
+                serde_json::Value::Bool(b) => { self.worksheet.write_boolean(self.rows, col, *b)?; },
+                serde_json::Value::Number(n) => { self.worksheet.write_number(self.rows, col, n.as_f64().unwrap_or_default())?; },
+                serde_json::Value::String(s) => match chrono::DateTime::parse_from_rfc3339(s) {
+                    Ok(date) => { self.worksheet.write_datetime_with_format(self.rows, col, date.naive_local(), &self.date)?; },
+                    Err(_) => { self.worksheet.write_string(self.rows, col, s)?; },
+                },
+                value => { self.worksheet.write_string(self.rows, col, value.to_string())?; },
+            }
+        }
+        Ok(())
+    }
+    pub fn finish(mut self) -> Result<(), Box<dyn std::error::Error+Send+Sync>> {
+        if !self.columns.is_empty() {
+            let last = (self.columns.len() - 1) as ColNum;
+            self.worksheet.set_freeze_panes(1, 0)?;
+            self.worksheet.autofilter(0, 0, self.rows, last)?;
+            self.worksheet.autofit();
+        }
+        let mut workbook = Workbook::new();
+        workbook.push_worksheet(self.worksheet);
+        workbook.save(&self.path)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,30 @@
+extern crate fema;
+use fema::{Breaker,CircuitBreaker,Config,Error};
+use std::time::Duration;
+
+fn timeout() -> Error {
+    Error::Timeout { url: String::from("http://localhost/"), after: Duration::from_secs(60) }
+}
+
+#[test]
+fn repeated_failures_back_off_for_longer_each_time() {
+    let cfg = Config { circuit_breaker_threshold: 2, circuit_breaker_base_secs: 10, circuit_breaker_max_secs: 25, ..Config::default() };
+    let mut breaker = CircuitBreaker::new(&cfg);
+    assert_eq!(breaker.failed(&timeout()), Breaker::Closed);
+    assert!(!breaker.is_open());
+    assert_eq!(breaker.failed(&timeout()), Breaker::Open(Duration::from_secs(10)));
+    assert_eq!(breaker.failed(&timeout()), Breaker::Open(Duration::from_secs(20)));
+    assert_eq!(breaker.failed(&timeout()), Breaker::Open(Duration::from_secs(25)));
+    assert!(breaker.is_open());
+    // One good poll closes it, and the count starts over
+    breaker.succeeded();
+    assert!(!breaker.is_open());
+    assert_eq!(breaker.failed(&timeout()), Breaker::Closed);
+}
+
+#[test]
+fn only_api_failures_are_ridden_out() {
+    assert!(CircuitBreaker::counts(&timeout()));
+    assert!(!CircuitBreaker::counts(&Error::Config(String::from("bad"))));
+    assert!(!CircuitBreaker::counts(&Error::Io(std::io::Error::other("disk full"))));
+}
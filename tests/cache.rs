@@ -0,0 +1,38 @@
+extern crate fema;
+mod common;
+use common::scratch;
+use fema::{ResponseCache,Validators};
+
+#[test]
+fn stats_count_the_cached_responses() {
+    let dir = scratch("cache-stats");
+    let cache = ResponseCache::new(&dir.join("cache"));
+    // Nothing cached before the directory exists
+    assert_eq!(cache.stats().unwrap().entries, 0);
+    let validators = Validators { etag: Some(String::from("\"a\"")), last_modified: None };
+    let before = chrono::Utc::now() - chrono::Duration::seconds(5);
+    cache.store("http://example.com/a", &validators, b"first").unwrap();
+    cache.store("http://example.com/b", &Validators::default(), b"second body").unwrap();
+    let stats = cache.stats().unwrap();
+    assert_eq!(stats.entries, 2);
+    // The bodies along with their metadata
+    assert!(stats.bytes > 16, "{:?}", stats);
+    let (oldest, newest) = (stats.oldest.unwrap(), stats.newest.unwrap());
+    assert!(before <= oldest && oldest <= newest, "{:?}", stats);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn purge_empties_the_cache() {
+    let dir = scratch("cache-purge");
+    let cache = ResponseCache::new(&dir);
+    assert_eq!(cache.purge().unwrap().entries, 0);
+    cache.store("http://example.com/a", &Validators::default(), b"first").unwrap();
+    cache.save_run(fema::Dataset::FemaWebDeclarationAreas, &fema::CachedRun { started: chrono::Utc::now(), filter: String::new() }).unwrap();
+    assert_eq!(cache.purge().unwrap().entries, 1);
+    assert!(dir.is_dir());
+    assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 0);
+    assert!(cache.validators("http://example.com/a").is_none());
+    assert!(cache.last_run(fema::Dataset::FemaWebDeclarationAreas).unwrap().is_none());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
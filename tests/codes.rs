@@ -0,0 +1,36 @@
+extern crate fema;
+extern crate serde_json;
+use fema::{Config,Entry,ProgramType,StateCode};
+
+#[test]
+fn codes_are_read_without_regard_to_case() {
+    assert_eq!(StateCode::from(" tx"), StateCode::TX);
+    assert_eq!(ProgramType::from("ia"), ProgramType::IndividualAssistance);
+    assert_eq!("hm".parse::<ProgramType>(), Ok(ProgramType::HazardMitigation));
+    assert_eq!(StateCode::PR.to_string(), "PR");
+    assert_eq!(StateCode::ALL.len(), fema::STATE_CODES.len());
+}
+
+#[test]
+fn unknown_codes_in_records_are_kept() {
+    let entry: Entry = serde_json::from_value(serde_json::json!({ "programTypeCode": "XX", "stateCode": "la", "designatedDate": "2020-01-01T00:00:00.000Z", "id": "a" })).unwrap();
+    assert_eq!(entry.programTypeCode, ProgramType::Other(String::from("XX")));
+    assert!(!entry.programTypeCode.is_known());
+    assert_eq!(entry.stateCode, StateCode::LA);
+    let written = serde_json::to_value(&entry).unwrap();
+    assert_eq!(written["programTypeCode"], "XX");
+    assert_eq!(written["stateCode"], "LA");
+}
+
+#[test]
+fn unknown_codes_in_filters_are_rejected() {
+    let e = "ZZ".parse::<StateCode>().unwrap_err();
+    assert!(e.contains("Unknown state code ZZ") && e.contains("TX"), "{}", e);
+    let cfg: Config = serde_json::from_value(serde_json::json!({ "states": ["TX", "ZZ"] })).unwrap();
+    let e = cfg.validate().unwrap_err();
+    assert!(e.contains("ZZ") && e.contains("states"), "{}", e);
+    assert!(Config { program_types: Some(vec![ProgramType::from("IX")]), ..Config::default() }.validate().is_err());
+    let channel = fema::ChatChannel { states: Some(vec![StateCode::from("Texas")]), url: String::from("http://localhost/"), ..fema::ChatChannel::default() };
+    assert!(Config { chat: vec![channel], ..Config::default() }.validate().is_err());
+    assert!(Config { states: Some(vec![StateCode::TX]), program_types: Some(vec![ProgramType::PublicAssistance]), ..Config::default() }.validate().is_ok());
+}
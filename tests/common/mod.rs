@@ -0,0 +1,334 @@
+/*
+ * Hand-rolled mock of the OpenFEMA API shared by the integration tests, serving canned pages of
+ * FemaWebDeclarationAreas entries
+ */
+#![allow(dead_code)]
+use tokio::io::{AsyncReadExt,AsyncWriteExt};
+use tokio::net::TcpListener;
+
+// An empty directory to work in, named after the test's process so concurrent runs don't share it
+pub fn scratch(name: &str) -> std::path::PathBuf {
+    let dir = std::env::temp_dir().join(format!("fema-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+pub fn entry(id: usize) -> String {
+    entry_of(&id.to_string(), "abc", "Anderson (County)")
+}
+
+// The JSON of an entry with the given id, hash, and placeName
+pub fn entry_of(id: &str, hash: &str, place: &str) -> String {
+    format!(r#"{{"disasterNumber":4000,"programTypeCode":"IA","programTypeDescription":"Individual Assistance","stateCode":"TX","placeCode":"99001","placeName":"{}","designatedDate":"2020-01-01T00:00:00.000Z","entryDate":"2020-01-01T00:00:00.000Z","updateDate":"2020-01-01T00:00:00.000Z","closeoutDate":null,"hash":"{}","lastRefresh":"2020-01-01T00:00:00.000Z","id":"{}"}}"#, place, hash, id)
+}
+
+// The same entry, deserialized
+pub fn parsed_entry(id: &str, hash: &str, place: &str) -> fema::Entry {
+    serde_json::from_str(&entry_of(id, hash, place)).unwrap()
+}
+
+pub fn page(metadata: Option<usize>, ids: std::ops::Range<usize>) -> String {
+    page_of(metadata.map(|count| (count, 1000)), ids)
+}
+
+// A page whose metadata reports the page size the server applied along with the count
+pub fn page_of(metadata: Option<(usize, usize)>, ids: std::ops::Range<usize>) -> String {
+    let entries: Vec<String> = ids.map(entry).collect();
+    let metadata = match metadata {
+        Some((count, top)) => format!(r#""metadata":{{"skip":0,"top":{},"count":{},"filter":"","format":"json","metadata":true,"orderby":{{}},"select":"","entityname":"FemaWebDeclarationAreas","version":"v1","url":"/api/open/v1/FemaWebDeclarationAreas","rundate":"2020-01-01T00:00:00.000Z","DeprecationInformation":{{"depDate":null}}}},"#, top, count),
+        None => String::new(),
+    };
+    format!(r#"{{{}"FemaWebDeclarationAreas":[{}]}}"#, metadata, entries.join(","))
+}
+
+// Serves whichever body is paired with the $skip value in the request, or a 404, counting requests served
+pub async fn serve(pages: Vec<(&'static str, String)>) -> (String, std::sync::Arc<std::sync::atomic::AtomicUsize>) {
+    serve_pages(pages, false).await
+}
+
+// Like serve, but never answers requests for other pages, as if the server had stalled
+pub async fn serve_then_stall(pages: Vec<(&'static str, String)>) -> (String, std::sync::Arc<std::sync::atomic::AtomicUsize>) {
+    serve_pages(pages, true).await
+}
+
+async fn serve_pages(pages: Vec<(&'static str, String)>, stall: bool) -> (String, std::sync::Arc<std::sync::atomic::AtomicUsize>) {
+    serve_matching(pages.into_iter().map(|(skip, body)| (format!("$skip={}&", skip), body)).collect(), stall).await
+}
+
+// Like serve, but pairing each body with any text in the request, e.g. a dataset's path, the first match winning
+pub async fn serve_routes(routes: Vec<(&'static str, String)>) -> (String, std::sync::Arc<std::sync::atomic::AtomicUsize>) {
+    serve_matching(routes.into_iter().map(|(pattern, body)| (String::from(pattern), body)).collect(), false).await
+}
+
+async fn serve_matching(routes: Vec<(String, String)>, stall: bool) -> (String, std::sync::Arc<std::sync::atomic::AtomicUsize>) {
+    let mut listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let served = std::sync::Arc::new(std::sync::atomic::AtomicUsize::new(0));
+    let counter = served.clone();
+    tokio::spawn(async move {
+        loop {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut buf = [0; 8192];
+            let n = socket.read(&mut buf).await.unwrap();
+            let request = String::from_utf8_lossy(&buf[..n]).into_owned();
+            let (status, body) = match routes.iter().find(|(pattern, _)| request.contains(pattern.as_str())) {
+                Some((_, body)) => ("200 OK", body.clone()),
+                None if stall => {
+                    tokio::spawn(async move {
+                        let _socket = socket;
+                        futures::future::pending::<()>().await
+                    });
+                    continue;
+                },
+                None => ("404 Not Found", String::new()),
+            };
+            let head = format!("HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n", status, body.len());
+            socket.write_all(head.as_bytes()).await.unwrap();
+            socket.write_all(body.as_bytes()).await.unwrap();
+            counter.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+        }
+    });
+    (format!("http://{}/", addr), served)
+}
+
+// Like serve, but answering each page after its delay in milliseconds, several at once, so later pages can arrive first
+pub async fn serve_delayed(pages: Vec<(&'static str, String, u64)>) -> String {
+    let mut listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let pages = std::sync::Arc::new(pages);
+    tokio::spawn(async move {
+        loop {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let pages = pages.clone();
+            tokio::spawn(async move {
+                let mut buf = [0; 8192];
+                let n = socket.read(&mut buf).await.unwrap();
+                let request = String::from_utf8_lossy(&buf[..n]).into_owned();
+                let (status, body) = match pages.iter().find(|(skip, _, _)| request.contains(&format!("$skip={}&", skip))) {
+                    Some((_, body, delay)) => {
+                        tokio::time::delay_for(std::time::Duration::from_millis(*delay)).await;
+                        ("200 OK", body.clone())
+                    },
+                    None => ("404 Not Found", String::new()),
+                };
+                let head = format!("HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n", status, body.len());
+                socket.write_all(head.as_bytes()).await.unwrap();
+                socket.write_all(body.as_bytes()).await.unwrap();
+            });
+        }
+    });
+    format!("http://{}/", addr)
+}
+
+// Like serve, but keeps each connection open for further requests and counts connections accepted
+pub async fn serve_keep_alive(pages: Vec<(&'static str, String)>) -> (String, std::sync::Arc<std::sync::atomic::AtomicUsize>) {
+    let mut listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let accepted = std::sync::Arc::new(std::sync::atomic::AtomicUsize::new(0));
+    let counter = accepted.clone();
+    let pages = std::sync::Arc::new(pages);
+    tokio::spawn(async move {
+        loop {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            counter.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            let pages = pages.clone();
+            tokio::spawn(async move {
+                let mut buf = [0; 8192];
+                loop {
+                    let n = socket.read(&mut buf).await.unwrap_or(0);
+                    if n == 0 {
+                        break;
+                    }
+                    let request = String::from_utf8_lossy(&buf[..n]).into_owned();
+                    let body = pages.iter().find(|(skip, _)| request.contains(&format!("$skip={}&", skip))).map(|(_, body)| body.clone()).unwrap();
+                    let head = format!("HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n", body.len());
+                    socket.write_all(head.as_bytes()).await.unwrap();
+                    socket.write_all(body.as_bytes()).await.unwrap();
+                }
+            });
+        }
+    });
+    (format!("http://{}/", addr), accepted)
+}
+
+/*
+ * Accepts POSTed requests, answering the first failures of them with a 500 and the rest with a 204,
+ * and keeps each successful request's headers and body
+ */
+pub async fn capture(failures: usize) -> (String, std::sync::Arc<std::sync::Mutex<Vec<(String, String)>>>) {
+    let mut listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let received = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+    let requests = received.clone();
+    tokio::spawn(async move {
+        let mut attempts = 0;
+        loop {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut request = Vec::new();
+            let mut buf = [0; 8192];
+            // Read until the headers are in, then until the body is as long as they say
+            let (head, length) = loop {
+                let n = socket.read(&mut buf).await.unwrap();
+                request.extend_from_slice(&buf[..n]);
+                if let Some(end) = request.windows(4).position(|w| w == b"\r\n\r\n") {
+                    let head = String::from_utf8_lossy(&request[..end]).into_owned();
+                    let length = head.lines().find_map(|line| line.to_ascii_lowercase().strip_prefix("content-length:").map(|v| v.trim().parse::<usize>().unwrap())).unwrap_or(0);
+                    request.drain(..end + 4);
+                    break (head, length);
+                }
+            };
+            while request.len() < length {
+                let n = socket.read(&mut buf).await.unwrap();
+                request.extend_from_slice(&buf[..n]);
+            }
+            attempts += 1;
+            let status = if attempts <= failures { "500 Internal Server Error" } else { "204 No Content" };
+            if attempts > failures {
+                requests.lock().unwrap().push((head, String::from_utf8_lossy(&request).into_owned()));
+            }
+            socket.write_all(format!("HTTP/1.1 {}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n", status).as_bytes()).await.unwrap();
+        }
+    });
+    (format!("http://{}/hook", addr), received)
+}
+
+// Plain SMTP server accepting every message, keeping the data of each, on the returned port
+pub async fn smtp() -> (u16, std::sync::Arc<std::sync::Mutex<Vec<String>>>) {
+    use tokio::io::{AsyncBufReadExt,BufReader};
+    let mut listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let received = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+    let messages = received.clone();
+    tokio::spawn(async move {
+        loop {
+            let (socket, _) = listener.accept().await.unwrap();
+            let messages = messages.clone();
+            tokio::spawn(async move {
+                let (reader, mut writer) = tokio::io::split(socket);
+                let mut reader = BufReader::new(reader);
+                writer.write_all(b"220 localhost ESMTP\r\n").await.unwrap();
+                let mut line = String::new();
+                while reader.read_line(&mut line).await.unwrap() > 0 {
+                    let command = line.trim_end().to_ascii_uppercase();
+                    line.clear();
+                    let reply: &[u8] = if command.starts_with("DATA") {
+                        writer.write_all(b"354 End data with <CR><LF>.<CR><LF>\r\n").await.unwrap();
+                        let mut data = String::new();
+                        while reader.read_line(&mut line).await.unwrap() > 0 && line != ".\r\n" {
+                            data.push_str(&line);
+                            line.clear();
+                        }
+                        line.clear();
+                        messages.lock().unwrap().push(data);
+                        b"250 OK\r\n"
+                    }
+                    else if command.starts_with("QUIT") {
+                        writer.write_all(b"221 Bye\r\n").await.unwrap();
+                        break;
+                    }
+                    else {
+                        b"250 OK\r\n"
+                    };
+                    writer.write_all(reply).await.unwrap();
+                }
+            });
+        }
+    });
+    (port, received)
+}
+
+/*
+ * MQTT broker accepting every connection, acknowledging QoS 1 publishes, and keeping the topic and
+ * payload of each PUBLISH along with the client id of each CONNECT, as ("CONNECT", client id)
+ */
+pub async fn mqtt() -> (String, std::sync::Arc<std::sync::Mutex<Vec<(String, String)>>>) {
+    async fn read_packet(socket: &mut tokio::net::TcpStream) -> Option<(u8, Vec<u8>)> {
+        let header = socket.read_u8().await.ok()?;
+        let (mut length, mut shift) = (0, 0);
+        loop {
+            let byte = socket.read_u8().await.ok()?;
+            length |= ((byte & 0x7f) as usize) << shift;
+            shift += 7;
+            if byte & 0x80 == 0 {
+                break;
+            }
+        }
+        let mut body = vec![0; length];
+        socket.read_exact(&mut body).await.ok()?;
+        Some((header, body))
+    }
+    fn string(body: &[u8]) -> (String, &[u8]) {
+        let length = u16::from_be_bytes([body[0], body[1]]) as usize;
+        (String::from_utf8_lossy(&body[2..2 + length]).into_owned(), &body[2 + length..])
+    }
+    let mut listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let received = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+    let messages = received.clone();
+    tokio::spawn(async move {
+        loop {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let messages = messages.clone();
+            tokio::spawn(async move {
+                while let Some((header, body)) = read_packet(&mut socket).await {
+                    match header >> 4 {
+                        1 => {
+                            // Protocol name, level, flags, and keep alive come before the client id
+                            let (client_id, _) = string(&body[10..]);
+                            messages.lock().unwrap().push((String::from("CONNECT"), client_id));
+                            socket.write_all(&[0x20, 2, 0, 0]).await.unwrap();
+                        },
+                        3 => {
+                            let (topic, rest) = string(&body);
+                            let qos = (header >> 1) & 3;
+                            let payload = if qos > 0 {
+                                socket.write_all(&[0x40, 2, rest[0], rest[1]]).await.unwrap();
+                                &rest[2..]
+                            }
+                            else {
+                                rest
+                            };
+                            messages.lock().unwrap().push((topic, String::from_utf8_lossy(payload).into_owned()));
+                        },
+                        _ => break,
+                    }
+                }
+            });
+        }
+    });
+    (format!("mqtt://{}", addr), received)
+}
+
+/*
+ * Like serve, but with an ETag on every page, answering requests whose If-None-Match has it with 304
+ * Not Modified. Counts the full responses and the 304s separately.
+ */
+pub async fn serve_conditional(pages: Vec<(&'static str, String)>) -> (String, std::sync::Arc<std::sync::atomic::AtomicUsize>, std::sync::Arc<std::sync::atomic::AtomicUsize>) {
+    let mut listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let (served, not_modified) = (std::sync::Arc::new(std::sync::atomic::AtomicUsize::new(0)), std::sync::Arc::new(std::sync::atomic::AtomicUsize::new(0)));
+    let (served_counter, not_modified_counter) = (served.clone(), not_modified.clone());
+    tokio::spawn(async move {
+        loop {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut buf = [0; 8192];
+            let n = socket.read(&mut buf).await.unwrap();
+            let request = String::from_utf8_lossy(&buf[..n]).into_owned();
+            let (skip, body) = pages.iter().find(|(skip, _)| request.contains(&format!("$skip={}&", skip))).unwrap();
+            let etag = format!("\"page-{}-{}\"", skip, body.len());
+            let conditional = request.lines().any(|line| line.to_ascii_lowercase().starts_with("if-none-match:") && line.ends_with(&etag));
+            if conditional {
+                socket.write_all(format!("HTTP/1.1 304 Not Modified\r\nETag: {}\r\nConnection: close\r\n\r\n", etag).as_bytes()).await.unwrap();
+                not_modified_counter.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            }
+            else {
+                let head = format!("HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nETag: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n", etag, body.len());
+                socket.write_all(head.as_bytes()).await.unwrap();
+                socket.write_all(body.as_bytes()).await.unwrap();
+                served_counter.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            }
+        }
+    });
+    (format!("http://{}/", addr), served, not_modified)
+}
@@ -0,0 +1,145 @@
+extern crate chrono;
+extern crate fema;
+extern crate serde_json;
+use fema::{Config,ProgramType,StateCode};
+
+fn vars(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+    pairs.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()
+}
+
+#[test]
+fn env_overrides_fields() {
+    let mut cfg = Config::default();
+    cfg.apply_env(vars(&[
+        ("FEMA_WEB_DECLARATION_NUM_YEARS_PREVIOUS", "1"),
+        ("FEMA_WEB_DECLARATION_CSV", "/data/out.csv"),
+        ("FEMA_WEB_DECLARATION_OUTPUT_FORMAT", "ndjson"),
+        ("FEMA_WEB_DECLARATION_STATES", "TX, LA"),
+        ("FEMA_WEB_DECLARATION_PROGRAM_TYPES", r#"["IA"]"#),
+        ("FEMA_WEB_DECLARATION_JSON_LOGS", "true"),
+        ("FEMA_WEB_DECLARATION_PROGRESS_FILE", "null"),
+        ("HOME", "/root"),
+    ])).unwrap();
+    assert_eq!(cfg.num_years_previous, 1);
+    assert_eq!(cfg.csv, Some(std::path::PathBuf::from("/data/out.csv")));
+    assert_eq!(cfg.output_format, fema::OutputFormat::Ndjson);
+    assert_eq!(cfg.states, Some(vec![StateCode::TX, StateCode::LA]));
+    assert_eq!(cfg.program_types, Some(vec![ProgramType::IndividualAssistance]));
+    assert!(cfg.json_logs);
+    assert_eq!(cfg.progress_file, None);
+}
+
+#[test]
+fn env_values_the_field_cant_take_are_rejected() {
+    let mut cfg = Config::default();
+    let err = cfg.apply_env(vars(&[("FEMA_WEB_DECLARATION_NUM_YEARS_PREVIOUS", "three")])).unwrap_err();
+    assert!(err.contains("FEMA_WEB_DECLARATION_NUM_YEARS_PREVIOUS"));
+    assert_eq!(cfg.num_years_previous, Config::default().num_years_previous);
+    let err = cfg.apply_env(vars(&[("FEMA_WEB_DECLARATION_NUM_YEARS", "1")])).unwrap_err();
+    assert!(err.contains("no config field"));
+}
+
+#[test]
+fn validate_checks_page_size_and_template() {
+    assert!(Config::default().validate().is_ok());
+    assert!(Config { page_size: 0, ..Config::default() }.validate().is_err());
+    assert!(Config { output_template: Some(String::from("out-{nope}.csv")), ..Config::default() }.validate().is_err());
+    assert!(Config { csv: Some(std::path::PathBuf::from("out-{nope}.csv")), ..Config::default() }.validate().is_err());
+    assert!(Config { compress: Some(fema::Compression::Zstd), ..Config::default() }.validate().is_ok());
+    assert!(Config { compress: Some(fema::Compression::Gzip), output_format: fema::OutputFormat::Html, ..Config::default() }.validate().is_err());
+    assert!(Config { enrich: true, ..Config::default() }.validate().is_ok());
+    assert!(Config { enrich: true, dataset: fema::Dataset::DisasterDeclarationsSummaries, ..Config::default() }.validate().is_err());
+    assert!(Config { enrich: true, enrich_fields: Vec::new(), ..Config::default() }.validate().is_err());
+    assert!(Config { summarize: true, split_by: Some(String::from("stateCode")), ..Config::default() }.validate().is_err());
+}
+
+#[test]
+fn csv_path_can_be_a_template() {
+    use chrono::TimeZone;
+    assert_eq!(Config::default().path_template(), None);
+    let cfg = Config { csv: Some(std::path::PathBuf::from("declarations-{date}-{years}y.csv")), num_years_previous: 3, ..Config::default() };
+    assert_eq!(cfg.path_template().as_deref(), Some("declarations-{date}-{years}y.csv"));
+    let states = vec![StateCode::from("tx"), StateCode::LA];
+    let values = fema::TemplateValues {
+        now: chrono::Utc.with_ymd_and_hms(2024, 1, 10, 5, 6, 7).unwrap(), count: 12, state: None, ext: "csv",
+        dataset: cfg.dataset, years: cfg.num_years_previous, states: Some(&states),
+    };
+    assert_eq!(fema::expand_template("declarations-{date}-{years}y.csv", &values).unwrap(), "declarations-2024-01-10-3y.csv");
+    assert_eq!(fema::expand_template("{dataset}/{states}-{time}-{count}.{ext}", &values).unwrap(), "FemaWebDeclarationAreas/TX-LA-050607-12.csv");
+    assert_eq!(fema::expand_template("{states}", &fema::TemplateValues { states: None, ..values }).unwrap(), "all");
+}
+
+#[test]
+fn watch_takes_an_interval_or_a_cron_schedule() {
+    use chrono::TimeZone;
+    assert!(Config::default().watch().unwrap().is_none());
+    let now = chrono::Local.with_ymd_and_hms(2024, 1, 10, 5, 0, 0).unwrap();
+    let every = Config { watch: Some(String::from("15m")), ..Config::default() }.watch().unwrap().unwrap();
+    assert_eq!(every.next_wait(now), Some(std::time::Duration::from_secs(15 * 60)));
+    let cron = Config { schedule: Some(String::from("0 6 * * *")), ..Config::default() }.watch().unwrap().unwrap();
+    assert_eq!(cron.next_wait(now), Some(std::time::Duration::from_secs(60 * 60)));
+    assert!(Config { schedule: Some(String::from("0 6 * *")), ..Config::default() }.watch().is_err());
+    assert!(Config { watch: Some(String::from("1h")), schedule: Some(String::from("0 6 * * *")), ..Config::default() }.watch().is_err());
+}
+
+#[test]
+fn output_timezone_takes_utc_local_or_an_iana_name() {
+    use fema::OutputTimezone;
+    let cfg: Config = serde_json::from_value(serde_json::json!({ "output_timezone": "America/Chicago" })).unwrap();
+    let chicago = cfg.output_timezone.unwrap();
+    assert_eq!(chicago.to_string(), "America/Chicago");
+    // Designated in the evening local time, the day before in UTC terms it isn't
+    assert_eq!(chicago.convert("2017-08-26T00:30:00.000Z").as_deref(), Some("2017-08-25T19:30:00-05:00"));
+    assert_eq!(chicago.convert("2017-01-26T00:30:00Z").as_deref(), Some("2017-01-25T18:30:00-06:00"));
+    assert_eq!(chicago.convert("Anderson (County)"), None);
+    assert_eq!("utc".parse::<OutputTimezone>(), Ok(OutputTimezone::Utc));
+    assert_eq!("Local".parse::<OutputTimezone>(), Ok(OutputTimezone::Local));
+    assert_eq!(OutputTimezone::Utc.convert("2017-08-25T19:30:00-05:00").as_deref(), Some("2017-08-26T00:30:00Z"));
+    assert!("Central".parse::<OutputTimezone>().is_err());
+    assert!(serde_json::from_value::<Config>(serde_json::json!({ "output_timezone": "Mars/Olympus" })).is_err());
+}
+
+#[test]
+fn csv_dialect_is_checked() {
+    let cfg: Config = serde_json::from_value(serde_json::json!({ "csv_delimiter": "\t", "csv_quote_style": "always", "csv_line_ending": "crlf" })).unwrap();
+    assert_eq!(cfg.csv_quote_style, fema::QuoteStyle::Always);
+    assert!(cfg.validate().is_ok());
+    assert!(Config { csv_delimiter: '"', ..Config::default() }.validate().is_err());
+    assert!(Config { csv_delimiter: 'é', ..Config::default() }.validate().is_err());
+    let e = Config { csv_header: false, output_format: fema::OutputFormat::Json, ..Config::default() }.validate().unwrap_err();
+    assert!(e.contains("only apply to csv output"), "{}", e);
+    assert!(serde_json::from_value::<Config>(serde_json::json!({ "csv_line_ending": "cr" })).is_err());
+}
+
+#[test]
+fn output_columns_are_fields_optionally_renamed() {
+    use fema::OutputColumn;
+    let cfg: Config = serde_json::from_value(serde_json::json!({ "output_columns": ["placeName -> \"Place\"", "designatedDate"] })).unwrap();
+    let columns = cfg.output_columns.as_deref().unwrap();
+    assert_eq!(columns[0], OutputColumn { field: String::from("placeName"), name: String::from("Place") });
+    assert_eq!(columns[1].to_string(), "designatedDate");
+    assert!(cfg.validate().is_ok());
+    assert!("-> Place".parse::<OutputColumn>().is_err());
+    let cfg: Config = serde_json::from_value(serde_json::json!({ "output_columns": ["placeName -> Place", "stateCode -> Place"] })).unwrap();
+    assert!(cfg.validate().unwrap_err().contains("Place"));
+}
+
+#[test]
+fn append_mode_needs_plain_csv_with_ids() {
+    let append = Config { csv_mode: fema::CsvMode::Append, ..Config::default() };
+    assert!(append.validate().is_ok());
+    assert!(Config { output_format: fema::OutputFormat::Ndjson, ..append.clone() }.validate().is_err());
+    assert!(Config { compress: Some(fema::Compression::Gzip), ..append.clone() }.validate().is_err());
+    assert!(Config { csv_header: false, ..append.clone() }.validate().is_err());
+    assert!(Config { csv: Some(std::path::PathBuf::from("out-{count}.csv")), ..append.clone() }.validate().is_err());
+    assert!(Config { select: Some(vec![String::from("placeName")]), ..append.clone() }.validate().unwrap_err().contains("id"));
+    assert!(Config { output_columns: Some(vec!["id -> ID".parse().unwrap()]), ..append }.validate().is_ok());
+}
+
+#[test]
+fn sort_window_needs_an_order() {
+    let window = Config { sort_window: Some(100), order_by: Some(String::from("designatedDate")), ..Config::default() };
+    assert!(window.validate().is_ok());
+    assert!(Config { order_by: None, ..window.clone() }.validate().unwrap_err().contains("order_by"));
+    assert!(Config { sort_window: Some(0), ..window }.validate().is_err());
+}
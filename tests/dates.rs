@@ -0,0 +1,60 @@
+extern crate chrono;
+extern crate csv;
+extern crate fema;
+extern crate serde_json;
+use chrono::{TimeZone,Utc};
+use fema::{Config,DateFormat,Entry,OutputFormat};
+
+fn entry() -> Entry {
+    Entry {
+        designatedDate: Utc.with_ymd_and_hms(2017, 8, 25, 17, 30, 0).unwrap(),
+        closeoutDate: None,
+        id: String::from("a"),
+        ..Entry::default()
+    }
+}
+
+// The designatedDate and closeoutDate cells of the entry written as CSV in format
+fn csv_dates(format: &DateFormat) -> (String, String) {
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    format.scope(|| writer.serialize(entry())).unwrap();
+    let written = String::from_utf8(writer.into_inner().unwrap()).unwrap();
+    let mut reader = csv::Reader::from_reader(written.as_bytes());
+    let headers = reader.headers().unwrap().clone();
+    let row = reader.records().next().unwrap().unwrap();
+    let field = |name| row[headers.iter().position(|header| header == name).unwrap()].to_owned();
+    (field("designatedDate"), field("closeoutDate"))
+}
+
+#[test]
+fn dates_are_written_in_the_chosen_format() {
+    assert_eq!(csv_dates(&DateFormat::Rfc3339), (String::from("2017-08-25T17:30:00Z"), String::new()));
+    assert_eq!(csv_dates(&DateFormat::Epoch), (String::from("1503682200"), String::new()));
+    assert_eq!(csv_dates(&"%Y-%m-%d".parse().unwrap()), (String::from("2017-08-25"), String::new()));
+    assert_eq!(csv_dates(&"%m/%d/%Y %H:%M".parse().unwrap()), (String::from("08/25/2017 17:30"), String::new()));
+}
+
+#[test]
+fn the_format_only_applies_within_its_scope() {
+    DateFormat::Epoch.scope(|| assert_eq!(serde_json::to_value(entry()).unwrap()["designatedDate"], 1503682200));
+    assert_eq!(serde_json::to_value(entry()).unwrap()["designatedDate"], "2017-08-25T17:30:00Z");
+}
+
+#[test]
+fn selected_dates_are_converted_in_their_own_offset() {
+    let format: DateFormat = "%Y-%m-%d".parse().unwrap();
+    assert_eq!(format.convert("2017-08-25T19:30:00-05:00"), Some(serde_json::json!("2017-08-25")));
+    assert_eq!(DateFormat::Epoch.convert("2017-08-25T12:30:00-05:00"), Some(serde_json::json!(1503682200)));
+    assert_eq!(format.convert("Anderson (County)"), None);
+}
+
+#[test]
+fn invalid_formats_are_rejected() {
+    assert_eq!("EPOCH".parse::<DateFormat>(), Ok(DateFormat::Epoch));
+    assert!("%Q".parse::<DateFormat>().is_err());
+    assert!("yyyy-mm-dd".parse::<DateFormat>().is_err());
+    let cfg: Config = serde_json::from_value(serde_json::json!({ "date_format": "%Y-%m-%d" })).unwrap();
+    assert!(cfg.validate().is_ok());
+    let cfg = Config { output_format: OutputFormat::Json, ..cfg };
+    assert!(cfg.validate().unwrap_err().contains("csv and xlsx"));
+}
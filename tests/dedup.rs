@@ -0,0 +1,50 @@
+extern crate fema;
+extern crate serde_json;
+mod common;
+use common::{parsed_entry,scratch};
+use fema::{Row,SeenSet};
+
+#[test]
+fn records_are_seen_again_only_once_they_change() {
+    let dir = scratch("dedup-changes");
+    let path = dir.join("seen.json");
+    let mut seen = SeenSet::load(&path).unwrap();
+    assert!(seen.is_empty());
+    assert!(seen.insert(&parsed_entry("a", "1", "Anderson")));
+    assert!(seen.insert(&parsed_entry("b", "1", "Anderson")));
+    assert!(!seen.insert(&parsed_entry("a", "1", "Anderson")));
+    seen.save().unwrap();
+    let mut seen = SeenSet::load(&path).unwrap();
+    assert_eq!(seen.len(), 2);
+    assert!(!seen.insert(&parsed_entry("b", "1", "Anderson")));
+    assert!(seen.insert(&parsed_entry("b", "2", "Anderson")));
+    assert!(!seen.insert(&parsed_entry("b", "2", "Anderson")));
+    assert!(seen.insert(&parsed_entry("c", "1", "Anderson")));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn selected_fields_are_deduplicated_by_id_and_updated_date() {
+    let dir = scratch("dedup-rows");
+    let mut seen = SeenSet::load(&dir.join("seen.json")).unwrap();
+    let select = vec![String::from("id"), String::from("updateDate")];
+    let row = |id: &str, updated: &str| {
+        let record = serde_json::json!({ "id": id, "updateDate": updated, "placeName": "Anderson" });
+        Row::from_map(record.as_object().unwrap().clone(), &select)
+    };
+    assert!(seen.insert(&row("a", "2020-01-01T00:00:00.000Z")));
+    assert!(!seen.insert(&row("a", "2020-01-01T00:00:00.000Z")));
+    assert!(seen.insert(&row("a", "2021-01-01T00:00:00.000Z")));
+    // Without an id there's no telling records apart, so they're all kept
+    let anonymous = Row::from_map(serde_json::json!({ "placeName": "Anderson" }).as_object().unwrap().clone(), &[String::from("placeName")]);
+    assert!(seen.insert(&anonymous));
+    assert!(seen.insert(&anonymous));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn dedup_files_are_not_for_diff_mode() {
+    let cfg = fema::Config { dedup_file: Some("seen.json".into()), ..fema::Config::default() };
+    assert!(cfg.validate().is_ok());
+    assert!(fema::Config { diff: Some("snapshot.json".into()), ..cfg }.validate().is_err());
+}
@@ -0,0 +1,38 @@
+extern crate fema;
+extern crate serde_json;
+mod common;
+use common::{parsed_entry,scratch};
+use fema::{ChangeKind,Snapshot};
+
+#[test]
+fn changes_are_found_by_id_and_hash() {
+    let dir = scratch("diff-changes");
+    let path = dir.join("snapshot.json");
+    assert!(Snapshot::load(&path).unwrap().is_none());
+    let mut previous = Snapshot::new(&path);
+    previous.extend(&[parsed_entry("a", "1", "Anderson"), parsed_entry("b", "1", "Bexar"), parsed_entry("c", "1", "Collin")]).unwrap();
+    previous.save().unwrap();
+    let previous = Snapshot::load(&path).unwrap().unwrap();
+    assert_eq!(previous.len(), 3);
+    let mut current = Snapshot::new(&path);
+    // The hash is what counts, so a differing field under the same hash isn't a change
+    current.extend(&[parsed_entry("b", "2", "Bexar"), parsed_entry("c", "1", "Dallas"), parsed_entry("d", "1", "Denton")]).unwrap();
+    let changes = current.diff(&previous);
+    let kinds: Vec<(ChangeKind, &str)> = changes.iter().map(|change| (change.change, change.id.as_str())).collect();
+    assert_eq!(kinds, vec![(ChangeKind::Removed, "a"), (ChangeKind::Changed, "b"), (ChangeKind::Added, "d")]);
+    assert_eq!(changes[1].previous.as_ref().unwrap()["hash"], "1");
+    assert_eq!(changes[1].record["hash"], "2");
+    fema::write_changes(&dir.join("changes.json"), &changes).unwrap();
+    let written: Vec<fema::Change> = serde_json::from_str(&std::fs::read_to_string(dir.join("changes.json")).unwrap()).unwrap();
+    assert_eq!(written, changes);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn diff_mode_needs_complete_pulls() {
+    let cfg = fema::Config { diff: Some("snapshot.json".into()), ..fema::Config::default() };
+    assert!(cfg.validate().is_ok());
+    assert!(fema::Config { sync_state: Some("sync.json".into()), ..cfg.clone() }.validate().is_err());
+    assert!(fema::Config { watch: Some(String::from("1h")), ..cfg.clone() }.validate().is_err());
+    assert!(fema::Config { select: Some(vec![String::from("placeName")]), ..cfg }.validate().is_err());
+}
@@ -0,0 +1,56 @@
+extern crate fema;
+extern crate serde_json;
+use fema::{Entry,Page,SchemaDrift};
+
+// A page whose entries have lost their placeCode and gained a countyName
+fn drifted() -> serde_json::Value {
+    let entry = |id: &str| serde_json::json!({
+        "disasterNumber": 4000, "programTypeCode": "IA", "programTypeDescription": "Individual Assistance",
+        "stateCode": "TX", "placeName": "Anderson", "countyName": "Anderson County",
+        "designatedDate": "2020-01-01T00:00:00.000Z", "entryDate": "2020-01-01T00:00:00.000Z", "updateDate": "2020-01-01T00:00:00.000Z",
+        "hash": "abc", "lastRefresh": "2020-01-01T00:00:00.000Z", "id": id,
+    });
+    serde_json::json!({ "FemaWebDeclarationAreas": [entry("a"), entry("b")] })
+}
+
+#[test]
+fn drifted_entries_are_still_read() {
+    let page: Page<Entry> = Page::from_value(drifted(), "FemaWebDeclarationAreas", &SchemaDrift::new(false)).unwrap();
+    assert_eq!(page.records.len(), 2);
+    assert_eq!(page.records[1].id, "b");
+    assert_eq!(page.records[0].placeCode, "");
+    assert_eq!(page.records[0].extra["countyName"], "Anderson County");
+    // Added fields aren't written out with the known ones
+    let written = serde_json::to_value(&page.records[0]).unwrap();
+    assert!(written.get("countyName").is_none());
+    assert_eq!(written["placeCode"], "");
+}
+
+#[test]
+fn strict_mode_fails_drifted_pages() {
+    let e = Page::<Entry>::from_value(drifted(), "FemaWebDeclarationAreas", &SchemaDrift::new(true)).unwrap_err();
+    let message = e.to_string();
+    assert!(message.contains("countyName") && message.contains("placeCode"), "{}", message);
+}
+
+#[test]
+fn matching_records_are_not_drift() {
+    let page = serde_json::json!({ "FemaWebDeclarationAreas": [{ "id": "a", "anything": 1 }] });
+    let page: Page<serde_json::Value> = Page::from_value(page, "FemaWebDeclarationAreas", &SchemaDrift::new(true)).unwrap();
+    assert_eq!(page.records.len(), 1);
+}
+
+#[test]
+fn missing_dates_are_none_and_ids_are_required() {
+    let page = serde_json::json!({ "FemaWebDeclarationAreas": [{ "designatedDate": "2020-01-01T00:00:00.000Z", "id": "a" }] });
+    let page: Page<Entry> = Page::from_value(page, "FemaWebDeclarationAreas", &SchemaDrift::new(false)).unwrap();
+    assert_eq!(page.records[0].lastRefresh, None);
+    assert_eq!(page.records[0].updateDate, None);
+    assert!(serde_json::to_value(&page.records[0]).unwrap()["entryDate"].is_null());
+    // Without an id, records couldn't be told apart, so the page fails rather than giving them all an empty one
+    let page = serde_json::json!({ "FemaWebDeclarationAreas": [{ "designatedDate": "2020-01-01T00:00:00.000Z" }] });
+    let e = Page::<Entry>::from_value(page, "FemaWebDeclarationAreas", &SchemaDrift::new(false)).unwrap_err();
+    assert!(e.to_string().contains("id"), "{}", e);
+    let page = serde_json::json!({ "FemaWebDeclarationAreas": [{ "id": "a" }] });
+    assert!(Page::<Entry>::from_value(page, "FemaWebDeclarationAreas", &SchemaDrift::new(false)).is_err());
+}
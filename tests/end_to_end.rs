@@ -0,0 +1,1037 @@
+extern crate fema;
+extern crate tokio;
+extern crate toml;
+extern crate hmac;
+extern crate sha2;
+extern crate flate2;
+extern crate zstd;
+extern crate reqwest;
+mod common;
+use common::{capture,entry,mqtt,page,scratch,serve,serve_conditional,serve_delayed,serve_routes,serve_then_stall,smtp};
+
+// A scratch directory holding the config file, with {dir} in config replaced by the directory's path
+fn configured(name: &str, config: &str) -> std::path::PathBuf {
+    let dir = scratch(name);
+    std::fs::write(dir.join("config.toml"), config.replace("{dir}", dir.to_str().unwrap())).unwrap();
+    dir
+}
+
+fn command(dir: &std::path::Path, base_url: &str) -> tokio::process::Command {
+    let mut command = tokio::process::Command::new(env!("CARGO_BIN_EXE_fema"));
+    command.arg("--config").arg(dir.join("config.toml"))
+        .current_dir(dir)
+        .env("XDG_CONFIG_HOME", dir.join("xdg"))
+        .env("HOME", dir)
+        .env("FEMA_BASE_URL", base_url);
+    command
+}
+
+// Runs the binary against the mock server with a config file in a scratch directory, returning that directory
+async fn run(name: &str, base_url: &str, config: &str) -> (std::process::Output, std::path::PathBuf) {
+    let dir = configured(name, config);
+    let output = command(&dir, base_url).output().await.unwrap();
+    (output, dir)
+}
+
+#[tokio::test]
+async fn pages_are_written_to_csv() {
+    let (uri, served) = serve(vec![
+        ("0", page(Some(1500), 0..1000)),
+        ("1000", page(None, 1000..1500)),
+    ]).await;
+    let (output, dir) = run("csv", &uri, "csv = \"{dir}/out.csv\"\nprogress_file = \"{dir}/progress.json\"\n").await;
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let mut reader = csv::Reader::from_path(dir.join("out.csv")).unwrap();
+    let headers = reader.headers().unwrap().clone();
+    assert_eq!(headers.get(0), Some("disasterNumber"));
+    assert_eq!(headers.get(headers.len()-1), Some("id"));
+    let ids: Vec<String> = reader.records().map(|r| r.unwrap().get(headers.len()-1).unwrap().to_owned()).collect();
+    assert_eq!(ids.len(), 1500);
+    assert_eq!(ids[0], "0");
+    assert_eq!(ids[1499], "1499");
+    assert_eq!(served.load(std::sync::atomic::Ordering::SeqCst), 2);
+    // A completed run leaves nothing to resume
+    assert!(!dir.join("progress.json").exists());
+    // The default logging config is written out on the first run, logging under the data directory
+    assert!(dir.join("xdg/fema-web-declaration/log4rs.yml").is_file());
+    assert!(dir.join(".local/share/fema-web-declaration/log/fema-web-declaration.log").is_file());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn ndjson_output_round_trips() {
+    let (uri, _) = serve(vec![("0", page(Some(3), 0..3))]).await;
+    let (output, dir) = run("ndjson", &uri, "csv = \"{dir}/out.ndjson\"\noutput_format = \"ndjson\"\nprogress_file = \"{dir}/progress.json\"\n").await;
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let contents = std::fs::read_to_string(dir.join("out.ndjson")).unwrap();
+    let entries: Vec<serde_json::Value> = contents.lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+    assert_eq!(entries.len(), 3);
+    assert_eq!(entries[2]["id"], "2");
+    assert_eq!(entries[0]["stateCode"], "TX");
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn missing_endpoint_exits_with_deprecated_status() {
+    let (uri, _) = serve(vec![]).await;
+    let (output, dir) = run("missing", &format!("{}missing", uri), "csv = \"{dir}/out.csv\"\nprogress_file = \"{dir}/progress.json\"\n").await;
+    assert_eq!(output.status.code(), Some(3), "{}", String::from_utf8_lossy(&output.stderr));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn interrupted_run_keeps_whole_pages() {
+    let (uri, _) = serve_then_stall(vec![("0", page(Some(2000), 0..1000))]).await;
+    let dir = configured("interrupt", "csv = \"{dir}/out.csv\"\nprogress_file = \"{dir}/progress.json\"\n");
+    let child = command(&dir, &uri).stdout(std::process::Stdio::null()).stderr(std::process::Stdio::null()).spawn().unwrap();
+    // The progress file appears once the first page is on disk, the second page never arrives
+    while !dir.join("progress.json").exists() {
+        tokio::time::delay_for(std::time::Duration::from_millis(20)).await;
+    }
+    std::process::Command::new("kill").arg("-TERM").arg(child.id().to_string()).status().unwrap();
+    let status = tokio::time::timeout(std::time::Duration::from_secs(10), child).await.unwrap().unwrap();
+    assert_eq!(status.code(), Some(130));
+    let mut reader = csv::Reader::from_path(dir.join("out.csv")).unwrap();
+    let records: Vec<csv::StringRecord> = reader.records().collect::<Result<_, _>>().unwrap();
+    assert_eq!(records.len(), 1000);
+    // Left behind for --resume
+    assert!(dir.join("progress.json").exists());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn json_logs_carry_run_fields() {
+    let (uri, _) = serve(vec![("0", page(Some(3), 0..3))]).await;
+    let (output, dir) = run("json-logs", &uri, "csv = \"{dir}/out.csv\"\njson_logs = true\n").await;
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let log = std::fs::read_to_string(dir.join(".local/share/fema-web-declaration/log/fema-web-declaration.log")).unwrap();
+    let lines: Vec<serde_json::Value> = log.lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+    // The progress summary at the end of the fetch is logged at info level by default
+    let summary = lines.iter().find(|line| line["target"] == "fema::meter").unwrap();
+    assert_eq!(summary["level"], "INFO");
+    assert_eq!(summary["mdc"]["dataset"], "FemaWebDeclarationAreas");
+    assert_eq!(summary["mdc"]["page"], "1");
+    assert!(summary["mdc"]["filter"].as_str().unwrap().contains("closeoutDate"));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn config_subcommand_sets_and_validates() {
+    let dir = configured("config", "csv = \"{dir}/out.csv\"\n");
+    let config = |args: &[&str]| command(&dir, "http://127.0.0.1:9/").arg("config").args(args).output();
+    let path = config(&["path"]).await.unwrap();
+    assert_eq!(String::from_utf8_lossy(&path.stdout).trim(), dir.join("config.toml").to_str().unwrap());
+    assert!(config(&["set", "states", "TX,LA"]).await.unwrap().status.success());
+    assert!(config(&["set", "num_years_previous", "1"]).await.unwrap().status.success());
+    let show = config(&["show"]).await.unwrap();
+    let shown: fema::Config = toml::from_str(&String::from_utf8_lossy(&show.stdout)).unwrap();
+    assert_eq!(shown.num_years_previous, 1);
+    assert_eq!(shown.states, Some(vec![fema::StateCode::TX, fema::StateCode::LA]));
+    // Overrides show up but aren't saved
+    let show = command(&dir, "http://127.0.0.1:9/").env("FEMA_WEB_DECLARATION_PAGE_SIZE", "500").args(["config", "show"]).output().await.unwrap();
+    assert!(String::from_utf8_lossy(&show.stdout).contains("page_size = 500"));
+    assert!(!std::fs::read_to_string(dir.join("config.toml")).unwrap().contains("page_size = 500"));
+    assert!(config(&["validate"]).await.unwrap().status.success());
+    assert_eq!(config(&["set", "page_size", "big"]).await.unwrap().status.code(), Some(2));
+    assert!(config(&["set", "page_size", "0"]).await.unwrap().status.success());
+    assert_eq!(config(&["validate"]).await.unwrap().status.code(), Some(2));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn years_on_the_command_line_replace_a_configured_since() {
+    let dir = configured("years", "since = \"2017-08-01\"\n");
+    let show = command(&dir, "http://127.0.0.1:9/").args(["--years", "2", "config", "show"]).output().await.unwrap();
+    assert!(show.status.success(), "{}", String::from_utf8_lossy(&show.stderr));
+    let shown: fema::Config = toml::from_str(&String::from_utf8_lossy(&show.stdout)).unwrap();
+    assert_eq!(shown.num_years_previous, 2);
+    assert_eq!(shown.since, None);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn completions_list_subcommands_and_values() {
+    let dir = configured("completions", "");
+    let output = command(&dir, "http://127.0.0.1:9/").args(["completions", "bash"]).output().await.unwrap();
+    assert!(output.status.success());
+    let script = String::from_utf8_lossy(&output.stdout);
+    for word in ["diagnose", "completions", "ndjson", "FemaWebDeclarationAreas", "TX"] {
+        assert!(script.contains(word), "{} missing from the completion script", word);
+    }
+    // State codes are checked against the list but case doesn't matter
+    let show = command(&dir, "http://127.0.0.1:9/").args(["--state", "tx,la", "config", "show"]).output().await.unwrap();
+    let shown: fema::Config = toml::from_str(&String::from_utf8_lossy(&show.stdout)).unwrap();
+    assert_eq!(shown.states, Some(vec![fema::StateCode::TX, fema::StateCode::LA]));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn watch_mode_only_writes_new_records() {
+    let (uri, served) = serve(vec![("0", page(Some(3), 0..3))]).await;
+    let dir = configured("watch", "csv = \"{dir}/out.csv\"\nwatch = \"1s\"\n");
+    let child = command(&dir, &uri).stdout(std::process::Stdio::null()).stderr(std::process::Stdio::null()).spawn().unwrap();
+    while served.load(std::sync::atomic::Ordering::SeqCst) < 2 {
+        tokio::time::delay_for(std::time::Duration::from_millis(20)).await;
+    }
+    // Stopping between polls is a normal end to watching
+    tokio::time::delay_for(std::time::Duration::from_millis(200)).await;
+    std::process::Command::new("kill").arg("-TERM").arg(child.id().to_string()).status().unwrap();
+    let status = tokio::time::timeout(std::time::Duration::from_secs(10), child).await.unwrap().unwrap();
+    assert!(status.success());
+    let mut reader = csv::Reader::from_path(dir.join("out.csv")).unwrap();
+    let records: Vec<csv::StringRecord> = reader.records().collect::<Result<_, _>>().unwrap();
+    assert_eq!(records.len(), 3);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn diff_mode_reports_changes_since_the_previous_run() {
+    let config = "csv = \"{dir}/out.csv\"\ndiff = \"{dir}/snapshot.json\"\ndiff_output = \"{dir}/changes.csv\"\n";
+    let dir = configured("diff", config);
+    let (uri, _) = serve(vec![("0", page(Some(3), 0..3))]).await;
+    let output = command(&dir, &uri).output().await.unwrap();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    // There's nothing to compare the first run with
+    assert!(dir.join("snapshot.json").is_file());
+    assert!(!dir.join("changes.csv").exists());
+    let (uri, _) = serve(vec![("0", page(Some(3), 1..4))]).await;
+    let output = command(&dir, &uri).output().await.unwrap();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let mut reader = csv::Reader::from_path(dir.join("changes.csv")).unwrap();
+    let headers = reader.headers().unwrap().clone();
+    let id = headers.iter().position(|name| name == "id").unwrap();
+    let changes: Vec<(String, String)> = reader.records().map(|r| {
+        let r = r.unwrap();
+        (r[0].to_owned(), r[id].to_owned())
+    }).collect();
+    assert_eq!(changes, vec![(String::from("removed"), String::from("0")), (String::from("added"), String::from("3"))]);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn records_already_written_are_left_out_of_later_runs() {
+    let dir = configured("dedup", "csv = \"{dir}/out.ndjson\"\noutput_format = \"ndjson\"\ndedup_file = \"{dir}/seen.json\"\n");
+    let (uri, _) = serve(vec![("0", page(Some(3), 0..3))]).await;
+    let output = command(&dir, &uri).output().await.unwrap();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(std::fs::read_to_string(dir.join("out.ndjson")).unwrap().lines().count(), 3);
+    let (uri, _) = serve(vec![("0", page(Some(3), 1..4))]).await;
+    let output = command(&dir, &uri).output().await.unwrap();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let contents = std::fs::read_to_string(dir.join("out.ndjson")).unwrap();
+    let ids: Vec<String> = contents.lines().map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap()["id"].as_str().unwrap().to_owned()).collect();
+    assert_eq!(ids, vec![String::from("3")]);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn csv_can_be_appended_to_across_runs() {
+    let dir = configured("append", "csv = \"{dir}/out.csv\"\ncsv_mode = \"append\"\nprogress_file = \"{dir}/progress.json\"\n");
+    for ids in [0..3, 1..4] {
+        let (uri, _) = serve(vec![("0", page(Some(3), ids))]).await;
+        let output = command(&dir, &uri).output().await.unwrap();
+        assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    }
+    let mut reader = csv::Reader::from_path(dir.join("out.csv")).unwrap();
+    let index = reader.headers().unwrap().iter().position(|header| header == "id").unwrap();
+    let ids: Vec<String> = reader.records().map(|record| record.unwrap()[index].to_owned()).collect();
+    assert_eq!(ids, vec!["0", "1", "2", "3"]);
+    // Nor is one written with other columns or in another dialect
+    for (args, stamp) in [(&["--csv-delimiter=;"][..], "false"), (&["--csv-line-ending=crlf"], "false"), (&[], "true")] {
+        let (uri, _) = serve(vec![("0", page(Some(3), 3..6))]).await;
+        let output = command(&dir, &uri).args(args).env("FEMA_WEB_DECLARATION_STAMP_FETCH_TIME", stamp).output().await.unwrap();
+        assert_eq!(output.status.code(), Some(2), "{}", String::from_utf8_lossy(&output.stderr));
+        assert!(String::from_utf8_lossy(&output.stderr).contains("can't be appended to"), "{}", String::from_utf8_lossy(&output.stderr));
+    }
+    assert_eq!(csv::Reader::from_path(dir.join("out.csv")).unwrap().records().count(), 4);
+    std::fs::remove_dir_all(&dir).unwrap();
+    // The ids are found under their output column's name
+    let dir = configured("append-columns", "csv = \"{dir}/out.csv\"\ncsv_mode = \"append\"\noutput_columns = [\"id -> ID\", \"placeName -> Place\"]\n");
+    for ids in [0..2, 0..3] {
+        let (uri, _) = serve(vec![("0", page(Some(3), ids))]).await;
+        let output = command(&dir, &uri).output().await.unwrap();
+        assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    }
+    assert_eq!(std::fs::read_to_string(dir.join("out.csv")).unwrap(),
+        "ID,Place\n0,Anderson (County)\n1,Anderson (County)\n2,Anderson (County)\n");
+    // A file without that column isn't appended to
+    std::fs::write(dir.join("out.csv"), "id,Place\n0,Anderson (County)\n").unwrap();
+    let (uri, _) = serve(vec![("0", page(Some(3), 0..3))]).await;
+    let output = command(&dir, &uri).output().await.unwrap();
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("no ID column"), "{}", String::from_utf8_lossy(&output.stderr));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn pages_out_of_order_are_sorted_within_the_window() {
+    // The second page's records all belong before the first's
+    let (uri, _) = serve(vec![
+        ("0", page(Some(1500), 2000..3000)),
+        ("1000", page(None, 1000..1500)),
+    ]).await;
+    let (output, dir) = run("sort-window", &uri, "csv = \"{dir}/out.csv\"\norder_by = \"id\"\nsort_window = 1000\n").await;
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let mut reader = csv::Reader::from_path(dir.join("out.csv")).unwrap();
+    let index = reader.headers().unwrap().iter().position(|header| header == "id").unwrap();
+    let ids: Vec<usize> = reader.records().map(|record| record.unwrap()[index].parse().unwrap()).collect();
+    assert_eq!(ids, (1000..1500).chain(2000..3000).collect::<Vec<_>>());
+    let log = std::fs::read_to_string(dir.join(".local/share/fema-web-declaration/log/fema-web-declaration.log")).unwrap();
+    assert!(!log.contains("out of order"), "{}", log);
+    std::fs::remove_dir_all(&dir).unwrap();
+    // With a smaller window the first page's first records are written before the second page comes
+    let (uri, _) = serve(vec![
+        ("0", page(Some(1500), 2000..3000)),
+        ("1000", page(None, 1000..1500)),
+    ]).await;
+    let (output, dir) = run("sort-window-small", &uri, "csv = \"{dir}/out.csv\"\norder_by = \"id\"\nsort_window = 100\n").await;
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let log = std::fs::read_to_string(dir.join(".local/share/fema-web-declaration/log/fema-web-declaration.log")).unwrap();
+    assert!(log.contains("500 records came more than sort_window records out of place"), "{}", log);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn pages_are_written_as_they_arrive_unless_server_order_is_kept() {
+    let ids = |config: &'static str| async move {
+        // The second page takes longer than the third
+        let uri = serve_delayed(vec![
+            ("0", page(Some(3000), 0..1000), 0),
+            ("1000", page(None, 1000..2000), 500),
+            ("2000", page(None, 2000..3000), 0),
+        ]).await;
+        let (output, dir) = run("server-order", &uri, config).await;
+        assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+        let mut reader = csv::Reader::from_path(dir.join("out.csv")).unwrap();
+        let index = reader.headers().unwrap().iter().position(|header| header == "id").unwrap();
+        let ids: Vec<usize> = reader.records().map(|record| record.unwrap()[index].parse().unwrap()).collect();
+        std::fs::remove_dir_all(&dir).unwrap();
+        ids
+    };
+    assert_eq!(ids("csv = \"{dir}/out.csv\"\n").await, (0..1000).chain(2000..3000).chain(1000..2000).collect::<Vec<_>>());
+    assert_eq!(ids("csv = \"{dir}/out.csv\"\npreserve_server_order = true\n").await, (0..3000).collect::<Vec<_>>());
+    // A run that can be resumed keeps the order too, since it resumes after the number of pages written
+    assert_eq!(ids("csv = \"{dir}/out.csv\"\nprogress_file = \"{dir}/progress.json\"\n").await, (0..3000).collect::<Vec<_>>());
+}
+
+#[tokio::test]
+async fn future_dates_are_dropped_from_v2_and_selected_records_too() {
+    for options in ["", "api_version = \"v2\"\n", "select = [\"id\", \"designatedDate\"]\n"] {
+        let body = page(Some(3), 0..3).replacen(r#""designatedDate":"2020-01-01T00:00:00.000Z""#, r#""designatedDate":"2999-01-01T00:00:00.000Z""#, 1);
+        let (uri, _) = serve(vec![("0", body)]).await;
+        let (output, dir) = run("future-dates", &uri, &format!("csv = \"{{dir}}/out.csv\"\ndrop_future_dates = true\n{}", options)).await;
+        assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+        let mut reader = csv::Reader::from_path(dir.join("out.csv")).unwrap();
+        let index = reader.headers().unwrap().iter().position(|header| header == "id").unwrap();
+        let ids: Vec<String> = reader.records().map(|record| record.unwrap()[index].to_owned()).collect();
+        assert_eq!(ids, vec!["1", "2"], "{}", options);
+        let log = std::fs::read_to_string(dir.join(".local/share/fema-web-declaration/log/fema-web-declaration.log")).unwrap();
+        assert!(log.contains("Dropped 1 entries with a designatedDate in the future."), "{}", log);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
+
+#[tokio::test]
+async fn suspicious_records_are_reported() {
+    let body = page(Some(3), 0..3).replacen("Anderson (County)", "", 1).replace(r#""id":"2""#, r#""id":"0""#);
+    let (uri, _) = serve(vec![("0", body)]).await;
+    let (output, dir) = run("quality", &uri, "csv = \"{dir}/out.csv\"\nvalidation_report = \"{dir}/issues.csv\"\n").await;
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    // Suspicious records are still written
+    let mut reader = csv::Reader::from_path(dir.join("out.csv")).unwrap();
+    assert_eq!(reader.records().count(), 3);
+    let mut reader = csv::Reader::from_path(dir.join("issues.csv")).unwrap();
+    let issues: Vec<(String, String)> = reader.records().map(|r| {
+        let r = r.unwrap();
+        (r[1].to_owned(), r[2].to_owned())
+    }).collect();
+    assert_eq!(issues, vec![(String::from("0"), String::from("missing-value")), (String::from("0"), String::from("duplicate-id"))]);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn dropped_fields_are_left_empty_unless_strict() {
+    let body = page(Some(3), 0..3).replace(r#""placeCode":"99001","#, "");
+    let (uri, _) = serve(vec![("0", body)]).await;
+    let dir = configured("drift", "csv = \"{dir}/out.csv\"\n");
+    let output = command(&dir, &uri).output().await.unwrap();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let mut reader = csv::Reader::from_path(dir.join("out.csv")).unwrap();
+    let place = reader.headers().unwrap().iter().position(|name| name == "placeCode").unwrap();
+    let records: Vec<csv::StringRecord> = reader.records().collect::<Result<_, _>>().unwrap();
+    assert_eq!(records.len(), 3);
+    assert_eq!(&records[0][place], "");
+    let output = command(&dir, &uri).arg("--strict-schema").output().await.unwrap();
+    assert_eq!(output.status.code(), Some(5), "{}", String::from_utf8_lossy(&output.stderr));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn new_records_are_posted_to_the_webhook() {
+    use hmac::Mac;
+    let (uri, _) = serve(vec![("0", page(Some(3), 0..3))]).await;
+    let (hook, received) = capture(1).await;
+    let config = format!("csv = \"{{dir}}/out.csv\"\nsync_state = \"{{dir}}/sync.json\"\nwebhook_url = \"{}\"\nwebhook_secret = \"s3cret\"\nretry_base_delay_ms = 1\n", hook);
+    let (output, dir) = run("webhook", &uri, &config).await;
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    // The first attempt failed and was retried
+    let received = received.lock().unwrap();
+    assert_eq!(received.len(), 1);
+    let (head, body) = &received[0];
+    let payload: serde_json::Value = serde_json::from_str(body).unwrap();
+    assert_eq!(payload["dataset"], "FemaWebDeclarationAreas");
+    assert_eq!(payload["count"], 3);
+    assert_eq!(payload["records"][2]["id"], "2");
+    let mut mac = hmac::Hmac::<sha2::Sha256>::new_from_slice(b"s3cret").unwrap();
+    mac.update(body.as_bytes());
+    let expected = format!("x-fema-signature-256: sha256={:x}", mac.finalize().into_bytes());
+    assert!(head.lines().any(|line| line.eq_ignore_ascii_case(&expected)), "{}", head);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn selected_fields_are_posted_to_the_webhook() {
+    let (uri, _) = serve(vec![("0", page(Some(2), 0..2))]).await;
+    let (hook, received) = capture(0).await;
+    let config = format!("csv = \"{{dir}}/out.csv\"\nselect = [\"id\", \"placeName\"]\nwebhook_url = \"{}\"\n", hook);
+    let (output, dir) = run("webhook-select", &uri, &config).await;
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let received = received.lock().unwrap();
+    assert_eq!(received.len(), 1);
+    let payload: serde_json::Value = serde_json::from_str(&received[0].1).unwrap();
+    assert_eq!(payload["count"], 2);
+    assert_eq!(payload["records"][1], serde_json::json!({"id": "1", "placeName": "Anderson (County)"}));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn chat_channels_are_alerted_about_wanted_records() {
+    let (uri, _) = serve(vec![("0", page(Some(2), 0..2))]).await;
+    let (slack, slack_received) = capture(0).await;
+    let (discord, discord_received) = capture(0).await;
+    let config = format!("csv = \"{{dir}}/out.csv\"\nsync_state = \"{{dir}}/sync.json\"\n\n[[chat]]\nkind = \"slack\"\nurl = \"{}\"\nprogram_types = [\"IA\"]\n\n[[chat]]\nkind = \"discord\"\nurl = \"{}\"\nprogram_types = [\"PA\"]\n", slack, discord);
+    let (output, dir) = run("chat", &uri, &config).await;
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let received = slack_received.lock().unwrap();
+    assert_eq!(received.len(), 1);
+    let message: serde_json::Value = serde_json::from_str(&received[0].1).unwrap();
+    assert_eq!(message["text"].as_str().unwrap().lines().count(), 2);
+    assert!(message["text"].as_str().unwrap().starts_with("New IA designation: Anderson (County), TX"));
+    // None of the records are PA designations
+    assert!(discord_received.lock().unwrap().is_empty());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn new_records_are_emailed() {
+    let (uri, _) = serve(vec![("0", page(Some(3), 0..3))]).await;
+    let (port, received) = smtp().await;
+    let config = format!("csv = \"{{dir}}/out.csv\"\nsync_state = \"{{dir}}/sync.json\"\n\n[email]\nsmtp_host = \"127.0.0.1\"\nsmtp_port = {}\ntls = \"none\"\nfrom = \"fema@example.com\"\nto = [\"em@example.com\"]\n", port);
+    let (output, dir) = run("email", &uri, &config).await;
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let received = received.lock().unwrap();
+    assert_eq!(received.len(), 1);
+    let message = &received[0];
+    assert!(message.contains("Subject: FemaWebDeclarationAreas: 3 new, 0 changed, 0 removed"), "{}", message);
+    assert!(message.contains("To: em@example.com"), "{}", message);
+    assert_eq!(message.matches("added: TX Anderson (County): IA designated 2020-01-01").count(), 3, "{}", message);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn unchanged_pages_come_from_the_http_cache() {
+    let (uri, served, not_modified) = serve_conditional(vec![
+        ("0", page(Some(1500), 0..1000)),
+        ("1000", page(None, 1000..1500)),
+    ]).await;
+    let config = "csv = \"{dir}/out.csv\"\nhttp_cache = \"{dir}/cache\"\n";
+    let (output, dir) = run("http-cache", &uri, config).await;
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let first = std::fs::read_to_string(dir.join("out.csv")).unwrap();
+    assert_eq!(served.load(std::sync::atomic::Ordering::SeqCst), 2);
+    // The second run asks whether each page changed and gets the same records from the cache
+    let output = command(&dir, &uri).output().await.unwrap();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(served.load(std::sync::atomic::Ordering::SeqCst), 2);
+    assert_eq!(not_modified.load(std::sync::atomic::Ordering::SeqCst), 2);
+    assert_eq!(std::fs::read_to_string(dir.join("out.csv")).unwrap(), first);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn the_http_cache_can_be_inspected_and_purged() {
+    let (uri, _) = serve(vec![("0", page(Some(2), 0..2))]).await;
+    let (output, dir) = run("cache-command", &uri, "csv = \"{dir}/out.csv\"\nhttp_cache = \"{dir}/cache\"\n").await;
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let output = command(&dir, &uri).args(["cache", "stats"]).output().await.unwrap();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.starts_with("1 cached responses taking up "), "{}", stdout);
+    assert!(stdout.contains("Oldest stored ") && stdout.contains("Newest stored "), "{}", stdout);
+    let output = command(&dir, &uri).args(["cache", "purge"]).output().await.unwrap();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert!(String::from_utf8_lossy(&output.stdout).starts_with("Removed 1 cached responses"), "{}", String::from_utf8_lossy(&output.stdout));
+    assert_eq!(std::fs::read_dir(dir.join("cache")).unwrap().count(), 0);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn unknown_codes_are_rejected_before_counting() {
+    let (uri, served) = serve(vec![("0", page(Some(2), 0..2))]).await;
+    let dir = configured("count-codes", "states = [\"XX\"]\n");
+    for args in [&["--count"][..], &["--count", "--by-state"]] {
+        let output = command(&dir, &uri).args(args).output().await.unwrap();
+        assert_eq!(output.status.code(), Some(2), "{}", String::from_utf8_lossy(&output.stderr));
+    }
+    assert_eq!(served.load(std::sync::atomic::Ordering::SeqCst), 0);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn offline_runs_repeat_the_last_run_from_the_cache() {
+    let (uri, served) = serve(vec![
+        ("0", page(Some(1500), 0..1000)),
+        ("1000", page(None, 1000..1500)),
+    ]).await;
+    let dir = configured("offline", "csv = \"{dir}/out.csv\"\nhttp_cache = \"{dir}/cache\"\n");
+    // Nothing has been cached to repeat yet
+    let output = command(&dir, &uri).arg("--offline").output().await.unwrap();
+    assert_eq!(output.status.code(), Some(2), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(served.load(std::sync::atomic::Ordering::SeqCst), 0);
+    let output = command(&dir, &uri).output().await.unwrap();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(served.load(std::sync::atomic::Ordering::SeqCst), 2);
+    // The same records written in another format without asking the server again
+    std::fs::write(dir.join("config.toml"), format!("csv = \"{0}/out.ndjson\"\noutput_format = \"ndjson\"\nhttp_cache = \"{0}/cache\"\n", dir.display())).unwrap();
+    let output = command(&dir, &uri).arg("--offline").output().await.unwrap();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(served.load(std::sync::atomic::Ordering::SeqCst), 2);
+    let contents = std::fs::read_to_string(dir.join("out.ndjson")).unwrap();
+    assert_eq!(contents.lines().count(), 1500);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn recorded_runs_replay_without_the_server() {
+    let (uri, served) = serve(vec![
+        ("0", page(Some(1500), 0..1000)),
+        ("1000", page(None, 1000..1500)),
+    ]).await;
+    let (output, dir) = run("record", &uri, "csv = \"{dir}/out.csv\"\n").await;
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let output = command(&dir, &uri).arg("--replay").arg(dir.join("recording")).output().await.unwrap();
+    assert_eq!(output.status.code(), Some(2), "{}", String::from_utf8_lossy(&output.stderr));
+    let output = command(&dir, &uri).arg("--record").arg(dir.join("recording")).output().await.unwrap();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(served.load(std::sync::atomic::Ordering::SeqCst), 4);
+    let recorded = std::fs::read_to_string(dir.join("out.csv")).unwrap();
+    std::fs::remove_file(dir.join("out.csv")).unwrap();
+    let output = command(&dir, &uri).arg("--replay").arg(dir.join("recording")).output().await.unwrap();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(served.load(std::sync::atomic::Ordering::SeqCst), 4);
+    assert_eq!(std::fs::read_to_string(dir.join("out.csv")).unwrap(), recorded);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+// The mock server stands in for a proxy, only answering requests that carry the credentials for user:secret
+const PROXY_AUTHORIZATION: &str = "proxy-authorization: Basic dXNlcjpzZWNyZXQ=";
+
+#[tokio::test]
+async fn requests_go_through_the_configured_proxy() {
+    let (proxy, served) = serve_routes(vec![(PROXY_AUTHORIZATION, page(Some(3), 0..3))]).await;
+    let config = format!("csv = \"{{dir}}/out.csv\"\n[proxy]\nurl = \"{}\"\nusername = \"user\"\npassword = \"secret\"\n", proxy);
+    let (output, dir) = run("proxy", "http://fema.invalid/api/open", &config).await;
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(served.load(std::sync::atomic::Ordering::SeqCst), 1);
+    assert_eq!(csv::Reader::from_path(dir.join("out.csv")).unwrap().records().count(), 3);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn the_proxy_environment_variables_are_honored() {
+    let (proxy, served) = serve_routes(vec![(PROXY_AUTHORIZATION, page(Some(3), 0..3))]).await;
+    let dir = configured("proxy-env", "csv = \"{dir}/out.csv\"\n");
+    let output = command(&dir, "http://fema.invalid/api/open")
+        .env("HTTP_PROXY", proxy.replace("http://", "http://user:secret@"))
+        .output().await.unwrap();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(served.load(std::sync::atomic::Ordering::SeqCst), 1);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+// Each of the server's routes only answers requests carrying the given header
+#[tokio::test]
+async fn requests_identify_themselves_as_configured() {
+    let (uri, _) = serve_routes(vec![(concat!("user-agent: fema-web-declaration/", env!("CARGO_PKG_VERSION"), "\r\n"), page(Some(3), 0..3))]).await;
+    let (output, dir) = run("user-agent", &uri, "").await;
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    std::fs::write(dir.join("config.toml"), "user_agent = \"county-ops (ops@example.gov)\"\n[headers]\nX-Contact = \"ops@example.gov\"\n").unwrap();
+    for header in ["user-agent: county-ops (ops@example.gov)\r\n", "x-contact: ops@example.gov\r\n", "x-team: Emergency Management\r\n"] {
+        let (uri, served) = serve_routes(vec![(header, page(Some(3), 0..3))]).await;
+        let output = command(&dir, &uri).arg("--header").arg("X-Team: Emergency Management").output().await.unwrap();
+        assert!(output.status.success(), "{}: {}", header, String::from_utf8_lossy(&output.stderr));
+        assert_eq!(served.load(std::sync::atomic::Ordering::SeqCst), 1);
+    }
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn entries_fan_out_to_every_configured_sink() {
+    let (uri, _) = serve(vec![("0", page(Some(3), 0..3))]).await;
+    let config = "csv = \"{dir}/out.csv\"\nprogress_file = \"{dir}/progress.json\"\nstore = \"{dir}/store\"\nsqlite = \"{dir}/cache.db\"\n";
+    let (output, dir) = run("fan-out", &uri, config).await;
+    if !cfg!(all(feature = "store", feature = "sqlite")) {
+        assert_eq!(output.status.code(), Some(2), "{}", String::from_utf8_lossy(&output.stderr));
+        std::fs::remove_dir_all(&dir).unwrap();
+        return;
+    }
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(csv::Reader::from_path(dir.join("out.csv")).unwrap().records().count(), 3);
+    assert!(dir.join("cache.db").exists());
+    let listed = command(&dir, &uri).arg("list").output().await.unwrap();
+    assert!(listed.status.success(), "{}", String::from_utf8_lossy(&listed.stderr));
+    assert_eq!(String::from_utf8_lossy(&listed.stdout).lines().count(), 4);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[cfg(feature = "sqlite")]
+#[tokio::test]
+async fn sqlite_tables_from_before_are_migrated() {
+    let dir = configured("sqlite-migrate", "csv = \"{dir}/out.csv\"\nprogress_file = \"{dir}/progress.json\"\nsqlite = \"{dir}/cache.db\"\n");
+    let conn = rusqlite::Connection::open(dir.join("cache.db")).unwrap();
+    conn.execute_batch("CREATE TABLE fema_web_declaration_areas (id TEXT PRIMARY KEY NOT NULL, disasterNumber INTEGER NOT NULL, programTypeCode TEXT NOT NULL,
+        programTypeDescription TEXT NOT NULL, stateCode TEXT NOT NULL, placeCode TEXT NOT NULL, placeName TEXT NOT NULL, designatedDate TEXT NOT NULL,
+        entryDate TEXT NOT NULL, updateDate TEXT NOT NULL, hash TEXT NOT NULL, lastRefresh TEXT NOT NULL);
+        INSERT INTO fema_web_declaration_areas VALUES ('old', 4000, 'IA', 'Individual Assistance', 'TX', '99001', 'Anderson (County)',
+            '2019-01-01T00:00:00+00:00', '2019-01-01T00:00:00+00:00', '2019-01-01T00:00:00+00:00', 'abc', '2019-01-01T00:00:00+00:00');").unwrap();
+    drop(conn);
+    // The table gains a closeoutDate column and takes entries without an updateDate
+    let body = page(Some(2), 0..2).replace(r#""updateDate":"2020-01-01T00:00:00.000Z","#, "")
+        .replacen(r#""closeoutDate":null"#, r#""closeoutDate":"2021-06-30T00:00:00.000Z""#, 1);
+    let (uri, _) = serve(vec![("0", body)]).await;
+    let output = command(&dir, &uri).output().await.unwrap();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let conn = rusqlite::Connection::open(dir.join("cache.db")).unwrap();
+    let rows: Vec<(String, Option<String>, Option<String>)> = conn.prepare("SELECT id, updateDate, closeoutDate FROM fema_web_declaration_areas ORDER BY id").unwrap()
+        .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?))).unwrap().map(Result::unwrap).collect();
+    assert_eq!(rows, vec![
+        (String::from("0"), None, Some(String::from("2021-06-30T00:00:00+00:00"))),
+        (String::from("1"), None, None),
+        (String::from("old"), Some(String::from("2019-01-01T00:00:00+00:00")), None),
+    ]);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn new_records_are_published_over_mqtt() {
+    let (uri, _) = serve(vec![("0", page(Some(2), 0..2))]).await;
+    let (broker, received) = mqtt().await;
+    let config = format!("csv = \"{{dir}}/out.csv\"\nsync_state = \"{{dir}}/sync.json\"\n\n[mqtt]\nurl = \"{}\"\n", broker);
+    let (output, dir) = run("mqtt", &uri, &config).await;
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let received = received.lock().unwrap();
+    assert_eq!(received.len(), 3, "{:?}", received);
+    assert_eq!(received[0], (String::from("CONNECT"), String::from("fema-web-declaration")));
+    for (i, (topic, payload)) in received[1..].iter().enumerate() {
+        assert_eq!(topic, "fema/TX/IA");
+        let record: serde_json::Value = serde_json::from_str(payload).unwrap();
+        assert_eq!(record["id"], i.to_string());
+    }
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn atom_feed_can_replace_the_output_file() {
+    let (uri, _) = serve(vec![("0", page(Some(3), 0..3))]).await;
+    let dir = configured("atom", "atom_feed = \"{dir}/feed.xml\"\nprogress_file = \"{dir}/progress.json\"\n");
+    let output = command(&dir, &uri).arg("--no-csv").output().await.unwrap();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert!(!dir.join("out.csv").exists());
+    let feed = std::fs::read_to_string(dir.join("feed.xml")).unwrap();
+    assert!(feed.starts_with("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<feed xmlns=\"http://www.w3.org/2005/Atom\">"));
+    assert!(feed.ends_with("</feed>\n"));
+    assert_eq!(feed.matches("<entry>").count(), 3);
+    assert!(feed.contains("<id>urn:fema:FemaWebDeclarationAreas:2</id>"));
+    assert!(feed.contains("<title>Individual Assistance declared for Anderson (County), TX</title>"));
+    assert!(feed.contains("<updated>2020-01-01T00:00:00Z</updated>"));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn html_output_is_a_single_page_table() {
+    let (uri, _) = serve(vec![("0", page(Some(3), 0..3))]).await;
+    let (output, dir) = run("html", &uri, "csv = \"{dir}/out.html\"\noutput_format = \"html\"\nstates = [\"TX\"]\nprogress_file = \"{dir}/progress.json\"\n").await;
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let page = std::fs::read_to_string(dir.join("out.html")).unwrap();
+    assert!(page.starts_with("<!DOCTYPE html>"));
+    assert!(page.ends_with("</html>\n"));
+    // The query is shown, escaped
+    assert!(page.contains("(stateCode eq 'TX')"));
+    // Columns keep the record's field order
+    assert!(page.contains("<thead><tr><th>disasterNumber</th><th>programTypeCode</th>"));
+    assert!(page.contains("<th>id</th></tr></thead>"));
+    assert_eq!(page.matches("<tr><td>").count(), 3);
+    assert!(page.contains("<td>Anderson (County)</td>"));
+    assert!(page.contains("<script>"));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn output_can_be_split_into_a_file_per_state() {
+    // The first record is in LA, the rest in TX
+    let (uri, _) = serve(vec![("0", page(Some(3), 0..3).replacen("\"TX\"", "\"LA\"", 1))]).await;
+    let (output, dir) = run("split", &uri, "csv = \"{dir}/out.csv\"\nsplit_by = \"stateCode\"\nmanifest = \"{dir}/manifest.json\"\nprogress_file = \"{dir}/progress.json\"\n").await;
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert!(!dir.join("out.csv").exists());
+    let la = std::fs::read_to_string(dir.join("out").join("LA.csv")).unwrap();
+    let tx = std::fs::read_to_string(dir.join("out").join("TX.csv")).unwrap();
+    // Each file gets its own header
+    assert_eq!(la.lines().count(), 2);
+    assert_eq!(tx.lines().count(), 3);
+    assert!(la.starts_with("disasterNumber,"));
+    assert!(tx.starts_with("disasterNumber,"));
+    assert!(la.lines().nth(1).unwrap().ends_with(",0"));
+    let manifest = std::fs::read_to_string(dir.join("manifest.json")).unwrap();
+    assert!(manifest.contains("LA.csv"));
+    assert!(manifest.contains("TX.csv"));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn split_output_templates_need_the_state() {
+    let (output, dir) = run("split-template", "http://127.0.0.1:9", "output_template = \"{dir}/out-{date}.csv\"\nsplit_by = \"stateCode\"\n").await;
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("{state}"));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn csv_path_placeholders_are_expanded() {
+    let (uri, _) = serve(vec![("0", page(Some(2), 0..2))]).await;
+    let (output, dir) = run("csv-template", &uri, "csv = \"{dir}/{years}y/declarations-{states}-{count}.csv\"\nnum_years_previous = 3\nstates = [\"TX\"]\nprogress_file = \"{dir}/progress.json\"\n").await;
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let written = std::fs::read_to_string(dir.join("3y").join("declarations-TX-2.csv")).unwrap();
+    assert_eq!(written.lines().count(), 3);
+    assert!(!dir.join("3y").join("declarations-TX-0.csv.partial").exists());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn output_can_be_compressed_as_it_is_written() {
+    use std::io::Read;
+    let (uri, _) = serve(vec![("0", page(Some(3), 0..3))]).await;
+    let (output, dir) = run("gzip", &uri, "csv = \"{dir}/out.csv\"\ncompress = \"gzip\"\nprogress_file = \"{dir}/progress.json\"\n").await;
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert!(!dir.join("out.csv").exists());
+    // No progress is kept since a compressed file can't be picked up partway
+    assert!(!dir.join("progress.json").exists());
+    let mut csv = String::new();
+    flate2::read::GzDecoder::new(std::fs::File::open(dir.join("out.csv.gz")).unwrap()).read_to_string(&mut csv).unwrap();
+    assert_eq!(csv.lines().count(), 4);
+    assert!(csv.starts_with("disasterNumber,"));
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    let (uri, _) = serve(vec![("0", page(Some(3), 0..3))]).await;
+    let (output, dir) = run("zstd", &uri, "csv = \"{dir}/out.ndjson.zst\"\noutput_format = \"ndjson\"\ncompress = \"zstd\"\n").await;
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let ndjson = String::from_utf8(zstd::decode_all(std::fs::File::open(dir.join("out.ndjson.zst")).unwrap()).unwrap()).unwrap();
+    assert_eq!(ndjson.lines().count(), 3);
+    assert!(ndjson.lines().next().unwrap().contains("\"id\":\"0\""));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn xlsx_output_needs_its_feature() {
+    let (uri, _) = serve(vec![("0", page(Some(3), 0..3))]).await;
+    let (output, dir) = run("xlsx", &uri, "csv = \"{dir}/out.xlsx\"\noutput_format = \"xlsx\"\nprogress_file = \"{dir}/progress.json\"\n").await;
+    if cfg!(feature = "xlsx-output") {
+        assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+        // A workbook is a zip archive
+        assert!(std::fs::read(dir.join("out.xlsx")).unwrap().starts_with(b"PK\x03\x04"));
+    }
+    else {
+        assert!(!output.status.success());
+        assert!(String::from_utf8_lossy(&output.stderr).contains("xlsx-output feature"));
+    }
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn arrow_output_needs_its_feature() {
+    let (uri, _) = serve(vec![("0", page(Some(3), 0..3))]).await;
+    let (output, dir) = run("arrow", &uri, "csv = \"{dir}/out.arrow\"\noutput_format = \"arrow\"\nprogress_file = \"{dir}/progress.json\"\n").await;
+    if cfg!(feature = "arrow-output") {
+        assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+        let file = std::fs::read(dir.join("out.arrow")).unwrap();
+        assert!(file.starts_with(b"ARROW1"));
+        assert!(file.ends_with(b"ARROW1"));
+    }
+    else {
+        assert!(!output.status.success());
+        assert!(String::from_utf8_lossy(&output.stderr).contains("arrow-output feature"));
+    }
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn geojson_output_places_records_on_a_map() {
+    // The last record's place isn't a county
+    let mut body = page(Some(2), 0..2);
+    let last = body.rfind("\"99001\"").unwrap();
+    body.replace_range(last..last+7, "\"00000\"");
+    let (uri, _) = serve(vec![("0", body)]).await;
+    let (output, dir) = run("geojson", &uri, "csv = \"{dir}/out.geojson\"\noutput_format = \"geojson\"\nprogress_file = \"{dir}/progress.json\"\n").await;
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let collection: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(dir.join("out.geojson")).unwrap()).unwrap();
+    assert_eq!(collection["type"], "FeatureCollection");
+    let features = collection["features"].as_array().unwrap();
+    assert_eq!(features.len(), 2);
+    assert_eq!(features[0]["type"], "Feature");
+    assert_eq!(features[0]["id"], "0");
+    assert_eq!(features[0]["properties"]["placeName"], "Anderson (County)");
+    let (lat, lon) = fema::county_centroid("TX", "001").unwrap();
+    assert_eq!(features[0]["geometry"], serde_json::json!({ "type": "Point", "coordinates": [lon, lat] }));
+    assert!(features[1]["geometry"].is_null());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn place_codes_can_be_decoded_into_columns() {
+    let (uri, _) = serve(vec![("0", page(Some(2), 0..2))]).await;
+    let (output, dir) = run("decode-places", &uri, "csv = \"{dir}/out.csv\"\ndecode_places = true\nprogress_file = \"{dir}/progress.json\"\n").await;
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let mut reader = csv::Reader::from_path(dir.join("out.csv")).unwrap();
+    let headers = reader.headers().unwrap().clone();
+    assert_eq!(headers.iter().rev().take(4).collect::<Vec<_>>(), ["is_statewide", "county_name", "county_fips", "id"]);
+    let row = reader.records().next().unwrap().unwrap();
+    assert_eq!(row.iter().rev().take(3).collect::<Vec<_>>(), ["false", "Anderson County", "48001"]);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn records_can_be_enriched_with_their_disaster() {
+    let summary = r#"{"disasterNumber":4000,"incidentType":"Hurricane","declarationTitle":"HURRICANE HARVEY","incidentBeginDate":"2017-08-23T00:00:00.000Z"}"#;
+    let summaries = page(Some(1), 0..0).replace(r#""FemaWebDeclarationAreas":[]"#, &format!(r#""DisasterDeclarationsSummaries":[{}]"#, summary));
+    let (uri, _) = serve_routes(vec![
+        ("/DisasterDeclarationsSummaries?", summaries),
+        ("$skip=0&", page(Some(2), 0..2)),
+    ]).await;
+    let (output, dir) = run("enrich", &uri, "csv = \"{dir}/out.csv\"\nenrich = true\nprogress_file = \"{dir}/progress.json\"\n").await;
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let mut reader = csv::Reader::from_path(dir.join("out.csv")).unwrap();
+    let headers = reader.headers().unwrap().clone();
+    assert_eq!(headers.iter().rev().take(3).collect::<Vec<_>>(), ["incidentBeginDate", "declarationTitle", "incidentType"]);
+    let rows: Vec<csv::StringRecord> = reader.records().map(Result::unwrap).collect();
+    assert_eq!(rows.len(), 2);
+    assert_eq!(rows[1].iter().rev().take(3).collect::<Vec<_>>(), ["2017-08-23T00:00:00.000Z", "HURRICANE HARVEY", "Hurricane"]);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn disasters_are_looked_up_in_concurrent_batches() {
+    // 120 records of 60 disasters, more than one batch's worth, the last of which has no summary
+    let entries: Vec<String> = (0..120).map(|id| entry(id).replace(r#""disasterNumber":4000"#, &format!(r#""disasterNumber":{}"#, 4000 + id % 60))).collect();
+    let records = page(Some(120), 0..0).replace(r#""FemaWebDeclarationAreas":[]"#, &format!(r#""FemaWebDeclarationAreas":[{}]"#, entries.join(",")));
+    let summaries: Vec<String> = (4000..4059).map(|number| format!(r#"{{"disasterNumber":{},"incidentType":"Flood","declarationTitle":"FLOODING","incidentBeginDate":"2017-08-23T00:00:00.000Z"}}"#, number)).collect();
+    let summaries = page(Some(59), 0..0).replace(r#""FemaWebDeclarationAreas":[]"#, &format!(r#""DisasterDeclarationsSummaries":[{}]"#, summaries.join(",")));
+    let (uri, _) = serve_routes(vec![
+        ("/DisasterDeclarationsSummaries?", summaries),
+        ("$skip=0&", records),
+    ]).await;
+    let dir = configured("enrich-batches", "csv = \"{dir}/out.csv\"\nenrich = true\nconcurrency = 2\n");
+    // Logging at info level to stdout, for the lookup counts
+    std::fs::create_dir_all(dir.join("xdg/fema-web-declaration")).unwrap();
+    std::fs::write(dir.join("xdg/fema-web-declaration/log4rs.yml"), "appenders:\n  stdout:\n    kind: console\nroot:\n  level: info\n  appenders:\n    - stdout\n").unwrap();
+    let output = command(&dir, &uri).output().await.unwrap();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let mut reader = csv::Reader::from_path(dir.join("out.csv")).unwrap();
+    let index = reader.headers().unwrap().iter().position(|header| header == "incidentType").unwrap();
+    let types: Vec<String> = reader.records().map(|record| record.unwrap()[index].to_owned()).collect();
+    assert_eq!(types.iter().filter(|t| *t == "Flood").count(), 118);
+    let log = String::from_utf8_lossy(&output.stdout);
+    assert!(log.contains("Requested the summaries of 60 disasters in 2 requests."), "{}", log);
+    assert!(log.contains("Enriched 118 of 120 results (98.3%)"), "{}", log);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn timestamps_can_be_written_in_another_timezone() {
+    let (uri, _) = serve(vec![("0", page(Some(2), 0..2))]).await;
+    let (output, dir) = run("timezone", &uri, "csv = \"{dir}/out.csv\"\noutput_timezone = \"America/Chicago\"\nstamp_fetch_time = true\nprogress_file = \"{dir}/progress.json\"\n").await;
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let mut reader = csv::Reader::from_path(dir.join("out.csv")).unwrap();
+    let headers = reader.headers().unwrap().clone();
+    let row = reader.records().next().unwrap().unwrap();
+    let field = |name| &row[headers.iter().position(|header| header == name).unwrap()];
+    assert_eq!(field("designatedDate"), "2019-12-31T18:00:00-06:00");
+    assert_eq!(field("lastRefresh"), "2019-12-31T18:00:00-06:00");
+    assert_eq!(field("placeName"), "Anderson (County)");
+    assert!(field("fetched_at").ends_with("-05:00") || field("fetched_at").ends_with("-06:00"), "{}", field("fetched_at"));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn dates_can_be_written_in_another_format() {
+    let (uri, _) = serve(vec![("0", page(Some(2), 0..2))]).await;
+    let (output, dir) = run("date-format", &uri, "csv = \"{dir}/out.csv\"\ndate_format = \"%Y-%m-%d\"\nprogress_file = \"{dir}/progress.json\"\n").await;
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let mut reader = csv::Reader::from_path(dir.join("out.csv")).unwrap();
+    let headers = reader.headers().unwrap().clone();
+    let row = reader.records().next().unwrap().unwrap();
+    assert_eq!(&row[headers.iter().position(|header| header == "designatedDate").unwrap()], "2020-01-01");
+    std::fs::remove_dir_all(&dir).unwrap();
+    // Selected fields and the local day of a converted timestamp
+    let (output, dir) = run("date-format-select", &uri,
+        "csv = \"{dir}/out.csv\"\nselect = [\"id\", \"designatedDate\"]\noutput_timezone = \"America/Chicago\"\ndate_format = \"%Y-%m-%d\"\nprogress_file = \"{dir}/progress.json\"\n").await;
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(std::fs::read_to_string(dir.join("out.csv")).unwrap(), "id,designatedDate\n0,2019-12-31\n1,2019-12-31\n");
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn csv_dialect_can_be_configured() {
+    let (uri, _) = serve(vec![("0", page(Some(2), 0..2))]).await;
+    let (output, dir) = run("csv-dialect", &uri,
+        "csv = \"{dir}/out.csv\"\nselect = [\"id\", \"disasterNumber\", \"placeName\"]\ncsv_delimiter = \";\"\ncsv_quote_style = \"non-numeric\"\ncsv_line_ending = \"crlf\"\nprogress_file = \"{dir}/progress.json\"\n").await;
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(std::fs::read_to_string(dir.join("out.csv")).unwrap(),
+        "\"id\";\"disasterNumber\";\"placeName\"\r\n0;4000;\"Anderson (County)\"\r\n1;4000;\"Anderson (County)\"\r\n");
+    std::fs::remove_dir_all(&dir).unwrap();
+    let (output, dir) = run("csv-no-header", &uri, "csv = \"{dir}/out.csv\"\ncsv_delimiter = \"\\t\"\ncsv_header = false\nprogress_file = \"{dir}/progress.json\"\n").await;
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let written = std::fs::read_to_string(dir.join("out.csv")).unwrap();
+    assert_eq!(written.lines().count(), 2);
+    assert!(written.starts_with("4000\tIA\tIndividual Assistance\tTX\t"), "{}", written);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn output_columns_are_picked_renamed_and_ordered() {
+    let (uri, _) = serve(vec![("0", page(Some(2), 0..2))]).await;
+    let (output, dir) = run("output-columns", &uri,
+        "csv = \"{dir}/out.csv\"\noutput_columns = [\"placeName -> Place\", \"stateCode -> State\", \"designatedDate\", \"county_name -> County\"]\ndecode_places = true\nprogress_file = \"{dir}/progress.json\"\n").await;
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(std::fs::read_to_string(dir.join("out.csv")).unwrap(),
+        "Place,State,designatedDate,County\nAnderson (County),TX,2020-01-01T00:00:00Z,Anderson County\nAnderson (County),TX,2020-01-01T00:00:00Z,Anderson County\n");
+    std::fs::remove_dir_all(&dir).unwrap();
+    // Split on a field that isn't written
+    let (output, dir) = run("output-columns-split", &uri,
+        "csv = \"{dir}/out.json\"\noutput_format = \"json\"\noutput_columns = [\"id -> ID\"]\nsplit_by = \"stateCode\"\nprogress_file = \"{dir}/progress.json\"\n").await;
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let records: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(dir.join("out/TX.json")).unwrap()).unwrap();
+    assert_eq!(records, serde_json::json!([{ "ID": "0" }, { "ID": "1" }]));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn records_can_be_summarized_instead_of_written() {
+    let (uri, _) = serve(vec![("0", page(Some(2), 0..2))]).await;
+    let (output, dir) = run("summarize", &uri, "csv = \"{dir}/out.csv\"\nsummarize = true\nsummary = \"{dir}/summary.csv\"\nprogress_file = \"{dir}/progress.json\"\n").await;
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert!(!dir.join("out.csv").exists());
+    assert_eq!(std::fs::read_to_string(dir.join("summary.csv")).unwrap(), "state,program_type,month,count\nTX,IA,2020-01,2\n");
+    let table = String::from_utf8_lossy(&output.stdout);
+    assert!(table.contains("TX     IA            2020-01      2"), "{}", table);
+    assert!(table.contains("Total: 2"), "{}", table);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn tui_needs_its_feature() {
+    let dir = configured("tui", "csv = \"{dir}/out.csv\"\n");
+    let output = command(&dir, "http://127.0.0.1:9/").arg("tui").output().await.unwrap();
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    if cfg!(feature = "tui") {
+        // Nothing has been fetched yet, so there's no output file to browse
+        assert!(stderr.contains("Failed to open"), "{}", stderr);
+    }
+    else {
+        assert!(stderr.contains("tui feature"), "{}", stderr);
+    }
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn cached_entries_are_served_over_http() {
+    let body = page(Some(2), 0..2).replacen(r#""closeoutDate":null"#, r#""closeoutDate":"2021-06-30T00:00:00.000Z""#, 1);
+    let (uri, _) = serve(vec![("0", body)]).await;
+    let port = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+    // Cached in the SQLite database when there's one, in memory otherwise
+    let sqlite = if cfg!(feature = "sqlite") { "sqlite = \"{dir}/cache.db\"\n" } else { "" };
+    let dir = configured("serve", &format!("progress_file = \"{{dir}}/progress.json\"\n{}", sqlite));
+    let child = command(&dir, &uri).arg("serve").arg("--listen").arg(format!("127.0.0.1:{}", port))
+        .stdout(std::process::Stdio::null()).stderr(std::process::Stdio::null()).spawn().unwrap();
+    let get = |path: &str| reqwest::get(reqwest::Url::parse(&format!("http://127.0.0.1:{}{}", port, path)).unwrap());
+    // The cache fills in once the server is up and the first refresh is done
+    let mut areas = Vec::new();
+    for _ in 0..100 {
+        if let Ok(response) = get("/areas?state=tx").await {
+            areas = response.json::<Vec<serde_json::Value>>().await.unwrap();
+            if areas.len() == 2 {
+                break;
+            }
+        }
+        tokio::time::delay_for(std::time::Duration::from_millis(50)).await;
+    }
+    assert_eq!(areas.len(), 2);
+    assert_eq!(get("/areas?state=LA").await.unwrap().json::<Vec<serde_json::Value>>().await.unwrap().len(), 0);
+    assert_eq!(get("/areas?since=2021-01-01").await.unwrap().json::<Vec<serde_json::Value>>().await.unwrap().len(), 0);
+    assert_eq!(get("/areas?colour=red").await.unwrap().status(), reqwest::StatusCode::BAD_REQUEST);
+    let metrics = get("/metrics").await.unwrap().text().await.unwrap();
+    assert!(metrics.contains("\nfema_new_records_total 2\n"), "{}", metrics);
+    let entry: serde_json::Value = get("/areas/1").await.unwrap().json().await.unwrap();
+    assert_eq!(entry["placeName"], "Anderson (County)");
+    assert!(entry["closeoutDate"].is_null());
+    // Kept through the SQLite database too
+    let entry: serde_json::Value = get("/areas/0").await.unwrap().json().await.unwrap();
+    assert_eq!(entry["closeoutDate"], "2021-06-30T00:00:00Z");
+    assert_eq!(get("/areas/nope").await.unwrap().status(), reqwest::StatusCode::NOT_FOUND);
+    std::process::Command::new("kill").arg("-TERM").arg(child.id().to_string()).status().unwrap();
+    let status = tokio::time::timeout(std::time::Duration::from_secs(10), child).await.unwrap().unwrap();
+    assert!(status.success());
+    assert_eq!(dir.join("cache.db").exists(), cfg!(feature = "sqlite"));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn metrics_are_pushed_after_a_run() {
+    let (uri, _) = serve(vec![("0", page(Some(2), 0..2))]).await;
+    let (gateway, received) = capture(0).await;
+    let config = format!("csv = \"{{dir}}/out.csv\"\nprogress_file = \"{{dir}}/progress.json\"\nmetrics_push_url = \"{}\"\n", gateway);
+    let (output, dir) = run("metrics-push", &uri, &config).await;
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let received = received.lock().unwrap();
+    assert_eq!(received.len(), 1);
+    let (head, body) = &received[0];
+    assert!(head.starts_with("PUT /hook/metrics/job/fema-web-declaration "), "{}", head);
+    assert!(body.contains("\nfema_pages_fetched_total 1\n"), "{}", body);
+    assert!(body.contains("\nfema_new_records_total 2\n"), "{}", body);
+    assert!(body.contains("\nfema_run_failures_total 0\n"), "{}", body);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn outputs_are_uploaded_to_s3() {
+    let (uri, _) = serve(vec![("0", page(Some(2), 0..2))]).await;
+    let (endpoint, received) = capture(0).await;
+    let config = format!("csv = \"{{dir}}/out.csv\"\nprogress_file = \"{{dir}}/progress.json\"\nmanifest = \"{{dir}}/manifest.json\"\n\n[s3]\nbucket = \"exports\"\nprefix = \"fema\"\nendpoint = \"{}\"\naccess_key_id = \"AKIDEXAMPLE\"\nsecret_access_key = \"secret\"\n", endpoint);
+    let (output, dir) = run("s3", &uri, &config).await;
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let received = received.lock().unwrap();
+    assert_eq!(received.len(), 2);
+    let (head, body) = &received[0];
+    let request_line = head.lines().next().unwrap();
+    assert!(request_line.starts_with("PUT /hook/exports/fema/") && request_line.ends_with("/out.csv HTTP/1.1"), "{}", head);
+    let head = head.to_ascii_lowercase();
+    assert!(head.contains("\r\ncontent-type: text/csv\r\n"), "{}", head);
+    assert!(head.contains("\r\nauthorization: aws4-hmac-sha256 credential=akidexample/"), "{}", head);
+    assert_eq!(*body, std::fs::read_to_string(dir.join("out.csv")).unwrap());
+    // The manifest goes up last, next to the run's files
+    let (head, body) = &received[1];
+    let run_prefix = &request_line[..request_line.rfind("/out.csv").unwrap()];
+    assert!(head.starts_with(&format!("{}/manifest.json ", run_prefix)), "{}", head);
+    assert!(head.to_ascii_lowercase().contains("\r\ncontent-type: application/json\r\n"), "{}", head);
+    assert!(body.contains("out.csv"), "{}", body);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,234 @@
+extern crate chrono;
+extern crate fema;
+extern crate serde_json;
+use chrono::{TimeZone,Utc};
+use fema::{build_filter,Config,DateBound,ProgramType,StateCode};
+
+#[test]
+fn default_config_filter() {
+    let now = Utc.with_ymd_and_hms(2020, 11, 1, 12, 0, 0).unwrap();
+    assert_eq!(build_filter(&Config::default(), now),
+        "designatedDate gt'2017-11-01T12:00:00.000Z' and closeoutDate eq null");
+}
+
+#[test]
+fn years_previous_filter() {
+    let now = Utc.with_ymd_and_hms(2020, 11, 1, 12, 0, 0).unwrap();
+    let cfg = Config { num_years_previous: 1, ..Config::default() };
+    assert_eq!(build_filter(&cfg, now),
+        "designatedDate gt'2019-11-01T12:00:00.000Z' and closeoutDate eq null");
+    let cfg = Config { num_years_previous: 0, ..Config::default() };
+    assert_eq!(build_filter(&cfg, now),
+        "designatedDate gt'2020-11-01T12:00:00.000Z' and closeoutDate eq null");
+}
+
+// The cutoff is counted back in calendar years in UTC and formatted with millisecond precision and a Z suffix
+fn cutoff_clause(now: chrono::DateTime<Utc>, num_years_previous: usize) -> String {
+    let cfg = Config { num_years_previous, ..Config::default() };
+    build_filter(&cfg, now)
+}
+
+#[test]
+fn cutoff_at_utc_midnight() {
+    let now = Utc.with_ymd_and_hms(2021, 1, 1, 0, 0, 0).unwrap();
+    assert_eq!(cutoff_clause(now, 1), "designatedDate gt'2020-01-01T00:00:00.000Z' and closeoutDate eq null");
+}
+
+#[test]
+fn cutoff_just_before_utc_midnight() {
+    let now = Utc.with_ymd_and_hms(2020, 12, 31, 23, 59, 59).unwrap() + chrono::Duration::milliseconds(999);
+    assert_eq!(cutoff_clause(now, 1), "designatedDate gt'2019-12-31T23:59:59.999Z' and closeoutDate eq null");
+}
+
+#[test]
+fn cutoff_from_non_utc_now() {
+    // 2021-01-01 02:00 at UTC+5 is still 2020-12-31 in UTC
+    let now = chrono::FixedOffset::east_opt(5 * 3600).unwrap().with_ymd_and_hms(2021, 1, 1, 2, 0, 0).unwrap().with_timezone(&Utc);
+    assert_eq!(cutoff_clause(now, 1), "designatedDate gt'2019-12-31T21:00:00.000Z' and closeoutDate eq null");
+}
+
+#[test]
+fn cutoff_on_leap_day() {
+    let now = Utc.with_ymd_and_hms(2021, 2, 28, 23, 59, 59).unwrap();
+    assert_eq!(cutoff_clause(now, 1), "designatedDate gt'2020-02-28T23:59:59.000Z' and closeoutDate eq null");
+    // There's no 29 February the year before, so the cutoff is the last day of February
+    let now = Utc.with_ymd_and_hms(2020, 2, 29, 12, 0, 0).unwrap();
+    assert_eq!(cutoff_clause(now, 1), "designatedDate gt'2019-02-28T12:00:00.000Z' and closeoutDate eq null");
+    assert_eq!(cutoff_clause(now, 4), "designatedDate gt'2016-02-29T12:00:00.000Z' and closeoutDate eq null");
+}
+
+#[test]
+fn cutoff_across_leap_day() {
+    let now = Utc.with_ymd_and_hms(2020, 3, 1, 0, 0, 0).unwrap();
+    assert_eq!(cutoff_clause(now, 1), "designatedDate gt'2019-03-01T00:00:00.000Z' and closeoutDate eq null");
+    let now = Utc.with_ymd_and_hms(2024, 3, 1, 0, 0, 0).unwrap() + chrono::Duration::milliseconds(1);
+    assert_eq!(cutoff_clause(now, 4), "designatedDate gt'2020-03-01T00:00:00.001Z' and closeoutDate eq null");
+}
+
+#[test]
+fn cutoff_moves_a_day_at_a_time_with_the_http_cache() {
+    let cfg = Config { num_years_previous: 1, http_cache: Some(std::path::PathBuf::from("cache")), ..Config::default() };
+    let morning = Utc.with_ymd_and_hms(2021, 1, 1, 8, 30, 0).unwrap();
+    let evening = Utc.with_ymd_and_hms(2021, 1, 1, 23, 59, 59).unwrap();
+    assert_eq!(build_filter(&cfg, morning), "designatedDate gt'2020-01-01T00:00:00.000Z' and closeoutDate eq null");
+    assert_eq!(build_filter(&cfg, evening), build_filter(&cfg, morning));
+}
+
+#[test]
+fn cutoff_from_an_explicit_date() {
+    let now = Utc.with_ymd_and_hms(2020, 11, 1, 12, 0, 0).unwrap();
+    let cfg = Config { since: chrono::NaiveDate::from_ymd_opt(2017, 8, 1).map(DateBound::Day), num_years_previous: 1, ..Config::default() };
+    assert_eq!(build_filter(&cfg, now), "designatedDate ge'2017-08-01T00:00:00.000Z' and closeoutDate eq null");
+    let cfg: Config = serde_json::from_value(serde_json::json!({ "since": "2017-08-01" })).unwrap();
+    assert_eq!(fema::cutoff(&cfg, now), Utc.with_ymd_and_hms(2017, 8, 1, 0, 0, 0).unwrap());
+}
+
+#[test]
+fn date_range_filter() {
+    let now = Utc.with_ymd_and_hms(2020, 11, 1, 12, 0, 0).unwrap();
+    // A day until includes the whole day
+    let cfg: Config = serde_json::from_value(serde_json::json!({ "since": "2017-08-01", "until": "2017-10-31" })).unwrap();
+    assert!(cfg.validate().is_ok());
+    assert_eq!(build_filter(&cfg, now),
+        "designatedDate ge'2017-08-01T00:00:00.000Z' and designatedDate lt'2017-11-01T00:00:00.000Z' and closeoutDate eq null");
+    let cfg: Config = serde_json::from_value(serde_json::json!({ "since": "2017-08-25T12:00:00-05:00", "until": "2017-08-26T00:00:00Z" })).unwrap();
+    assert_eq!(build_filter(&cfg, now),
+        "designatedDate ge'2017-08-25T17:00:00.000Z' and designatedDate lt'2017-08-26T00:00:00.000Z' and closeoutDate eq null");
+    assert_eq!(cfg.since.unwrap().to_string(), "2017-08-25T17:00:00Z");
+    // Until alone still counts back num_years_previous for the start
+    let cfg = Config { until: Some("2020-06-30".parse().unwrap()), num_years_previous: 1, ..Config::default() };
+    assert_eq!(build_filter(&cfg, now),
+        "designatedDate gt'2019-11-01T12:00:00.000Z' and designatedDate lt'2020-07-01T00:00:00.000Z' and closeoutDate eq null");
+    assert!("08/01/2017".parse::<DateBound>().is_err());
+    let cfg = Config { since: Some("2017-11-01".parse().unwrap()), until: Some("2017-10-31".parse().unwrap()), ..Config::default() };
+    assert!(cfg.validate().is_err());
+}
+
+#[test]
+fn closed_declarations_can_be_included() {
+    let now = Utc.with_ymd_and_hms(2020, 11, 1, 12, 0, 0).unwrap();
+    let cfg = Config { include_closed: true, ..Config::default() };
+    assert_eq!(build_filter(&cfg, now), "designatedDate gt'2017-11-01T12:00:00.000Z'");
+    let cfg = Config { include_closed: true, dataset: fema::Dataset::DisasterDeclarationsSummaries, ..Config::default() };
+    assert_eq!(build_filter(&cfg, now), "declarationDate gt'2017-11-01T12:00:00.000Z'");
+    assert!(fema::Dataset::FemaWebDeclarationAreas.select(fema::ApiVersion::V1).unwrap().split(',').any(|field| field == "closeoutDate"));
+    let entry: fema::Entry = serde_json::from_value(serde_json::json!({ "id": "a", "designatedDate": "2019-01-01T00:00:00.000Z", "closeoutDate": "2019-06-30T00:00:00.000Z" })).unwrap();
+    assert_eq!(entry.closeoutDate, Some(Utc.with_ymd_and_hms(2019, 6, 30, 0, 0, 0).unwrap()));
+}
+
+#[test]
+fn dataset_filter_fields() {
+    let now = Utc.with_ymd_and_hms(2020, 11, 1, 12, 0, 0).unwrap();
+    let cfg = Config { dataset: fema::Dataset::DisasterDeclarationsSummaries, ..Config::default() };
+    assert_eq!(build_filter(&cfg, now),
+        "declarationDate gt'2017-11-01T12:00:00.000Z' and disasterCloseoutDate eq null");
+    let cfg = Config { dataset: fema::Dataset::HousingAssistanceOwners, ..Config::default() };
+    assert_eq!(build_filter(&cfg, now), "");
+}
+
+#[test]
+fn incremental_filter() {
+    let since = Utc.with_ymd_and_hms(2020, 10, 31, 6, 30, 0).unwrap();
+    assert_eq!(fema::refreshed_since("closeoutDate eq null", since),
+        "closeoutDate eq null and lastRefresh gt'2020-10-31T06:30:00.000Z'");
+    assert_eq!(fema::refreshed_since("", since), "lastRefresh gt'2020-10-31T06:30:00.000Z'");
+}
+
+#[test]
+fn sync_checkpoint_only_moves_forward() {
+    let path = std::env::temp_dir().join(format!("fema-sync-{}.json", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let mut state = fema::SyncState::load(&path).unwrap();
+    assert_eq!(state.checkpoint(fema::Dataset::FemaWebDeclarationAreas), None);
+    let later = Utc.with_ymd_and_hms(2020, 11, 1, 0, 0, 0).unwrap();
+    state.advance(fema::Dataset::FemaWebDeclarationAreas, later);
+    state.advance(fema::Dataset::FemaWebDeclarationAreas, Utc.with_ymd_and_hms(2020, 1, 1, 0, 0, 0).unwrap());
+    state.save().unwrap();
+    let state = fema::SyncState::load(&path).unwrap();
+    assert_eq!(state.checkpoint(fema::Dataset::FemaWebDeclarationAreas), Some(later));
+    assert_eq!(state.checkpoint(fema::Dataset::HousingAssistanceOwners), None);
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn states_filter() {
+    let now = Utc.with_ymd_and_hms(2020, 11, 1, 12, 0, 0).unwrap();
+    let cfg = Config { states: Some(vec![StateCode::TX, StateCode::from("la")]), ..Config::default() };
+    assert_eq!(build_filter(&cfg, now),
+        "designatedDate gt'2017-11-01T12:00:00.000Z' and closeoutDate eq null and (stateCode eq 'TX' or stateCode eq 'LA')");
+    let cfg = Config { states: Some(vec![StateCode::TX]), dataset: fema::Dataset::HousingAssistanceOwners, ..Config::default() };
+    assert_eq!(build_filter(&cfg, now), "(state eq 'TX')");
+}
+
+#[test]
+fn program_types_filter() {
+    let now = Utc.with_ymd_and_hms(2020, 11, 1, 12, 0, 0).unwrap();
+    let cfg = Config { program_types: Some(vec![ProgramType::from("ia"), ProgramType::HazardMitigation]), ..Config::default() };
+    assert_eq!(build_filter(&cfg, now),
+        "designatedDate gt'2017-11-01T12:00:00.000Z' and closeoutDate eq null and (programTypeCode eq 'IA' or programTypeCode eq 'HM')");
+    let cfg = Config { program_types: Some(vec![ProgramType::IndividualAssistance]), dataset: fema::Dataset::HousingAssistanceOwners, ..Config::default() };
+    assert_eq!(build_filter(&cfg, now), "");
+}
+
+#[test]
+fn extra_filter_is_anded() {
+    let now = Utc.with_ymd_and_hms(2020, 11, 1, 12, 0, 0).unwrap();
+    let cfg = Config { extra_filter: Some(String::from("disasterNumber ge 4000 or placeCode eq '99001'")), ..Config::default() };
+    assert_eq!(build_filter(&cfg, now),
+        "designatedDate gt'2017-11-01T12:00:00.000Z' and closeoutDate eq null and (disasterNumber ge 4000 or placeCode eq '99001')");
+}
+
+#[test]
+fn configured_select_replaces_default() {
+    let client = fema::FemaClient::new(Config::default()).unwrap();
+    assert!(client.query("").contains("$select=disasterNumber,"));
+    let cfg = Config { select: Some(vec![String::from("id"), String::from("closeoutDate")]), ..Config::default() };
+    let client = fema::FemaClient::new(cfg).unwrap();
+    assert_eq!(client.query(""), "$inlinecount=allpages&$select=id,closeoutDate");
+}
+
+#[test]
+fn selected_row_keeps_field_order() {
+    let record: serde_json::Map<String,serde_json::Value> = serde_json::from_str(r#"{"id":"abc","stateCode":"TX","extra":1}"#).unwrap();
+    let select = vec![String::from("stateCode"), String::from("closeoutDate"), String::from("id")];
+    let row = fema::Row::from_map(record, &select);
+    assert_eq!(row.names().collect::<Vec<_>>(), vec!["stateCode", "closeoutDate", "id"]);
+    assert_eq!(row.get("closeoutDate"), Some(&serde_json::Value::Null));
+    assert_eq!(row.get("extra"), None);
+}
+
+#[test]
+fn order_by_query() {
+    let cfg = Config { select: Some(vec![String::from("id")]), order_by: Some(String::from("designatedDate desc")), ..Config::default() };
+    let client = fema::FemaClient::new(cfg).unwrap();
+    assert_eq!(client.query("stateCode eq 'TX'"), "$inlinecount=allpages&$select=id&$filter=stateCode%20eq%20%27TX%27&$orderby=designatedDate%20desc");
+}
+
+#[test]
+fn query_values_are_percent_encoded() {
+    let cfg = Config { order_by: Some(String::from("placeName desc")), select: Some(vec![String::from("id"), String::from("place&Name")]),
+        expand: Some(vec![String::from("disaster summary")]), ..Config::default() };
+    let client = fema::FemaClient::new(cfg).unwrap();
+    // A quote in a value is doubled in OData, and an & would otherwise start another parameter
+    let filter = "placeName eq 'Bristol Bay & Lake and Peninsula' or placeName eq 'Prince of Wales-Hyder''s'";
+    let query = client.query(filter);
+    assert!(!query.contains(' ') && !query.contains('\''), "{}", query);
+    let url = reqwest::Url::parse(&fema::get_uri(false, client.base_uri(), &query, 0, Some(1000))).unwrap();
+    let pairs: std::collections::HashMap<String, String> = url.query_pairs().into_owned().collect();
+    assert_eq!(pairs["$filter"], filter);
+    assert_eq!(pairs["$orderby"], "placeName desc");
+    assert_eq!(pairs["$select"], "id,place&Name");
+    assert_eq!(pairs["$expand"], "disaster summary");
+    assert_eq!(pairs["$skip"], "0");
+    assert_eq!(pairs["$metadata"], "off");
+}
+
+#[test]
+fn api_version_selects_endpoint() {
+    let client = fema::FemaClient::new(Config::default()).unwrap();
+    assert_eq!(client.base_uri(), "https://www.fema.gov/api/open/v1/FemaWebDeclarationAreas");
+    let cfg = Config { api_version: Some(fema::ApiVersion::V2), ..Config::default() };
+    let client = fema::FemaClient::new(cfg).unwrap();
+    assert_eq!(client.base_uri(), "https://www.fema.gov/api/open/v2/FemaWebDeclarationAreas");
+    assert!(!client.query("").contains("$select"));
+}
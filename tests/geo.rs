@@ -0,0 +1,32 @@
+extern crate fema;
+extern crate serde_json;
+use serde_json::json;
+
+#[test]
+fn places_resolve_to_county_centroids() {
+    let (lat, lon) = fema::county_centroid("tx", "001").unwrap();
+    assert!((31.0..33.0).contains(&lat) && (-97.0..-95.0).contains(&lon));
+    // Declaration areas give the county as a placeCode of 99 and its FIPS code
+    assert_eq!(fema::place_centroid(&json!({ "stateCode": "TX", "placeCode": "99001" })), Some((lat, lon)));
+    // Disaster declaration summaries have the FIPS code on its own
+    assert_eq!(fema::place_centroid(&json!({ "state": "TX", "fipsCountyCode": "001", "placeCode": "99001" })), Some((lat, lon)));
+    // Not a county
+    assert_eq!(fema::place_centroid(&json!({ "stateCode": "TX", "placeCode": "48453" })), None);
+    assert_eq!(fema::place_centroid(&json!({ "stateCode": "TX" })), None);
+    assert_eq!(fema::county_centroid("TX", "999"), None);
+}
+
+#[test]
+fn place_codes_decode_to_counties() {
+    let place = fema::decode_place(&json!({ "stateCode": "TX", "placeCode": "99001", "placeName": "Anderson (County)" }));
+    assert_eq!(place, fema::Place { county_fips: Some(String::from("48001")), county_name: Some("Anderson County"), is_statewide: false });
+    assert_eq!(fema::county_name("md", "033"), Some("Prince George's County"));
+    assert_eq!(fema::state_fips("PR"), Some("72"));
+    // Statewide designations have no county
+    let statewide = fema::decode_place(&json!({ "stateCode": "LA", "placeCode": "0", "placeName": "Statewide" }));
+    assert_eq!(statewide, fema::Place { county_fips: None, county_name: None, is_statewide: true });
+    assert!(fema::decode_place(&json!({ "state": "LA", "fipsCountyCode": "000" })).is_statewide);
+    // A tribal area is neither
+    let tribal = fema::decode_place(&json!({ "stateCode": "AZ", "placeCode": "10030", "placeName": "Navajo Nation" }));
+    assert_eq!(tribal, fema::Place { county_fips: None, county_name: None, is_statewide: false });
+}
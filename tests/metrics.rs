@@ -0,0 +1,18 @@
+extern crate fema;
+use fema::METRICS;
+
+#[test]
+fn metrics_render_in_prometheus_text_format() {
+    METRICS.start_run();
+    METRICS.page(3);
+    METRICS.new_records(2);
+    METRICS.http_error(Some(503));
+    METRICS.http_error(None);
+    METRICS.succeeded();
+    let text = METRICS.render();
+    assert!(text.contains("# TYPE fema_pages_fetched_total counter\nfema_pages_fetched_total 1\n"), "{}", text);
+    assert!(text.contains("\nfema_records_fetched_total 3\n"), "{}", text);
+    assert!(text.contains("\nfema_last_run_new_records 2\n"), "{}", text);
+    assert!(text.contains("\nfema_http_errors_total{status=\"0\"} 1\nfema_http_errors_total{status=\"503\"} 1\n"), "{}", text);
+    assert!(!text.contains("fema_last_success_timestamp_seconds 0\n"), "{}", text);
+}
@@ -0,0 +1,95 @@
+extern crate fema;
+extern crate serde_json;
+extern crate tokio;
+use fema::{Change,ChatChannel,ChatKind,Dataset,EmailReport,KafkaSink,MqttSink};
+
+fn area(id: usize, program: &str, state: &str) -> serde_json::Value {
+    serde_json::json!({
+        "programTypeCode": program, "stateCode": state, "placeName": format!("Place {}", id),
+        "designatedDate": "2020-01-01T00:00:00.000Z", "disasterNumber": 4000, "id": id.to_string(),
+    })
+}
+
+#[test]
+fn templates_fill_in_record_fields() {
+    let record = area(1, "IA", "TX");
+    assert_eq!(fema::render(fema::DEFAULT_CHAT_TEMPLATE, &record), "New IA designation: Place 1, TX, designated 2020-01-01T00:00:00.000Z");
+    assert_eq!(fema::render("DR-{disasterNumber} {missing}} {", &record), "DR-4000 } {");
+}
+
+#[test]
+fn channels_only_hear_about_the_records_they_want() {
+    let channel = ChatChannel { kind: ChatKind::Discord, url: String::from("http://localhost/"), template: String::from("{placeName}"), program_types: Some(vec![fema::ProgramType::from("ia")]), states: None };
+    let records = vec![area(1, "IA", "TX"), area(2, "PA", "TX"), area(3, "IA", "LA")];
+    assert_eq!(channel.message(Dataset::FemaWebDeclarationAreas, &records).unwrap(), "Place 1\nPlace 3");
+    let channel = ChatChannel { states: Some(vec![fema::StateCode::FL]), ..channel };
+    assert_eq!(channel.message(Dataset::FemaWebDeclarationAreas, &records), None);
+    // Long lists are cut short
+    let channel = ChatChannel { states: None, program_types: None, ..channel };
+    let many: Vec<serde_json::Value> = (0..25).map(|id| area(id, "IA", "TX")).collect();
+    let message = channel.message(Dataset::FemaWebDeclarationAreas, &many).unwrap();
+    assert_eq!(message.lines().count(), ChatChannel::MAX_LINES + 1);
+    assert!(message.ends_with("…and 5 more"));
+}
+
+#[test]
+fn email_reports_can_attach_the_changes() {
+    let report = EmailReport { smtp_host: String::from("localhost"), from: String::from("fema@example.com"), to: vec![String::from("em@example.com")], attach_csv: true, ..EmailReport::default() };
+    assert!(report.validate().is_ok());
+    let changes = vec![Change::added(area(1, "IA", "TX")), Change::added(area(2, "PA", "LA"))];
+    let message = String::from_utf8(report.message(Dataset::FemaWebDeclarationAreas, &changes).unwrap().formatted()).unwrap();
+    assert!(message.contains("Subject: FemaWebDeclarationAreas: 2 new, 0 changed, 0 removed"));
+    assert!(message.contains("Content-Type: text/csv"));
+    assert!(message.contains("FemaWebDeclarationAreas-changes.csv"));
+    assert!(EmailReport { to: vec![String::from("not an address")], ..report.clone() }.validate().is_err());
+    assert!(EmailReport { to: Vec::new(), ..report }.validate().is_err());
+}
+
+#[test]
+fn desktop_notifications_name_the_first_few_records() {
+    let (summary, body) = fema::desktop_message(Dataset::FemaWebDeclarationAreas, &[area(1, "IA", "TX")]);
+    assert_eq!(summary, "1 new FemaWebDeclarationAreas record");
+    assert_eq!(body, "Place 1, TX (IA)");
+    let many: Vec<serde_json::Value> = (0..8).map(|id| area(id, "IA", "TX")).collect();
+    let (summary, body) = fema::desktop_message(Dataset::FemaWebDeclarationAreas, &many);
+    assert_eq!(summary, "8 new FemaWebDeclarationAreas records");
+    assert_eq!(body.lines().count(), fema::DESKTOP_LINES + 1);
+    assert!(body.ends_with("…and 3 more"));
+}
+
+#[test]
+fn mqtt_topics_are_filled_in_from_the_record() {
+    let sink = MqttSink { url: String::from("mqtt://localhost"), ..MqttSink::default() };
+    assert!(sink.validate().is_ok());
+    assert_eq!(sink.topic_for(&area(1, "IA", "TX")), "fema/TX/IA");
+    // Values can't add topic levels or wildcards
+    assert_eq!(sink.topic_for(&area(1, "IA/PA", "T#")), "fema/T_/IA_PA");
+    assert!(MqttSink { url: String::from("http://localhost"), ..sink.clone() }.validate().is_err());
+    assert!(MqttSink { topic: String::from("fema/+/{stateCode}"), ..sink.clone() }.validate().is_err());
+    assert!(MqttSink { qos: 2, ..sink.clone() }.validate().is_err());
+    assert!(MqttSink { password: Some(String::from("secret")), ..sink }.validate().is_err());
+}
+
+#[test]
+fn kafka_sink_needs_brokers_and_a_topic() {
+    let sink = KafkaSink { brokers: String::from("localhost:9092"), topic: String::from("declarations"), ..KafkaSink::default() };
+    if cfg!(feature = "kafka") {
+        assert!(sink.validate().is_ok());
+        assert!(KafkaSink { brokers: String::new(), ..sink.clone() }.validate().unwrap_err().contains("brokers"));
+        assert!(KafkaSink { topic: String::new(), ..sink }.validate().unwrap_err().contains("topic"));
+    }
+    else {
+        assert!(sink.validate().unwrap_err().contains("kafka feature"));
+    }
+}
+
+#[tokio::test]
+async fn kafka_publishing_fails_when_no_broker_acknowledges() {
+    let mut sink = KafkaSink { brokers: String::from("127.0.0.1:1"), topic: String::from("declarations"), ..KafkaSink::default() };
+    sink.properties.insert(String::from("message.timeout.ms"), String::from("500"));
+    let changes = vec![Change::added(area(1, "IA", "TX"))];
+    assert!(sink.publish(Dataset::FemaWebDeclarationAreas, &changes).await.is_err());
+    // Removed records aren't published, so there's nothing to wait for
+    let removed = vec![Change { change: fema::ChangeKind::Removed, ..Change::added(area(2, "IA", "TX")) }];
+    assert_eq!(sink.publish(Dataset::FemaWebDeclarationAreas, &removed).await.ok(), cfg!(feature = "kafka").then_some(0));
+}
@@ -0,0 +1,184 @@
+extern crate fema;
+extern crate tokio;
+mod common;
+use common::{entry,page,page_of,serve,serve_keep_alive};
+
+#[tokio::test]
+async fn zero_count_with_results_pages_until_empty() {
+    let (uri, served) = serve(vec![
+        ("0", page(Some(0), 0..1000)),
+        ("1000", page(None, 1000..1500)),
+        ("2000", page(None, 0..0)),
+    ]).await;
+    let client = fema::FemaClient::new(fema::Config::default()).unwrap().with_base_uri(&uri);
+    let entries = client.fetch_declaration_areas("closeoutDate eq null").await.unwrap();
+    assert_eq!(entries.len(), 1500);
+    assert_eq!(entries[1499].id, "1499");
+    assert_eq!(served.load(std::sync::atomic::Ordering::SeqCst), 3);
+}
+
+#[tokio::test]
+async fn count_is_trusted_when_consistent() {
+    let (uri, served) = serve(vec![
+        ("0", page(Some(1500), 0..1000)),
+        ("1000", page(None, 1000..1500)),
+    ]).await;
+    let client = fema::FemaClient::new(fema::Config::default()).unwrap().with_base_uri(&uri);
+    let entries = client.fetch_declaration_areas("closeoutDate eq null").await.unwrap();
+    assert_eq!(entries.len(), 1500);
+    assert_eq!(served.load(std::sync::atomic::Ordering::SeqCst), 2);
+}
+
+#[tokio::test]
+async fn sequential_pages_reuse_one_connection() {
+    let (uri, accepted) = serve_keep_alive(vec![
+        ("0", page(Some(0), 0..1000)),
+        ("1000", page(None, 1000..2000)),
+        ("2000", page(None, 0..0)),
+    ]).await;
+    let client = fema::FemaClient::new(fema::Config::default()).unwrap().with_base_uri(&uri);
+    let entries = client.fetch_declaration_areas("closeoutDate eq null").await.unwrap();
+    assert_eq!(entries.len(), 2000);
+    assert_eq!(accepted.load(std::sync::atomic::Ordering::SeqCst), 1);
+}
+
+#[tokio::test]
+async fn resuming_skips_pages_already_written() {
+    let (uri, served) = serve(vec![
+        ("0", page(Some(2500), 0..1000)),
+        ("1000", page(None, 1000..2000)),
+        ("2000", page(None, 2000..2500)),
+    ]).await;
+    let client = fema::FemaClient::new(fema::Config::default()).unwrap().with_base_uri(&uri);
+    let mut ids = Vec::new();
+    let received = client.fetch_each_from::<fema::Entry, _>("closeoutDate eq null", 2, |entries, _| {
+        ids.extend(entries.into_iter().map(|e| e.id));
+        Ok(())
+    }).await.unwrap();
+    assert_eq!(received, 500);
+    assert_eq!(ids.first().map(String::as_str), Some("2000"));
+    // The first page is still requested for the count, the second isn't
+    assert_eq!(served.load(std::sync::atomic::Ordering::SeqCst), 2);
+}
+
+#[tokio::test]
+async fn configured_page_size_is_used() {
+    let (uri, served) = serve(vec![
+        ("0", page_of(Some((1000, 400)), 0..400)),
+        ("400", page(None, 400..800)),
+        ("800", page(None, 800..1000)),
+    ]).await;
+    let cfg = fema::Config { page_size: 400, ..fema::Config::default() };
+    let client = fema::FemaClient::new(cfg).unwrap().with_base_uri(&uri);
+    let entries = client.fetch_declaration_areas("closeoutDate eq null").await.unwrap();
+    assert_eq!(entries.len(), 1000);
+    assert_eq!(served.load(std::sync::atomic::Ordering::SeqCst), 3);
+}
+
+#[tokio::test]
+async fn server_page_size_cap_is_followed() {
+    let (uri, served) = serve(vec![
+        ("0", page_of(Some((1200, 500)), 0..500)),
+        ("500", page(None, 500..1000)),
+        ("1000", page(None, 1000..1200)),
+    ]).await;
+    let cfg = fema::Config { preserve_server_order: true, ..fema::Config::default() };
+    let client = fema::FemaClient::new(cfg).unwrap().with_base_uri(&uri);
+    let entries = client.fetch_declaration_areas("closeoutDate eq null").await.unwrap();
+    assert_eq!(entries.len(), 1200);
+    assert_eq!(entries[1199].id, "1199");
+    assert_eq!(served.load(std::sync::atomic::Ordering::SeqCst), 3);
+}
+
+#[tokio::test]
+async fn page_size_over_the_limit_is_rejected() {
+    let cfg = fema::Config { page_size: fema::MAX_PAGE_SIZE + 1, ..fema::Config::default() };
+    let client = fema::FemaClient::new(cfg).unwrap().with_base_uri("http://127.0.0.1:9/");
+    let err = client.fetch_declaration_areas("").await.unwrap_err();
+    assert!(matches!(err, fema::Error::Config(_)));
+    assert!(err.to_string().contains("Page size"));
+}
+
+#[tokio::test]
+async fn malformed_page_is_reported_with_its_number() {
+    let (uri, _) = serve(vec![
+        ("0", page(Some(2500), 0..1000)),
+        ("1000", page(None, 1000..2000).replace(r#""disasterNumber":4000"#, r#""disasterNumber":"x""#)),
+        ("2000", page(None, 2000..2500)),
+    ]).await;
+    let client = fema::FemaClient::new(fema::Config::default()).unwrap().with_base_uri(&uri);
+    match client.fetch_declaration_areas("closeoutDate eq null").await.unwrap_err() {
+        fema::Error::Deserialize { page, .. } => assert_eq!(page, Some(1)),
+        e => panic!("expected a deserialization error, got {}", e),
+    }
+}
+
+#[tokio::test]
+async fn count_only_requests_one_page() {
+    let (uri, served) = serve(vec![("0", page(Some(1500), 0..1))]).await;
+    let client = fema::FemaClient::new(fema::Config::default()).unwrap().with_base_uri(&uri);
+    assert_eq!(client.count("closeoutDate eq null").await.unwrap(), 1500);
+    assert_eq!(served.load(std::sync::atomic::Ordering::SeqCst), 1);
+}
+
+#[tokio::test]
+async fn deprecated_endpoint_is_followed_to_new_url() {
+    let (new_uri, new_served) = serve(vec![("0", page(Some(2), 0..2))]).await;
+    let deprecated = page(Some(2), 0..2).replace(r#""DeprecationInformation":{"depDate":null}"#,
+        &format!(r#""DeprecationInformation":{{"depDate":"2020-01-01T00:00:00.000Z","depApiMessage":"Use v2.","depNewURL":"{}"}}"#, new_uri));
+    let (uri, served) = serve(vec![("0", deprecated)]).await;
+    let cfg = fema::Config { follow_new_url: true, ..fema::Config::default() };
+    let client = fema::FemaClient::new(cfg).unwrap().with_base_uri(&uri);
+    let entries = client.fetch_declaration_areas("closeoutDate eq null").await.unwrap();
+    assert_eq!(entries.len(), 2);
+    assert_eq!(served.load(std::sync::atomic::Ordering::SeqCst), 1);
+    assert_eq!(new_served.load(std::sync::atomic::Ordering::SeqCst), 1);
+}
+
+#[tokio::test]
+async fn jsona_pages_are_bare_arrays() {
+    let bare = |ids: std::ops::Range<usize>| format!("[{}]", ids.map(entry).collect::<Vec<_>>().join(","));
+    let (uri, served) = serve(vec![
+        ("0", page(Some(2500), 0..1000)),
+        ("1000", bare(1000..2000)),
+        ("2000", bare(2000..2500)),
+    ]).await;
+    let cfg = fema::Config { response_format: fema::ResponseFormat::Jsona, preserve_server_order: true, ..fema::Config::default() };
+    let client = fema::FemaClient::new(cfg).unwrap().with_base_uri(&uri);
+    let entries = client.fetch_declaration_areas("closeoutDate eq null").await.unwrap();
+    assert_eq!(entries.len(), 2500);
+    assert_eq!(entries[2499].id, "2499");
+    assert_eq!(served.load(std::sync::atomic::Ordering::SeqCst), 3);
+}
+
+#[tokio::test]
+async fn jsona_pages_are_checked_for_drift() {
+    let bare = format!("[{}]", entry(1000).replace(r#""hash":"abc","#, r#""hash":"abc","countyName":"Anderson County","#));
+    let (uri, _) = serve(vec![
+        ("0", page(Some(1001), 0..1000)),
+        ("1000", bare),
+    ]).await;
+    let cfg = fema::Config { response_format: fema::ResponseFormat::Jsona, strict_schema: true, ..fema::Config::default() };
+    let client = fema::FemaClient::new(cfg).unwrap().with_base_uri(&uri);
+    match client.fetch_declaration_areas("closeoutDate eq null").await.unwrap_err() {
+        fema::Error::Deserialize { page, source, .. } => {
+            assert_eq!(page, Some(1));
+            assert!(source.to_string().contains("countyName"), "{}", source);
+        },
+        e => panic!("expected a deserialization error, got {}", e),
+    }
+}
+
+#[tokio::test]
+async fn total_is_reported_from_the_first_page() {
+    let (uri, _) = serve(vec![
+        ("0", page(Some(1500), 0..1000)),
+        ("1000", page(None, 1000..1500)),
+    ]).await;
+    let total = std::sync::Arc::new(std::sync::atomic::AtomicUsize::new(0));
+    let reported = total.clone();
+    let client = fema::FemaClient::new(fema::Config::default()).unwrap().with_base_uri(&uri)
+        .with_on_total(move |count| reported.store(count, std::sync::atomic::Ordering::SeqCst));
+    client.fetch_declaration_areas("closeoutDate eq null").await.unwrap();
+    assert_eq!(total.load(std::sync::atomic::Ordering::SeqCst), 1500);
+}
@@ -0,0 +1,28 @@
+extern crate fema;
+use fema::HttpProxy;
+
+#[test]
+fn proxies_need_a_url_with_a_known_scheme() {
+    assert!(HttpProxy::default().validate().unwrap_err().contains("url"));
+    let proxy = HttpProxy { url: Some(String::from("ftp://proxy:21")), ..HttpProxy::default() };
+    assert!(proxy.validate().unwrap_err().contains("ftp"));
+    let proxy = HttpProxy { url: Some(String::from("socks5h://localhost:1080")), ..HttpProxy::default() };
+    assert_eq!(proxy.validate(), Ok(()));
+    let proxy = HttpProxy { url: None, https: Some(String::from("http://proxy:8080")), password: Some(String::from("secret")), ..proxy };
+    assert!(proxy.validate().unwrap_err().contains("username"));
+}
+
+#[test]
+fn configured_proxies_build_a_client() {
+    let cfg = fema::Config {
+        proxy: Some(HttpProxy {
+            http: Some(String::from("http://proxy:8080")),
+            https: Some(String::from("socks5://127.0.0.1:1080")),
+            username: Some(String::from("user")),
+            password: Some(String::from("secret")),
+            ..HttpProxy::default()
+        }),
+        ..fema::Config::default()
+    };
+    assert!(fema::http_client(&cfg).is_ok());
+}
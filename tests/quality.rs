@@ -0,0 +1,61 @@
+extern crate fema;
+extern crate serde_json;
+use fema::{IssueKind,Validator};
+
+fn record(id: &str, changes: serde_json::Value) -> serde_json::Value {
+    let mut record = serde_json::json!({
+        "disasterNumber": 4000, "programTypeCode": "IA", "stateCode": "TX", "placeName": "Anderson",
+        "designatedDate": "2020-01-01T00:00:00.000Z", "entryDate": "2020-01-01T00:00:00.000Z", "updateDate": "2020-01-01T00:00:00.000Z",
+        "id": id,
+    });
+    for (field, value) in changes.as_object().unwrap() {
+        record[field] = value.clone();
+    }
+    record
+}
+
+#[test]
+fn suspicious_records_are_flagged() {
+    let mut validator = Validator::new();
+    validator.check(1, &[
+        record("a", serde_json::json!({})),
+        record("b", serde_json::json!({ "designatedDate": "2999-01-01T00:00:00.000Z" })),
+        record("c", serde_json::json!({ "entryDate": "2021-01-01T00:00:00.000Z" })),
+        record("d", serde_json::json!({ "placeName": " ", "stateCode": null })),
+        record("a", serde_json::json!({})),
+    ]).unwrap();
+    // Ids only have to be unique within a page
+    validator.check(2, &[record("a", serde_json::json!({}))]).unwrap();
+    let found: Vec<(usize, &str, IssueKind, &str)> = validator.issues().iter()
+        .map(|issue| (issue.page, issue.id.as_str(), issue.issue, issue.field.as_str())).collect();
+    assert_eq!(found, vec![
+        (1, "b", IssueKind::FutureDate, "designatedDate"),
+        (1, "c", IssueKind::EntryAfterUpdate, "entryDate"),
+        (1, "d", IssueKind::MissingValue, "placeName"),
+        (1, "d", IssueKind::MissingValue, "stateCode"),
+        (1, "a", IssueKind::DuplicateId, "id"),
+    ]);
+}
+
+#[test]
+fn fields_a_dataset_lacks_are_not_flagged() {
+    let mut validator = Validator::new();
+    validator.check(1, &[serde_json::json!({ "disasterNumber": 4000 }), serde_json::json!({ "disasterNumber": 4000 })]).unwrap();
+    assert!(validator.issues().is_empty());
+}
+
+#[test]
+fn issues_are_written_as_csv_or_json() {
+    let dir = std::env::temp_dir().join(format!("fema-quality-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let mut validator = Validator::new();
+    validator.check(3, &[record("a", serde_json::json!({ "placeName": "" }))]).unwrap();
+    fema::write_issues(&dir.join("issues.csv"), validator.issues()).unwrap();
+    let csv = std::fs::read_to_string(dir.join("issues.csv")).unwrap();
+    assert_eq!(csv, "page,id,issue,field,detail\n3,a,missing-value,placeName,empty\n");
+    fema::write_issues(&dir.join("issues.json"), validator.issues()).unwrap();
+    let written: Vec<fema::Issue> = serde_json::from_str(&std::fs::read_to_string(dir.join("issues.json")).unwrap()).unwrap();
+    assert_eq!(written, validator.issues());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,123 @@
+extern crate fema;
+extern crate serde_json;
+extern crate tokio;
+use tokio::io::{AsyncReadExt,AsyncWriteExt};
+use tokio::net::TcpListener;
+
+const BODY: &str = r#"{"FemaWebDeclarationAreas":[{"disasterNumber":4000}]}"#;
+
+enum Reply {
+    // Promises the full body in the Content-Length but only sends this many bytes before closing
+    Truncated(usize),
+    Complete,
+    Status(u16),
+    // Sends this many bytes of the body and then nothing more, holding the connection open
+    Stalled(usize),
+    // A 429 asking for the given number of seconds before the next request
+    RateLimited(u64),
+}
+
+// Serves one reply per connection from the given list
+async fn serve(replies: Vec<Reply>) -> String {
+    let mut listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        for reply in replies {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut buf = [0; 4096];
+            let _ = socket.read(&mut buf).await.unwrap();
+            if let Reply::Stalled(sent) = reply {
+                let head = format!("HTTP/1.1 200 X\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n", BODY.len());
+                socket.write_all(head.as_bytes()).await.unwrap();
+                socket.write_all(&BODY.as_bytes()[..sent]).await.unwrap();
+                tokio::spawn(async move {
+                    tokio::time::delay_for(std::time::Duration::from_secs(10)).await;
+                    drop(socket);
+                });
+                continue;
+            }
+            let (status, sent, extra) = match reply {
+                Reply::Truncated(sent) => (200, sent, String::new()),
+                Reply::Complete => (200, BODY.len(), String::new()),
+                Reply::Status(status) => (status, BODY.len(), String::new()),
+                Reply::RateLimited(secs) => (429, BODY.len(), format!("Retry-After: {}\r\n", secs)),
+                Reply::Stalled(_) => unreachable!(),
+            };
+            let head = format!("HTTP/1.1 {} X\r\nContent-Type: application/json\r\nContent-Length: {}\r\n{}Connection: close\r\n\r\n", status, BODY.len(), extra);
+            socket.write_all(head.as_bytes()).await.unwrap();
+            socket.write_all(&BODY.as_bytes()[..sent]).await.unwrap();
+            socket.shutdown(std::net::Shutdown::Write).unwrap();
+        }
+    });
+    format!("http://{}/", addr)
+}
+
+fn policy(retries: usize, body_retries: usize) -> fema::RetryPolicy {
+    fema::RetryPolicy {
+        retries,
+        body_retries,
+        base_delay: std::time::Duration::from_millis(1),
+        max_delay: std::time::Duration::from_millis(10),
+        max_retry_after: std::time::Duration::from_secs(5),
+        read_timeout: Some(std::time::Duration::from_millis(500)),
+    }
+}
+
+#[tokio::test]
+async fn truncated_body_is_retried() {
+    let uri = serve(vec![Reply::Truncated(20), Reply::Complete]).await;
+    let response: serde_json::Value = fema::get_json(&reqwest::Client::new(), &uri, &policy(0, 3), None, None).await.unwrap();
+    assert_eq!(response["FemaWebDeclarationAreas"][0]["disasterNumber"], 4000);
+}
+
+#[tokio::test]
+async fn truncated_body_gives_up_after_retries() {
+    let uri = serve(vec![Reply::Truncated(20), Reply::Truncated(20)]).await;
+    let err = fema::get_json::<serde_json::Value>(&reqwest::Client::new(), &uri, &policy(3, 1), None, None).await.unwrap_err();
+    assert!(err.is_truncated_body());
+}
+
+#[tokio::test]
+async fn server_error_is_retried() {
+    let uri = serve(vec![Reply::Status(502), Reply::Status(503), Reply::Complete]).await;
+    let response: serde_json::Value = fema::get_json(&reqwest::Client::new(), &uri, &policy(2, 0), None, None).await.unwrap();
+    assert_eq!(response["FemaWebDeclarationAreas"][0]["disasterNumber"], 4000);
+}
+
+#[tokio::test]
+async fn client_error_is_not_retried() {
+    let uri = serve(vec![Reply::Status(400), Reply::Complete]).await;
+    let err = fema::get_json::<serde_json::Value>(&reqwest::Client::new(), &uri, &policy(3, 3), None, None).await.unwrap_err();
+    assert_eq!(err.status(), Some(400));
+}
+
+#[tokio::test]
+async fn rate_limited_requests_wait_as_asked() {
+    let uri = serve(vec![Reply::RateLimited(1), Reply::Complete]).await;
+    let started = std::time::Instant::now();
+    let response: serde_json::Value = fema::get_json(&reqwest::Client::new(), &uri, &policy(1, 0), None, None).await.unwrap();
+    assert_eq!(response["FemaWebDeclarationAreas"][0]["disasterNumber"], 4000);
+    assert!(started.elapsed() >= std::time::Duration::from_secs(1));
+}
+
+#[tokio::test]
+async fn overlong_retry_after_is_not_waited_out() {
+    let uri = serve(vec![Reply::RateLimited(3600), Reply::Complete]).await;
+    let err = fema::get_json::<serde_json::Value>(&reqwest::Client::new(), &uri, &policy(3, 0), None, None).await.unwrap_err();
+    assert_eq!(err.status(), Some(429));
+    assert_eq!(err.retry_after(), Some(std::time::Duration::from_secs(3600)));
+}
+
+#[tokio::test]
+async fn stalled_response_times_out_and_is_retried() {
+    let uri = serve(vec![Reply::Stalled(20), Reply::Complete]).await;
+    let response: serde_json::Value = fema::get_json(&reqwest::Client::new(), &uri, &policy(1, 0), None, None).await.unwrap();
+    assert_eq!(response["FemaWebDeclarationAreas"][0]["disasterNumber"], 4000);
+}
+
+#[tokio::test]
+async fn stalled_response_gives_up_after_retries() {
+    let uri = serve(vec![Reply::Stalled(20)]).await;
+    let err = fema::get_json::<serde_json::Value>(&reqwest::Client::new(), &uri, &policy(0, 3), None, None).await.unwrap_err();
+    assert!(matches!(err, fema::Error::Timeout { .. }), "{}", err);
+}
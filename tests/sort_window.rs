@@ -0,0 +1,53 @@
+extern crate fema;
+extern crate serde_json;
+use fema::{Row,SortWindow};
+use serde_json::{json,Value};
+
+fn row(state: &str, date: &str) -> Row {
+    Row(vec![(String::from("stateCode"), json!(state)), (String::from("designatedDate"), json!(date))])
+}
+
+fn dates(rows: &[Row]) -> Vec<&str> {
+    rows.iter().map(|row| row.get("designatedDate").and_then(Value::as_str).unwrap()).collect()
+}
+
+#[test]
+fn pages_out_of_order_within_the_window_come_out_sorted() {
+    let mut window = SortWindow::new("designatedDate", 4);
+    let mut written = window.push(vec![row("TX", "2020-01-03T00:00:00Z"), row("TX", "2020-01-04T00:00:00Z")]);
+    assert!(written.is_empty());
+    written.extend(window.push(vec![row("TX", "2020-01-01T00:00:00Z"), row("TX", "2020-01-02T00:00:00Z")]));
+    assert!(written.is_empty());
+    written.extend(window.push(vec![row("TX", "2020-01-06T00:00:00Z"), row("TX", "2020-01-05T00:00:00Z")]));
+    assert_eq!(dates(&written), vec!["2020-01-01T00:00:00Z", "2020-01-02T00:00:00Z"]);
+    written.extend(window.drain());
+    assert_eq!(dates(&written), vec!["2020-01-01T00:00:00Z", "2020-01-02T00:00:00Z", "2020-01-03T00:00:00Z",
+        "2020-01-04T00:00:00Z", "2020-01-05T00:00:00Z", "2020-01-06T00:00:00Z"]);
+    assert_eq!(window.take_late(), 0);
+}
+
+#[test]
+fn records_further_out_of_place_than_the_window_are_written_late() {
+    let mut window = SortWindow::new("designatedDate", 2);
+    let mut written = window.push(vec![row("TX", "2020-01-02T00:00:00Z"), row("TX", "2020-01-03T00:00:00Z"), row("TX", "2020-01-04T00:00:00Z")]);
+    written.extend(window.push(vec![row("TX", "2020-01-01T00:00:00Z")]));
+    written.extend(window.drain());
+    assert_eq!(dates(&written), vec!["2020-01-02T00:00:00Z", "2020-01-01T00:00:00Z", "2020-01-03T00:00:00Z", "2020-01-04T00:00:00Z"]);
+    assert_eq!(window.take_late(), 1);
+    assert_eq!(window.take_late(), 0);
+}
+
+#[test]
+fn each_key_sorts_in_its_own_direction() {
+    let mut window = SortWindow::new("stateCode, designatedDate desc", 10);
+    window.push(vec![
+        row("TX", "2020-01-01T00:00:00Z"),
+        row("AL", "2020-01-01T00:00:00Z"),
+        // The same time in another offset, which sorts by when it is rather than as text
+        row("TX", "2020-01-01T01:00:00+02:00"),
+        row("AL", "2020-01-02T00:00:00Z"),
+    ]);
+    let written = window.drain();
+    let keys: Vec<(&str, &str)> = written.iter().map(|row| (row.get("stateCode").and_then(Value::as_str).unwrap(), row.get("designatedDate").and_then(Value::as_str).unwrap())).collect();
+    assert_eq!(keys, vec![("AL", "2020-01-02T00:00:00Z"), ("AL", "2020-01-01T00:00:00Z"), ("TX", "2020-01-01T00:00:00Z"), ("TX", "2020-01-01T01:00:00+02:00")]);
+}